@@ -0,0 +1,405 @@
+use cosmwasm_std::{
+    log, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HandleResult, HumanAddr,
+    InitResponse, Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
+};
+use secretswap_stablepools::msg::{
+    FactoryInfo, HandleMsg as PoolHandleMsg, InitMsg as PoolInitMsg, Token,
+};
+
+use crate::{
+    error::ContractError,
+    msg::{Config, ConfigResponse, HandleMsg, InitMsg, PoolInfo, QueryMsg},
+    state::{
+        append_pool, read_config, read_pending_pool, read_pool, read_pool_count, read_pool_index,
+        read_token_pools, remove_pending_pool, store_config, store_pending_pool, store_pool,
+        PendingPool,
+    },
+};
+
+/// Pools returned by an AllPools page when it doesn't set a limit
+pub const DEFAULT_LIMIT: u32 = 10;
+/// Upper bound on the limit of an AllPools page
+pub const MAX_LIMIT: u32 = 30;
+
+pub fn init<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    _env: Env,
+    msg: InitMsg,
+) -> StdResult<InitResponse> {
+    let config = Config {
+        admin: msg.admin,
+        pool_code_id: msg.pool_code_id,
+        pool_code_hash: msg.pool_code_hash,
+        lp_token_code_id: msg.lp_token_code_id,
+        lp_token_code_hash: msg.lp_token_code_hash,
+        default_amp: msg.default_amp,
+        default_swap_fee_nom: msg.default_swap_fee_nom,
+        default_swap_fee_denom: msg.default_swap_fee_denom,
+        prng_seed: msg.prng_seed,
+    };
+    validate_config(&config)?;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(InitResponse {
+        messages: vec![],
+        log: vec![log("status", "success")], // See https://github.com/CosmWasm/wasmd/pull/386
+    })
+}
+
+pub fn handle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> HandleResult {
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err(
+            "The factory doesn't take native coins",
+        ));
+    }
+
+    match msg {
+        HandleMsg::CreatePool {
+            assets,
+            initial_tokens_viewing_key,
+            label,
+            amp,
+            swap_fee_nom,
+            swap_fee_denom,
+        } => try_create_pool(
+            deps,
+            env,
+            assets,
+            initial_tokens_viewing_key,
+            label,
+            amp,
+            swap_fee_nom,
+            swap_fee_denom,
+        ),
+        HandleMsg::RegisterPool { assets } => try_register_pool(deps, env, assets),
+        HandleMsg::UpdatePool { assets, admin } => try_update_pool(deps, env, assets, admin),
+        HandleMsg::MigratePoolAdmin { pool, new_admin } => {
+            try_migrate_pool_admin(deps, env, pool, new_admin)
+        }
+        HandleMsg::UpdateConfig {
+            admin,
+            pool_code_id,
+            pool_code_hash,
+            lp_token_code_id,
+            lp_token_code_hash,
+            default_amp,
+            default_swap_fee_nom,
+            default_swap_fee_denom,
+        } => try_update_config(
+            deps,
+            env,
+            admin,
+            pool_code_id,
+            pool_code_hash,
+            lp_token_code_id,
+            lp_token_code_hash,
+            default_amp,
+            default_swap_fee_nom,
+            default_swap_fee_denom,
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_create_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    assets: Vec<Token>,
+    initial_tokens_viewing_key: String,
+    label: String,
+    amp: Option<u64>,
+    swap_fee_nom: Option<Uint128>,
+    swap_fee_denom: Option<Uint128>,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+    if assets.len() < 2 {
+        return Err(StdError::generic_err("A pool needs at least two assets"));
+    }
+
+    // The pool registers itself from its init, which runs right after this handler
+    store_pending_pool(
+        &mut deps.storage,
+        &PendingPool {
+            assets: assets.iter().map(|t| t.address.clone()).collect(),
+            code_hash: config.pool_code_hash.clone(),
+        },
+    )?;
+
+    // The tx makes the seed differ between factories sharing a prng_seed, and keeps it from
+    // being derived from the pool index alone
+    let mut lp_token_prng_seed = config.prng_seed.0.clone();
+    lp_token_prng_seed.extend_from_slice(&(read_pool_count(&deps.storage)? as u64).to_be_bytes());
+    lp_token_prng_seed.extend_from_slice(&env.block.height.to_be_bytes());
+    lp_token_prng_seed.extend_from_slice(&env.block.time.to_be_bytes());
+    lp_token_prng_seed.extend_from_slice(env.message.sender.as_str().as_bytes());
+    let pool_msg = PoolInitMsg {
+        assets,
+        initial_tokens_viewing_key,
+        lp_token_code_id: config.lp_token_code_id,
+        lp_token_code_hash: config.lp_token_code_hash,
+        lp_token_prng_seed: Binary(lp_token_prng_seed),
+        lp_token_label: format!("{}-lp", label),
+        admin: env.contract.address.clone(),
+        amp: amp.unwrap_or(config.default_amp),
+        swap_fee_nom: swap_fee_nom.unwrap_or(config.default_swap_fee_nom),
+        swap_fee_denom: swap_fee_denom.unwrap_or(config.default_swap_fee_denom),
+        status: None,
+        round_down_pool_answer_to_nearest: Uint128(1),
+        max_swap_out_bps: None,
+        dynamic_fee: None,
+        dynamic_fee_base_bps: None,
+        dynamic_fee_max_bps: None,
+        dynamic_fee_min_bps: None,
+        depeg_threshold_bps: None,
+        max_referral_fee_bps: None,
+        admin_fee_bps: None,
+        max_volume_per_block: None,
+        rebalance_bonus_to_lp_bps: None,
+        deposit_bonus_bps: None,
+        per_asset_deposit_cap: None,
+        total_tvl_cap: None,
+        min_reserve_after_swap: None,
+        seed_tvl_cap: None,
+        factory: Some(FactoryInfo {
+            address: env.contract.address,
+            code_hash: env.contract_code_hash,
+        }),
+    };
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id: config.pool_code_id,
+            callback_code_hash: config.pool_code_hash,
+            msg: to_binary(&pool_msg)?,
+            send: vec![],
+            label: label.clone(),
+        })],
+        log: vec![log("action", "create_pool"), log("label", label)],
+        data: None,
+    })
+}
+
+pub fn try_register_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    assets: Vec<HumanAddr>,
+) -> HandleResult {
+    // Only a pool instantiated by CreatePool, over the assets it was given, may register
+    let pending = match read_pending_pool(&deps.storage)? {
+        Some(pending) if pending.assets == assets => pending,
+        _ => return Err(ContractError::Unauthorized.into()),
+    };
+    remove_pending_pool(&mut deps.storage);
+
+    append_pool(
+        &mut deps.storage,
+        &PoolInfo {
+            address: env.message.sender.clone(),
+            assets,
+            admin: env.contract.address,
+            code_hash: pending.code_hash,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "register_pool"),
+            log("pool", env.message.sender),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_update_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    assets: Vec<HumanAddr>,
+    admin: HumanAddr,
+) -> HandleResult {
+    let index =
+        read_pool_index(&deps.storage, &env.message.sender)?.ok_or(ContractError::Unauthorized)?;
+    let mut pool = read_pool(&deps.storage, index)?;
+    pool.assets = assets;
+    pool.admin = admin.clone();
+    store_pool(&mut deps.storage, index, &pool)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "update_pool"),
+            log("pool", env.message.sender),
+            log("admin", admin),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_migrate_pool_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pool: HumanAddr,
+    new_admin: HumanAddr,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+    let index = read_pool_index(&deps.storage, &pool)?
+        .ok_or_else(|| ContractError::UnknownPool(pool.clone()))?;
+    let pool_info = read_pool(&deps.storage, index)?;
+    if pool_info.admin != env.contract.address {
+        return Err(ContractError::NotAdministered(pool).into());
+    }
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pool.clone(),
+            callback_code_hash: pool_info.code_hash,
+            msg: to_binary(&PoolHandleMsg::ProposeNewAdmin {
+                address: new_admin.clone(),
+            })?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "migrate_pool_admin"),
+            log("pool", pool),
+            log("pending_admin", new_admin),
+        ],
+        data: None,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    admin: Option<HumanAddr>,
+    pool_code_id: Option<u64>,
+    pool_code_hash: Option<String>,
+    lp_token_code_id: Option<u64>,
+    lp_token_code_hash: Option<String>,
+    default_amp: Option<u64>,
+    default_swap_fee_nom: Option<Uint128>,
+    default_swap_fee_denom: Option<Uint128>,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut logs = vec![log("action", "update_config")];
+    if let Some(admin) = admin {
+        logs.push(log("admin", admin.clone()));
+        config.admin = admin;
+    }
+    if let Some(pool_code_id) = pool_code_id {
+        logs.push(log("pool_code_id", pool_code_id));
+        config.pool_code_id = pool_code_id;
+    }
+    if let Some(pool_code_hash) = pool_code_hash {
+        logs.push(log("pool_code_hash", pool_code_hash.clone()));
+        config.pool_code_hash = pool_code_hash;
+    }
+    if let Some(lp_token_code_id) = lp_token_code_id {
+        logs.push(log("lp_token_code_id", lp_token_code_id));
+        config.lp_token_code_id = lp_token_code_id;
+    }
+    if let Some(lp_token_code_hash) = lp_token_code_hash {
+        logs.push(log("lp_token_code_hash", lp_token_code_hash.clone()));
+        config.lp_token_code_hash = lp_token_code_hash;
+    }
+    if let Some(default_amp) = default_amp {
+        logs.push(log("default_amp", default_amp));
+        config.default_amp = default_amp;
+    }
+    if let Some(default_swap_fee_nom) = default_swap_fee_nom {
+        logs.push(log("default_swap_fee_nom", default_swap_fee_nom));
+        config.default_swap_fee_nom = default_swap_fee_nom;
+    }
+    if let Some(default_swap_fee_denom) = default_swap_fee_denom {
+        logs.push(log("default_swap_fee_denom", default_swap_fee_denom));
+        config.default_swap_fee_denom = default_swap_fee_denom;
+    }
+    validate_config(&config)?;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: logs,
+        data: None,
+    })
+}
+
+fn validate_config(config: &Config) -> StdResult<()> {
+    if config.default_amp == 0 {
+        return Err(StdError::generic_err("default_amp cannot be zero"));
+    }
+    if config.default_swap_fee_denom == Uint128::zero() {
+        return Err(StdError::generic_err(
+            "default_swap_fee_denom cannot be zero",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => {
+            let config = read_config(&deps.storage)?;
+            to_binary(&ConfigResponse {
+                admin: config.admin,
+                pool_code_id: config.pool_code_id,
+                pool_code_hash: config.pool_code_hash,
+                lp_token_code_id: config.lp_token_code_id,
+                lp_token_code_hash: config.lp_token_code_hash,
+                default_amp: config.default_amp,
+                default_swap_fee_nom: config.default_swap_fee_nom,
+                default_swap_fee_denom: config.default_swap_fee_denom,
+            })
+        }
+        QueryMsg::AllPools { start_after, limit } => {
+            to_binary(&query_all_pools(deps, start_after, limit)?)
+        }
+        QueryMsg::PoolsByToken { token } => {
+            let pools = read_token_pools(&deps.storage, &token)?
+                .into_iter()
+                .map(|index| read_pool(&deps.storage, index))
+                .collect::<StdResult<Vec<PoolInfo>>>()?;
+            to_binary(&pools)
+        }
+    }
+}
+
+fn query_all_pools<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<HumanAddr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<PoolInfo>> {
+    let start = match start_after {
+        Some(address) => {
+            read_pool_index(&deps.storage, &address)?.ok_or(ContractError::UnknownPool(address))?
+                + 1
+        }
+        None => 0,
+    };
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let end = start
+        .saturating_add(limit)
+        .min(read_pool_count(&deps.storage)?);
+
+    (start..end)
+        .map(|index| read_pool(&deps.storage, index))
+        .collect()
+}
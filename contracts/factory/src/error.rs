@@ -0,0 +1,26 @@
+use cosmwasm_std::{HumanAddr, StdError};
+use thiserror::Error;
+
+/// Failures callers may want to handle. Each message starts with a code in brackets that stays
+/// the same when the wording changes, so clients can match on it in the returned error string.
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("[unauthorized] Unauthorized")]
+    Unauthorized,
+
+    #[error("[unknown_pool] Pool {0:?} was not created by the factory")]
+    UnknownPool(HumanAddr),
+
+    #[error("[not_administered] Pool {0:?} is no longer administered by the factory")]
+    NotAdministered(HumanAddr),
+}
+
+impl From<ContractError> for StdError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            // Kept as the std variant so callers matching on StdError::Unauthorized still work
+            ContractError::Unauthorized => StdError::unauthorized(),
+            err => StdError::generic_err(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use schemars::JsonSchema;
+use secretswap_stablepools::msg::Token;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub admin: HumanAddr,
+    pub pool_code_id: u64,
+    pub pool_code_hash: String,
+    pub lp_token_code_id: u64,
+    pub lp_token_code_hash: String,
+    /// Used by CreatePool unless it sets its own
+    pub default_amp: u64,
+    pub default_swap_fee_nom: Uint128,
+    pub default_swap_fee_denom: Uint128,
+    /// Seeds the LP token of each pool, mixed with the pool's index and its CreatePool tx.
+    /// Never returned by queries.
+    pub prng_seed: Binary,
+}
+
+/// A pool instantiated by the factory, kept up to date by the pool's callbacks
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PoolInfo {
+    pub address: HumanAddr,
+    pub assets: Vec<HumanAddr>,
+    /// The factory itself until MigratePoolAdmin hands the pool over
+    pub admin: HumanAddr,
+    /// Code hash the pool was instantiated with, kept past pool_code_hash updates
+    pub code_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InitMsg {
+    pub admin: HumanAddr,
+    pub pool_code_id: u64,
+    pub pool_code_hash: String,
+    pub lp_token_code_id: u64,
+    pub lp_token_code_hash: String,
+    pub default_amp: u64,
+    pub default_swap_fee_nom: Uint128,
+    pub default_swap_fee_denom: Uint128,
+    pub prng_seed: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    /// Instantiates a stable pool over `assets`, with the factory as its admin and the default
+    /// amp and fee where unset. `label` must be unique on chain. Admin only.
+    CreatePool {
+        assets: Vec<Token>,
+        initial_tokens_viewing_key: String,
+        label: String,
+        amp: Option<u64>,
+        swap_fee_nom: Option<Uint128>,
+        swap_fee_denom: Option<Uint128>,
+    },
+    /// Callback from the init of a pool CreatePool is instantiating
+    RegisterPool { assets: Vec<HumanAddr> },
+    /// Callback from a registered pool whose assets or admin changed
+    UpdatePool {
+        assets: Vec<HumanAddr>,
+        admin: HumanAddr,
+    },
+    /// Proposes `new_admin` as the admin of a pool the factory administers. The pool is handed
+    /// over once `new_admin` calls AcceptAdmin on it. Admin only.
+    MigratePoolAdmin {
+        pool: HumanAddr,
+        new_admin: HumanAddr,
+    },
+    /// Admin only
+    UpdateConfig {
+        admin: Option<HumanAddr>,
+        pool_code_id: Option<u64>,
+        pool_code_hash: Option<String>,
+        lp_token_code_id: Option<u64>,
+        lp_token_code_hash: Option<String>,
+        default_amp: Option<u64>,
+        default_swap_fee_nom: Option<Uint128>,
+        default_swap_fee_denom: Option<Uint128>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    /// The factory's Config, as a ConfigResponse
+    Config {},
+    /// Registered pools in registration order, as a list of PoolInfo. Pages start after the
+    /// pool at `start_after` and hold `limit` pools, DEFAULT_LIMIT by default and at most
+    /// MAX_LIMIT.
+    AllPools {
+        start_after: Option<HumanAddr>,
+        limit: Option<u32>,
+    },
+    /// Registered pools holding `token`, as a list of PoolInfo
+    PoolsByToken { token: HumanAddr },
+}
+
+/// Config without the prng_seed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub admin: HumanAddr,
+    pub pool_code_id: u64,
+    pub pool_code_hash: String,
+    pub lp_token_code_id: u64,
+    pub lp_token_code_hash: String,
+    pub default_amp: u64,
+    pub default_swap_fee_nom: Uint128,
+    pub default_swap_fee_denom: Uint128,
+}
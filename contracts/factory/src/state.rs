@@ -0,0 +1,98 @@
+use cosmwasm_std::{HumanAddr, StdResult, Storage};
+use cosmwasm_storage::{bucket, bucket_read, ReadonlySingleton, Singleton};
+use serde::{Deserialize, Serialize};
+
+use crate::msg::{Config, PoolInfo};
+
+const CONFIG_KEY: &[u8] = b"config";
+
+pub fn store_config<S: Storage>(storage: &mut S, config: &Config) -> StdResult<()> {
+    Singleton::new(storage, CONFIG_KEY).save(config)
+}
+
+pub fn read_config<S: Storage>(storage: &S) -> StdResult<Config> {
+    ReadonlySingleton::new(storage, CONFIG_KEY).load()
+}
+
+// Pools are stored one per key, by index, so registering or updating one doesn't rewrite the
+// others. Each pool address maps to its index, and each token to the indexes of its pools.
+const POOLS_PREFIX: &[u8] = b"pools";
+const POOL_COUNT_KEY: &[u8] = b"pool_count";
+const POOL_INDEXES_PREFIX: &[u8] = b"pool_indexes";
+const TOKEN_POOLS_PREFIX: &[u8] = b"token_pools";
+
+/// Stores `pool` after the last one, indexed by its address and assets
+pub fn append_pool<S: Storage>(storage: &mut S, pool: &PoolInfo) -> StdResult<()> {
+    let index = read_pool_count(storage)?;
+    bucket(POOLS_PREFIX, storage).save(&index.to_be_bytes(), pool)?;
+    bucket(POOL_INDEXES_PREFIX, storage).save(pool.address.as_str().as_bytes(), &index)?;
+    for token in &pool.assets {
+        add_token_pool(storage, token, index)?;
+    }
+    Singleton::new(storage, POOL_COUNT_KEY).save(&(index + 1))
+}
+
+/// Overwrites the pool at `index`, which must keep its address, and reindexes its assets
+pub fn store_pool<S: Storage>(storage: &mut S, index: u32, pool: &PoolInfo) -> StdResult<()> {
+    let previous = read_pool(storage, index)?;
+    for token in previous.assets.iter().filter(|t| !pool.assets.contains(t)) {
+        let mut pools = read_token_pools(storage, token)?;
+        pools.retain(|i| *i != index);
+        bucket(TOKEN_POOLS_PREFIX, storage).save(token.as_str().as_bytes(), &pools)?;
+    }
+    for token in pool.assets.iter().filter(|t| !previous.assets.contains(t)) {
+        add_token_pool(storage, token, index)?;
+    }
+    bucket(POOLS_PREFIX, storage).save(&index.to_be_bytes(), pool)
+}
+
+fn add_token_pool<S: Storage>(storage: &mut S, token: &HumanAddr, index: u32) -> StdResult<()> {
+    let mut pools = read_token_pools(storage, token)?;
+    // Kept sorted, so a token's pools list in registration order
+    if let Err(position) = pools.binary_search(&index) {
+        pools.insert(position, index);
+    }
+    bucket(TOKEN_POOLS_PREFIX, storage).save(token.as_str().as_bytes(), &pools)
+}
+
+pub fn read_pool_count<S: Storage>(storage: &S) -> StdResult<u32> {
+    Ok(ReadonlySingleton::new(storage, POOL_COUNT_KEY)
+        .may_load()?
+        .unwrap_or(0))
+}
+
+pub fn read_pool<S: Storage>(storage: &S, index: u32) -> StdResult<PoolInfo> {
+    bucket_read(POOLS_PREFIX, storage).load(&index.to_be_bytes())
+}
+
+pub fn read_pool_index<S: Storage>(storage: &S, address: &HumanAddr) -> StdResult<Option<u32>> {
+    bucket_read(POOL_INDEXES_PREFIX, storage).may_load(address.as_str().as_bytes())
+}
+
+/// Indexes of the pools listing `token`, in registration order
+pub fn read_token_pools<S: Storage>(storage: &S, token: &HumanAddr) -> StdResult<Vec<u32>> {
+    Ok(bucket_read(TOKEN_POOLS_PREFIX, storage)
+        .may_load(token.as_str().as_bytes())?
+        .unwrap_or_default())
+}
+
+/// The pool CreatePool is instantiating, until its init registers it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingPool {
+    pub assets: Vec<HumanAddr>,
+    pub code_hash: String,
+}
+
+const PENDING_POOL_KEY: &[u8] = b"pending_pool";
+
+pub fn store_pending_pool<S: Storage>(storage: &mut S, pool: &PendingPool) -> StdResult<()> {
+    Singleton::new(storage, PENDING_POOL_KEY).save(pool)
+}
+
+pub fn read_pending_pool<S: Storage>(storage: &S) -> StdResult<Option<PendingPool>> {
+    ReadonlySingleton::new(storage, PENDING_POOL_KEY).may_load()
+}
+
+pub fn remove_pending_pool<S: Storage>(storage: &mut S) {
+    Singleton::<S, PendingPool>::new(storage, PENDING_POOL_KEY).remove()
+}
@@ -0,0 +1,422 @@
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+};
+use cosmwasm_std::{
+    coins, from_binary, to_binary, Binary, CosmosMsg, Extern, HandleResult, HumanAddr, StdError,
+    Uint128, WasmMsg,
+};
+use secretswap_stablepools::msg::{
+    FactoryHandleMsg, FactoryInfo, HandleMsg as PoolHandleMsg, InitMsg as PoolInitMsg, Token,
+};
+
+use crate::contract::{handle, init, query, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::msg::{ConfigResponse, HandleMsg, InitMsg, PoolInfo, QueryMsg};
+
+const ADMIN: &str = "admin";
+const USER: &str = "user";
+const POOL: &str = "pool";
+
+fn init_factory() -> Extern<MockStorage, MockApi, MockQuerier> {
+    let mut deps = mock_dependencies(20, &[]);
+    let msg = InitMsg {
+        admin: HumanAddr::from(ADMIN),
+        pool_code_id: 1,
+        pool_code_hash: "pool_hash".to_string(),
+        lp_token_code_id: 2,
+        lp_token_code_hash: "lp_token_hash".to_string(),
+        default_amp: 100,
+        default_swap_fee_nom: Uint128(3),
+        default_swap_fee_denom: Uint128(1000),
+        prng_seed: Binary(b"seed".to_vec()),
+    };
+    init(&mut deps, mock_env(ADMIN, &[]), msg).unwrap();
+
+    deps
+}
+
+fn token(address: &str) -> Token {
+    Token {
+        address: HumanAddr::from(address),
+        code_hash: format!("{}_hash", address),
+        native: false,
+    }
+}
+
+fn create_pool_msg(assets: &[&str]) -> HandleMsg {
+    HandleMsg::CreatePool {
+        assets: assets.iter().map(|address| token(address)).collect(),
+        initial_tokens_viewing_key: "viewing_key".to_string(),
+        label: "pool".to_string(),
+        amp: None,
+        swap_fee_nom: None,
+        swap_fee_denom: None,
+    }
+}
+
+/// Sends `msg`, one of the pool's factory callbacks, from `pool`
+fn pool_callback(
+    deps: &mut Extern<MockStorage, MockApi, MockQuerier>,
+    pool: &str,
+    msg: FactoryHandleMsg,
+) -> HandleResult {
+    // The pool's callback has to deserialize as the factory's handle message
+    let msg = from_binary(&to_binary(&msg).unwrap()).unwrap();
+    handle(deps, mock_env(pool, &[]), msg)
+}
+
+fn register_msg(assets: &[&str]) -> FactoryHandleMsg {
+    FactoryHandleMsg::RegisterPool {
+        assets: assets.iter().map(|a| HumanAddr::from(*a)).collect(),
+    }
+}
+
+fn pools(deps: &Extern<MockStorage, MockApi, MockQuerier>) -> Vec<PoolInfo> {
+    let msg = QueryMsg::AllPools {
+        start_after: None,
+        limit: None,
+    };
+    from_binary(&query(deps, msg).unwrap()).unwrap()
+}
+
+fn pools_by_token(deps: &Extern<MockStorage, MockApi, MockQuerier>, token: &str) -> Vec<PoolInfo> {
+    let msg = QueryMsg::PoolsByToken {
+        token: HumanAddr::from(token),
+    };
+    from_binary(&query(deps, msg).unwrap()).unwrap()
+}
+
+/// Creates a pool over `assets` and registers it from `pool`, like its init would
+fn add_pool(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, pool: &str, assets: &[&str]) {
+    handle(deps, mock_env(ADMIN, &[]), create_pool_msg(assets)).unwrap();
+    pool_callback(deps, pool, register_msg(assets)).unwrap();
+}
+
+#[test]
+fn create_pool_with_defaults() {
+    let mut deps = init_factory();
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        create_pool_msg(&["token0", "token1"]),
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        create_pool_msg(&["token0"]),
+    );
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert!(msg.contains("at least two")),
+        _ => panic!("Must return generic error"),
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &coins(1, "uscrt")),
+        create_pool_msg(&["token0", "token1"]),
+    );
+    assert!(res.is_err());
+
+    let env = mock_env(ADMIN, &[]);
+    let res = handle(
+        &mut deps,
+        env.clone(),
+        create_pool_msg(&["token0", "token1"]),
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    let pool_msg: PoolInitMsg = match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id,
+            callback_code_hash,
+            msg,
+            label,
+            ..
+        }) => {
+            assert_eq!(*code_id, 1);
+            assert_eq!(callback_code_hash, "pool_hash");
+            assert_eq!(label, "pool");
+            from_binary(msg).unwrap()
+        }
+        msg => panic!("Unexpected message {:?}", msg),
+    };
+    assert_eq!(pool_msg.assets, vec![token("token0"), token("token1")]);
+    assert_eq!(pool_msg.amp, 100);
+    assert_eq!(pool_msg.swap_fee_nom, Uint128(3));
+    assert_eq!(pool_msg.swap_fee_denom, Uint128(1000));
+    assert_eq!(pool_msg.lp_token_code_id, 2);
+    assert_eq!(pool_msg.admin, HumanAddr::from(MOCK_CONTRACT_ADDR));
+    assert_eq!(
+        pool_msg.factory,
+        Some(FactoryInfo {
+            address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            code_hash: env.contract_code_hash,
+        })
+    );
+
+    // Overrides replace the defaults
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::CreatePool {
+            assets: vec![token("token0"), token("token1")],
+            initial_tokens_viewing_key: "viewing_key".to_string(),
+            label: "pool2".to_string(),
+            amp: Some(200),
+            swap_fee_nom: Some(Uint128(1)),
+            swap_fee_denom: None,
+        },
+    )
+    .unwrap();
+    let pool_msg: PoolInitMsg = match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Instantiate { msg, .. }) => from_binary(msg).unwrap(),
+        msg => panic!("Unexpected message {:?}", msg),
+    };
+    assert_eq!(pool_msg.amp, 200);
+    assert_eq!(pool_msg.swap_fee_nom, Uint128(1));
+    assert_eq!(pool_msg.swap_fee_denom, Uint128(1000));
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::UpdateConfig {
+            admin: None,
+            pool_code_id: Some(3),
+            pool_code_hash: None,
+            lp_token_code_id: None,
+            lp_token_code_hash: None,
+            default_amp: Some(50),
+            default_swap_fee_nom: None,
+            default_swap_fee_denom: None,
+        },
+    )
+    .unwrap();
+    let res = query(&deps, QueryMsg::Config {}).unwrap();
+    assert!(!String::from_utf8_lossy(res.as_slice()).contains("prng_seed"));
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.pool_code_id, 3);
+    assert_eq!(config.default_amp, 50);
+}
+
+#[test]
+fn lp_token_seed_depends_on_the_tx() {
+    let mut deps = init_factory();
+    let seed = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, height: u64| {
+        let mut env = mock_env(ADMIN, &[]);
+        env.block.height = height;
+        let res = handle(deps, env, create_pool_msg(&["token0", "token1"])).unwrap();
+        match &res.messages[0] {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { msg, .. }) => {
+                from_binary::<PoolInitMsg>(msg).unwrap().lp_token_prng_seed
+            }
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+    };
+
+    // Neither pool registered, so both would get the same index
+    let first = seed(&mut deps, 100);
+    assert_eq!(&first.as_slice()[..4], b"seed");
+    assert_ne!(first, seed(&mut deps, 101));
+}
+
+#[test]
+fn pools_register_and_update() {
+    let mut deps = init_factory();
+
+    // Nothing is being instantiated
+    let res = pool_callback(&mut deps, POOL, register_msg(&["token0", "token1"]));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        create_pool_msg(&["token0", "token1"]),
+    )
+    .unwrap();
+    let res = pool_callback(&mut deps, POOL, register_msg(&["token0", "token2"]));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    pool_callback(&mut deps, POOL, register_msg(&["token0", "token1"])).unwrap();
+    assert_eq!(
+        pools(&deps),
+        vec![PoolInfo {
+            address: HumanAddr::from(POOL),
+            assets: vec![HumanAddr::from("token0"), HumanAddr::from("token1")],
+            admin: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            code_hash: "pool_hash".to_string(),
+        }]
+    );
+
+    // A pool registers once
+    let res = pool_callback(&mut deps, "other", register_msg(&["token0", "token1"]));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let update = FactoryHandleMsg::UpdatePool {
+        assets: vec![
+            HumanAddr::from("token0"),
+            HumanAddr::from("token1"),
+            HumanAddr::from("token2"),
+        ],
+        admin: HumanAddr::from(MOCK_CONTRACT_ADDR),
+    };
+    let res = pool_callback(&mut deps, "other", update.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    pool_callback(&mut deps, POOL, update).unwrap();
+    assert_eq!(pools(&deps)[0].assets.len(), 3);
+    assert_eq!(pools_by_token(&deps, "token2"), pools(&deps));
+
+    // A removed asset no longer lists the pool
+    let update = FactoryHandleMsg::UpdatePool {
+        assets: vec![HumanAddr::from("token0"), HumanAddr::from("token2")],
+        admin: HumanAddr::from(MOCK_CONTRACT_ADDR),
+    };
+    pool_callback(&mut deps, POOL, update).unwrap();
+    assert!(pools_by_token(&deps, "token1").is_empty());
+    assert_eq!(pools_by_token(&deps, "token0"), pools(&deps));
+}
+
+#[test]
+fn migrate_pool_admin() {
+    let mut deps = init_factory();
+    add_pool(&mut deps, POOL, &["token0", "token1"]);
+    let migrate = |pool: &str| HandleMsg::MigratePoolAdmin {
+        pool: HumanAddr::from(pool),
+        new_admin: HumanAddr::from("new_admin"),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), migrate(POOL));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), migrate("other"));
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("[unknown_pool]")),
+        _ => panic!("Must return generic error"),
+    }
+
+    // Called with the code hash the pool was created with, not the current one
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::UpdateConfig {
+            admin: None,
+            pool_code_id: Some(3),
+            pool_code_hash: Some("new_pool_hash".to_string()),
+            lp_token_code_id: None,
+            lp_token_code_hash: None,
+            default_amp: None,
+            default_swap_fee_nom: None,
+            default_swap_fee_denom: None,
+        },
+    )
+    .unwrap();
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), migrate(POOL)).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from(POOL),
+            callback_code_hash: "pool_hash".to_string(),
+            msg: to_binary(&PoolHandleMsg::ProposeNewAdmin {
+                address: HumanAddr::from("new_admin"),
+            })
+            .unwrap(),
+            send: vec![],
+        })]
+    );
+
+    // The pool reports the new admin once it accepts
+    pool_callback(
+        &mut deps,
+        POOL,
+        FactoryHandleMsg::UpdatePool {
+            assets: vec![HumanAddr::from("token0"), HumanAddr::from("token1")],
+            admin: HumanAddr::from("new_admin"),
+        },
+    )
+    .unwrap();
+    assert_eq!(pools(&deps)[0].admin, HumanAddr::from("new_admin"));
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), migrate(POOL));
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert!(msg.starts_with("[not_administered]"))
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn pool_registry_queries() {
+    let mut deps = init_factory();
+    let count = MAX_LIMIT as usize + 5;
+    let addresses: Vec<String> = (0..count).map(|i| format!("pool{}", i)).collect();
+    for (i, address) in addresses.iter().enumerate() {
+        // Every pool has token0, every other pool has token1 or token2
+        let other = if i % 2 == 0 { "token1" } else { "token2" };
+        add_pool(&mut deps, address, &["token0", other]);
+    }
+    let all_pools = |start_after: Option<&str>, limit: Option<u32>| -> Vec<HumanAddr> {
+        let msg = QueryMsg::AllPools {
+            start_after: start_after.map(HumanAddr::from),
+            limit,
+        };
+        let pools: Vec<PoolInfo> = from_binary(&query(&deps, msg).unwrap()).unwrap();
+        pools.into_iter().map(|p| p.address).collect()
+    };
+    let expected = |range: std::ops::Range<usize>| -> Vec<HumanAddr> {
+        addresses[range]
+            .iter()
+            .map(|a| HumanAddr::from(a.as_str()))
+            .collect()
+    };
+
+    assert_eq!(all_pools(None, None), expected(0..DEFAULT_LIMIT as usize));
+    assert_eq!(all_pools(Some("pool2"), Some(3)), expected(3..6));
+    assert_eq!(
+        all_pools(None, Some(1_000)),
+        expected(0..MAX_LIMIT as usize)
+    );
+    assert_eq!(
+        all_pools(Some(addresses[count - 2].as_str()), None),
+        expected(count - 1..count)
+    );
+    assert_eq!(all_pools(Some(addresses[count - 1].as_str()), None), vec![]);
+    let res = query(
+        &deps,
+        QueryMsg::AllPools {
+            start_after: Some(HumanAddr::from("other")),
+            limit: None,
+        },
+    );
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("[unknown_pool]")),
+        _ => panic!("Must return generic error"),
+    }
+
+    assert_eq!(pools_by_token(&deps, "token0").len(), count);
+    let pools = pools_by_token(&deps, "token2");
+    assert_eq!(pools.len(), count / 2);
+    assert!(pools
+        .iter()
+        .all(|p| p.assets.contains(&HumanAddr::from("token2"))));
+    assert_eq!(pools[0].address, HumanAddr::from("pool1"));
+    assert!(pools_by_token(&deps, "token3").is_empty());
+}
@@ -0,0 +1,4 @@
+pub mod suite;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,591 @@
+//! An in-process chain for end-to-end tests. The stable pool and the LP token it instantiates
+//! run their real entry points, the pool's assets are mocked SNIP-20 ledgers.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use cosmwasm_std::testing::{mock_env, MockApi, MockQuerier, MockStorage};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, Binary, BlockInfo, Coin, CosmosMsg, Empty, Env, Extern,
+    HandleResponse, HumanAddr, Querier, QuerierResult, QueryRequest, StdError, StdResult,
+    SystemError, Uint128, WasmMsg, WasmQuery,
+};
+use lp_token::msg::{
+    HandleMsg as Snip20HandleMsg, QueryAnswer as Snip20QueryAnswer, QueryMsg as Snip20QueryMsg,
+};
+use lp_token::receiver::Snip20ReceiveMsg;
+use secretswap_stablepools::contract as pool;
+use secretswap_stablepools::msg::{InitMsg, Token};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub const ADMIN: &str = "admin";
+pub const POOL: &str = "pool";
+pub const POOL_CODE_HASH: &str = "pool_hash";
+/// Instantiated contracts are addressed by their label, and this is the LP token's
+pub const LP_TOKEN: &str = "lp_token";
+pub const LP_TOKEN_CODE_ID: u64 = 1;
+pub const LP_TOKEN_CODE_HASH: &str = "lp_token_hash";
+pub const POOL_VIEWING_KEY: &str = "pool_viewing_key";
+
+const CANONICAL_LENGTH: usize = 20;
+
+/// Code hash of the mocked asset at `address`
+pub fn code_hash(address: &str) -> String {
+    format!("{}_hash", address)
+}
+
+/// The pool over `tokens` that Suite::new instantiates
+pub fn init_msg(tokens: &[(&str, u8)]) -> InitMsg {
+    InitMsg {
+        assets: tokens
+            .iter()
+            .map(|(address, _)| Token {
+                address: HumanAddr::from(*address),
+                code_hash: code_hash(address),
+                native: false,
+            })
+            .collect(),
+        initial_tokens_viewing_key: POOL_VIEWING_KEY.to_string(),
+        lp_token_code_id: LP_TOKEN_CODE_ID,
+        lp_token_code_hash: LP_TOKEN_CODE_HASH.to_string(),
+        lp_token_prng_seed: Binary(b"seed".to_vec()),
+        lp_token_label: LP_TOKEN.to_string(),
+        admin: HumanAddr::from(ADMIN),
+        amp: 100,
+        swap_fee_nom: Uint128(3),
+        swap_fee_denom: Uint128(1000),
+        status: None,
+        round_down_pool_answer_to_nearest: Uint128(1),
+        max_swap_out_bps: None,
+        dynamic_fee: None,
+        dynamic_fee_base_bps: None,
+        dynamic_fee_max_bps: None,
+        dynamic_fee_min_bps: None,
+        depeg_threshold_bps: None,
+        max_referral_fee_bps: None,
+        admin_fee_bps: None,
+        max_volume_per_block: None,
+        rebalance_bonus_to_lp_bps: None,
+        deposit_bonus_bps: None,
+        per_asset_deposit_cap: None,
+        total_tvl_cap: None,
+        min_reserve_after_swap: None,
+        seed_tvl_cap: None,
+        factory: None,
+    }
+}
+
+/// SNIP-20 state of a mocked asset. Viewing keys are checked, allowances never expire.
+#[derive(Clone, Default)]
+pub struct MockToken {
+    pub decimals: u8,
+    pub total_supply: Uint128,
+    pub balances: HashMap<HumanAddr, Uint128>,
+    // (owner, spender) -> allowance
+    pub allowances: HashMap<(HumanAddr, HumanAddr), Uint128>,
+    pub viewing_keys: HashMap<HumanAddr, String>,
+    // contract -> code hash its Receive callbacks are sent with
+    pub receivers: HashMap<HumanAddr, String>,
+}
+
+impl MockToken {
+    pub fn balance(&self, address: &HumanAddr) -> Uint128 {
+        *self.balances.get(address).unwrap_or(&Uint128::zero())
+    }
+
+    fn transfer(&mut self, from: &HumanAddr, to: &HumanAddr, amount: Uint128) -> StdResult<()> {
+        let from_balance = (self.balance(from) - amount).map_err(|_| {
+            StdError::generic_err(format!(
+                "insufficient funds: balance={}, required={}",
+                self.balance(from),
+                amount
+            ))
+        })?;
+        self.balances.insert(from.clone(), from_balance);
+        let to_balance = self.balance(to) + amount;
+        self.balances.insert(to.clone(), to_balance);
+        Ok(())
+    }
+
+    fn spend_allowance(
+        &mut self,
+        owner: &HumanAddr,
+        spender: &HumanAddr,
+        amount: Uint128,
+    ) -> StdResult<()> {
+        let key = (owner.clone(), spender.clone());
+        let allowance = *self.allowances.get(&key).unwrap_or(&Uint128::zero());
+        let allowance = (allowance - amount).map_err(|_| {
+            StdError::generic_err(format!(
+                "insufficient allowance: allowance={}, required={}",
+                allowance, amount
+            ))
+        })?;
+        self.allowances.insert(key, allowance);
+        Ok(())
+    }
+
+    /// The Receive callback a Send owes `recipient`, if it registered for one
+    fn receive_msgs(
+        &self,
+        sender: HumanAddr,
+        from: HumanAddr,
+        recipient: HumanAddr,
+        amount: Uint128,
+        memo: Option<String>,
+        msg: Option<Binary>,
+    ) -> StdResult<Vec<CosmosMsg>> {
+        match self.receivers.get(&recipient) {
+            Some(code_hash) => Ok(vec![Snip20ReceiveMsg::new(sender, from, amount, memo, msg)
+                .into_cosmos_msg(code_hash.clone(), recipient)?]),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn handle(&mut self, sender: &HumanAddr, msg: Snip20HandleMsg) -> StdResult<Vec<CosmosMsg>> {
+        match msg {
+            Snip20HandleMsg::Transfer {
+                recipient, amount, ..
+            } => {
+                self.transfer(sender, &recipient, amount)?;
+                Ok(vec![])
+            }
+            Snip20HandleMsg::Send {
+                recipient,
+                amount,
+                msg,
+                memo,
+                ..
+            } => {
+                self.transfer(sender, &recipient, amount)?;
+                self.receive_msgs(sender.clone(), sender.clone(), recipient, amount, memo, msg)
+            }
+            Snip20HandleMsg::TransferFrom {
+                owner,
+                recipient,
+                amount,
+                ..
+            } => {
+                self.spend_allowance(&owner, sender, amount)?;
+                self.transfer(&owner, &recipient, amount)?;
+                Ok(vec![])
+            }
+            Snip20HandleMsg::SendFrom {
+                owner,
+                recipient,
+                amount,
+                msg,
+                memo,
+                ..
+            } => {
+                self.spend_allowance(&owner, sender, amount)?;
+                self.transfer(&owner, &recipient, amount)?;
+                self.receive_msgs(sender.clone(), owner, recipient, amount, memo, msg)
+            }
+            Snip20HandleMsg::IncreaseAllowance {
+                spender, amount, ..
+            } => {
+                let allowance = self
+                    .allowances
+                    .entry((sender.clone(), spender))
+                    .or_insert_with(Uint128::zero);
+                *allowance += amount;
+                Ok(vec![])
+            }
+            Snip20HandleMsg::SetViewingKey { key, .. } => {
+                self.viewing_keys.insert(sender.clone(), key);
+                Ok(vec![])
+            }
+            Snip20HandleMsg::RegisterReceive { code_hash, .. } => {
+                self.receivers.insert(sender.clone(), code_hash);
+                Ok(vec![])
+            }
+            _ => Err(StdError::generic_err(
+                "Not supported by the mocked SNIP-20 token",
+            )),
+        }
+    }
+
+    fn query(&self, address: &HumanAddr, msg: Snip20QueryMsg) -> StdResult<Binary> {
+        match msg {
+            Snip20QueryMsg::TokenInfo {} => to_binary(&Snip20QueryAnswer::TokenInfo {
+                name: address.to_string(),
+                symbol: "MOCK".to_string(),
+                decimals: self.decimals,
+                total_supply: Some(self.total_supply),
+            }),
+            Snip20QueryMsg::Balance { address, key } => {
+                self.check_viewing_key(&[&address], &key)?;
+                to_binary(&Snip20QueryAnswer::Balance {
+                    amount: self.balance(&address),
+                })
+            }
+            Snip20QueryMsg::Allowance {
+                owner,
+                spender,
+                key,
+            } => {
+                // Either side of an allowance may look it up
+                self.check_viewing_key(&[&owner, &spender], &key)?;
+                let allowance = *self
+                    .allowances
+                    .get(&(owner.clone(), spender.clone()))
+                    .unwrap_or(&Uint128::zero());
+                to_binary(&Snip20QueryAnswer::Allowance {
+                    spender,
+                    owner,
+                    allowance,
+                    expiration: None,
+                })
+            }
+            _ => Err(StdError::generic_err(
+                "Not supported by the mocked SNIP-20 token",
+            )),
+        }
+    }
+
+    fn check_viewing_key(&self, addresses: &[&HumanAddr], key: &str) -> StdResult<()> {
+        if addresses
+            .iter()
+            .any(|address| self.viewing_keys.get(*address).map(String::as_str) == Some(key))
+        {
+            Ok(())
+        } else {
+            Err(StdError::unauthorized())
+        }
+    }
+}
+
+enum Contract {
+    LpToken(Box<Extern<MockStorage, MockApi, MockQuerier>>),
+    Token(Box<MockToken>),
+}
+
+/// Every contract but the pool, which runs outside of it so that the pool's querier can
+/// borrow the rest while the pool executes
+#[derive(Default)]
+struct Chain {
+    // address -> (code hash, contract)
+    contracts: HashMap<HumanAddr, (String, Contract)>,
+}
+
+impl Chain {
+    fn query(
+        &self,
+        contract_addr: &HumanAddr,
+        callback_code_hash: &str,
+        msg: &Binary,
+    ) -> QuerierResult {
+        let (code_hash, contract) = match self.contracts.get(contract_addr) {
+            Some(contract) => contract,
+            None => {
+                return Err(SystemError::NoSuchContract {
+                    addr: contract_addr.clone(),
+                })
+            }
+        };
+        if code_hash != callback_code_hash {
+            return Err(SystemError::InvalidRequest {
+                error: format!("Wrong code hash for {}", contract_addr),
+                request: msg.clone(),
+            });
+        }
+
+        let msg = match from_binary(msg) {
+            Ok(msg) => msg,
+            Err(e) => return Ok(Err(e)),
+        };
+        Ok(match contract {
+            Contract::LpToken(deps) => lp_token::contract::query(&**deps, msg),
+            Contract::Token(token) => token.query(contract_addr, msg),
+        })
+    }
+}
+
+/// The pool's querier. Smart queries go to the chain's contracts, anything else, like bank
+/// balances, to MockQuerier.
+pub struct ChainQuerier {
+    base: MockQuerier,
+    chain: Rc<RefCell<Chain>>,
+}
+
+impl Querier for ChainQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = match from_slice(bin_request) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SystemError::InvalidRequest {
+                    error: format!("Parsing query request: {}", e),
+                    request: bin_request.into(),
+                })
+            }
+        };
+        match &request {
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr,
+                callback_code_hash,
+                msg,
+            }) => self
+                .chain
+                .borrow()
+                .query(contract_addr, callback_code_hash, msg),
+            _ => self.base.handle_query(&request),
+        }
+    }
+}
+
+pub struct Suite {
+    pub pool: Extern<MockStorage, MockApi, ChainQuerier>,
+    chain: Rc<RefCell<Chain>>,
+    pub block: BlockInfo,
+}
+
+impl Suite {
+    /// Mocks `tokens` and instantiates a pool over them, which instantiates its LP token and
+    /// gets called back with PostInitialize
+    pub fn new(tokens: &[(&str, u8)]) -> Self {
+        Self::instantiate(tokens, init_msg(tokens)).unwrap()
+    }
+
+    /// Like new, with a custom pool InitMsg
+    pub fn instantiate(tokens: &[(&str, u8)], msg: InitMsg) -> StdResult<Self> {
+        let mut chain = Chain::default();
+        for (address, decimals) in tokens {
+            chain.contracts.insert(
+                HumanAddr::from(*address),
+                (
+                    code_hash(address),
+                    Contract::Token(Box::new(MockToken {
+                        decimals: *decimals,
+                        ..MockToken::default()
+                    })),
+                ),
+            );
+        }
+        let chain = Rc::new(RefCell::new(chain));
+
+        let mut suite = Suite {
+            pool: Extern {
+                storage: MockStorage::default(),
+                api: MockApi::new(CANONICAL_LENGTH),
+                querier: ChainQuerier {
+                    base: MockQuerier::new(&[]),
+                    chain: chain.clone(),
+                },
+            },
+            chain,
+            block: mock_env(ADMIN, &[]).block,
+        };
+        let pool_address = HumanAddr::from(POOL);
+        let env = suite.env(&HumanAddr::from(ADMIN), &pool_address, POOL_CODE_HASH);
+        let res = pool::init(&mut suite.pool, env, msg)?;
+        suite.dispatch_all(&pool_address, res.messages)?;
+
+        Ok(suite)
+    }
+
+    /// Executes `msg` on `contract` as `sender`, then every message that sends in turn, each
+    /// one's own messages first like the chain does. Unlike the chain, a failure doesn't roll
+    /// back the messages executed before it.
+    pub fn execute<T: Serialize>(
+        &mut self,
+        sender: &str,
+        contract: &str,
+        msg: &T,
+    ) -> StdResult<HandleResponse> {
+        let contract = HumanAddr::from(contract);
+        let code_hash = self.code_hash(&contract)?;
+        self.execute_wasm(
+            &HumanAddr::from(sender),
+            &contract,
+            &code_hash,
+            to_binary(msg)?,
+        )
+    }
+
+    /// Smart-queries `contract`, like a client would
+    pub fn query<T: Serialize, R: DeserializeOwned>(
+        &self,
+        contract: &str,
+        msg: &T,
+    ) -> StdResult<R> {
+        let contract = HumanAddr::from(contract);
+        let msg = to_binary(msg)?;
+        let res = if contract == HumanAddr::from(POOL) {
+            pool::query(&self.pool, from_binary(&msg)?)?
+        } else {
+            let code_hash = self.code_hash(&contract)?;
+            self.chain
+                .borrow()
+                .query(&contract, &code_hash, &msg)
+                .map_err(|e| StdError::generic_err(format!("{:?}", e)))??
+        };
+        from_binary(&res)
+    }
+
+    /// Credits `amount` of a mocked asset to `address`, like a faucet would
+    pub fn mint(&mut self, token: &str, address: &str, amount: u128) {
+        let mut chain = self.chain.borrow_mut();
+        let token = match chain.contracts.get_mut(&HumanAddr::from(token)) {
+            Some((_, Contract::Token(token))) => token,
+            _ => panic!("{} is not a mocked token", token),
+        };
+        let address = HumanAddr::from(address);
+        let balance = token.balance(&address) + Uint128(amount);
+        token.balances.insert(address, balance);
+        token.total_supply += Uint128(amount);
+    }
+
+    /// State of the mocked asset at `address`
+    pub fn token(&self, address: &str) -> MockToken {
+        match self.chain.borrow().contracts.get(&HumanAddr::from(address)) {
+            Some((_, Contract::Token(token))) => (**token).clone(),
+            _ => panic!("{} is not a mocked token", address),
+        }
+    }
+
+    /// Balance of `address` in a mocked asset
+    pub fn balance(&self, token: &str, address: &str) -> u128 {
+        self.token(token).balance(&HumanAddr::from(address)).u128()
+    }
+
+    fn env(&self, sender: &HumanAddr, contract: &HumanAddr, code_hash: &str) -> Env {
+        let mut env = mock_env(sender.clone(), &[]);
+        env.block = self.block.clone();
+        env.contract.address = contract.clone();
+        env.contract_code_hash = code_hash.to_string();
+        env
+    }
+
+    fn code_hash(&self, contract: &HumanAddr) -> StdResult<String> {
+        if contract == &HumanAddr::from(POOL) {
+            return Ok(POOL_CODE_HASH.to_string());
+        }
+        self.chain
+            .borrow()
+            .contracts
+            .get(contract)
+            .map(|(code_hash, _)| code_hash.clone())
+            .ok_or_else(|| StdError::generic_err(format!("No contract at {}", contract)))
+    }
+
+    fn execute_wasm(
+        &mut self,
+        sender: &HumanAddr,
+        contract: &HumanAddr,
+        code_hash: &str,
+        msg: Binary,
+    ) -> StdResult<HandleResponse> {
+        if self.code_hash(contract)? != code_hash {
+            return Err(StdError::generic_err(format!(
+                "Wrong code hash for {}",
+                contract
+            )));
+        }
+
+        let env = self.env(sender, contract, code_hash);
+        let res = if contract == &HumanAddr::from(POOL) {
+            pool::handle(&mut self.pool, env, from_binary(&msg)?)?
+        } else {
+            let mut chain = self.chain.borrow_mut();
+            match chain.contracts.get_mut(contract) {
+                Some((_, Contract::LpToken(deps))) => {
+                    lp_token::contract::handle(&mut **deps, env, from_binary(&msg)?)?
+                }
+                Some((_, Contract::Token(token))) => HandleResponse {
+                    messages: token.handle(sender, from_binary(&msg)?)?,
+                    log: vec![],
+                    data: None,
+                },
+                None => unreachable!("code_hash found the contract"),
+            }
+        };
+        self.dispatch_all(contract, res.messages.clone())?;
+
+        Ok(res)
+    }
+
+    /// Only the LP token's code is stored, and native funds aren't modelled
+    fn instantiate_wasm(
+        &mut self,
+        sender: &HumanAddr,
+        code_id: u64,
+        code_hash: &str,
+        msg: Binary,
+        label: String,
+    ) -> StdResult<()> {
+        if code_id != LP_TOKEN_CODE_ID || code_hash != LP_TOKEN_CODE_HASH {
+            return Err(StdError::generic_err(format!(
+                "No code {} with hash {}",
+                code_id, code_hash
+            )));
+        }
+        let address = HumanAddr(label);
+        if self.code_hash(&address).is_ok() {
+            return Err(StdError::generic_err(format!(
+                "Label {} is already taken",
+                address
+            )));
+        }
+
+        let mut deps = Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(CANONICAL_LENGTH),
+            querier: MockQuerier::new(&[]),
+        };
+        let env = self.env(sender, &address, code_hash);
+        let res = lp_token::contract::init(&mut deps, env, from_binary(&msg)?)?;
+        self.chain.borrow_mut().contracts.insert(
+            address.clone(),
+            (code_hash.to_string(), Contract::LpToken(Box::new(deps))),
+        );
+
+        self.dispatch_all(&address, res.messages)
+    }
+
+    fn dispatch_all(&mut self, sender: &HumanAddr, messages: Vec<CosmosMsg>) -> StdResult<()> {
+        for msg in messages {
+            self.dispatch(sender, msg)?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, sender: &HumanAddr, msg: CosmosMsg) -> StdResult<()> {
+        match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                callback_code_hash,
+                msg,
+                send,
+            }) => {
+                check_no_funds(&send)?;
+                self.execute_wasm(sender, &contract_addr, &callback_code_hash, msg)?;
+                Ok(())
+            }
+            CosmosMsg::Wasm(WasmMsg::Instantiate {
+                code_id,
+                callback_code_hash,
+                msg,
+                send,
+                label,
+            }) => {
+                check_no_funds(&send)?;
+                self.instantiate_wasm(sender, code_id, &callback_code_hash, msg, label)
+            }
+            other => Err(StdError::generic_err(format!(
+                "Unsupported message {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+fn check_no_funds(send: &[Coin]) -> StdResult<()> {
+    if send.is_empty() {
+        Ok(())
+    } else {
+        Err(StdError::generic_err("Native funds aren't supported"))
+    }
+}
@@ -0,0 +1,256 @@
+use cosmwasm_std::{to_binary, HandleResponse, HumanAddr, StdError, StdResult, Uint128};
+use lp_token::msg::{
+    HandleMsg as Snip20HandleMsg, QueryAnswer as Snip20QueryAnswer, QueryMsg as Snip20QueryMsg,
+};
+use secretswap_stablepools::msg::{
+    ConfigResponse, HandleMsg, PoolsResponse, QueryMsg, SimulationResponse, Snip20ReceiveMsg,
+    TokenAmount,
+};
+
+use crate::suite::{code_hash, Suite, LP_TOKEN, POOL, POOL_CODE_HASH, POOL_VIEWING_KEY};
+
+const USER: &str = "user";
+const TRADER: &str = "trader";
+const USER_KEY: &str = "user_key";
+
+fn tokens(amount: u128, decimals: u8) -> u128 {
+    amount * 10u128.pow(decimals as u32)
+}
+
+/// Approves the pool for each deposit and provides them, like a wallet would
+fn provide_liquidity(
+    suite: &mut Suite,
+    sender: &str,
+    deposits: &[(&str, u128)],
+) -> StdResult<HandleResponse> {
+    for (token, amount) in deposits {
+        suite.execute(
+            sender,
+            token,
+            &Snip20HandleMsg::IncreaseAllowance {
+                spender: HumanAddr::from(POOL),
+                amount: Uint128(*amount),
+                expiration: None,
+                padding: None,
+            },
+        )?;
+    }
+    suite.execute(sender, POOL, &provide_msg(deposits))
+}
+
+fn provide_msg(deposits: &[(&str, u128)]) -> HandleMsg {
+    HandleMsg::ProvideLiquidity {
+        assets: deposits
+            .iter()
+            .map(|(token, amount)| TokenAmount {
+                address: HumanAddr::from(*token),
+                code_hash: code_hash(token),
+                amount: Uint128(*amount),
+            })
+            .collect(),
+        cancel_if_no_bonus: None,
+        min_lp_out: None,
+        deadline: None,
+    }
+}
+
+/// Sends `amount` of `token` to the pool with a hook message
+fn send_to_pool(
+    suite: &mut Suite,
+    sender: &str,
+    token: &str,
+    amount: u128,
+    msg: &Snip20ReceiveMsg,
+) -> StdResult<HandleResponse> {
+    suite.execute(
+        sender,
+        token,
+        &Snip20HandleMsg::Send {
+            recipient: HumanAddr::from(POOL),
+            amount: Uint128(amount),
+            msg: Some(to_binary(msg)?),
+            memo: None,
+            padding: None,
+        },
+    )
+}
+
+fn lp_balance(suite: &Suite, address: &str, key: &str) -> u128 {
+    let msg = Snip20QueryMsg::Balance {
+        address: HumanAddr::from(address),
+        key: key.to_string(),
+    };
+    match suite.query(LP_TOKEN, &msg).unwrap() {
+        Snip20QueryAnswer::Balance { amount } => amount.u128(),
+        other => panic!("Must return the balance, got {:?}", other),
+    }
+}
+
+fn lp_total_supply(suite: &Suite) -> u128 {
+    match suite
+        .query(LP_TOKEN, &Snip20QueryMsg::TokenInfo {})
+        .unwrap()
+    {
+        Snip20QueryAnswer::TokenInfo { total_supply, .. } => total_supply.unwrap().u128(),
+        other => panic!("Must return the token info, got {:?}", other),
+    }
+}
+
+fn reserves(suite: &Suite) -> Vec<u128> {
+    let pools: PoolsResponse = suite.query(POOL, &QueryMsg::GetPools {}).unwrap();
+    pools
+        .assets
+        .iter()
+        .map(|asset| asset.balance.u128())
+        .collect()
+}
+
+#[test]
+fn instantiates_and_binds_lp_token() {
+    let suite = Suite::new(&[("usdt", 6), ("dai", 18)]);
+
+    let config: ConfigResponse = suite.query(POOL, &QueryMsg::GetConfig {}).unwrap();
+    assert_eq!(config.lp_token_address, HumanAddr::from(LP_TOKEN));
+    match suite
+        .query(LP_TOKEN, &Snip20QueryMsg::TokenInfo {})
+        .unwrap()
+    {
+        Snip20QueryAnswer::TokenInfo {
+            decimals,
+            total_supply,
+            ..
+        } => {
+            assert_eq!(decimals, 18);
+            assert_eq!(total_supply, Some(Uint128::zero()));
+        }
+        other => panic!("Must return the token info, got {:?}", other),
+    }
+
+    // The pool can read its balances and is called back when sent assets
+    for address in &["usdt", "dai"] {
+        let token = suite.token(address);
+        let pool = HumanAddr::from(POOL);
+        assert_eq!(
+            token.viewing_keys.get(&pool),
+            Some(&POOL_VIEWING_KEY.to_string())
+        );
+        assert_eq!(
+            token.receivers.get(&pool),
+            Some(&POOL_CODE_HASH.to_string())
+        );
+    }
+}
+
+#[test]
+fn deposit_swap_withdraw() {
+    let mut suite = Suite::new(&[("usdt", 6), ("dai", 18)]);
+    suite.mint("usdt", USER, tokens(1_000, 6));
+    suite.mint("dai", USER, tokens(1_000, 18));
+
+    provide_liquidity(
+        &mut suite,
+        USER,
+        &[("usdt", tokens(1_000, 6)), ("dai", tokens(1_000, 18))],
+    )
+    .unwrap();
+
+    // The first deposit mints its value, normalized to 18 decimals
+    suite
+        .execute(
+            USER,
+            LP_TOKEN,
+            &Snip20HandleMsg::SetViewingKey {
+                key: USER_KEY.to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+    let minted = lp_balance(&suite, USER, USER_KEY);
+    assert_eq!(minted, tokens(2_000, 18));
+    assert_eq!(lp_total_supply(&suite), minted);
+    assert_eq!(reserves(&suite), vec![tokens(1_000, 6), tokens(1_000, 18)]);
+    assert_eq!(suite.balance("usdt", USER), 0);
+    assert_eq!(suite.balance("dai", USER), 0);
+
+    // The swap pays out what Simulation quoted
+    suite.mint("usdt", TRADER, tokens(100, 6));
+    let simulation: SimulationResponse = suite
+        .query(
+            POOL,
+            &QueryMsg::Simulation {
+                offer_token: HumanAddr::from("usdt"),
+                ask_token: HumanAddr::from("dai"),
+                amount: Uint128(tokens(100, 6)),
+            },
+        )
+        .unwrap();
+    send_to_pool(
+        &mut suite,
+        TRADER,
+        "usdt",
+        tokens(100, 6),
+        &Snip20ReceiveMsg::Swap {
+            to_token: HumanAddr::from("dai"),
+            recipient: None,
+            referrer: None,
+            referral_fee_bps: None,
+            expected_return: None,
+            max_slippage_bps: None,
+            deadline: None,
+            unwrap: None,
+        },
+    )
+    .unwrap();
+    let return_amount = simulation.return_amount.u128();
+    assert!(return_amount > tokens(99, 18));
+    assert_eq!(suite.balance("usdt", TRADER), 0);
+    assert_eq!(suite.balance("dai", TRADER), return_amount);
+
+    // Without an admin fee the reserves are exactly what the pool holds
+    let after_swap = reserves(&suite);
+    assert_eq!(
+        after_swap,
+        vec![tokens(1_100, 6), tokens(1_000, 18) - return_amount]
+    );
+    assert_eq!(suite.balance("usdt", POOL), after_swap[0]);
+    assert_eq!(suite.balance("dai", POOL), after_swap[1]);
+
+    // Burning every LP token pays out the whole pool
+    send_to_pool(
+        &mut suite,
+        USER,
+        LP_TOKEN,
+        minted,
+        &Snip20ReceiveMsg::WithdrawLiquidity {
+            deadline: None,
+            unwrap: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(lp_balance(&suite, USER, USER_KEY), 0);
+    assert_eq!(lp_total_supply(&suite), 0);
+    assert_eq!(reserves(&suite), vec![0, 0]);
+    assert_eq!(suite.balance("usdt", USER), after_swap[0]);
+    assert_eq!(suite.balance("dai", USER), after_swap[1]);
+    assert_eq!(suite.balance("usdt", POOL), 0);
+    assert_eq!(suite.balance("dai", POOL), 0);
+}
+
+#[test]
+fn deposit_without_allowance_fails() {
+    let mut suite = Suite::new(&[("usdt", 6), ("dai", 18)]);
+    suite.mint("usdt", USER, tokens(1_000, 6));
+
+    // The pool checks allowances up front, through its own viewing key
+    let res = suite.execute(USER, POOL, &provide_msg(&[("usdt", tokens(1_000, 6))]));
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert!(
+            msg.contains("Insufficient allowance for usdt"),
+            "got {:?}",
+            msg
+        ),
+        other => panic!("Must return generic error, got {:?}", other),
+    }
+    assert_eq!(suite.balance("usdt", USER), tokens(1_000, 6));
+    assert_eq!(lp_total_supply(&suite), 0);
+}
@@ -0,0 +1,314 @@
+use cosmwasm_std::{
+    from_binary, log, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HandleResult,
+    HumanAddr, InitResponse, Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
+};
+use secret_toolkit::snip20 as snip20_utils;
+use secretswap_stablepools::msg::Snip20ReceiveMsg as PoolReceiveMsg;
+
+use crate::{
+    error::ContractError,
+    msg::{Config, HandleMsg, Hop, InitMsg, QueryMsg, RouterReceiveMsg, Token},
+    state::{
+        read_config, read_route, read_tokens, remove_route, store_config, store_route,
+        store_tokens, Route,
+    },
+};
+
+/// Upper bound on the number of hops in a single route
+pub const MAX_HOPS: usize = 6;
+
+pub fn init<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: InitMsg,
+) -> StdResult<InitResponse> {
+    let config = Config {
+        admin: msg.admin,
+        viewing_key: msg.viewing_key,
+    };
+    store_config(&mut deps.storage, &config)?;
+    let messages = register_tokens(deps, &env, &config, msg.tokens)?;
+
+    Ok(InitResponse {
+        messages,
+        log: vec![log("status", "success")], // See https://github.com/CosmWasm/wasmd/pull/386
+    })
+}
+
+pub fn handle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> HandleResult {
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err(
+            "The router doesn't take native coins",
+        ));
+    }
+
+    match msg {
+        HandleMsg::Receive { from, msg, amount } => receive_snip20(deps, env, from, amount, msg),
+        HandleMsg::ContinueRoute {} => try_continue_route(deps, env),
+        HandleMsg::RegisterTokens { tokens } => try_register_tokens(deps, env, tokens),
+    }
+}
+
+pub fn receive_snip20<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    msg: Binary,
+) -> HandleResult {
+    match from_binary(&msg)? {
+        RouterReceiveMsg::Route {
+            hops,
+            recipient,
+            min_received,
+        } => {
+            let recipient = recipient.unwrap_or(sender);
+            try_route(deps, env, amount, hops, recipient, min_received)
+        }
+    }
+}
+
+pub fn try_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+    hops: Vec<Hop>,
+    recipient: HumanAddr,
+    min_received: Option<Uint128>,
+) -> HandleResult {
+    if hops.is_empty() || hops.len() > MAX_HOPS {
+        return Err(StdError::generic_err(format!(
+            "A route must have between 1 and {} hops",
+            MAX_HOPS
+        )));
+    }
+    if amount == Uint128::zero() {
+        return Err(StdError::generic_err("Cannot route a zero amount"));
+    }
+    // A route's state lives until its last hop, so only one can run at a time
+    if read_route(&deps.storage)?.is_some() {
+        return Err(ContractError::RouteInProgress.into());
+    }
+    // Only tokens the router can measure its balance of can be passed along
+    let tokens = read_tokens(&deps.storage)?;
+    find_token(&tokens, &env.message.sender)?;
+    for hop in hops.iter() {
+        find_token(&tokens, &hop.ask_token)?;
+    }
+
+    let route = Route {
+        hops,
+        next_hop: 0,
+        token: env.message.sender.clone(),
+        balance_before: Uint128::zero(),
+        recipient,
+        min_received,
+    };
+    dispatch_hop(deps, env, route, amount)
+}
+
+/// Sends `amount` of the route's current token to the next hop's pool, then calls the router
+/// back to pick up what the pool paid out
+fn dispatch_hop<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    mut route: Route,
+    amount: Uint128,
+) -> HandleResult {
+    let tokens = read_tokens(&deps.storage)?;
+    let hop = route.hops[route.next_hop].clone();
+    let offer_token = find_token(&tokens, &route.token)?;
+    let ask_token = find_token(&tokens, &hop.ask_token)?;
+
+    let messages = vec![
+        snip20_utils::send_msg(
+            hop.pool.clone(),
+            amount,
+            Some(to_binary(&PoolReceiveMsg::Swap {
+                to_token: hop.ask_token.clone(),
+                recipient: None,
+                referrer: None,
+                referral_fee_bps: None,
+                expected_return: None,
+                max_slippage_bps: None,
+                deadline: None,
+                unwrap: None,
+            })?),
+            None,
+            256,
+            offer_token.code_hash.clone(),
+            offer_token.address.clone(),
+        )?,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.clone(),
+            callback_code_hash: env.contract_code_hash.clone(),
+            msg: to_binary(&HandleMsg::ContinueRoute {})?,
+            send: vec![],
+        }),
+    ];
+    let logs = vec![
+        log("action", "route_hop"),
+        log("pool", hop.pool),
+        log("offer", format!("{} {}", amount, route.token)),
+    ];
+
+    route.balance_before = query_balance(deps, &env, ask_token)?;
+    route.token = hop.ask_token;
+    route.next_hop += 1;
+    store_route(&mut deps.storage, &route)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_continue_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    if env.message.sender != env.contract.address {
+        return Err(ContractError::Unauthorized.into());
+    }
+    let route =
+        read_route(&deps.storage)?.ok_or_else(|| StdError::generic_err("No route in progress"))?;
+
+    let tokens = read_tokens(&deps.storage)?;
+    let token = find_token(&tokens, &route.token)?.clone();
+    let balance = query_balance(deps, &env, &token)?;
+    let received = (balance - route.balance_before).unwrap_or_else(|_| Uint128::zero());
+    if received == Uint128::zero() {
+        return Err(StdError::generic_err(format!(
+            "Hop {} returned no {:?}",
+            route.next_hop, route.token
+        )));
+    }
+
+    if route.next_hop < route.hops.len() {
+        return dispatch_hop(deps, env, route, received);
+    }
+
+    if let Some(min_received) = route.min_received {
+        if received < min_received {
+            return Err(ContractError::ReceivedBelowMinimum {
+                received,
+                min_received,
+            }
+            .into());
+        }
+    }
+    remove_route(&mut deps.storage);
+
+    Ok(HandleResponse {
+        messages: vec![snip20_utils::transfer_msg(
+            route.recipient.clone(),
+            received,
+            None,
+            256,
+            token.code_hash,
+            token.address,
+        )?],
+        log: vec![
+            log("action", "route"),
+            log("recipient", route.recipient),
+            log("received", format!("{} {}", received, route.token)),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_register_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    tokens: Vec<Token>,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut logs = vec![log("action", "register_tokens")];
+    for token in tokens.iter() {
+        logs.push(log("token", token.address.clone()));
+    }
+    let messages = register_tokens(deps, &env, &config, tokens)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+/// Stores `tokens`, replacing the code hash of any already registered, and sets the router's
+/// receiver and viewing key on each
+fn register_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+    tokens: Vec<Token>,
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut registered = read_tokens(&deps.storage)?;
+    let mut messages = vec![];
+    for token in tokens {
+        messages.push(snip20_utils::set_viewing_key_msg(
+            config.viewing_key.clone(),
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?);
+        messages.push(snip20_utils::register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?);
+
+        registered.retain(|t| t.address != token.address);
+        registered.push(token);
+    }
+    store_tokens(&mut deps.storage, &registered)?;
+
+    Ok(messages)
+}
+
+fn find_token<'a>(tokens: &'a [Token], address: &HumanAddr) -> StdResult<&'a Token> {
+    tokens
+        .iter()
+        .find(|t| &t.address == address)
+        .ok_or_else(|| ContractError::UnknownToken(address.clone()).into())
+}
+
+fn query_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    token: &Token,
+) -> StdResult<Uint128> {
+    let config = read_config(&deps.storage)?;
+    let balance = snip20_utils::balance_query(
+        &deps.querier,
+        env.contract.address.clone(),
+        config.viewing_key,
+        256,
+        token.code_hash.clone(),
+        token.address.clone(),
+    )?;
+
+    Ok(balance.amount)
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Tokens {} => to_binary(&read_tokens(&deps.storage)?),
+    }
+}
@@ -0,0 +1,34 @@
+use cosmwasm_std::{HumanAddr, StdError, Uint128};
+use thiserror::Error;
+
+/// Failures callers may want to handle. Each message starts with a code in brackets that stays
+/// the same when the wording changes, so clients can match on it in the returned error string.
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("[unauthorized] Unauthorized")]
+    Unauthorized,
+
+    #[error("[unknown_token] Token {0:?} is not registered with the router")]
+    UnknownToken(HumanAddr),
+
+    #[error("[reentrant] Another route is in progress")]
+    RouteInProgress,
+
+    #[error(
+        "[slippage_exceeded] Route returned {received}, less than the minimum of {min_received}"
+    )]
+    ReceivedBelowMinimum {
+        received: Uint128,
+        min_received: Uint128,
+    },
+}
+
+impl From<ContractError> for StdError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            // Kept as the std variant so callers matching on StdError::Unauthorized still work
+            ContractError::Unauthorized => StdError::unauthorized(),
+            err => StdError::generic_err(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, Empty, Extern, HumanAddr, Querier, QuerierResult,
+    QueryRequest, SystemError, Uint128, WasmQuery,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// mock_dependencies is a drop-in replacement for cosmwasm_std::testing::mock_dependencies
+/// this uses our CustomQuerier.
+pub fn mock_dependencies(canonical_length: usize) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let contract_addr = HumanAddr::from(MOCK_CONTRACT_ADDR);
+
+    Extern {
+        storage: MockStorage::default(),
+        api: MockApi::new(canonical_length),
+        querier: WasmMockQuerier::new(MockQuerier::new(&[(&contract_addr, &[])])),
+    }
+}
+
+/// Answers SNIP-20 balance queries, the only ones the router makes
+pub struct WasmMockQuerier {
+    base: MockQuerier,
+    // token address -> (code hash, holder -> balance)
+    tokens: HashMap<HumanAddr, (String, HashMap<HumanAddr, Uint128>)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum MockSnip20QueryMsg {
+    Balance { address: HumanAddr, key: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum MockSnip20QueryAnswer {
+    Balance { amount: Uint128 },
+}
+
+impl Querier for WasmMockQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = match from_slice(bin_request) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SystemError::InvalidRequest {
+                    error: format!("Parsing query request: {}", e),
+                    request: bin_request.into(),
+                })
+            }
+        };
+        self.handle_query(&request)
+    }
+}
+
+impl WasmMockQuerier {
+    pub fn new(base: MockQuerier) -> Self {
+        WasmMockQuerier {
+            base,
+            tokens: HashMap::new(),
+        }
+    }
+
+    pub fn handle_query(&self, request: &QueryRequest<Empty>) -> QuerierResult {
+        match request {
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr,
+                callback_code_hash,
+                msg,
+            }) => {
+                let (code_hash, balances) = match self.tokens.get(contract_addr) {
+                    Some(token) => token,
+                    None => {
+                        return Err(SystemError::NoSuchContract {
+                            addr: contract_addr.clone(),
+                        })
+                    }
+                };
+                if code_hash != callback_code_hash {
+                    return Err(SystemError::InvalidRequest {
+                        error: format!("Wrong code hash for {}", contract_addr),
+                        request: msg.clone(),
+                    });
+                }
+
+                match from_binary(msg) {
+                    Ok(MockSnip20QueryMsg::Balance { address, .. }) => {
+                        Ok(to_binary(&MockSnip20QueryAnswer::Balance {
+                            amount: *balances.get(&address).unwrap_or(&Uint128::zero()),
+                        }))
+                    }
+                    Err(e) => Ok(Err(e)),
+                }
+            }
+            _ => self.base.handle_query(request),
+        }
+    }
+
+    /// Registers a SNIP-20 token contract at `address`, with code hash `<address>_hash`
+    pub fn with_token(&mut self, address: &str) {
+        self.tokens.insert(
+            HumanAddr::from(address),
+            (format!("{}_hash", address), HashMap::new()),
+        );
+    }
+
+    /// Adds to a balance, like a transfer into `holder` would
+    pub fn add_balance(&mut self, token: &str, holder: &str, amount: u128) {
+        let balance = self
+            .tokens
+            .get_mut(&HumanAddr::from(token))
+            .expect("token must be registered with with_token first")
+            .1
+            .entry(HumanAddr::from(holder))
+            .or_insert_with(Uint128::zero);
+        *balance = Uint128(balance.u128() + amount);
+    }
+}
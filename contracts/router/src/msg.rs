@@ -0,0 +1,67 @@
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A SNIP-20 token the router is registered with
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Token {
+    pub address: HumanAddr,
+    pub code_hash: String,
+}
+
+/// Swaps the hop's input, the previous hop's output, into `ask_token` in the stable pool at
+/// `pool`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Hop {
+    pub pool: HumanAddr,
+    pub ask_token: HumanAddr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub admin: HumanAddr,
+    /// Set on every registered token, to measure what each hop returns
+    pub viewing_key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InitMsg {
+    pub admin: HumanAddr,
+    pub viewing_key: String,
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    Receive {
+        from: HumanAddr,
+        msg: Binary,
+        amount: Uint128,
+    },
+    /// Callback the router sends itself after each hop's swap
+    ContinueRoute {},
+    /// Registers the router's receiver and viewing key with more tokens, so routes can pass
+    /// through them. Admin only.
+    RegisterTokens { tokens: Vec<Token> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RouterReceiveMsg {
+    /// Swaps the sent tokens through the hops in order, each hop's output being the next
+    /// one's input, and sends the last output to `recipient`, the sender by default. Reverts
+    /// if that's less than min_received. Every token on the route must be registered.
+    Route {
+        hops: Vec<Hop>,
+        recipient: Option<HumanAddr>,
+        min_received: Option<Uint128>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    /// The registered tokens, as a list of Token
+    Tokens {},
+}
@@ -0,0 +1,53 @@
+use cosmwasm_std::{HumanAddr, StdResult, Storage, Uint128};
+use cosmwasm_storage::{ReadonlySingleton, Singleton};
+use serde::{Deserialize, Serialize};
+
+use crate::msg::{Config, Hop, Token};
+
+const CONFIG_KEY: &[u8] = b"config";
+
+pub fn store_config<S: Storage>(storage: &mut S, config: &Config) -> StdResult<()> {
+    Singleton::new(storage, CONFIG_KEY).save(config)
+}
+
+pub fn read_config<S: Storage>(storage: &S) -> StdResult<Config> {
+    ReadonlySingleton::new(storage, CONFIG_KEY).load()
+}
+
+const TOKENS_KEY: &[u8] = b"tokens";
+
+pub fn store_tokens<S: Storage>(storage: &mut S, tokens: &[Token]) -> StdResult<()> {
+    Singleton::new(storage, TOKENS_KEY).save(&tokens.to_vec())
+}
+
+pub fn read_tokens<S: Storage>(storage: &S) -> StdResult<Vec<Token>> {
+    Ok(ReadonlySingleton::new(storage, TOKENS_KEY)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// A route whose hops are being executed. The router holds `token`, the output of the last
+/// dispatched hop, measured against `balance_before` once that hop's pool has paid out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Route {
+    pub hops: Vec<Hop>,
+    pub next_hop: usize,
+    pub token: HumanAddr,
+    pub balance_before: Uint128,
+    pub recipient: HumanAddr,
+    pub min_received: Option<Uint128>,
+}
+
+const ROUTE_KEY: &[u8] = b"route";
+
+pub fn store_route<S: Storage>(storage: &mut S, route: &Route) -> StdResult<()> {
+    Singleton::new(storage, ROUTE_KEY).save(route)
+}
+
+pub fn read_route<S: Storage>(storage: &S) -> StdResult<Option<Route>> {
+    ReadonlySingleton::new(storage, ROUTE_KEY).may_load()
+}
+
+pub fn remove_route<S: Storage>(storage: &mut S) {
+    Singleton::<S, Route>::new(storage, ROUTE_KEY).remove()
+}
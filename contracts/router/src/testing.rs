@@ -0,0 +1,259 @@
+use cosmwasm_std::testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{
+    coins, from_binary, to_binary, CosmosMsg, Extern, HandleResult, HumanAddr, StdError, Uint128,
+    WasmMsg,
+};
+use secret_toolkit::snip20 as snip20_utils;
+use secretswap_stablepools::msg::Snip20ReceiveMsg as PoolReceiveMsg;
+
+use crate::contract::{handle, init, query, MAX_HOPS};
+use crate::mock_querier::{mock_dependencies, WasmMockQuerier};
+use crate::msg::{HandleMsg, Hop, InitMsg, QueryMsg, RouterReceiveMsg, Token};
+use crate::state::read_route;
+
+const ADMIN: &str = "admin";
+const USER: &str = "user";
+const VIEWING_KEY: &str = "viewing_key";
+
+fn token(address: &str) -> Token {
+    Token {
+        address: HumanAddr::from(address),
+        code_hash: format!("{}_hash", address),
+    }
+}
+
+fn init_router(tokens: &[&str]) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let mut deps = mock_dependencies(20);
+    for address in tokens {
+        deps.querier.with_token(address);
+    }
+    let msg = InitMsg {
+        admin: HumanAddr::from(ADMIN),
+        viewing_key: VIEWING_KEY.to_string(),
+        tokens: tokens.iter().map(|address| token(address)).collect(),
+    };
+    init(&mut deps, mock_env(ADMIN, &[]), msg).unwrap();
+
+    deps
+}
+
+fn hop(pool: &str, ask_token: &str) -> Hop {
+    Hop {
+        pool: HumanAddr::from(pool),
+        ask_token: HumanAddr::from(ask_token),
+    }
+}
+
+/// Sends `amount` of `token` into the router with a Route message, like a SNIP-20 Send would
+fn route(
+    deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+    token: &str,
+    amount: u128,
+    hops: Vec<Hop>,
+    min_received: Option<u128>,
+) -> HandleResult {
+    deps.querier.add_balance(token, MOCK_CONTRACT_ADDR, amount);
+    handle(
+        deps,
+        mock_env(token, &[]),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(amount),
+            msg: to_binary(&RouterReceiveMsg::Route {
+                hops,
+                recipient: None,
+                min_received: min_received.map(Uint128),
+            })
+            .unwrap(),
+        },
+    )
+}
+
+/// Pays `amount` of `token` to the router, like the last hop's pool would, and runs the
+/// ContinueRoute callback
+fn continue_route(
+    deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+    token: &str,
+    amount: u128,
+) -> HandleResult {
+    deps.querier.add_balance(token, MOCK_CONTRACT_ADDR, amount);
+    handle(
+        deps,
+        mock_env(MOCK_CONTRACT_ADDR, &[]),
+        HandleMsg::ContinueRoute {},
+    )
+}
+
+fn swap_hop_msg(pool: &str, offer_token: &str, amount: u128, ask_token: &str) -> CosmosMsg {
+    snip20_utils::send_msg(
+        HumanAddr::from(pool),
+        Uint128(amount),
+        Some(
+            to_binary(&PoolReceiveMsg::Swap {
+                to_token: HumanAddr::from(ask_token),
+                recipient: None,
+                referrer: None,
+                referral_fee_bps: None,
+                expected_return: None,
+                max_slippage_bps: None,
+                deadline: None,
+                unwrap: None,
+            })
+            .unwrap(),
+        ),
+        None,
+        256,
+        format!("{}_hash", offer_token),
+        HumanAddr::from(offer_token),
+    )
+    .unwrap()
+}
+
+fn assert_generic_err<T: std::fmt::Debug>(res: Result<T, StdError>, expected: &str) {
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert!(
+            msg.contains(expected),
+            "expected an error containing {:?}, got {:?}",
+            expected,
+            msg
+        ),
+        res => panic!("expected a generic error, got {:?}", res),
+    }
+}
+
+#[test]
+fn init_registers_tokens() {
+    let mut deps = mock_dependencies(20);
+    let msg = InitMsg {
+        admin: HumanAddr::from(ADMIN),
+        viewing_key: VIEWING_KEY.to_string(),
+        tokens: vec![token("token0")],
+    };
+    let res = init(&mut deps, mock_env(ADMIN, &[]), msg).unwrap();
+    assert_eq!(res.messages.len(), 2);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::RegisterTokens {
+            tokens: vec![token("token1")],
+        },
+    );
+    assert_eq!(res.unwrap_err(), StdError::unauthorized());
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::RegisterTokens {
+            tokens: vec![token("token1"), token("token0")],
+        },
+    )
+    .unwrap();
+
+    let tokens: Vec<Token> = from_binary(&query(&deps, QueryMsg::Tokens {}).unwrap()).unwrap();
+    assert_eq!(tokens, vec![token("token1"), token("token0")]);
+}
+
+#[test]
+fn routes_through_every_hop() {
+    let mut deps = init_router(&["token0", "token1", "token2"]);
+    let hops = vec![hop("pool_a", "token1"), hop("pool_b", "token2")];
+
+    let res = route(&mut deps, "token0", 1_000, hops, Some(950)).unwrap();
+    assert_eq!(
+        res.messages[0],
+        swap_hop_msg("pool_a", "token0", 1_000, "token1")
+    );
+    match &res.messages[1] {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        }) => {
+            assert_eq!(contract_addr, &HumanAddr::from(MOCK_CONTRACT_ADDR));
+            assert_eq!(
+                from_binary::<HandleMsg>(msg).unwrap(),
+                HandleMsg::ContinueRoute {}
+            );
+        }
+        _ => panic!("Must call the router back after the hop"),
+    }
+
+    // only the router itself continues a route, and one route runs at a time
+    let res = handle(&mut deps, mock_env(USER, &[]), HandleMsg::ContinueRoute {});
+    assert_eq!(res.unwrap_err(), StdError::unauthorized());
+    let res = route(
+        &mut deps,
+        "token0",
+        1_000,
+        vec![hop("pool_a", "token1")],
+        None,
+    );
+    assert_generic_err(res, "[reentrant] ");
+
+    // each hop swaps what the previous one paid out
+    let res = continue_route(&mut deps, "token1", 990).unwrap();
+    assert_eq!(
+        res.messages[0],
+        swap_hop_msg("pool_b", "token1", 990, "token2")
+    );
+
+    let res = continue_route(&mut deps, "token2", 980).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![snip20_utils::transfer_msg(
+            HumanAddr::from(USER),
+            Uint128(980),
+            None,
+            256,
+            "token2_hash".to_string(),
+            HumanAddr::from("token2"),
+        )
+        .unwrap()]
+    );
+    assert_eq!(read_route(&deps.storage).unwrap(), None);
+}
+
+#[test]
+fn route_min_received() {
+    let mut deps = init_router(&["token0", "token1"]);
+
+    route(
+        &mut deps,
+        "token0",
+        1_000,
+        vec![hop("pool", "token1")],
+        Some(995),
+    )
+    .unwrap();
+    let res = continue_route(&mut deps, "token1", 990);
+    assert_generic_err(res, "[slippage_exceeded] ");
+}
+
+#[test]
+fn route_rejects_invalid_hops() {
+    let mut deps = init_router(&["token0", "token1"]);
+
+    let res = route(&mut deps, "token0", 1_000, vec![], None);
+    assert_generic_err(res, "between 1 and");
+    let res = route(
+        &mut deps,
+        "token0",
+        1_000,
+        vec![hop("pool", "token1"); MAX_HOPS + 1],
+        None,
+    );
+    assert_generic_err(res, "between 1 and");
+    let res = route(
+        &mut deps,
+        "token0",
+        1_000,
+        vec![hop("pool", "token2")],
+        None,
+    );
+    assert_generic_err(res, "[unknown_token] ");
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &coins(1_000, "uscrt")),
+        HandleMsg::ContinueRoute {},
+    );
+    assert_generic_err(res, "native coins");
+}
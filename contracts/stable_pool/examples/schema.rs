@@ -0,0 +1,69 @@
+use std::env::current_dir;
+use std::fs::create_dir_all;
+
+use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
+
+use secretswap_stablepools::msg::{
+    AdminFeesResponse, AmountInResponse, AmountOutResponse, BatchOperationResult,
+    BatchSwapSimulationResponse, ConfigResponse, CurveStateResponse, DepositCapsResponse,
+    DepthCurveResponse, DepthPoint, EffectiveFeeResponse, ExecutionPriceResponse, HandleMsg,
+    ImbalanceResponse, InitMsg, LiquidityTokenResponse, LpFlowsResponse,
+    LpNeededForWithdrawalResponse, LpValueResponse, MostNeededTokenResponse, PoolSnapshotResponse,
+    PoolsResponse, PositionResponse, ProvideLiquidityResponse, QueryMsg,
+    RebalancingDepositResponse, ReverseSimulationResponse, SimulationResponse, Snip20ReceiveMsg,
+    SwapResponse, TokenByIndexResponse, TokenIndexResponse, TokensResponse,
+    TransactionHistoryResponse, TvlResponse, TwapResponse, VirtualPriceTwapResponse,
+    VolumeResponse, WithdrawLiquidityResponse,
+};
+
+fn main() {
+    let mut out_dir = current_dir().unwrap();
+    out_dir.push("schema");
+    create_dir_all(&out_dir).unwrap();
+    remove_schemas(&out_dir).unwrap();
+
+    export_schema(&schema_for!(InitMsg), &out_dir);
+    export_schema(&schema_for!(HandleMsg), &out_dir);
+    export_schema(&schema_for!(Snip20ReceiveMsg), &out_dir);
+    export_schema(&schema_for!(QueryMsg), &out_dir);
+
+    // Set as HandleResponse data
+    export_schema(&schema_for!(ProvideLiquidityResponse), &out_dir);
+    export_schema(&schema_for!(SwapResponse), &out_dir);
+    export_schema(&schema_for!(WithdrawLiquidityResponse), &out_dir);
+    export_schema(&schema_for!(BatchOperationResult), &out_dir);
+
+    // Query responses, in QueryMsg order
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(TokensResponse), &out_dir);
+    export_schema(&schema_for!(PoolsResponse), &out_dir);
+    export_schema(&schema_for!(MostNeededTokenResponse), &out_dir);
+    export_schema(&schema_for!(BatchSwapSimulationResponse), &out_dir);
+    export_schema(&schema_for!(LpValueResponse), &out_dir);
+    export_schema(&schema_for!(LpNeededForWithdrawalResponse), &out_dir);
+    export_schema(&schema_for!(VolumeResponse), &out_dir);
+    export_schema(&schema_for!(TokenIndexResponse), &out_dir);
+    export_schema(&schema_for!(TokenByIndexResponse), &out_dir);
+    export_schema(&schema_for!(CurveStateResponse), &out_dir);
+    export_schema(&schema_for!(RebalancingDepositResponse), &out_dir);
+    export_schema(&schema_for!(EffectiveFeeResponse), &out_dir);
+    export_schema(&schema_for!(PoolSnapshotResponse), &out_dir);
+    export_schema(&schema_for!(DepthCurveResponse), &out_dir);
+    export_schema(&schema_for!(ExecutionPriceResponse), &out_dir);
+    export_schema(&schema_for!(AmountOutResponse), &out_dir);
+    export_schema(&schema_for!(AmountInResponse), &out_dir);
+    export_schema(&schema_for!(SimulationResponse), &out_dir);
+    export_schema(&schema_for!(ReverseSimulationResponse), &out_dir);
+    export_schema(&schema_for!(ImbalanceResponse), &out_dir);
+    export_schema(&schema_for!(LiquidityTokenResponse), &out_dir);
+    export_schema(&schema_for!(TvlResponse), &out_dir);
+    export_schema(&schema_for!(DepositCapsResponse), &out_dir);
+    export_schema(&schema_for!(LpFlowsResponse), &out_dir);
+    export_schema(&schema_for!(AdminFeesResponse), &out_dir);
+    export_schema(&schema_for!(VirtualPriceTwapResponse), &out_dir);
+    export_schema(&schema_for!(TwapResponse), &out_dir);
+    // MaxInputForImpact
+    export_schema(&schema_for!(DepthPoint), &out_dir);
+    export_schema(&schema_for!(PositionResponse), &out_dir);
+    export_schema(&schema_for!(TransactionHistoryResponse), &out_dir);
+}
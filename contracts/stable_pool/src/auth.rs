@@ -0,0 +1,159 @@
+use std::convert::TryFrom;
+
+use bech32::ToBase32;
+use cosmwasm_std::{to_vec, HumanAddr, StdResult, Storage, Uint128};
+use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use ripemd160::{Digest, Ripemd160};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::error::ContractError;
+use crate::msg::{Permission, Permit, PermitParams};
+use crate::state::{is_permit_revoked, read_viewing_key};
+
+/// Bech32 prefix of the addresses permits are signed by
+pub const ADDRESS_PREFIX: &str = "secret";
+const PUB_KEY_TYPE: &str = "tendermint/PubKeySecp256k1";
+
+pub fn hash_viewing_key(key: &str) -> [u8; 32] {
+    let mut hashed = [0u8; 32];
+    hashed.copy_from_slice(&Sha256::digest(key.as_bytes()));
+    hashed
+}
+
+/// Checks `key` against the viewing key `address` set with SetViewingKey
+pub fn check_viewing_key<S: Storage>(storage: &S, address: &HumanAddr, key: &str) -> StdResult<()> {
+    // Hashed whether or not a key is set, so the time taken doesn't tell
+    let hashed = hash_viewing_key(key);
+    match read_viewing_key(storage, address)? {
+        Some(expected) if ct_eq(&expected, &hashed) => Ok(()),
+        _ => Err(ContractError::Unauthorized.into()),
+    }
+}
+
+fn ct_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the account that signed `permit`, once it's checked that the permit is valid for
+/// `pool`, grants `permission` or Owner, and hasn't been revoked
+pub fn validate_permit<S: Storage>(
+    storage: &S,
+    pool: &HumanAddr,
+    permit: &Permit,
+    permission: Permission,
+) -> StdResult<HumanAddr> {
+    let invalid = |reason: &str| ContractError::InvalidPermit(reason.to_string());
+    let params = &permit.params;
+    if !params.allowed_tokens.contains(pool) {
+        return Err(invalid("is not valid for this pool").into());
+    }
+    if !params
+        .permissions
+        .iter()
+        .any(|p| *p == permission || *p == Permission::Owner)
+    {
+        return Err(
+            ContractError::InvalidPermit(format!("does not grant {:?}", permission)).into(),
+        );
+    }
+
+    let pub_key = &permit.signature.pub_key;
+    if pub_key.r#type != PUB_KEY_TYPE {
+        return Err(invalid("has an unsupported public key type").into());
+    }
+    // Addresses are derived from the compressed key, which from_sec1_bytes doesn't require
+    if pub_key.value.len() != 33 {
+        return Err(invalid("has a malformed public key").into());
+    }
+    let public_key = VerifyingKey::from_sec1_bytes(pub_key.value.as_slice())
+        .map_err(|_| invalid("has a malformed public key"))?;
+    let signature = Signature::try_from(permit.signature.signature.as_slice())
+        .map_err(|_| invalid("has a malformed signature"))?;
+    // Hashes the sign doc with SHA-256 itself. High-S signatures are rejected, as the Cosmos SDK
+    // does
+    if public_key
+        .verify(&to_vec(&SignDoc::new(params))?, &signature)
+        .is_err()
+    {
+        return Err(invalid("signature does not match its params").into());
+    }
+
+    let account = address(pub_key.value.as_slice())?;
+    if is_permit_revoked(storage, &account, &params.permit_name)? {
+        return Err(invalid("was revoked").into());
+    }
+    Ok(account)
+}
+
+/// Address of the account holding the compressed secp256k1 `pub_key`
+fn address(pub_key: &[u8]) -> StdResult<HumanAddr> {
+    let hash = Ripemd160::digest(&Sha256::digest(pub_key));
+    bech32::encode(ADDRESS_PREFIX, hash.to_base32())
+        .map(HumanAddr)
+        .map_err(|_| ContractError::InvalidPermit("has a malformed public key".to_string()).into())
+}
+
+/// The amino sign doc wallets sign for a permit. Fields are declared in sorted order, which
+/// is the order they're serialized and signed in.
+#[derive(Serialize)]
+struct SignDoc {
+    account_number: Uint128,
+    chain_id: String,
+    fee: Fee,
+    memo: String,
+    msgs: Vec<SignDocMsg>,
+    sequence: Uint128,
+}
+
+#[derive(Serialize)]
+struct Fee {
+    amount: Vec<FeeAmount>,
+    gas: Uint128,
+}
+
+#[derive(Serialize)]
+struct FeeAmount {
+    amount: Uint128,
+    denom: String,
+}
+
+#[derive(Serialize)]
+struct SignDocMsg {
+    #[serde(rename = "type")]
+    msg_type: String,
+    value: PermitContent,
+}
+
+#[derive(Serialize)]
+struct PermitContent {
+    allowed_tokens: Vec<HumanAddr>,
+    permissions: Vec<Permission>,
+    permit_name: String,
+}
+
+impl SignDoc {
+    fn new(params: &PermitParams) -> Self {
+        SignDoc {
+            account_number: Uint128::zero(),
+            chain_id: params.chain_id.clone(),
+            fee: Fee {
+                amount: vec![FeeAmount {
+                    amount: Uint128::zero(),
+                    denom: "uscrt".to_string(),
+                }],
+                gas: Uint128(1),
+            },
+            memo: String::new(),
+            msgs: vec![SignDocMsg {
+                msg_type: "query_permit".to_string(),
+                value: PermitContent {
+                    allowed_tokens: params.allowed_tokens.clone(),
+                    permissions: params.permissions.clone(),
+                    permit_name: params.permit_name.clone(),
+                },
+            }],
+            sequence: Uint128::zero(),
+        }
+    }
+}
@@ -1,27 +1,79 @@
-use std::{
-    ops::{Add, Mul, Sub},
-    u128,
-};
-
 use cosmwasm_std::{
-    debug_print, from_binary, log, to_binary, Api, Binary, CosmosMsg, Decimal, Env, Extern,
-    HandleResponse, HandleResult, HumanAddr, InitResponse, Querier, StdError, StdResult, Storage,
-    Uint128, WasmMsg,
+    from_binary, log, to_binary, Api, BankMsg, Binary, Coin, CosmosMsg, Decimal, Env, Extern,
+    HandleResponse, HandleResult, HumanAddr, InitResponse, LogAttribute, Querier, StdError,
+    StdResult, Storage, Uint128, WasmMsg,
 };
 use primitive_types::U256;
+use std::fmt::Display;
 
 use lp_token as snip20;
 use secret_toolkit::snip20 as snip20_utils;
 
 use crate::{
-    math::{decimal_multiplication, decimal_subtraction, reverse_decimal},
-    msg::{Config, HandleMsg, InitMsg, QueryMsg, Snip20ReceiveMsg, Token, TokenAmount, TokenInfo},
+    auth::{check_viewing_key, hash_viewing_key, validate_permit},
+    error::ContractError,
+    math::{
+        balances_after_imbalance_fee, convert_decimals, decimal_factor, decimal_from_u256_ratio,
+        denormalize, get_d, get_dy, imbalance_fee_bps, marginal_price, max_deviation_bps,
+        normalize, rebalancing_fee_bps, round_down_normalized, withdraw_one_coin,
+        NORMALIZED_DECIMALS,
+    },
+    msg::{
+        AdminFeesResponse, AmountInResponse, AmountOutResponse, AssetFlow, AssetSnapshot,
+        BatchOperation, BatchOperationResult, BatchSwapSimulationResponse, Config, ConfigResponse,
+        CurveStateResponse, DepositCapsResponse, DepthCurveResponse, DepthPoint,
+        EffectiveFeeResponse, ExecutionPriceResponse, FactoryHandleMsg, HandleMsg,
+        ImbalanceResponse, InitMsg, LiquidityTokenResponse, LpFlowsResponse,
+        LpNeededForWithdrawalResponse, LpValueResponse, MostNeededTokenResponse, Permission,
+        Permit, PoolAsset, PoolSnapshotResponse, PoolStatus, PoolsResponse, PositionResponse,
+        ProvideLiquidityResponse, QueryMsg, QueryWithPermit, RebalancingDepositResponse,
+        ReverseSimulationResponse, SimulatedSwap, SimulationResponse, Snip20ReceiveMsg,
+        SwapResponse, SwapSimulationResponse, SwapTx, Token, TokenAmount, TokenByIndexResponse,
+        TokenIndexResponse, TokenInfo, TokenVolume, TokensResponse, TransactionHistoryResponse,
+        TvlResponse, TwapResponse, VirtualPriceTwapResponse, VolumeResponse,
+        WithdrawLiquidityResponse, DEFAULT_MIN_RESERVE_AFTER_SWAP,
+    },
     querier::query_token_decimals,
-    state::{read_all_assets, read_config, store_all_assets, store_config},
+    state::{
+        append_swap_tx, next_swap_tx_id, push_price_observation, push_virtual_price,
+        read_admin_fees, read_all_assets, read_block_volume, read_config,
+        read_last_price_observation, read_last_virtual_price, read_lock, read_lp_flows,
+        read_next_asset_id, read_position, read_price_observations, read_reserves,
+        read_swap_tx_count, read_swap_txs, read_virtual_prices, read_volumes, revoke_permit,
+        store_admin_fees, store_all_assets, store_asset, store_block_volume, store_config,
+        store_lock, store_lp_flows, store_next_asset_id, store_position, store_reserves,
+        store_viewing_key, store_volumes, BlockVolume, CumulativePrice, PriceObservation,
+        VirtualPriceObservation,
+    },
     u256_math::*,
 };
 
-use crate::querier::{query_token_balance, query_token_total_supply};
+use crate::querier::{query_asset_balance, query_token_allowance, query_token_total_supply};
+
+/// Upper bound on the number of swaps a single BatchSwapSimulation query can price
+pub const MAX_BATCH_SIMULATIONS: usize = 20;
+
+/// Upper bound on the number of operations in a single Batch
+pub const MAX_BATCH_OPERATIONS: usize = 10;
+
+/// Upper bound on the page_size of a TransactionHistory query
+pub const MAX_HISTORY_PAGE_SIZE: u32 = 50;
+
+/// Upper bound on the number of points a single DepthCurve query can price
+pub const MAX_DEPTH_POINTS: u32 = 20;
+pub const MAX_IMPACT_SEARCH_ITERATIONS: u32 = 64;
+
+/// Coarsest allowed rounding granularity, a whole token at 18 decimals
+pub const MAX_ROUND_DOWN_TO_NEAREST: u128 = 1_000_000_000_000_000_000;
+
+/// Native coins are micro-denominated, e.g. uscrt
+pub const NATIVE_DECIMALS: u8 = 6;
+
+/// Denom wrapped by wrapped_native assets, the only one SNIP-20 Deposit takes
+pub const WRAPPED_NATIVE_DENOM: &str = "uscrt";
+
+/// Basis points in 100%
+pub const MAX_BPS: u32 = 10_000;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
@@ -31,56 +83,39 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     if msg.swap_fee_denom == Uint128::zero() {
         return Err(StdError::generic_err("swap_fee_denom cannot be zero"));
     }
+    if msg.amp == 0 {
+        return Err(StdError::generic_err("amp cannot be zero"));
+    }
 
     let mut messages: Vec<CosmosMsg> = vec![];
     let mut assets: Vec<TokenInfo> = vec![];
 
     // Setup pool's tokens
     for token in msg.assets {
-        // Set initial viewing key for token
-        messages.push(snip20_utils::set_viewing_key_msg(
-            msg.initial_tokens_viewing_key.clone(),
-            None,
-            256,
-            token.code_hash.clone(),
-            token.address.clone(),
-        )?);
-
-        // Register for receive message from token
-        messages.push(snip20_utils::register_receive_msg(
-            env.contract_code_hash.clone(),
-            None,
-            256,
-            token.code_hash.clone(),
-            token.address.clone(),
-        )?);
-
-        let decimals = query_token_decimals(deps, &token.address, &token.code_hash)?;
-        if decimals > 18 {
-            return Err(StdError::generic_err(format!(
-                "Decimals must not exceed 18 for token: {:?}",
-                token
-            )));
-        }
-
-        assets.push(TokenInfo {
-            address: token.address.clone(),
-            code_hash: token.code_hash.clone(),
-            viewing_key: msg.initial_tokens_viewing_key.clone(),
-            decimals,
-        })
+        let (asset, asset_messages) = register_asset(
+            deps,
+            &env,
+            token,
+            assets.len() as u32,
+            &msg.initial_tokens_viewing_key,
+        )?;
+        assets.push(asset);
+        messages.extend(asset_messages);
     }
 
     store_all_assets(&mut deps.storage, &assets)?;
+    store_next_asset_id(&mut deps.storage, assets.len() as u32)?;
+    store_reserves(&mut deps.storage, &vec![Uint128::zero(); assets.len()])?;
 
     // Create LP token
     messages.extend(vec![CosmosMsg::Wasm(WasmMsg::Instantiate {
         code_id: msg.lp_token_code_id,
         msg: to_binary(&snip20::msg::InitMsg {
-            name: format!("StableSwap Liquidity Provider (LP) token for TODO"),
-            admin: Some(env.contract.address),
-            symbol: "STABLE-LP".to_string(),
-            decimals: 18,
+            // SNIP-20 names are 3-30 bytes and symbols 3-6 capital letters
+            name: "StableSwap LP token".to_string(),
+            admin: Some(env.contract.address.clone()),
+            symbol: "STBLP".to_string(),
+            decimals: NORMALIZED_DECIMALS,
             initial_balances: None,
             prng_seed: msg.lp_token_prng_seed,
             config: Some(snip20::msg::InitConfig {
@@ -92,8 +127,8 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
             }),
             after_init_hook: Some(snip20::msg::AfterInitHook {
                 msg: to_binary(&HandleMsg::PostInitialize {})?,
-                contract_addr: env.contract.address,
-                code_hash: env.contract_code_hash,
+                contract_addr: env.contract.address.clone(),
+                code_hash: env.contract_code_hash.clone(),
             }),
         })?,
         send: vec![],
@@ -101,18 +136,53 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         callback_code_hash: msg.lp_token_code_hash.clone(),
     })]);
 
-    store_config(
-        &mut deps.storage,
-        &Config {
-            admin: msg.admin,
-            swap_fee_nom: msg.swap_fee_nom,
-            swap_fee_denom: msg.swap_fee_denom,
-            is_halted: msg.is_halted,
-            round_down_pool_answer_to_nearest: msg.round_down_pool_answer_to_nearest,
-            lp_token_address: HumanAddr::default(),
-            lp_token_code_hash: msg.lp_token_code_hash,
-        },
-    )?;
+    let seed_tvl_cap = msg.seed_tvl_cap.unwrap_or_else(Uint128::zero);
+    let config = Config {
+        admin: msg.admin,
+        pending_admin: None,
+        operators: vec![],
+        pool_address: env.contract.address.clone(),
+        amp: msg.amp,
+        swap_fee_nom: msg.swap_fee_nom,
+        swap_fee_denom: msg.swap_fee_denom,
+        status: msg.status.unwrap_or_default(),
+        is_halted: false,
+        round_down_pool_answer_to_nearest: msg.round_down_pool_answer_to_nearest,
+        max_swap_out_bps: msg.max_swap_out_bps.unwrap_or(0),
+        dynamic_fee: msg.dynamic_fee.unwrap_or(false),
+        dynamic_fee_base_bps: msg.dynamic_fee_base_bps.unwrap_or(0),
+        dynamic_fee_max_bps: msg.dynamic_fee_max_bps.unwrap_or(0),
+        dynamic_fee_min_bps: msg.dynamic_fee_min_bps.unwrap_or(0),
+        depeg_threshold_bps: msg.depeg_threshold_bps.unwrap_or(0),
+        max_referral_fee_bps: msg.max_referral_fee_bps.unwrap_or(0),
+        admin_fee_bps: msg.admin_fee_bps.unwrap_or(0),
+        max_volume_per_block: msg.max_volume_per_block.unwrap_or_else(Uint128::zero),
+        rebalance_bonus_to_lp_bps: msg.rebalance_bonus_to_lp_bps.unwrap_or(0),
+        deposit_bonus_bps: msg.deposit_bonus_bps.unwrap_or(0),
+        per_asset_deposit_cap: msg.per_asset_deposit_cap.unwrap_or_else(Uint128::zero),
+        total_tvl_cap: msg.total_tvl_cap.unwrap_or_else(Uint128::zero),
+        min_reserve_after_swap: msg
+            .min_reserve_after_swap
+            .unwrap_or(Uint128(DEFAULT_MIN_RESERVE_AFTER_SWAP)),
+        seed_mode: seed_tvl_cap > Uint128::zero(),
+        seed_tvl_cap,
+        lp_token_address: HumanAddr::default(),
+        lp_token_code_hash: msg.lp_token_code_hash,
+        factory: msg.factory,
+    };
+    validate_config(&config)?;
+    store_config(&mut deps.storage, &config)?;
+
+    if let Some(factory) = &config.factory {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: factory.address.clone(),
+            callback_code_hash: factory.code_hash.clone(),
+            msg: to_binary(&FactoryHandleMsg::RegisterPool {
+                assets: assets.iter().map(|t| t.address.clone()).collect(),
+            })?,
+            send: vec![],
+        }));
+    }
 
     Ok(InitResponse {
         messages,
@@ -125,14 +195,142 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> HandleResult {
+    match msg {
+        HandleMsg::ProvideLiquidity { .. } | HandleMsg::SwapNative { .. } => {}
+        _ => check_no_funds(&env)?,
+    }
+
     match msg {
         HandleMsg::Receive { amount, msg, from } => receive_snip20(deps, env, from, amount, msg),
         HandleMsg::PostInitialize {} => try_post_initialize(deps, env),
         HandleMsg::ProvideLiquidity {
             assets,
             cancel_if_no_bonus,
-        } => try_provide_liquidity(deps, env, assets, cancel_if_no_bonus),
-        HandleMsg::UpdateViewingKeys {} => todo!(),
+            min_lp_out,
+            deadline,
+        } => {
+            check_deadline(&env, deadline)?;
+            try_provide_liquidity(deps, env, assets, cancel_if_no_bonus, min_lp_out)
+        }
+        HandleMsg::FinalizeDeposit {
+            depositor,
+            deposits,
+            cancel_if_no_bonus,
+            min_lp_out,
+        } => try_finalize_deposit(
+            deps,
+            env,
+            depositor,
+            deposits,
+            cancel_if_no_bonus.unwrap_or(false),
+            min_lp_out,
+        ),
+        HandleMsg::SwapNative {
+            to_token,
+            recipient,
+            referrer,
+            referral_fee_bps,
+        } => try_swap_native(deps, env, to_token, recipient, referrer, referral_fee_bps),
+        HandleMsg::FinalizeWrappedSwap {
+            token,
+            amount,
+            to_token,
+            swapper,
+            recipient,
+            referrer,
+            referral_fee_bps,
+        } => {
+            // Sent by the pool itself once the wrapped coins arrived
+            if env.message.sender != env.contract.address {
+                return Err(ContractError::Unauthorized.into());
+            }
+            try_swap(
+                deps,
+                env,
+                amount,
+                token,
+                to_token,
+                swapper,
+                recipient,
+                referrer.map(|referrer| (referrer, referral_fee_bps)),
+                None,
+                false,
+            )
+        }
+        HandleMsg::UpdateViewingKeys { viewing_key } => {
+            try_update_viewing_keys(deps, env, viewing_key)
+        }
+        HandleMsg::UpdateTokenViewingKey { token, viewing_key } => {
+            try_update_token_viewing_key(deps, env, token, viewing_key)
+        }
+        HandleMsg::SetAssetEnabled { token, enabled } => {
+            try_set_asset_enabled(deps, env, token, enabled)
+        }
+        HandleMsg::AddAsset { token, viewing_key } => try_add_asset(deps, env, token, viewing_key),
+        HandleMsg::SetWrappedNative {
+            token,
+            wrapped_native,
+        } => try_set_wrapped_native(deps, env, token, wrapped_native),
+        HandleMsg::RemoveAsset { token } => try_remove_asset(deps, env, token),
+        HandleMsg::UpdateConfig {
+            swap_fee_nom,
+            swap_fee_denom,
+            round_down_pool_answer_to_nearest,
+            max_swap_out_bps,
+            dynamic_fee,
+            dynamic_fee_base_bps,
+            dynamic_fee_max_bps,
+            dynamic_fee_min_bps,
+            depeg_threshold_bps,
+            max_referral_fee_bps,
+            admin_fee_bps,
+            max_volume_per_block,
+            rebalance_bonus_to_lp_bps,
+            deposit_bonus_bps,
+            per_asset_deposit_cap,
+            total_tvl_cap,
+            min_reserve_after_swap,
+            status,
+        } => try_update_config(
+            deps,
+            env,
+            swap_fee_nom,
+            swap_fee_denom,
+            round_down_pool_answer_to_nearest,
+            max_swap_out_bps,
+            dynamic_fee,
+            dynamic_fee_base_bps,
+            dynamic_fee_max_bps,
+            dynamic_fee_min_bps,
+            depeg_threshold_bps,
+            max_referral_fee_bps,
+            admin_fee_bps,
+            max_volume_per_block,
+            rebalance_bonus_to_lp_bps,
+            deposit_bonus_bps,
+            per_asset_deposit_cap,
+            total_tvl_cap,
+            min_reserve_after_swap,
+            status,
+        ),
+        HandleMsg::EmergencyWithdraw { recipient } => try_emergency_withdraw(deps, env, recipient),
+        HandleMsg::WithdrawAdminFees { recipient } => try_withdraw_admin_fees(deps, env, recipient),
+        HandleMsg::SetRounding {
+            round_down_to_nearest,
+        } => try_set_rounding(deps, env, round_down_to_nearest),
+        HandleMsg::SnapshotVirtualPrice {} => try_snapshot_virtual_price(deps, env),
+        HandleMsg::Batch { operations } => try_batch(deps, env, operations),
+        HandleMsg::FinalizeBatch {} => try_finalize_batch(deps, env),
+        HandleMsg::EndSeedMode {} => try_end_seed_mode(deps, env),
+        HandleMsg::Halt {} => try_set_status(deps, env, PoolStatus::WithdrawOnly),
+        HandleMsg::Resume {} => try_set_status(deps, env, PoolStatus::Active),
+        HandleMsg::SetStatus { status } => try_set_status(deps, env, status),
+        HandleMsg::SyncReserves {} => try_sync_reserves(deps, env),
+        HandleMsg::ProposeNewAdmin { address } => try_propose_new_admin(deps, env, address),
+        HandleMsg::AcceptAdmin {} => try_accept_admin(deps, env),
+        HandleMsg::SetOperators { operators } => try_set_operators(deps, env, operators),
+        HandleMsg::SetViewingKey { key } => try_set_viewing_key(deps, env, key),
+        HandleMsg::RevokePermit { permit_name } => try_revoke_permit(deps, env, permit_name),
     }
 }
 
@@ -149,7 +347,15 @@ pub fn receive_snip20<S: Storage, A: Api, Q: Querier>(
         Snip20ReceiveMsg::Swap {
             to_token,
             recipient,
+            referrer,
+            referral_fee_bps,
+            expected_return,
+            max_slippage_bps,
+            deadline,
+            unwrap,
         } => {
+            check_deadline(&env, deadline)?;
+            let min_return = min_return(expected_return, max_slippage_bps)?;
             let supported_tokens = read_all_assets(&deps.storage)?;
 
             if !supported_tokens
@@ -157,17 +363,10 @@ pub fn receive_snip20<S: Storage, A: Api, Q: Querier>(
                 .any(|t| t.address == receive_token_address)
             {
                 // only asset contract can execute this message
-                return Err(StdError::generic_err(format!(
-                    "Unknown source asset {:?}",
-                    receive_token_address,
-                )));
+                return Err(ContractError::UnknownSourceAsset(receive_token_address).into());
             }
             if !supported_tokens.iter().any(|t| t.address == to_token) {
-                // only asset contract can execute this message
-                return Err(StdError::generic_err(format!(
-                    "Unknown destination asset {:?}",
-                    receive_token_address,
-                )));
+                return Err(ContractError::UnknownDestinationAsset(to_token).into());
             }
 
             try_swap(
@@ -176,19 +375,91 @@ pub fn receive_snip20<S: Storage, A: Api, Q: Querier>(
                 amount,
                 receive_token_address,
                 to_token,
+                sender.clone(),
                 recipient.unwrap_or(sender),
+                referrer.map(|referrer| (referrer, referral_fee_bps)),
+                min_return,
+                unwrap.unwrap_or(false),
             )
         }
-        Snip20ReceiveMsg::WithdrawLiquidity {} => {
-            let config = read_config(&deps.storage)?;
-            if env.message.sender != config.lp_token_address {
-                return Err(StdError::generic_err(format!(
-                    "Unknown liqudity token {:?}",
-                    env.message.sender,
-                )));
+        Snip20ReceiveMsg::SwapExactOut {
+            to_token,
+            ask_amount,
+            recipient,
+        } => {
+            let supported_tokens = read_all_assets(&deps.storage)?;
+            if !supported_tokens
+                .iter()
+                .any(|t| t.address == receive_token_address)
+            {
+                return Err(ContractError::UnknownSourceAsset(receive_token_address).into());
+            }
+            if !supported_tokens.iter().any(|t| t.address == to_token) {
+                return Err(ContractError::UnknownDestinationAsset(to_token).into());
             }
 
-            try_withdraw_liquidity(deps, env, sender, amount)
+            let recipient = recipient.unwrap_or_else(|| sender.clone());
+            try_swap_exact_out(deps, env, sender, amount, to_token, ask_amount, recipient)
+        }
+        Snip20ReceiveMsg::WithdrawLiquidity { deadline, unwrap } => {
+            check_deadline(&env, deadline)?;
+            check_lp_token_sender(&read_config(&deps.storage)?, &env)?;
+
+            try_withdraw_liquidity(deps, env, sender, amount, None, unwrap.unwrap_or(false))
+        }
+        Snip20ReceiveMsg::WithdrawLiquidityWithMin {
+            min_amounts,
+            deadline,
+            unwrap,
+        } => {
+            check_deadline(&env, deadline)?;
+            check_lp_token_sender(&read_config(&deps.storage)?, &env)?;
+
+            try_withdraw_liquidity(
+                deps,
+                env,
+                sender,
+                amount,
+                Some(min_amounts),
+                unwrap.unwrap_or(false),
+            )
+        }
+        Snip20ReceiveMsg::WithdrawLiquidityOneToken {
+            token,
+            min_amount,
+            deadline,
+            unwrap,
+        } => {
+            check_deadline(&env, deadline)?;
+            check_lp_token_sender(&read_config(&deps.storage)?, &env)?;
+
+            try_withdraw_liquidity_one_token(
+                deps,
+                env,
+                sender,
+                amount,
+                token,
+                min_amount,
+                unwrap.unwrap_or(false),
+            )
+        }
+        Snip20ReceiveMsg::WithdrawImbalanced {
+            amounts,
+            max_burn,
+            deadline,
+        } => {
+            check_deadline(&env, deadline)?;
+            check_lp_token_sender(&read_config(&deps.storage)?, &env)?;
+
+            try_withdraw_imbalanced(deps, env, sender, amount, amounts, max_burn)
+        }
+        Snip20ReceiveMsg::ZapIn { min_lp_out } => try_zap_in(deps, env, sender, amount, min_lp_out),
+        Snip20ReceiveMsg::Deposit {
+            min_lp_out,
+            deadline,
+        } => {
+            check_deadline(&env, deadline)?;
+            try_receive_deposit(deps, env, sender, amount, min_lp_out)
         }
     }
 }
@@ -202,7 +473,17 @@ pub fn try_post_initialize<S: Storage, A: Api, Q: Querier>(
 
     // permission check
     if config.lp_token_address != HumanAddr::default() {
-        return Err(StdError::unauthorized());
+        return Err(ContractError::Unauthorized.into());
+    }
+    // Queries fail on a code hash mismatch, so this only succeeds if the caller runs the LP code
+    let lp_decimals = query_token_decimals(deps, &env.message.sender, &config.lp_token_code_hash)
+        .map_err(|_| ContractError::Unauthorized)?;
+    // LP amounts are minted straight from the invariant, which is normalized to 18 decimals
+    if lp_decimals != NORMALIZED_DECIMALS {
+        return Err(StdError::generic_err(format!(
+            "LP token must have {} decimals, got {}",
+            NORMALIZED_DECIMALS, lp_decimals
+        )));
     }
 
     config.lp_token_address = env.message.sender.clone();
@@ -215,7 +496,7 @@ pub fn try_post_initialize<S: Storage, A: Api, Q: Querier>(
             None,
             256,
             config.lp_token_code_hash,
-            config.lp_token_address,
+            config.lp_token_address.clone(),
         )?],
         log: vec![log("liquidity_token_address", config.lp_token_address)],
         data: None,
@@ -228,257 +509,5048 @@ pub fn try_provide_liquidity<S: Storage, A: Api, Q: Querier>(
     env: Env,
     assets_deposits: Vec<TokenAmount>,
     cancel_if_no_bonus: Option<bool>,
+    min_lp_out: Option<Uint128>,
 ) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    check_deposits_open(&config)?;
+    if assets_deposits
+        .iter()
+        .all(|deposit| deposit.amount == Uint128::zero())
+    {
+        return Err(StdError::generic_err(
+            "Cannot provide liquidity without a non-zero deposit",
+        ));
+    }
+    for (i, deposit) in assets_deposits.iter().enumerate() {
+        if assets_deposits[..i]
+            .iter()
+            .any(|other| other.address == deposit.address)
+        {
+            return Err(StdError::generic_err(format!(
+                "Duplicate deposit of asset {:?}",
+                deposit.address
+            )));
+        }
+    }
+
     let supported_tokens = read_all_assets(&deps.storage)?;
 
     let mut messages = vec![];
     let mut logs = vec![log("action", "provide_liquidity")];
+    let mut short_allowances = vec![];
     for deposited_token in assets_deposits.iter() {
-        if !supported_tokens
+        let supported_token = match supported_tokens
+            .iter()
+            .find(|supported_token| supported_token.address == deposited_token.address)
+        {
+            Some(t) => t,
+            None => {
+                return Err(ContractError::UnknownAsset(deposited_token.address.clone()).into());
+            }
+        };
+        if !supported_token.enabled {
+            return Err(ContractError::AssetDisabled(deposited_token.address.clone()).into());
+        }
+        check_not_withdraw_only(supported_token)?;
+
+        if supported_token.native {
+            // Native funds are already sent along with this message
+            let sent_amount = env
+                .message
+                .sent_funds
+                .iter()
+                .find(|coin| coin.denom == deposited_token.address.as_str())
+                .map(|coin| coin.amount)
+                .unwrap_or_else(Uint128::zero);
+            if sent_amount != deposited_token.amount {
+                return Err(StdError::generic_err(format!(
+                    "Native deposit of {} {} does not match the sent funds ({})",
+                    deposited_token.amount, deposited_token.address, sent_amount
+                )));
+            }
+        } else {
+            // A transfer_from past the allowance would revert the whole tx without saying
+            // which token was short, so check all of them up front
+            let allowance = query_token_allowance(
+                deps,
+                &supported_token.address,
+                &supported_token.code_hash,
+                &env.message.sender,
+                &env.contract.address,
+                &supported_token.viewing_key,
+                env.block.time,
+            )?;
+            if allowance < deposited_token.amount {
+                short_allowances.push(format!(
+                    "{} (allowance {}, deposit {})",
+                    deposited_token.address, allowance, deposited_token.amount
+                ));
+            }
+
+            // Execute TransferFrom msg to receive funds, with the stored code hash rather
+            // than the caller's
+            messages.push(snip20_utils::transfer_from_msg(
+                env.message.sender.clone(),
+                env.contract.address.clone(),
+                deposited_token.amount,
+                None,
+                256,
+                supported_token.code_hash.clone(),
+                deposited_token.address.clone(),
+            )?);
+        }
+
+        logs.push(log("token", deposited_token.address.clone()));
+    }
+    if !short_allowances.is_empty() {
+        return Err(StdError::generic_err(format!(
+            "Insufficient allowance for {}",
+            short_allowances.join(", ")
+        )));
+    }
+
+    for coin in env.message.sent_funds.iter() {
+        if !assets_deposits
             .iter()
-            .any(|supported_token| supported_token.address == deposited_token.address)
+            .any(|deposit| deposit.address.as_str() == coin.denom)
         {
             return Err(StdError::generic_err(format!(
-                "Token not supported: {:?} ",
-                deposited_token
+                "Sent funds of {} are not part of the deposit",
+                coin.denom
             )));
         }
+    }
 
-        // Execute TransferFrom msg to receive funds
-        messages.push(snip20_utils::transfer_from_msg(
-            env.message.sender.clone(),
-            env.contract.address.clone(),
-            deposited_token.amount,
-            None,
-            256,
-            deposited_token.code_hash.clone(),
-            deposited_token.address.clone(),
-        )?);
+    // Priced on the requested amounts to fail early and to report the share, FinalizeDeposit
+    // prices again on what actually arrived
+    let cancel_if_no_bonus = cancel_if_no_bonus.unwrap_or(false);
+    let old_balances = read_reserves(&deps.storage)?;
+    let mut new_balances =
+        balances_after_deposits(&old_balances, &supported_tokens, &assets_deposits);
+    let bonuses = deposit_bonus(
+        &config,
+        &supported_tokens,
+        &old_balances,
+        &new_balances,
+        &read_admin_fees(&deps.storage)?,
+    )?;
+    if cancel_if_no_bonus && bonuses.iter().all(|bonus| *bonus == Uint128::zero()) {
+        return Err(ContractError::NoDepositBonus.into());
+    }
 
-        logs.push(log("token", deposited_token.address));
+    let old_balances = normalize_balances(&old_balances, &supported_tokens)?;
+    if config.per_asset_deposit_cap > Uint128::zero() || config.total_tvl_cap > Uint128::zero() {
+        check_deposit_caps(
+            &config,
+            &supported_tokens,
+            &normalize_balances(&new_balances, &supported_tokens)?,
+            &assets_deposits,
+        )?;
+    }
+    for (balance, bonus) in new_balances.iter_mut().zip(bonuses) {
+        *balance += bonus;
     }
+    let new_balances = normalize_balances(&new_balances, &supported_tokens)?;
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let share = share_after_bonus(
+        &config,
+        &old_balances,
+        &new_balances,
+        deposit_share(&config, &old_balances, &new_balances, total_supply)?,
+        total_supply,
+    )?;
+    check_min_lp_out(share, min_lp_out)?;
+
+    // Held until FinalizeDeposit, so nothing can claim the deposit before it's measured
+    enter_lock(&mut deps.storage)?;
+
+    // Runs after the transfers, to mint for what actually arrived
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        callback_code_hash: env.contract_code_hash,
+        msg: to_binary(&HandleMsg::FinalizeDeposit {
+            depositor: env.message.sender,
+            deposits: assets_deposits.clone(),
+            cancel_if_no_bonus: Some(cancel_if_no_bonus),
+            min_lp_out,
+        })?,
+        send: vec![],
+    }));
+
+    // The data of FinalizeDeposit doesn't reach the caller, so it gets the share priced here
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: Some(to_binary(&ProvideLiquidityResponse {
+            lp_amount: share,
+            deposits: assets_deposits,
+        })?),
+    })
+}
 
-    if Some(true) == cancel_if_no_bonus {
-        // TODO
+pub fn try_finalize_deposit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    depositor: HumanAddr,
+    deposits: Vec<TokenAmount>,
+    cancel_if_no_bonus: bool,
+    min_lp_out: Option<Uint128>,
+) -> HandleResult {
+    if env.message.sender != env.contract.address {
+        return Err(ContractError::Unauthorized.into());
     }
 
-    let config = read_config(&deps.storage)?;
+    let mut config = read_config(&deps.storage)?;
+    record_observations(deps, &config, env.block.time)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let mut reserves = read_reserves(&deps.storage)?;
 
-    // For now share = sum of all deposits
-    let mut share = Uint128::zero();
-    for token_deposit in assets_deposits.iter() {
-        let decimals: u8 = supported_tokens
-            .iter()
-            .find(|a| a.address == token_deposit.address)
-            .unwrap() // can unwrap because if we're here then it's already tested
-            .decimals;
+    let mut logs = vec![
+        log("action", "finalize_deposit"),
+        log("depositor", depositor.clone()),
+    ];
+    let mut received = vec![];
+    let old_balances = reserves.clone();
+    let mut new_balances = reserves.clone();
+    for (i, token) in supported_tokens.iter().enumerate() {
+        // Only the deposited assets are queried, to measure what arrived
+        if let Some(deposit) = deposits.iter().find(|d| d.address == token.address) {
+            let balance = query_lp_balance(deps, &supported_tokens, i, &env.contract.address)?;
+            let amount = received_amount(balance, reserves[i], deposit.amount);
+            new_balances[i] += amount;
+            // Anything else sent to the pool is left to the LPs
+            reserves[i] = balance;
+            let value = normalize(amount, token.decimals).ok_or_else(|| {
+                StdError::generic_err(format!(
+                    "Cannot normalize token deposit for {} decimals: {:?}",
+                    NORMALIZED_DECIMALS, deposit
+                ))
+            })?;
+            // "<amount> <token> <value normalized to 18 decimals>"
+            logs.push(log(
+                "deposited",
+                format!("{} {} {}", amount, token.address, value),
+            ));
+            received.push(TokenAmount {
+                address: token.address.clone(),
+                code_hash: token.code_hash.clone(),
+                amount,
+            });
+        }
+    }
 
-        let factor: u128 = 10u128.pow(18 - decimals as u32); // not checked_pow because 10^18 < 2^128
+    if received.iter().all(|r| r.amount == Uint128::zero()) {
+        return Err(StdError::generic_err(
+            "No tokens were received for the deposit",
+        ));
+    }
 
-        let normalized_deposit: u128 =
-            token_deposit
-                .amount
-                .u128()
-                .checked_mul(factor)
-                .ok_or_else(|| {
-                    StdError::generic_err(format!(
-                        "Cannot normalize token deposit for 18 decimals: {:?}",
-                        token_deposit
-                    ))
-                })?;
+    // The bonus moves admin fees into the pool, so the LP minted for it dilutes no one
+    let mut admin_fees = read_admin_fees(&deps.storage)?;
+    let bonuses = deposit_bonus(
+        &config,
+        &supported_tokens,
+        &old_balances,
+        &new_balances,
+        &admin_fees,
+    )?;
+    for (i, bonus) in bonuses.iter().enumerate() {
+        if *bonus == Uint128::zero() {
+            continue;
+        }
+        new_balances[i] += *bonus;
+        reserves[i] += *bonus;
+        admin_fees[i] = (admin_fees[i] - *bonus)?;
+        logs.push(log(
+            "bonus",
+            format!("{} {}", bonus, supported_tokens[i].address),
+        ));
+    }
+    if cancel_if_no_bonus && bonuses.iter().all(|bonus| *bonus == Uint128::zero()) {
+        return Err(ContractError::NoDepositBonus.into());
+    }
 
-        share += normalized_deposit.into();
+    let old_balances = normalize_balances(&old_balances, &supported_tokens)?;
+    let new_balances = normalize_balances(&new_balances, &supported_tokens)?;
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let share = deposit_share(&config, &old_balances, &new_balances, total_supply)?;
+    if share == Uint128::zero() {
+        return Err(StdError::generic_err("Deposit would mint no LP tokens"));
     }
+    let share = share_after_bonus(&config, &old_balances, &new_balances, share, total_supply)?;
+    check_min_lp_out(share, min_lp_out)?;
 
-    messages.push(snip20_utils::mint_msg(
-        env.message.sender,
+    store_reserves(&mut deps.storage, &reserves)?;
+    store_admin_fees(&mut deps.storage, &admin_fees)?;
+    update_seed_mode(&mut deps.storage, &mut config, &supported_tokens, &reserves)?;
+    add_lp_flows(&mut deps.storage, share, Uint128::zero())?;
+    add_position_flows(
+        &mut deps.storage,
+        &depositor,
         share,
-        None,
-        256,
-        config.lp_token_code_hash,
-        config.lp_token_address,
-    )?);
+        Uint128::zero(),
+        &received,
+        &[],
+    )?;
+    exit_lock(&mut deps.storage)?;
+
     logs.push(log("share", share.to_string()));
 
     Ok(HandleResponse {
-        messages,
+        messages: vec![snip20_utils::mint_msg(
+            depositor,
+            share,
+            None,
+            256,
+            config.lp_token_code_hash,
+            config.lp_token_address,
+        )?],
         log: logs,
-        data: None,
+        data: Some(to_binary(&ProvideLiquidityResponse {
+            lp_amount: share,
+            deposits: received,
+        })?),
     })
 }
 
-pub fn try_withdraw_liquidity<S: Storage, A: Api, Q: Querier>(
+pub fn try_zap_in<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    sender: HumanAddr,
+    depositor: HumanAddr,
     amount: Uint128,
+    min_lp_out: Option<Uint128>,
 ) -> HandleResult {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    let liquidity_addr: HumanAddr = deps.api.human_address(&pair_info.liquidity_token)?;
+    let mut config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    // The deposit is priced on a swap
+    check_deposits_open(&config)?;
+    check_swaps_open(&config)?;
+    record_observations(deps, &config, env.block.time)?;
 
-    let pools: [Token; 2] = pair_info.query_pools(&deps, &env.contract.address)?;
-    let total_share: Uint128 =
-        query_token_total_supply(&deps, &liquidity_addr, &pair_info.token_code_hash)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_index = supported_tokens
+        .iter()
+        .position(|t| t.address == env.message.sender)
+        .ok_or_else(|| ContractError::UnknownSourceAsset(env.message.sender.clone()))?;
+    let src_token = &supported_tokens[src_index];
+    if !src_token.enabled {
+        return Err(ContractError::AssetDisabled(src_token.address.clone()).into());
+    }
+    check_not_withdraw_only(src_token)?;
 
-    let refund_assets: Vec<Token> = pools
+    let reserves = read_reserves(&deps.storage)?;
+    let src_balance = query_lp_balance(deps, &supported_tokens, src_index, &env.contract.address)?;
+    let amount = received_amount(src_balance, reserves[src_index], amount);
+    if amount == Uint128::zero() {
+        return Err(StdError::generic_err(
+            "No tokens were received for the deposit",
+        ));
+    }
+    // Split by the reserves from before the deposit
+    let normalized_balances = normalize_balances(&reserves, &supported_tokens)?;
+    let split_total = supported_tokens
         .iter()
-        .map(|a| {
-            // withdrawn_asset_amount = a.amount * amount / total_share
+        .zip(normalized_balances.iter())
+        .filter(|(token, _)| token.enabled && !token.withdraw_only)
+        .try_fold(U256::zero(), |total, (_, balance)| {
+            total.checked_add(*balance)
+        });
+
+    let mut logs = vec![log("action", "zap_in"), log("depositor", depositor.clone())];
+    // Each leg is swapped on the pool as the previous ones left it, as in a Batch
+    let mut balances = reserves.clone();
+    let mut admin_fees = read_admin_fees(&deps.storage)?;
+    let mut volumes = read_volumes(&deps.storage)?;
+    let mut swapped = Uint128::zero();
+    let mut deposits = vec![];
+    let mut src_position = 0;
+    // An empty pool has nothing to balance against, so the whole amount is deposited as is
+    if split_total != Some(U256::zero()) {
+        for (i, token) in supported_tokens.iter().enumerate() {
+            if i == src_index {
+                src_position = deposits.len();
+                continue;
+            }
+            if !token.enabled || token.withdraw_only {
+                continue;
+            }
+            let swap_in = u256_to_uint128(div(
+                mul(
+                    Some(U256::from(amount.u128())),
+                    Some(normalized_balances[i]),
+                ),
+                split_total,
+            ))
+            .ok_or_else(|| StdError::generic_err("Cannot split the zap deposit"))?;
+            if swap_in == Uint128::zero() {
+                continue;
+            }
 
-            let current_pool_amount = Some(U256::from(a.amount.u128()));
-            let withdrawn_share_amount = Some(U256::from(amount.u128()));
-            let total_share = Some(U256::from(total_share.u128()));
+            if config.depeg_threshold_bps > 0 {
+                check_depeg(
+                    &config,
+                    &supported_tokens,
+                    &balances,
+                    src_token,
+                    token,
+                    swap_in,
+                )?;
+            }
+            let simulation = simulate_swap_at(
+                &config,
+                &supported_tokens,
+                &balances,
+                src_token,
+                token,
+                swap_in,
+            )?;
+            let swap_out = simulation.return_amount;
+            if config.max_swap_out_bps > 0 {
+                let max_out = balances[i].multiply_ratio(config.max_swap_out_bps, MAX_BPS);
+                if swap_out > max_out {
+                    return Err(StdError::generic_err(format!(
+                        "Swap output {} exceeds the max of {} ({} bps of the {:?} reserve)",
+                        swap_out, max_out, config.max_swap_out_bps, token.address
+                    )));
+                }
+            }
+            let admin_fee = admin_fee_amount(&config, simulation.fee_amount, Uint128::zero());
+            check_min_reserve(&config, token, balances[i], swap_out + admin_fee)?;
 
-            let withdrawn_asset_amount = div(
-                mul(current_pool_amount, withdrawn_share_amount),
-                total_share,
-            )
-                .ok_or_else(|| {
-                    StdError::generic_err(format!(
-                    "Cannot calculate current_pool_amount {} * withdrawn_share_amount {} / total_share {}",
-                    a.amount,
-                    amount,
-                    total_share.unwrap()
-                    ))
-                })?;
+            balances[src_index] += swap_in;
+            balances[i] = (balances[i] - (swap_out + admin_fee)).map_err(|_| {
+                StdError::generic_err(format!("Not enough liquidity of {:?}", token.address))
+            })?;
+            admin_fees[i] += admin_fee;
+            add_volume(&mut volumes, &src_token.address, swap_in, Uint128::zero());
+            add_volume(&mut volumes, &token.address, Uint128::zero(), swap_out);
+            logs.push(log(
+                "swapped",
+                format!(
+                    "{} {} {} {}",
+                    swap_in, src_token.address, swap_out, token.address
+                ),
+            ));
+            swapped += swap_in;
+            deposits.push(TokenAmount {
+                address: token.address.clone(),
+                code_hash: token.code_hash.clone(),
+                amount: swap_out,
+            });
+        }
+    }
+    deposits.insert(
+        src_position,
+        TokenAmount {
+            address: src_token.address.clone(),
+            code_hash: src_token.code_hash.clone(),
+            amount: (amount - swapped)?,
+        },
+    );
 
-            Ok(Token {
-                info: a.info.clone(),
-                amount: Uint128(withdrawn_asset_amount.low_u128()),
-            })
-        })
-        .collect::<StdResult<Vec<Token>>>()?;
+    // The rest of the amount and the swap outputs are deposited together, on the pool as the
+    // swaps left it
+    let old_balances = normalize_balances(&balances, &supported_tokens)?;
+    let new_balances = normalize_balances(
+        &balances_after_deposits(&balances, &supported_tokens, &deposits),
+        &supported_tokens,
+    )?;
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let share = deposit_share(&config, &old_balances, &new_balances, total_supply)?;
+    if share == Uint128::zero() {
+        return Err(StdError::generic_err("Deposit would mint no LP tokens"));
+    }
+    let share = share_after_bonus(&config, &old_balances, &new_balances, share, total_supply)?;
+    check_min_lp_out(share, min_lp_out)?;
+    check_deposit_caps(
+        &config,
+        &supported_tokens,
+        &new_balances,
+        // The swap outputs never leave the pool, only the source asset grows
+        &deposits[src_position..=src_position],
+    )?;
+    if config.max_volume_per_block > Uint128::zero() && swapped > Uint128::zero() {
+        add_block_volume(
+            &mut deps.storage,
+            &config,
+            env.block.height,
+            src_token,
+            swapped,
+        )?;
+    }
+
+    enter_lock(&mut deps.storage)?;
+    store_volumes(&mut deps.storage, &volumes)?;
+    // Net of the swaps and the deposit, the source reserve grows by the amount and the others
+    // only lose their admin fees. Anything else sent to the pool is left to the LPs.
+    let mut reserves = balances_after_deposits(&balances, &supported_tokens, &deposits);
+    reserves[src_index] = src_balance;
+    store_reserves(&mut deps.storage, &reserves)?;
+    store_admin_fees(&mut deps.storage, &admin_fees)?;
+    update_seed_mode(&mut deps.storage, &mut config, &supported_tokens, &reserves)?;
+    add_lp_flows(&mut deps.storage, share, Uint128::zero())?;
+    // The depositor only parted with the source asset
+    add_position_flows(
+        &mut deps.storage,
+        &depositor,
+        share,
+        Uint128::zero(),
+        &[TokenAmount {
+            address: src_token.address.clone(),
+            code_hash: src_token.code_hash.clone(),
+            amount,
+        }],
+        &[],
+    )?;
+    exit_lock(&mut deps.storage)?;
+
+    logs.push(log("share", share.to_string()));
 
-    // update pool info
     Ok(HandleResponse {
-        messages: vec![
-            // refund asset tokens
-            refund_assets[0].clone().into_msg(
-                deps,
-                env.contract.address.clone(),
-                sender.clone(),
-            )?,
-            refund_assets[1].clone().into_msg(
-                deps,
-                env.contract.address.clone(),
-                sender.clone(),
-            )?,
-            // burn liquidity token
-            snip20_utils::burn_msg(
-                amount,
-                None,
-                256,
-                pair_info.token_code_hash,
-                deps.api.human_address(&pair_info.liquidity_token)?,
-            )?,
-        ],
-        log: vec![
-            log("action", "withdraw_liquidity"),
-            log("withdrawn_share", &amount.to_string()),
-            log(
-                "refund_assets",
-                format!("{}, {}", refund_assets[0].clone(), refund_assets[1].clone()),
-            ),
-        ],
-        data: None,
+        messages: vec![snip20_utils::mint_msg(
+            depositor,
+            share,
+            None,
+            256,
+            config.lp_token_code_hash,
+            config.lp_token_address,
+        )?],
+        log: logs,
+        data: Some(to_binary(&ProvideLiquidityResponse {
+            lp_amount: share,
+            deposits,
+        })?),
     })
 }
 
-// CONTRACT - a user must do token approval
-pub fn try_swap<S: Storage, A: Api, Q: Querier>(
+/// Deposits the `amount` of a pool token sent with Snip20ReceiveMsg::Deposit
+pub fn try_receive_deposit<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    src_amount: Uint128,
-    src_token: HumanAddr,
-    dst_token: HumanAddr,
-    recipient: HumanAddr,
+    depositor: HumanAddr,
+    amount: Uint128,
+    min_lp_out: Option<Uint128>,
 ) -> HandleResult {
-    let supported_tokens = read_all_assets(&deps.storage)?;
+    let mut config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    check_deposits_open(&config)?;
+    record_observations(deps, &config, env.block.time)?;
 
-    let src_token = supported_tokens
-        .iter()
-        .find(|t| t.address == src_token)
-        .unwrap() /* this was checked before going into try_swap */;
-    let dst_token = supported_tokens
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
         .iter()
-        .find(|t| t.address == dst_token)
-        .unwrap() /* this was checked before going into try_swap */;
+        .position(|t| t.address == env.message.sender)
+        .ok_or_else(|| ContractError::UnknownSourceAsset(env.message.sender.clone()))?;
+    let token = &supported_tokens[index];
+    if !token.enabled {
+        return Err(ContractError::AssetDisabled(token.address.clone()).into());
+    }
+    check_not_withdraw_only(token)?;
 
-    // Normalize amount (due to decimals differences)
-    let mut dst_amount = src_amount.u128();
-    if src_token.decimals > dst_token.decimals {
-        let factor: u128 = 10u128.pow((src_token.decimals - dst_token.decimals) as u32);
-        dst_amount = dst_amount / factor;
-    } else if dst_token.decimals > src_token.decimals {
-        let factor: u128 = 10u128.pow((dst_token.decimals - src_token.decimals) as u32);
-        dst_amount = dst_amount * factor;
+    let mut reserves = read_reserves(&deps.storage)?;
+    let balance = query_lp_balance(deps, &supported_tokens, index, &env.contract.address)?;
+    let amount = received_amount(balance, reserves[index], amount);
+    if amount == Uint128::zero() {
+        return Err(StdError::generic_err(
+            "No tokens were received for the deposit",
+        ));
     }
+    let value = normalize(amount, token.decimals)
+        .ok_or_else(|| StdError::generic_err("Cannot normalize the deposit"))?;
 
-    // Take fee
-    let config = read_config(&deps.storage)?;
+    let old_balances = normalize_balances(&reserves, &supported_tokens)?;
+    let mut new_balances = reserves.clone();
+    new_balances[index] += amount;
+    let new_balances = normalize_balances(&new_balances, &supported_tokens)?;
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let share = deposit_share(&config, &old_balances, &new_balances, total_supply)?;
+    if share == Uint128::zero() {
+        return Err(StdError::generic_err("Deposit would mint no LP tokens"));
+    }
+    let share = share_after_bonus(&config, &old_balances, &new_balances, share, total_supply)?;
+    check_min_lp_out(share, min_lp_out)?;
+    let deposits = vec![TokenAmount {
+        address: token.address.clone(),
+        code_hash: token.code_hash.clone(),
+        amount,
+    }];
+    check_deposit_caps(&config, &supported_tokens, &new_balances, &deposits)?;
 
-    dst_amount = dst_amount * config.swap_fee_nom.u128() / config.swap_fee_denom.u128();
+    let logs = vec![
+        log("action", "deposit"),
+        log("depositor", depositor.clone()),
+        // "<amount> <token> <value normalized to 18 decimals>"
+        log(
+            "deposited",
+            format!("{} {} {}", amount, token.address, value),
+        ),
+        log("share", share.to_string()),
+    ];
 
-    // TODO
+    enter_lock(&mut deps.storage)?;
+    // Anything else sent to the pool is left to the LPs
+    reserves[index] = balance;
+    store_reserves(&mut deps.storage, &reserves)?;
+    update_seed_mode(&mut deps.storage, &mut config, &supported_tokens, &reserves)?;
+    add_lp_flows(&mut deps.storage, share, Uint128::zero())?;
+    add_position_flows(
+        &mut deps.storage,
+        &depositor,
+        share,
+        Uint128::zero(),
+        &deposits,
+        &[],
+    )?;
+    exit_lock(&mut deps.storage)?;
 
-    let mut messages = Vec::<CosmosMsg>::new();
-    messages.push(return_asset.clone().into_msg(
-        &deps,
-        env.contract.address.clone(),
-        to.clone().unwrap_or(sender.clone()),
+    Ok(HandleResponse {
+        messages: vec![snip20_utils::mint_msg(
+            depositor,
+            share,
+            None,
+            256,
+            config.lp_token_code_hash,
+            config.lp_token_address,
+        )?],
+        log: logs,
+        data: Some(to_binary(&ProvideLiquidityResponse {
+            lp_amount: share,
+            deposits,
+        })?),
+    })
+}
+
+/// Reduces the `share` minted for a deposit that took the pool from `old_balances` to
+/// `new_balances`, with `total_supply` LP out before it, so that rebalance_bonus_to_lp_bps of
+/// its bonus stays with the existing LPs. The minted LP is then worth the deposited value plus
+/// the rest of the bonus.
+fn share_after_bonus(
+    config: &Config,
+    old_balances: &[U256],
+    new_balances: &[U256],
+    share: Uint128,
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    // There's no one to share with in an empty pool
+    if config.rebalance_bonus_to_lp_bps == 0 || total_supply == Uint128::zero() {
+        return Ok(share);
+    }
+
+    let overflow = || StdError::generic_err("Cannot compute the deposit bonus");
+    let sum = |balances: &[U256]| {
+        balances
+            .iter()
+            .try_fold(U256::zero(), |acc, x| acc.checked_add(*x))
+    };
+    let deposited_value = sub(sum(new_balances), sum(old_balances)).ok_or_else(overflow)?;
+    let d = get_d(config.amp, new_balances)?;
+    let supply = U256::from(total_supply.u128());
+    let share_value = div(
+        mul(Some(U256::from(share.u128())), Some(d)),
+        add(Some(supply), Some(U256::from(share.u128()))),
+    )
+    .ok_or_else(overflow)?;
+    if share_value <= deposited_value {
+        return Ok(share);
+    }
+
+    // Mint m so that m * D / (supply + m) = target, i.e. m = target * supply / (D - target)
+    let kept = div(
+        mul(
+            Some(share_value - deposited_value),
+            Some(U256::from(config.rebalance_bonus_to_lp_bps)),
+        ),
+        Some(U256::from(MAX_BPS)),
+    );
+    let target = sub(Some(share_value), kept);
+    let reduced = u256_to_uint128(div(mul(target, Some(supply)), sub(Some(d), target)))
+        .ok_or_else(overflow)?;
+
+    Ok(if reduced < share { reduced } else { share })
+}
+
+/// `balances` with the amounts of `deposits` added
+fn balances_after_deposits(
+    balances: &[Uint128],
+    supported_tokens: &[TokenInfo],
+    deposits: &[TokenAmount],
+) -> Vec<Uint128> {
+    let mut new_balances = balances.to_vec();
+    for (i, token) in supported_tokens.iter().enumerate() {
+        if let Some(deposit) = deposits.iter().find(|d| d.address == token.address) {
+            new_balances[i] += deposit.amount;
+        }
+    }
+    new_balances
+}
+
+/// deposit_bonus_bps of what a deposit taking the pool from `old_balances` to `new_balances`
+/// adds to each asset below the mean normalized balance, up to the mean, capped by the asset's
+/// `admin_fees`. In the assets' own decimals, zero where no bonus applies.
+fn deposit_bonus(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    old_balances: &[Uint128],
+    new_balances: &[Uint128],
+    admin_fees: &[Uint128],
+) -> StdResult<Vec<Uint128>> {
+    if config.deposit_bonus_bps == 0 {
+        return Ok(vec![Uint128::zero(); supported_tokens.len()]);
+    }
+
+    let overflow = || StdError::generic_err("Cannot compute the deposit bonus");
+    let old_balances = normalize_balances(old_balances, supported_tokens)?;
+    let new_balances = normalize_balances(new_balances, supported_tokens)?;
+    let mean = div(
+        old_balances
+            .iter()
+            .try_fold(U256::zero(), |acc, x| acc.checked_add(*x)),
+        Some(U256::from(old_balances.len())),
+    )
+    .ok_or_else(overflow)?;
+
+    let mut bonuses = vec![];
+    for (i, token) in supported_tokens.iter().enumerate() {
+        let eligible = new_balances[i].min(mean).saturating_sub(old_balances[i]);
+        let bonus = div(
+            mul(Some(eligible), Some(U256::from(config.deposit_bonus_bps))),
+            Some(U256::from(MAX_BPS)),
+        )
+        .and_then(|bonus| denormalize(bonus, token.decimals))
+        .ok_or_else(overflow)?;
+        bonuses.push(if bonus < admin_fees[i] {
+            bonus
+        } else {
+            admin_fees[i]
+        });
+    }
+
+    Ok(bonuses)
+}
+
+/// Fails if `balances`, the normalized pool balances after `deposits`, put a deposited asset
+/// over per_asset_deposit_cap or the pool over total_tvl_cap
+fn check_deposit_caps(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[U256],
+    deposits: &[TokenAmount],
+) -> StdResult<()> {
+    if config.per_asset_deposit_cap > Uint128::zero() {
+        let cap = U256::from(config.per_asset_deposit_cap.u128());
+        for (token, balance) in supported_tokens.iter().zip(balances) {
+            // An asset already over a lowered cap only blocks deposits of that asset
+            let deposited = deposits
+                .iter()
+                .any(|d| d.address == token.address && d.amount > Uint128::zero());
+            if deposited && *balance > cap {
+                return Err(StdError::generic_err(format!(
+                    "Deposit would take {} to {}, over the per-asset cap of {}",
+                    token.address, balance, cap
+                )));
+            }
+        }
+    }
+    if config.total_tvl_cap > Uint128::zero() {
+        let tvl = balances
+            .iter()
+            .try_fold(U256::zero(), |acc, x| acc.checked_add(*x))
+            .ok_or_else(|| StdError::generic_err("Cannot sum pool balances"))?;
+        if tvl > U256::from(config.total_tvl_cap.u128()) {
+            return Err(StdError::generic_err(format!(
+                "Deposit would take the pool to {}, over the TVL cap of {}",
+                tvl, config.total_tvl_cap
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Fails if `share` LP tokens are less than `min_lp_out`
+fn check_min_lp_out(share: Uint128, min_lp_out: Option<Uint128>) -> StdResult<()> {
+    if let Some(min_lp_out) = min_lp_out {
+        if share < min_lp_out {
+            return Err(ContractError::LpOutBelowMinimum { share, min_lp_out }.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Amount received since `reserve` was recorded, up to `expected`. Tokens sent to the pool
+/// directly stay in it for the LPs instead of being credited to the next deposit or swap.
+fn received_amount(balance: Uint128, reserve: Uint128, expected: Uint128) -> Uint128 {
+    let received = (balance - reserve).unwrap_or_else(|_| Uint128::zero());
+    if received < expected {
+        received
+    } else {
+        expected
+    }
+}
+
+/// LP to mint for a deposit that takes the pool from `old_balances` to `new_balances`, both
+/// normalized, with `total_supply` LP out before it. Proportional to the growth of the
+/// invariant, so an imbalanced deposit only gets what it adds to D, less the imbalance fee on
+/// how far it is from a balanced one. The first deposit mints D. A deposit of an asset the pool
+/// held none of brings it into the invariant, and is valued by how much that grows D.
+fn deposit_share(
+    config: &Config,
+    old_balances: &[U256],
+    new_balances: &[U256],
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    let overflow = || StdError::generic_err("Cannot compute the deposit share");
+    let old_value = get_d(config.amp, old_balances)?;
+    if total_supply == Uint128::zero() || old_value.is_zero() {
+        return u256_to_uint128(Some(get_d(config.amp, new_balances)?)).ok_or_else(overflow);
+    }
+
+    let (fee_nom, fee_denom) = imbalance_fee_ratio(config);
+    let new_value = get_d(
+        config.amp,
+        &balances_after_imbalance_fee(config.amp, old_balances, new_balances, fee_nom, fee_denom)?,
+    )?;
+    u256_to_uint128(div(
+        mul(
+            Some(U256::from(total_supply.u128())),
+            Some(new_value.saturating_sub(old_value)),
+        ),
+        Some(old_value),
+    ))
+    .ok_or_else(overflow)
+}
+
+/// Fee on the imbalanced part of a deposit, as (nom, denom): the swap fee, or the base rate
+/// when dynamic fees are on
+fn imbalance_fee_ratio(config: &Config) -> (U256, U256) {
+    if config.dynamic_fee {
+        (U256::from(config.dynamic_fee_base_bps), U256::from(MAX_BPS))
+    } else {
+        (
+            U256::from(config.swap_fee_nom.u128()),
+            U256::from(config.swap_fee_denom.u128()),
+        )
+    }
+}
+
+pub fn try_withdraw_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    min_amounts: Option<Vec<Uint128>>,
+    unwrap: bool,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    if config.status == PoolStatus::Halted {
+        return Err(ContractError::Halted.into());
+    }
+    record_observations(deps, &config, env.block.time)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let mut reserves = read_reserves(&deps.storage)?;
+    if let Some(min_amounts) = &min_amounts {
+        if min_amounts.len() != supported_tokens.len() {
+            return Err(StdError::generic_err(format!(
+                "Expected {} min amounts, got {}",
+                supported_tokens.len(),
+                min_amounts.len()
+            )));
+        }
+    }
+
+    let total_share: Uint128 =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+
+    let mut messages = vec![];
+    let mut logs = vec![
+        log("action", "withdraw_liquidity"),
+        log("withdrawn_share", amount.to_string()),
+    ];
+    let mut refunds = vec![];
+    // Disabled assets are still refunded, so LPs can always exit in full
+    for (i, token) in supported_tokens.iter().enumerate() {
+        let pool_amount = reserves[i];
+
+        // withdrawn_asset_amount = pool_amount * amount / total_share
+        let withdrawn_asset_amount = div(
+            mul(
+                Some(U256::from(pool_amount.u128())),
+                Some(U256::from(amount.u128())),
+            ),
+            Some(U256::from(total_share.u128())),
+        )
+        .ok_or_else(|| {
+            StdError::generic_err(format!(
+                "Cannot calculate pool_amount {} * withdrawn_share_amount {} / total_share {}",
+                pool_amount, amount, total_share
+            ))
+        })?;
+        let withdrawn_asset_amount =
+            round_down_answer(&config, token, Uint128(withdrawn_asset_amount.low_u128()))?;
+        if let Some(min_amounts) = &min_amounts {
+            if withdrawn_asset_amount < min_amounts[i] {
+                return Err(ContractError::RefundBelowMinimum {
+                    amount: withdrawn_asset_amount,
+                    token: token.address.clone(),
+                    min_amount: min_amounts[i],
+                }
+                .into());
+            }
+        }
+
+        messages.extend(payout_msgs(
+            token,
+            &env.contract.address,
+            sender.clone(),
+            withdrawn_asset_amount,
+            unwrap && token.wrapped_native,
+        )?);
+        logs.push(log(
+            "refund_asset",
+            format!("{} {}", withdrawn_asset_amount, token.address),
+        ));
+        refunds.push(TokenAmount {
+            address: token.address.clone(),
+            code_hash: token.code_hash.clone(),
+            amount: withdrawn_asset_amount,
+        });
+        reserves[i] = (pool_amount - withdrawn_asset_amount)?;
+    }
+
+    enter_lock(&mut deps.storage)?;
+    store_reserves(&mut deps.storage, &reserves)?;
+    add_lp_flows(&mut deps.storage, Uint128::zero(), amount)?;
+    add_position_flows(
+        &mut deps.storage,
+        &sender,
+        Uint128::zero(),
+        amount,
+        &[],
+        &refunds,
+    )?;
+
+    // burn liquidity token
+    messages.push(snip20_utils::burn_msg(
+        amount,
+        None,
+        256,
+        config.lp_token_code_hash,
+        config.lp_token_address,
+    )?);
+
+    exit_lock(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: Some(to_binary(&WithdrawLiquidityResponse { refunds })?),
+    })
+}
+
+/// Burns `amount` LP for a payout in `token` alone. Priced like Curve's
+/// remove_liquidity_one_coin, so it pays the imbalance fee on top of the slippage.
+pub fn try_withdraw_liquidity_one_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    token: HumanAddr,
+    min_amount: Uint128,
+    unwrap: bool,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    if config.status == PoolStatus::Halted {
+        return Err(ContractError::Halted.into());
+    }
+    record_observations(deps, &config, env.block.time)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
+        .iter()
+        .position(|t| t.address == token)
+        .ok_or_else(|| ContractError::UnknownDestinationAsset(token.clone()))?;
+    let token_info = &supported_tokens[index];
+    // Priced like a swap into it, which disabled assets don't take
+    if !token_info.enabled {
+        return Err(ContractError::AssetDisabled(token).into());
+    }
+
+    let total_share: Uint128 =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    if amount >= total_share {
+        return Err(StdError::generic_err(
+            "The last LP tokens can only be withdrawn in every asset",
+        ));
+    }
+    let mut reserves = read_reserves(&deps.storage)?;
+    let balances = normalize_balances(&reserves, &supported_tokens)?;
+    if balances[index].is_zero() {
+        return Err(ContractError::EmptyAsset(token).into());
+    }
+
+    let (fee_nom, fee_denom) = imbalance_fee_ratio(&config);
+    let (withdrawn, fee) = withdraw_one_coin(
+        config.amp,
+        &balances,
+        index,
+        U256::from(amount.u128()),
+        U256::from(total_share.u128()),
+        fee_nom,
+        fee_denom,
+    )?;
+    let overflow = || StdError::generic_err("Cannot compute the single-token withdrawal");
+    let withdrawn = denormalize(withdrawn, token_info.decimals).ok_or_else(overflow)?;
+    let withdrawn = round_down_answer(&config, token_info, withdrawn)?;
+    let fee = denormalize(fee, token_info.decimals).ok_or_else(overflow)?;
+    if withdrawn < min_amount {
+        return Err(ContractError::RefundBelowMinimum {
+            amount: withdrawn,
+            token,
+            min_amount,
+        }
+        .into());
+    }
+
+    let refunds = vec![TokenAmount {
+        address: token.clone(),
+        code_hash: token_info.code_hash.clone(),
+        amount: withdrawn,
+    }];
+    add_position_flows(
+        &mut deps.storage,
+        &sender,
+        Uint128::zero(),
+        amount,
+        &[],
+        &refunds,
+    )?;
+    let mut messages = payout_msgs(token_info, &env.contract.address, sender, withdrawn, unwrap)?;
+    messages.push(snip20_utils::burn_msg(
+        amount,
+        None,
+        256,
+        config.lp_token_code_hash,
+        config.lp_token_address,
     )?);
+    let logs = vec![
+        log("action", "withdraw_liquidity_one_token"),
+        log("withdrawn_share", amount.to_string()),
+        log("refund_asset", format!("{} {}", withdrawn, token)),
+        log("fee_amount", fee.to_string()),
+    ];
+
+    enter_lock(&mut deps.storage)?;
+    reserves[index] = (reserves[index] - withdrawn)?;
+    store_reserves(&mut deps.storage, &reserves)?;
+    add_lp_flows(&mut deps.storage, Uint128::zero(), amount)?;
+    exit_lock(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: Some(to_binary(&WithdrawLiquidityResponse { refunds })?),
+    })
+}
+
+/// Pays out exactly `amounts` for at most `max_burn` of the `amount` LP sent. Priced like
+/// Curve's remove_liquidity_imbalance, the burn is the share of the invariant the withdrawal
+/// takes after the imbalance fee. Unburned LP is sent back.
+pub fn try_withdraw_imbalanced<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    amounts: Vec<Uint128>,
+    max_burn: Uint128,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    if config.status == PoolStatus::Halted {
+        return Err(ContractError::Halted.into());
+    }
+    record_observations(deps, &config, env.block.time)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    if amounts.len() != supported_tokens.len() {
+        return Err(StdError::generic_err(format!(
+            "Expected {} amounts, got {}",
+            supported_tokens.len(),
+            amounts.len()
+        )));
+    }
+    if amounts.iter().all(|a| a.is_zero()) {
+        return Err(StdError::generic_err("Nothing to withdraw"));
+    }
+
+    let mut reserves = read_reserves(&deps.storage)?;
+    let old_balances = normalize_balances(&reserves, &supported_tokens)?;
+    for ((token, withdrawn), balance) in supported_tokens
+        .iter()
+        .zip(&amounts)
+        .zip(reserves.iter_mut())
+    {
+        if withdrawn.is_zero() {
+            continue;
+        }
+        // Priced like a swap into it, which disabled assets don't take
+        if !token.enabled {
+            return Err(ContractError::AssetDisabled(token.address.clone()).into());
+        }
+        if withdrawn >= balance {
+            return Err(StdError::generic_err(format!(
+                "Withdrawal would leave no {} in the pool",
+                token.address
+            )));
+        }
+        *balance = (*balance - *withdrawn)?;
+    }
+    let new_balances = normalize_balances(&reserves, &supported_tokens)?;
+
+    // burn = supply * (D0 - D2) / D0, rounded up, where D2 is the invariant after the fee
+    let total_share: Uint128 =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let (fee_nom, fee_denom) = imbalance_fee_ratio(&config);
+    let d0 = get_d(config.amp, &old_balances)?;
+    let d2 = get_d(
+        config.amp,
+        &balances_after_imbalance_fee(
+            config.amp,
+            &old_balances,
+            &new_balances,
+            fee_nom,
+            fee_denom,
+        )?,
+    )?;
+    let burn = u256_to_uint128(div_ceil(
+        mul(
+            Some(U256::from(total_share.u128())),
+            Some(d0.saturating_sub(d2)),
+        ),
+        Some(d0),
+    ))
+    .ok_or_else(|| StdError::generic_err("Cannot compute the LP to burn"))?;
+    if burn > max_burn {
+        return Err(ContractError::BurnAboveMaximum { burn, max_burn }.into());
+    }
+    if burn > amount {
+        return Err(StdError::generic_err(format!(
+            "Withdrawal would burn {} LP tokens, more than the {} sent",
+            burn, amount
+        )));
+    }
+
+    let mut messages = vec![];
+    let mut logs = vec![
+        log("action", "withdraw_imbalanced"),
+        log("withdrawn_share", burn.to_string()),
+    ];
+    let mut refunds = vec![];
+    for (token, withdrawn) in supported_tokens.iter().zip(&amounts) {
+        if withdrawn.is_zero() {
+            continue;
+        }
+        messages.push(transfer_asset_msg(
+            token,
+            &env.contract.address,
+            sender.clone(),
+            *withdrawn,
+        )?);
+        logs.push(log(
+            "refund_asset",
+            format!("{} {}", withdrawn, token.address),
+        ));
+        refunds.push(TokenAmount {
+            address: token.address.clone(),
+            code_hash: token.code_hash.clone(),
+            amount: *withdrawn,
+        });
+    }
+    let unburned = (amount - burn)?;
+    add_position_flows(
+        &mut deps.storage,
+        &sender,
+        Uint128::zero(),
+        burn,
+        &[],
+        &refunds,
+    )?;
+    if unburned > Uint128::zero() {
+        messages.push(snip20_utils::transfer_msg(
+            sender,
+            unburned,
+            None,
+            256,
+            config.lp_token_code_hash.clone(),
+            config.lp_token_address.clone(),
+        )?);
+    }
+    messages.push(snip20_utils::burn_msg(
+        burn,
+        None,
+        256,
+        config.lp_token_code_hash,
+        config.lp_token_address,
+    )?);
+
+    enter_lock(&mut deps.storage)?;
+    store_reserves(&mut deps.storage, &reserves)?;
+    add_lp_flows(&mut deps.storage, Uint128::zero(), burn)?;
+    exit_lock(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: Some(to_binary(&WithdrawLiquidityResponse { refunds })?),
+    })
+}
+
+pub fn try_swap_native<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    to_token: HumanAddr,
+    recipient: Option<HumanAddr>,
+    referrer: Option<HumanAddr>,
+    referral_fee_bps: Option<u32>,
+) -> HandleResult {
+    if env.message.sent_funds.len() != 1 {
+        return Err(StdError::generic_err(
+            "Exactly one native coin must be sent to swap",
+        ));
+    }
+    let src_token = HumanAddr(env.message.sent_funds[0].denom.clone());
+    let amount = env.message.sent_funds[0].amount;
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let native = supported_tokens
+        .iter()
+        .any(|t| t.native && t.address == src_token);
+    let wrapped = supported_tokens
+        .iter()
+        .find(|t| t.wrapped_native && src_token.as_str() == WRAPPED_NATIVE_DENOM);
+    if !native && wrapped.is_none() {
+        return Err(ContractError::UnknownSourceAsset(src_token).into());
+    }
+    if !supported_tokens.iter().any(|t| t.address == to_token) {
+        return Err(ContractError::UnknownDestinationAsset(to_token).into());
+    }
+
+    let swapper = env.message.sender.clone();
+    let recipient = recipient.unwrap_or_else(|| swapper.clone());
+    let wrapped = match wrapped {
+        Some(wrapped) if !native => wrapped,
+        _ => {
+            return try_swap(
+                deps,
+                env,
+                amount,
+                src_token,
+                to_token,
+                swapper,
+                recipient,
+                referrer.map(|referrer| (referrer, referral_fee_bps)),
+                None,
+                false,
+            )
+        }
+    };
+    if wrapped.address == to_token {
+        return Err(StdError::generic_err(format!(
+            "Wrap {} through {:?} itself instead of swapping",
+            WRAPPED_NATIVE_DENOM, to_token
+        )));
+    }
+
+    // Wrapped first, then swapped from the wrapped asset once it's in the pool's balance
+    Ok(HandleResponse {
+        messages: vec![
+            snip20_utils::deposit_msg(
+                amount,
+                None,
+                256,
+                wrapped.code_hash.clone(),
+                wrapped.address.clone(),
+            )?,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: env.contract.address,
+                callback_code_hash: env.contract_code_hash,
+                msg: to_binary(&HandleMsg::FinalizeWrappedSwap {
+                    token: wrapped.address.clone(),
+                    amount,
+                    to_token,
+                    swapper,
+                    recipient,
+                    referrer,
+                    referral_fee_bps,
+                })?,
+                send: vec![],
+            }),
+        ],
+        log: vec![
+            log("action", "wrap"),
+            log("wrapped", format!("{} {}", amount, wrapped.address)),
+        ],
+        data: None,
+    })
+}
+
+// CONTRACT - a user must do token approval
+/// `referral` is the referrer and the share of the fee in bps it asked for, if any. Fails if
+/// the output is below `min_return`.
+#[allow(clippy::too_many_arguments)]
+pub fn try_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    src_amount: Uint128,
+    src_token: HumanAddr,
+    dst_token: HumanAddr,
+    swapper: HumanAddr,
+    recipient: HumanAddr,
+    referral: Option<(HumanAddr, Option<u32>)>,
+    min_return: Option<Uint128>,
+    unwrap: bool,
+) -> HandleResult {
+    if src_amount == Uint128::zero() {
+        return Err(StdError::generic_err("Cannot swap a zero amount"));
+    }
+    let mut config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    check_swaps_open(&config)?;
+    record_observations(deps, &config, env.block.time)?;
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let src_index = supported_tokens
+        .iter()
+        .position(|t| t.address == src_token)
+        .unwrap() /* this was checked before going into try_swap */;
+    let dst_index = supported_tokens
+        .iter()
+        .position(|t| t.address == dst_token)
+        .unwrap() /* this was checked before going into try_swap */;
+    let src_token = &supported_tokens[src_index];
+    let dst_token = &supported_tokens[dst_index];
+
+    // Swap what actually arrived, which is less than sent for tokens with a transfer fee
+    let mut reserves = read_reserves(&deps.storage)?;
+    let src_balance = query_lp_balance(deps, &supported_tokens, src_index, &env.contract.address)?;
+    let src_amount = received_amount(src_balance, reserves[src_index], src_amount);
+    if src_amount == Uint128::zero() {
+        return Err(StdError::generic_err(
+            "No tokens were received for the swap",
+        ));
+    }
+
+    // Priced on the reserves, which don't include the input yet
+    let balances = reserves.clone();
+
+    if config.depeg_threshold_bps > 0 {
+        check_depeg(
+            &config,
+            &supported_tokens,
+            &balances,
+            src_token,
+            dst_token,
+            src_amount,
+        )?;
+    }
+
+    if config.max_volume_per_block > Uint128::zero() {
+        add_block_volume(
+            &mut deps.storage,
+            &config,
+            env.block.height,
+            src_token,
+            src_amount,
+        )?;
+    }
+
+    // Seed mode is capped on the reserves before this swap, and only waives the admin's fees
+    let seeding = update_seed_mode(&mut deps.storage, &mut config, &supported_tokens, &reserves)?
+        && swapper == config.admin;
+    let simulation = if seeding {
+        let fee_free = Config {
+            swap_fee_nom: Uint128::zero(),
+            dynamic_fee: false,
+            ..config.clone()
+        };
+        simulate_swap_at(
+            &fee_free,
+            &supported_tokens,
+            &balances,
+            src_token,
+            dst_token,
+            src_amount,
+        )?
+    } else {
+        simulate_swap_at(
+            &config,
+            &supported_tokens,
+            &balances,
+            src_token,
+            dst_token,
+            src_amount,
+        )?
+    };
+    let dst_amount = simulation.return_amount;
+    if let Some(min_return) = min_return {
+        if dst_amount < min_return {
+            return Err(ContractError::ReturnBelowMinimum {
+                amount: dst_amount,
+                min_return,
+            }
+            .into());
+        }
+    }
+
+    let dst_balance = balances[dst_index];
+    if config.max_swap_out_bps > 0 {
+        let max_out = dst_balance.multiply_ratio(config.max_swap_out_bps, MAX_BPS);
+        if dst_amount > max_out {
+            return Err(StdError::generic_err(format!(
+                "Swap output {} exceeds the max of {} ({} bps of the {:?} reserve)",
+                dst_amount, max_out, config.max_swap_out_bps, dst_token.address
+            )));
+        }
+    }
+
+    let mut messages = payout_msgs(
+        dst_token,
+        &env.contract.address,
+        recipient.clone(),
+        dst_amount,
+        unwrap,
+    )?;
+    let mut logs = vec![
+        log("action", "swap"),
+        log("src_token", src_token.address.clone()),
+        log("dst_token", dst_token.address.clone()),
+        log("src_amount", src_amount.to_string()),
+        log("dst_amount", dst_amount.to_string()),
+        log("fee_amount", simulation.fee_amount.to_string()),
+    ];
+
+    // The referral cut comes out of the fee, so the swapper gets the same amount either way
+    let mut referral_amount = Uint128::zero();
+    if let Some((referrer, referral_fee_bps)) = referral {
+        let referral_fee_bps = referral_fee_bps.unwrap_or(config.max_referral_fee_bps);
+        if referral_fee_bps > config.max_referral_fee_bps {
+            return Err(StdError::generic_err(format!(
+                "Referral fee of {} bps exceeds the max of {} bps",
+                referral_fee_bps, config.max_referral_fee_bps
+            )));
+        }
+
+        referral_amount = simulation
+            .fee_amount
+            .multiply_ratio(referral_fee_bps, MAX_BPS);
+        if referral_amount > Uint128::zero() {
+            messages.push(transfer_asset_msg(
+                dst_token,
+                &env.contract.address,
+                referrer.clone(),
+                referral_amount,
+            )?);
+        }
+        logs.push(log("referral", format!("{} {}", referral_amount, referrer)));
+    }
+    let admin_fee = admin_fee_amount(&config, simulation.fee_amount, referral_amount);
+    logs.push(log("admin_fee", admin_fee.to_string()));
+    check_min_reserve(
+        &config,
+        dst_token,
+        dst_balance,
+        Uint128(dst_amount.u128() + referral_amount.u128() + admin_fee.u128()),
+    )?;
+
+    enter_lock(&mut deps.storage)?;
+
+    let mut volumes = read_volumes(&deps.storage)?;
+    add_volume(
+        &mut volumes,
+        &src_token.address,
+        src_amount,
+        Uint128::zero(),
+    );
+    add_volume(
+        &mut volumes,
+        &dst_token.address,
+        Uint128::zero(),
+        Uint128(dst_amount.u128() + referral_amount.u128()),
+    );
+    store_volumes(&mut deps.storage, &volumes)?;
+    let tx = SwapTx {
+        id: next_swap_tx_id(&mut deps.storage)?,
+        from: swapper,
+        recipient,
+        src_token: src_token.address.clone(),
+        src_amount,
+        dst_token: dst_token.address.clone(),
+        dst_amount,
+        fee_amount: simulation.fee_amount,
+        block_height: env.block.height,
+        block_time: env.block.time,
+    };
+    record_swap_tx(&mut deps.storage, &tx)?;
+
+    // If the pool holds less than the payout its transfer fails and the swap reverts anyway.
+    // Anything else sent to the pool is left to the LPs.
+    reserves[src_index] = src_balance;
+    reserves[dst_index] = (dst_balance
+        - Uint128(dst_amount.u128() + referral_amount.u128() + admin_fee.u128()))
+    .unwrap_or_else(|_| Uint128::zero());
+    store_reserves(&mut deps.storage, &reserves)?;
+    let mut admin_fees = read_admin_fees(&deps.storage)?;
+    admin_fees[dst_index] += admin_fee;
+    store_admin_fees(&mut deps.storage, &admin_fees)?;
+
+    exit_lock(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: Some(to_binary(&SwapResponse {
+            return_amount: dst_amount,
+            fee_amount: simulation.fee_amount,
+        })?),
+    })
+}
+
+/// Swaps only the part of the `amount` received that `ask_amount` takes, refunding the rest
+pub fn try_swap_exact_out<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    to_token: HumanAddr,
+    ask_amount: Uint128,
+    recipient: HumanAddr,
+) -> HandleResult {
+    if ask_amount == Uint128::zero() {
+        return Err(StdError::generic_err("Cannot swap for a zero amount"));
+    }
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_index = supported_tokens
+        .iter()
+        .position(|t| t.address == env.message.sender)
+        .unwrap() /* this was checked before going into try_swap_exact_out */;
+    let src_token = supported_tokens[src_index].clone();
+
+    let src_balance = query_lp_balance(deps, &supported_tokens, src_index, &env.contract.address)?;
+    let received = received_amount(
+        src_balance,
+        read_reserves(&deps.storage)?[src_index],
+        amount,
+    );
+    let amount_in = swap_amount_in(
+        deps,
+        ask_amount,
+        src_token.address.clone(),
+        to_token.clone(),
+    )?;
+    if amount_in > received {
+        return Err(ContractError::InputAboveSent {
+            amount_in,
+            token: src_token.address,
+            sent: received,
+        }
+        .into());
+    }
+
+    let pool_address = env.contract.address.clone();
+    let mut res = try_swap(
+        deps,
+        env,
+        amount_in,
+        src_token.address.clone(),
+        to_token,
+        sender.clone(),
+        recipient,
+        None,
+        Some(ask_amount),
+        false,
+    )?;
+
+    // try_swap credits the pool's whole balance, but the excess is still the sender's
+    let refund = (received - amount_in)?;
+    if refund > Uint128::zero() {
+        let mut reserves = read_reserves(&deps.storage)?;
+        reserves[src_index] = (reserves[src_index] - refund)?;
+        store_reserves(&mut deps.storage, &reserves)?;
+        res.messages.push(transfer_asset_msg(
+            &src_token,
+            &pool_address,
+            sender,
+            refund,
+        )?);
+        res.log.push(log("refund", refund.to_string()));
+    }
+
+    Ok(res)
+}
+
+pub fn try_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    operations: Vec<BatchOperation>,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    if operations.is_empty() || operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(StdError::generic_err(format!(
+            "A batch must have between 1 and {} operations",
+            MAX_BATCH_OPERATIONS
+        )));
+    }
+    record_observations(deps, &config, env.block.time)?;
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let asset_index = |address: &HumanAddr| {
+        let index = supported_tokens
+            .iter()
+            .position(|t| &t.address == address)
+            .ok_or_else(|| ContractError::UnknownAsset(address.clone()))?;
+        if supported_tokens[index].native {
+            return Err(StdError::generic_err(format!(
+                "Native asset {:?} can't be used in a batch",
+                address
+            )));
+        }
+        Ok(index)
+    };
+
+    // Threaded through the operations, and checked against the pool's balances by FinalizeBatch
+    let mut balances = read_reserves(&deps.storage)?;
+    let mut total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let mut amounts_in = vec![Uint128::zero(); supported_tokens.len()];
+    let mut amounts_out = vec![Uint128::zero(); supported_tokens.len()];
+    let mut minted = Uint128::zero();
+    let mut admin_fees = read_admin_fees(&deps.storage)?;
+    let mut volumes = read_volumes(&deps.storage)?;
+    let mut results = vec![];
+    let mut logs = vec![log("action", "batch")];
+    for operation in operations {
+        match operation {
+            BatchOperation::ProvideLiquidity { assets, min_lp_out } => {
+                check_deposits_open(&config)?;
+                let old_balances = normalize_balances(&balances, &supported_tokens)?;
+                let mut deposits: Vec<TokenAmount> = vec![];
+                for deposit in assets {
+                    let i = asset_index(&deposit.address)?;
+                    let token = &supported_tokens[i];
+                    if !token.enabled {
+                        return Err(ContractError::AssetDisabled(token.address.clone()).into());
+                    }
+                    check_not_withdraw_only(token)?;
+                    if deposits.iter().any(|d| d.address == token.address) {
+                        return Err(StdError::generic_err(format!(
+                            "Duplicate deposit of asset {:?}",
+                            token.address
+                        )));
+                    }
+                    balances[i] += deposit.amount;
+                    amounts_in[i] += deposit.amount;
+                    deposits.push(TokenAmount {
+                        address: token.address.clone(),
+                        code_hash: token.code_hash.clone(),
+                        amount: deposit.amount,
+                    });
+                }
+
+                if deposits.iter().all(|d| d.amount == Uint128::zero()) {
+                    return Err(StdError::generic_err(
+                        "Cannot provide liquidity without a non-zero deposit",
+                    ));
+                }
+                let new_balances = normalize_balances(&balances, &supported_tokens)?;
+                check_deposit_caps(&config, &supported_tokens, &new_balances, &deposits)?;
+                let share = deposit_share(&config, &old_balances, &new_balances, total_supply)?;
+                if share == Uint128::zero() {
+                    return Err(StdError::generic_err("Deposit would mint no LP tokens"));
+                }
+                let share =
+                    share_after_bonus(&config, &old_balances, &new_balances, share, total_supply)?;
+                check_min_lp_out(share, min_lp_out)?;
+
+                total_supply += share;
+                minted += share;
+                logs.push(log("share", share.to_string()));
+                results.push(BatchOperationResult::ProvideLiquidity(
+                    ProvideLiquidityResponse {
+                        lp_amount: share,
+                        deposits,
+                    },
+                ));
+            }
+            BatchOperation::Swap {
+                offer,
+                to_token,
+                min_return,
+            } => {
+                check_swaps_open(&config)?;
+                if offer.amount == Uint128::zero() {
+                    return Err(StdError::generic_err("Cannot swap a zero amount"));
+                }
+                let src_index = asset_index(&offer.address)?;
+                let dst_index = asset_index(&to_token)?;
+                let src_token = &supported_tokens[src_index];
+                let dst_token = &supported_tokens[dst_index];
+
+                if config.depeg_threshold_bps > 0 {
+                    check_depeg(
+                        &config,
+                        &supported_tokens,
+                        &balances,
+                        src_token,
+                        dst_token,
+                        offer.amount,
+                    )?;
+                }
+                if config.max_volume_per_block > Uint128::zero() {
+                    add_block_volume(
+                        &mut deps.storage,
+                        &config,
+                        env.block.height,
+                        src_token,
+                        offer.amount,
+                    )?;
+                }
+                let simulation = simulate_swap_at(
+                    &config,
+                    &supported_tokens,
+                    &balances,
+                    src_token,
+                    dst_token,
+                    offer.amount,
+                )?;
+                let dst_amount = simulation.return_amount;
+                if config.max_swap_out_bps > 0 {
+                    let max_out =
+                        balances[dst_index].multiply_ratio(config.max_swap_out_bps, MAX_BPS);
+                    if dst_amount > max_out {
+                        return Err(StdError::generic_err(format!(
+                            "Swap output {} exceeds the max of {} ({} bps of the {:?} reserve)",
+                            dst_amount, max_out, config.max_swap_out_bps, dst_token.address
+                        )));
+                    }
+                }
+                let admin_fee = admin_fee_amount(&config, simulation.fee_amount, Uint128::zero());
+                check_min_reserve(
+                    &config,
+                    dst_token,
+                    balances[dst_index],
+                    dst_amount + admin_fee,
+                )?;
+                if let Some(min_return) = min_return {
+                    if dst_amount < min_return {
+                        return Err(ContractError::ReturnBelowMinimum {
+                            amount: dst_amount,
+                            min_return,
+                        }
+                        .into());
+                    }
+                }
+
+                balances[src_index] += offer.amount;
+                balances[dst_index] =
+                    (balances[dst_index] - (dst_amount + admin_fee)).map_err(|_| {
+                        StdError::generic_err(format!(
+                            "Not enough liquidity of {:?}",
+                            dst_token.address
+                        ))
+                    })?;
+                amounts_in[src_index] += offer.amount;
+                amounts_out[dst_index] += dst_amount;
+                admin_fees[dst_index] += admin_fee;
+                add_volume(
+                    &mut volumes,
+                    &src_token.address,
+                    offer.amount,
+                    Uint128::zero(),
+                );
+                add_volume(
+                    &mut volumes,
+                    &dst_token.address,
+                    Uint128::zero(),
+                    dst_amount,
+                );
+                logs.push(log(
+                    "swapped",
+                    format!(
+                        "{} {} {} {}",
+                        offer.amount, src_token.address, dst_amount, dst_token.address
+                    ),
+                ));
+                let tx = SwapTx {
+                    id: next_swap_tx_id(&mut deps.storage)?,
+                    from: env.message.sender.clone(),
+                    recipient: env.message.sender.clone(),
+                    src_token: src_token.address.clone(),
+                    src_amount: offer.amount,
+                    dst_token: dst_token.address.clone(),
+                    dst_amount,
+                    fee_amount: simulation.fee_amount,
+                    block_height: env.block.height,
+                    block_time: env.block.time,
+                };
+                record_swap_tx(&mut deps.storage, &tx)?;
+                results.push(BatchOperationResult::Swap(SwapResponse {
+                    return_amount: dst_amount,
+                    fee_amount: simulation.fee_amount,
+                }));
+            }
+        }
+    }
+
+    // Pull what the sender owes first, then pay out the rest
+    let mut messages = vec![];
+    for (i, token) in supported_tokens.iter().enumerate() {
+        if amounts_in[i] > amounts_out[i] {
+            messages.push(snip20_utils::transfer_from_msg(
+                env.message.sender.clone(),
+                env.contract.address.clone(),
+                (amounts_in[i] - amounts_out[i])?,
+                None,
+                256,
+                token.code_hash.clone(),
+                token.address.clone(),
+            )?);
+        }
+    }
+    for (i, token) in supported_tokens.iter().enumerate() {
+        if amounts_out[i] > amounts_in[i] {
+            messages.push(transfer_asset_msg(
+                token,
+                &env.contract.address,
+                env.message.sender.clone(),
+                (amounts_out[i] - amounts_in[i])?,
+            )?);
+        }
+    }
+    if minted > Uint128::zero() {
+        messages.push(snip20_utils::mint_msg(
+            env.message.sender.clone(),
+            minted,
+            None,
+            256,
+            config.lp_token_code_hash.clone(),
+            config.lp_token_address.clone(),
+        )?);
+    }
+
+    // Held until FinalizeBatch has checked that the transfers delivered these balances
+    enter_lock(&mut deps.storage)?;
+    store_volumes(&mut deps.storage, &volumes)?;
+    store_reserves(&mut deps.storage, &balances)?;
+    store_admin_fees(&mut deps.storage, &admin_fees)?;
+    add_lp_flows(&mut deps.storage, minted, Uint128::zero())?;
+    if minted > Uint128::zero() {
+        let deposited: Vec<TokenAmount> = results
+            .iter()
+            .filter_map(|result| match result {
+                BatchOperationResult::ProvideLiquidity(response) => Some(&response.deposits),
+                BatchOperationResult::Swap(_) => None,
+            })
+            .flatten()
+            .cloned()
+            .collect();
+        add_position_flows(
+            &mut deps.storage,
+            &env.message.sender,
+            minted,
+            Uint128::zero(),
+            &deposited,
+            &[],
+        )?;
+    }
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        callback_code_hash: env.contract_code_hash,
+        msg: to_binary(&HandleMsg::FinalizeBatch {})?,
+        send: vec![],
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: Some(to_binary(&results)?),
+    })
+}
+
+/// Fails the batch if the pool holds less than it priced the operations on, which happens
+/// with tokens that charge a fee on transfer
+pub fn try_finalize_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    if env.message.sender != env.contract.address {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let expected = read_reserves(&deps.storage)?;
+    let balances = query_pool_balances(deps, &config, &supported_tokens)?;
+    for ((token, balance), expected) in supported_tokens.iter().zip(&balances).zip(&expected) {
+        if balance < expected {
+            return Err(StdError::generic_err(format!(
+                "Pool holds {} {}, less than the {} the batch was priced on",
+                balance, token.address, expected
+            )));
+        }
+    }
+
+    store_reserves(&mut deps.storage, &balances)?;
+    update_seed_mode(&mut deps.storage, &mut config, &supported_tokens, &balances)?;
+    exit_lock(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "finalize_batch")],
+        data: None,
+    })
+}
+
+/// Adds a swap to the current block's volume, rejecting it if that goes over the limit
+fn add_block_volume<S: Storage>(
+    storage: &mut S,
+    config: &Config,
+    height: u64,
+    src_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<()> {
+    let previous_volume = match read_block_volume(storage)? {
+        Some(block_volume) if block_volume.height == height => block_volume.volume,
+        _ => Uint128::zero(),
+    };
+
+    let volume = u256_to_uint128(add(
+        Some(U256::from(previous_volume.u128())),
+        normalize(src_amount, src_token.decimals),
+    ))
+    .ok_or_else(|| StdError::generic_err("Cannot compute block volume"))?;
+    if volume > config.max_volume_per_block {
+        return Err(StdError::generic_err(format!(
+            "Swap would bring this block's volume to {}, above the limit of {}",
+            volume, config.max_volume_per_block
+        )));
+    }
+
+    store_block_volume(storage, &BlockVolume { height, volume })
+}
+
+/// Share of a swap's `fee_amount` set aside for the protocol, out of what the referral cut
+/// leaves
+fn admin_fee_amount(config: &Config, fee_amount: Uint128, referral_amount: Uint128) -> Uint128 {
+    (fee_amount - referral_amount)
+        .unwrap_or_else(|_| Uint128::zero())
+        .multiply_ratio(config.admin_fee_bps, MAX_BPS)
+}
+
+/// Fails if paying `amount` of `token` out of `balance` leaves less than the configured
+/// minimum, keeping every asset clear of zero
+fn check_min_reserve(
+    config: &Config,
+    token: &TokenInfo,
+    balance: Uint128,
+    amount: Uint128,
+) -> StdResult<()> {
+    let remaining = (balance - amount).unwrap_or_else(|_| Uint128::zero());
+    let normalized = normalize(remaining, token.decimals)
+        .ok_or_else(|| StdError::generic_err("Cannot normalize the remaining balance"))?;
+    if normalized < U256::from(config.min_reserve_after_swap.u128()) || amount > balance {
+        return Err(StdError::generic_err(format!(
+            "Swap would leave {} {} in the pool, below the minimum of {} normalized",
+            remaining, token.address, config.min_reserve_after_swap
+        )));
+    }
+    Ok(())
+}
+
+/// Lowest swap output accepted, `max_slippage_bps` below `expected_return`
+fn min_return(
+    expected_return: Option<Uint128>,
+    max_slippage_bps: Option<u32>,
+) -> StdResult<Option<Uint128>> {
+    let expected_return = match (expected_return, max_slippage_bps) {
+        (Some(expected_return), _) => expected_return,
+        (None, None) => return Ok(None),
+        (None, Some(_)) => {
+            return Err(StdError::generic_err(
+                "max_slippage_bps requires an expected_return",
+            ))
+        }
+    };
+    let max_slippage_bps = max_slippage_bps.unwrap_or(0);
+    if max_slippage_bps > MAX_BPS {
+        return Err(StdError::generic_err(format!(
+            "max_slippage_bps must not exceed {}",
+            MAX_BPS
+        )));
+    }
+
+    Ok(Some(
+        expected_return.multiply_ratio(MAX_BPS - max_slippage_bps, MAX_BPS),
+    ))
+}
+
+/// Withdrawals come in through Receive, and only the pool's LP token may send them
+fn check_lp_token_sender(config: &Config, env: &Env) -> StdResult<()> {
+    if env.message.sender != config.lp_token_address {
+        return Err(ContractError::UnknownLpToken(env.message.sender.clone()).into());
+    }
+    Ok(())
+}
+
+fn check_deadline(env: &Env, deadline: Option<u64>) -> StdResult<()> {
+    match deadline {
+        Some(deadline) if env.block.time > deadline => Err(ContractError::DeadlineExpired {
+            deadline,
+            now: env.block.time,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Native coins sent to a handler that doesn't take them would be stuck in the pool
+fn check_no_funds(env: &Env) -> StdResult<()> {
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err(
+            "This message does not accept native coins",
+        ));
+    }
+    Ok(())
+}
+
+/// Lists `token` as asset `id`, starting from a zero reserve. For a SNIP-20 token this queries
+/// its decimals and returns the messages setting `viewing_key` and the receive hook on it.
+fn register_asset<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    token: Token,
+    id: u32,
+    viewing_key: &str,
+) -> StdResult<(TokenInfo, Vec<CosmosMsg>)> {
+    if token.native {
+        let asset = TokenInfo {
+            id,
+            address: token.address,
+            code_hash: String::new(),
+            viewing_key: String::new(),
+            decimals: NATIVE_DECIMALS,
+            enabled: true,
+            native: true,
+            withdraw_only: false,
+            wrapped_native: false,
+        };
+        return Ok((asset, vec![]));
+    }
+
+    let messages = vec![
+        // Set initial viewing key for token
+        snip20_utils::set_viewing_key_msg(
+            viewing_key.to_string(),
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?,
+        // Register for receive message from token
+        snip20_utils::register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?,
+    ];
+
+    let decimals = query_token_decimals(deps, &token.address, &token.code_hash)?;
+    if decimals > NORMALIZED_DECIMALS {
+        return Err(StdError::generic_err(format!(
+            "Decimals must not exceed {} for token: {:?}",
+            NORMALIZED_DECIMALS, token
+        )));
+    }
+
+    let asset = TokenInfo {
+        id,
+        address: token.address,
+        code_hash: token.code_hash,
+        viewing_key: viewing_key.to_string(),
+        decimals,
+        enabled: true,
+        native: false,
+        withdraw_only: false,
+        wrapped_native: false,
+    };
+    Ok((asset, messages))
+}
+
+/// Rounds an amount the pool pays out of `token` down to round_down_pool_answer_to_nearest,
+/// normalized
+fn round_down_answer(config: &Config, token: &TokenInfo, amount: Uint128) -> StdResult<Uint128> {
+    round_down_normalized(
+        amount,
+        token.decimals,
+        config.round_down_pool_answer_to_nearest,
+    )
+    .ok_or_else(|| StdError::generic_err(format!("Cannot round {} {}", amount, token.address)))
+}
+
+/// Rejects deposits and swaps into the pool of an asset that's being removed
+fn check_not_withdraw_only(token: &TokenInfo) -> StdResult<()> {
+    if token.withdraw_only {
+        return Err(ContractError::AssetWithdrawOnly(token.address.clone()).into());
+    }
+    Ok(())
+}
+
+fn is_admin_or_operator(config: &Config, sender: &HumanAddr) -> bool {
+    *sender == config.admin || config.operators.contains(sender)
+}
+
+/// Rejects calls that need the LP token before PostInitialize has bound it
+fn check_initialized(config: &Config) -> StdResult<()> {
+    if config.lp_token_address == HumanAddr::default() {
+        return Err(ContractError::NotInitialized.into());
+    }
+    Ok(())
+}
+
+/// Swaps are open while the pool is Active or DepositsDisabled
+fn check_swaps_open(config: &Config) -> StdResult<()> {
+    match config.status {
+        PoolStatus::Active | PoolStatus::DepositsDisabled => Ok(()),
+        PoolStatus::SwapsDisabled => Err(ContractError::SwapsDisabled.into()),
+        status => Err(ContractError::NotActive(status).into()),
+    }
+}
+
+/// Deposits are open while the pool is Active or SwapsDisabled
+fn check_deposits_open(config: &Config) -> StdResult<()> {
+    match config.status {
+        PoolStatus::Active | PoolStatus::SwapsDisabled => Ok(()),
+        PoolStatus::DepositsDisabled => Err(ContractError::DepositsDisabled.into()),
+        status => Err(ContractError::NotActive(status).into()),
+    }
+}
+
+/// Ends seed mode for good once `reserves` reach the seed TVL cap, so a later drop in TVL
+/// can't reopen it. Returns whether the pool is still in seed mode.
+fn update_seed_mode<S: Storage>(
+    storage: &mut S,
+    config: &mut Config,
+    supported_tokens: &[TokenInfo],
+    reserves: &[Uint128],
+) -> StdResult<bool> {
+    if !config.seed_mode {
+        return Ok(false);
+    }
+    let tvl = normalize_balances(reserves, supported_tokens)?
+        .iter()
+        .try_fold(U256::zero(), |acc, x| acc.checked_add(*x))
+        .ok_or_else(|| StdError::generic_err("Cannot sum pool balances"))?;
+    if tvl >= U256::from(config.seed_tvl_cap.u128()) {
+        config.seed_mode = false;
+        store_config(storage, config)?;
+    }
+    Ok(config.seed_mode)
+}
+
+fn add_lp_flows<S: Storage>(storage: &mut S, minted: Uint128, burned: Uint128) -> StdResult<()> {
+    let mut flows = read_lp_flows(storage)?;
+    flows.minted = Uint128(flows.minted.u128().saturating_add(minted.u128()));
+    flows.burned = Uint128(flows.burned.u128().saturating_add(burned.u128()));
+    store_lp_flows(storage, &flows)
+}
+
+/// Adds to the position of `address`, saturating like the pool's LP flows
+fn add_position_flows<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    minted: Uint128,
+    burned: Uint128,
+    deposited: &[TokenAmount],
+    withdrawn: &[TokenAmount],
+) -> StdResult<()> {
+    let mut position = read_position(storage, address)?;
+    position.lp_minted = Uint128(position.lp_minted.u128().saturating_add(minted.u128()));
+    position.lp_burned = Uint128(position.lp_burned.u128().saturating_add(burned.u128()));
+    let flows = deposited
+        .iter()
+        .map(|d| (d, true))
+        .chain(withdrawn.iter().map(|w| (w, false)));
+    for (token_amount, is_deposit) in flows {
+        let index = match position
+            .assets
+            .iter()
+            .position(|a| a.address == token_amount.address)
+        {
+            Some(index) => index,
+            None => {
+                position.assets.push(AssetFlow {
+                    address: token_amount.address.clone(),
+                    deposited: Uint128::zero(),
+                    withdrawn: Uint128::zero(),
+                });
+                position.assets.len() - 1
+            }
+        };
+        let asset = &mut position.assets[index];
+        let total = if is_deposit {
+            &mut asset.deposited
+        } else {
+            &mut asset.withdrawn
+        };
+        *total = Uint128(total.u128().saturating_add(token_amount.amount.u128()));
+    }
+    store_position(storage, address, &position)
+}
+
+/// Appends `tx` to the history of the swapper, and of the recipient if that's another address
+fn record_swap_tx<S: Storage>(storage: &mut S, tx: &SwapTx) -> StdResult<()> {
+    append_swap_tx(storage, &tx.from, tx)?;
+    if tx.recipient != tx.from {
+        append_swap_tx(storage, &tx.recipient, tx)?;
+    }
+    Ok(())
+}
+
+/// Marks a swap, deposit or withdrawal as in progress, rejecting it if another one already is.
+/// Taken once a call's checks have passed, so failed calls never leave it set.
+fn enter_lock<S: Storage>(storage: &mut S) -> StdResult<()> {
+    if read_lock(storage)? {
+        return Err(ContractError::Reentrant.into());
+    }
+    store_lock(storage, true)
+}
+
+fn exit_lock<S: Storage>(storage: &mut S) -> StdResult<()> {
+    store_lock(storage, false)
+}
+
+/// Pays out `amount` of a pool asset, via the bank module for native coins
+fn transfer_asset_msg(
+    token: &TokenInfo,
+    pool_address: &HumanAddr,
+    recipient: HumanAddr,
+    amount: Uint128,
+) -> StdResult<CosmosMsg> {
+    if token.native {
+        Ok(CosmosMsg::Bank(BankMsg::Send {
+            from_address: pool_address.clone(),
+            to_address: recipient,
+            amount: vec![Coin {
+                denom: token.address.to_string(),
+                amount,
+            }],
+        }))
+    } else {
+        snip20_utils::transfer_msg(
+            recipient,
+            amount,
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )
+    }
+}
+
+/// Pays out `amount` of a pool asset like transfer_asset_msg, or with `unwrap` as uscrt
+/// redeemed from a wrapped_native asset
+fn payout_msgs(
+    token: &TokenInfo,
+    pool_address: &HumanAddr,
+    recipient: HumanAddr,
+    amount: Uint128,
+    unwrap: bool,
+) -> StdResult<Vec<CosmosMsg>> {
+    if !unwrap {
+        return Ok(vec![transfer_asset_msg(
+            token,
+            pool_address,
+            recipient,
+            amount,
+        )?]);
+    }
+    if !token.wrapped_native {
+        return Err(StdError::generic_err(format!(
+            "{:?} is not a wrapped native token, it can't be unwrapped",
+            token.address
+        )));
+    }
+
+    // The redeemed coins reach the pool before the bank send runs
+    Ok(vec![
+        snip20_utils::redeem_msg(
+            amount,
+            None,
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?,
+        CosmosMsg::Bank(BankMsg::Send {
+            from_address: pool_address.clone(),
+            to_address: recipient,
+            amount: vec![Coin {
+                denom: WRAPPED_NATIVE_DENOM.to_string(),
+                amount,
+            }],
+        }),
+    ])
+}
+
+/// Counters saturate instead of failing the swap on overflow
+fn add_volume(
+    volumes: &mut Vec<TokenVolume>,
+    token: &HumanAddr,
+    volume_in: Uint128,
+    volume_out: Uint128,
+) {
+    let index = match volumes.iter().position(|v| &v.address == token) {
+        Some(index) => index,
+        None => {
+            volumes.push(TokenVolume {
+                address: token.clone(),
+                volume_in: Uint128::zero(),
+                volume_out: Uint128::zero(),
+            });
+            volumes.len() - 1
+        }
+    };
+
+    let volume = &mut volumes[index];
+    volume.volume_in = Uint128(volume.volume_in.u128().saturating_add(volume_in.u128()));
+    volume.volume_out = Uint128(volume.volume_out.u128().saturating_add(volume_out.u128()));
+}
+
+/// Prices a swap of `src_amount` against the current pool state without executing it
+pub fn simulate_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<SwapSimulationResponse> {
+    simulate_swap_at(
+        config,
+        supported_tokens,
+        &read_reserves(&deps.storage)?,
+        src_token,
+        dst_token,
+        src_amount,
+    )
+}
+
+/// simulate_swap for a pool holding `balances`
+fn simulate_swap_at(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[Uint128],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<SwapSimulationResponse> {
+    for token in [src_token, dst_token].iter() {
+        if !token.enabled {
+            return Err(ContractError::AssetDisabled(token.address.clone()).into());
+        }
+    }
+    check_not_withdraw_only(src_token)?;
+
+    let dst_amount = swap_output(
+        config,
+        supported_tokens,
+        &normalize_balances(balances, supported_tokens)?,
+        src_token,
+        dst_token,
+        normalize(src_amount, src_token.decimals),
+    )?
+    .and_then(|amount| denormalize(amount, dst_token.decimals))
+    .map(|amount| U256::from(amount.u128()));
+
+    // Take fee. The fee is rounded up and the output down, so rounding never favors the trader
+    let (fee_nom, fee_denom) = swap_fee_ratio_at(
+        config,
+        supported_tokens,
+        balances,
+        src_token,
+        dst_token,
+        src_amount,
+    )?;
+    let fee = div_ceil(mul(dst_amount, Some(fee_nom)), Some(fee_denom));
+    let after_fee = sub(dst_amount, fee);
+
+    let to_u128 = |amount: Option<U256>| {
+        u256_to_uint128(amount)
+            .map(|amount| amount.u128())
+            .ok_or_else(|| {
+                StdError::generic_err(format!(
+                    "Cannot normalize swap amount {} of {:?} to {:?}",
+                    src_amount, src_token.address, dst_token.address
+                ))
+            })
+    };
+    let dst_amount = to_u128(dst_amount)?;
+    let after_fee = to_u128(after_fee)?;
+    // What the rounding cuts off stays in the pool, with the LPs
+    let return_amount = round_down_answer(config, dst_token, Uint128(after_fee))?;
+
+    Ok(SwapSimulationResponse {
+        return_amount,
+        fee_amount: Uint128(dst_amount - after_fee),
+    })
+}
+
+/// Output of swapping `amount` in a pool holding `balances`, all normalized, before fees.
+/// Priced on the invariant over the assets the pool holds, so neither side may be empty. None if
+/// `amount` is.
+fn swap_output(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[U256],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    amount: Option<U256>,
+) -> StdResult<Option<U256>> {
+    let amount = match amount {
+        Some(amount) => amount,
+        None => return Ok(None),
+    };
+    check_not_empty(supported_tokens, balances, &[src_token, dst_token])?;
+
+    Ok(Some(get_dy(
+        config.amp,
+        balances,
+        asset_index(supported_tokens, src_token),
+        asset_index(supported_tokens, dst_token),
+        amount,
+    )?))
+}
+
+/// Output per unit of input at the margin for a pool holding `balances`, normalized and before
+/// fees, as (nom, denom). Neither side may be empty, like in swap_output.
+fn spot_price_ratio(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[U256],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+) -> StdResult<(U256, U256)> {
+    check_not_empty(supported_tokens, balances, &[src_token, dst_token])?;
+
+    Ok(marginal_price(
+        config.amp,
+        balances,
+        asset_index(supported_tokens, src_token),
+        asset_index(supported_tokens, dst_token),
+    )?)
+}
+
+/// Fails if the pool holds none of any of `tokens`, which then have no price
+fn check_not_empty(
+    supported_tokens: &[TokenInfo],
+    balances: &[U256],
+    tokens: &[&TokenInfo],
+) -> StdResult<()> {
+    for token in tokens {
+        if balances[asset_index(supported_tokens, token)].is_zero() {
+            return Err(ContractError::EmptyAsset(token.address.clone()).into());
+        }
+    }
+    Ok(())
+}
+
+fn asset_index(supported_tokens: &[TokenInfo], token: &TokenInfo) -> usize {
+    supported_tokens
+        .iter()
+        .position(|t| t.address == token.address)
+        .unwrap() // token comes from supported_tokens
+}
+
+/// Fee of a swap as (nom, denom), from the pool imbalance when dynamic fees are on
+fn swap_fee_ratio<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<(U256, U256)> {
+    swap_fee_ratio_at(
+        config,
+        supported_tokens,
+        &read_reserves(&deps.storage)?,
+        src_token,
+        dst_token,
+        src_amount,
+    )
+}
+
+/// swap_fee_ratio for a pool holding `balances`
+fn swap_fee_ratio_at(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[Uint128],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<(U256, U256)> {
+    if config.dynamic_fee {
+        let fee_bps = dynamic_fee_bps(
+            config,
+            supported_tokens,
+            balances,
+            src_token,
+            dst_token,
+            src_amount,
+        )?;
+        Ok((U256::from(fee_bps), U256::from(MAX_BPS)))
+    } else {
+        Ok((
+            U256::from(config.swap_fee_nom.u128()),
+            U256::from(config.swap_fee_denom.u128()),
+        ))
+    }
+}
+
+/// Fee rate of a dynamic-fee swap, based on how imbalanced the pool would be after it, or with
+/// a dynamic_fee_min_bps floor on how much of the imbalance it removes if it removes any
+fn dynamic_fee_bps(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[Uint128],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<u32> {
+    let new_balances = normalized_balances_after_swap(
+        config,
+        supported_tokens,
+        balances,
+        src_token,
+        dst_token,
+        src_amount,
+    )?;
+    if config.dynamic_fee_min_bps > 0 {
+        let old_balances = normalize_balances(balances, supported_tokens)?;
+        if let Some(fee_bps) = rebalancing_fee_bps(
+            config.amp,
+            &old_balances,
+            &new_balances,
+            config.dynamic_fee_base_bps,
+            config.dynamic_fee_min_bps,
+        )? {
+            return Ok(fee_bps);
+        }
+    }
+
+    Ok(imbalance_fee_bps(
+        config.amp,
+        &new_balances,
+        config.dynamic_fee_base_bps,
+        config.dynamic_fee_max_bps,
+    )?)
+}
+
+/// Normalized `balances` as they would be after swapping `src_amount`, before fees
+fn normalized_balances_after_swap(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[Uint128],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<Vec<U256>> {
+    let normalized_balances = normalize_balances(balances, supported_tokens)?;
+    let amount_in = normalize(src_amount, src_token.decimals);
+    let amount_out = swap_output(
+        config,
+        supported_tokens,
+        &normalized_balances,
+        src_token,
+        dst_token,
+        amount_in,
+    )?;
+
+    let mut new_balances = vec![];
+    for (balance, token) in normalized_balances.iter().zip(supported_tokens) {
+        let mut new_balance = Some(*balance);
+        if token.address == src_token.address {
+            new_balance = add(new_balance, amount_in);
+        } else if token.address == dst_token.address {
+            new_balance = sub(new_balance, amount_out);
+        }
+        new_balances.push(new_balance.ok_or_else(|| {
+            StdError::generic_err(format!("Not enough liquidity of {:?}", dst_token.address))
+        })?);
+    }
+
+    Ok(new_balances)
+}
+
+/// Rejects swaps that would push the share of `src_token` in a pool holding `balances` above
+/// `depeg_threshold_bps`, i.e. keep absorbing a token that is being dumped in
+fn check_depeg(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[Uint128],
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+    src_amount: Uint128,
+) -> StdResult<()> {
+    let new_balances = normalized_balances_after_swap(
+        config,
+        supported_tokens,
+        balances,
+        src_token,
+        dst_token,
+        src_amount,
+    )?;
+    let src_index = supported_tokens
+        .iter()
+        .position(|t| t.address == src_token.address)
+        .unwrap(); // src_token comes from supported_tokens
+
+    let total = new_balances
+        .iter()
+        .try_fold(U256::zero(), |acc, x| acc.checked_add(*x));
+    let share_bps = div(
+        mul(Some(new_balances[src_index]), Some(U256::from(MAX_BPS))),
+        total,
+    )
+    .ok_or_else(|| StdError::generic_err("Cannot compute pool share"))?;
+
+    if share_bps > U256::from(config.depeg_threshold_bps) {
+        return Err(StdError::generic_err(format!(
+            "Swap would raise the pool share of {:?} to {} bps, above the depeg threshold of {} bps",
+            src_token.address, share_bps, config.depeg_threshold_bps
+        )));
+    }
+
+    Ok(())
+}
+
+fn find_asset<'a>(assets: &'a [TokenInfo], address: &HumanAddr) -> StdResult<&'a TokenInfo> {
+    assets
+        .iter()
+        .find(|t| &t.address == address)
+        .ok_or_else(|| ContractError::UnknownAsset(address.clone()).into())
+}
+
+pub fn try_emergency_withdraw<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: HumanAddr,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+    // Halting first makes the drain visible on chain before any funds move
+    if config.status != PoolStatus::Halted {
+        return Err(StdError::generic_err(
+            "Emergency withdrawal requires the pool to be halted",
+        ));
+    }
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let mut messages = vec![];
+    let mut logs = vec![
+        log("action", "emergency_withdraw"),
+        log("recipient", recipient.clone()),
+    ];
+    for token in supported_tokens.iter() {
+        let balance = query_asset_balance(deps, token, &env.contract.address)?;
+        if balance == Uint128::zero() {
+            continue;
+        }
+
+        messages.push(transfer_asset_msg(
+            token,
+            &env.contract.address,
+            recipient.clone(),
+            balance,
+        )?);
+        logs.push(log("withdrawn", format!("{} {}", balance, token.address)));
+    }
+    // The admin fees went out with everything else
+    store_reserves(
+        &mut deps.storage,
+        &vec![Uint128::zero(); supported_tokens.len()],
+    )?;
+    store_admin_fees(
+        &mut deps.storage,
+        &vec![Uint128::zero(); supported_tokens.len()],
+    )?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_withdraw_admin_fees<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: HumanAddr,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let mut messages = vec![];
+    let mut logs = vec![
+        log("action", "withdraw_admin_fees"),
+        log("recipient", recipient.clone()),
+    ];
+    for (token, amount) in supported_tokens.iter().zip(read_admin_fees(&deps.storage)?) {
+        if amount == Uint128::zero() {
+            continue;
+        }
+
+        messages.push(transfer_asset_msg(
+            token,
+            &env.contract.address,
+            recipient.clone(),
+            amount,
+        )?);
+        logs.push(log("withdrawn", format!("{} {}", amount, token.address)));
+    }
+    if messages.is_empty() {
+        return Err(StdError::generic_err("There are no admin fees to withdraw"));
+    }
+    store_admin_fees(
+        &mut deps.storage,
+        &vec![Uint128::zero(); supported_tokens.len()],
+    )?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_set_rounding<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    round_down_to_nearest: Uint128,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let previous = config.round_down_pool_answer_to_nearest;
+    config.round_down_pool_answer_to_nearest = round_down_to_nearest;
+    validate_config(&config)?;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_rounding"),
+            log("previous", previous),
+            log("round_down_to_nearest", round_down_to_nearest),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_snapshot_virtual_price<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+    check_initialized(&config)?;
+
+    record_observations(deps, &config, env.block.time)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "snapshot_virtual_price")],
+        data: None,
+    })
+}
+
+/// Reconciles the reserves, which price every operation, with the balances the pool holds
+pub fn try_sync_reserves<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+    check_initialized(&config)?;
+    record_observations(deps, &config, env.block.time)?;
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let balances = query_pool_balances(deps, &config, &supported_tokens)?;
+    let mut logs = vec![log("action", "sync_reserves")];
+    for (token, balance) in supported_tokens.iter().zip(&balances) {
+        logs.push(log("reserve", format!("{} {}", balance, token.address)));
+    }
+
+    // Fails while a deposit or batch is waiting on its callback
+    enter_lock(&mut deps.storage)?;
+    store_reserves(&mut deps.storage, &balances)?;
+    exit_lock(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_end_seed_mode<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+    if !config.seed_mode {
+        return Err(StdError::generic_err("The pool is not in seed mode"));
+    }
+
+    config.seed_mode = false;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "end_seed_mode")],
+        data: None,
+    })
+}
+
+pub fn try_set_status<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    status: PoolStatus,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    // Operators can only stop the pool, reopening it is up to the admin
+    let halting = status == PoolStatus::WithdrawOnly || status == PoolStatus::Halted;
+    if env.message.sender != config.admin
+        && !(halting && is_admin_or_operator(&config, &env.message.sender))
+    {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let previous_status = config.status;
+    config.status = status;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_status"),
+            log("previous_status", format!("{:?}", previous_status)),
+            log("status", format!("{:?}", status)),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_set_operators<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    operators: Vec<HumanAddr>,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut logs = vec![log("action", "set_operators")];
+    logs.extend(
+        operators
+            .iter()
+            .map(|operator| log("operator", operator.as_str())),
+    );
+    config.operators = operators;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_propose_new_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    config.pending_admin = Some(address.clone());
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "propose_new_admin"),
+            log("pending_admin", address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_accept_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if config.pending_admin.as_ref() != Some(&env.message.sender) {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let previous_admin = config.admin;
+    config.admin = env.message.sender;
+    config.pending_admin = None;
+    store_config(&mut deps.storage, &config)?;
+    let assets = read_all_assets(&deps.storage)?;
+
+    Ok(HandleResponse {
+        messages: factory_update_msg(&config, &assets)?.into_iter().collect(),
+        log: vec![
+            log("action", "accept_admin"),
+            log("previous_admin", previous_admin.as_str()),
+            log("admin", config.admin.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_set_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: String,
+) -> HandleResult {
+    store_viewing_key(
+        &mut deps.storage,
+        &env.message.sender,
+        &hash_viewing_key(&key),
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_viewing_key")],
+        data: None,
+    })
+}
+
+pub fn try_revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> HandleResult {
+    revoke_permit(&mut deps.storage, &env.message.sender, &permit_name)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "revoke_permit"),
+            log("permit_name", permit_name),
+        ],
+        data: None,
+    })
+}
+
+/// Reports the pool's current assets and admin to its factory, if it has one
+fn factory_update_msg(config: &Config, assets: &[TokenInfo]) -> StdResult<Option<CosmosMsg>> {
+    let factory = match &config.factory {
+        Some(factory) => factory,
+        None => return Ok(None),
+    };
+
+    Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: factory.address.clone(),
+        callback_code_hash: factory.code_hash.clone(),
+        msg: to_binary(&FactoryHandleMsg::UpdatePool {
+            assets: assets.iter().map(|t| t.address.clone()).collect(),
+            admin: config.admin.clone(),
+        })?,
+        send: vec![],
+    })))
+}
+
+/// Records the virtual price and advances the price accumulators, before the calling handler
+/// changes anything
+fn record_observations<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    config: &Config,
+    time: u64,
+) -> StdResult<()> {
+    record_virtual_price(deps, config, time)?;
+    accumulate_prices(deps, config, time)
+}
+
+/// Adds the spot price of every ordered asset pair, times the seconds it held since the last
+/// observation, to that pair's cumulative price. Reserves only change inside handlers, so the
+/// current reserves' price is the one that held since. Like the virtual price, only the first
+/// call in a block records, and a pair starts from zero when it's first observed.
+fn accumulate_prices<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    config: &Config,
+    time: u64,
+) -> StdResult<()> {
+    let last = read_last_price_observation(&deps.storage)?;
+    if let Some(last) = &last {
+        if last.time >= time {
+            return Ok(());
+        }
+    }
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let balances = normalize_balances(&read_reserves(&deps.storage)?, &supported_tokens)?;
+    let is_empty = |token: &TokenInfo| balances[asset_index(&supported_tokens, token)].is_zero();
+    let mut cumulative_prices = vec![];
+    for base in supported_tokens.iter() {
+        for quote in supported_tokens.iter().filter(|t| t.id != base.id) {
+            let previous = last
+                .as_ref()
+                .and_then(|last| Some((last.time, last.cumulative_price(base.id, quote.id)?)));
+            let cumulative = match previous {
+                // A pair with an empty side has no price, so it adds nothing while it lasts
+                Some((_, previous)) if is_empty(base) || is_empty(quote) => previous.u128(),
+                Some((last_time, previous)) => {
+                    let price =
+                        scaled_spot_price(config, &supported_tokens, &balances, base, quote)?;
+                    previous
+                        .u128()
+                        .wrapping_add(price.wrapping_mul((time - last_time) as u128))
+                }
+                None => 0,
+            };
+            cumulative_prices.push(CumulativePrice {
+                base: base.id,
+                quote: quote.id,
+                cumulative: Uint128(cumulative),
+            });
+        }
+    }
+
+    push_price_observation(
+        &mut deps.storage,
+        &PriceObservation {
+            time,
+            cumulative_prices,
+        },
+    )
+}
+
+/// Spot price of `base` in `quote` scaled by 10^18, as the price accumulators sum it
+fn scaled_spot_price(
+    config: &Config,
+    supported_tokens: &[TokenInfo],
+    balances: &[U256],
+    base: &TokenInfo,
+    quote: &TokenInfo,
+) -> StdResult<u128> {
+    let (price_nom, price_denom) =
+        spot_price_ratio(config, supported_tokens, balances, base, quote)?;
+    u256_to_uint128(div(
+        mul(
+            Some(price_nom),
+            Some(U256::exp10(NORMALIZED_DECIMALS as usize)),
+        ),
+        Some(price_denom),
+    ))
+    .map(|price| price.u128())
+    .ok_or_else(|| StdError::generic_err("Cannot calculate the spot price"))
+}
+
+/// Records the virtual price that held up to this block, before the calling handler changes
+/// anything. Only the first call in a block records, so a price that's pushed and reverted
+/// within one block carries no weight in the TWAP.
+fn record_virtual_price<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    config: &Config,
+    time: u64,
+) -> StdResult<()> {
+    if let Some(last) = read_last_virtual_price(&deps.storage)? {
+        if last.time >= time {
+            return Ok(());
+        }
+    }
+    if let Some(virtual_price) = virtual_price(deps, config)? {
+        push_virtual_price(
+            &mut deps.storage,
+            &VirtualPriceObservation {
+                time,
+                virtual_price,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Normalized reserves per LP token scaled by 10^18, None while there's no LP supply.
+/// Reserves rather than balances, so tokens sent ahead of a swap or deposit aren't counted.
+fn virtual_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<Option<Uint128>> {
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let reserves = read_reserves(&deps.storage)?;
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    if total_supply == Uint128::zero() {
+        return Ok(None);
+    }
+
+    let total = normalize_balances(&reserves, &supported_tokens)?
+        .iter()
+        .try_fold(U256::zero(), |acc, x| acc.checked_add(*x));
+    u256_to_uint128(div(
+        mul(total, Some(U256::exp10(NORMALIZED_DECIMALS as usize))),
+        Some(U256::from(total_supply.u128())),
+    ))
+    .map(Some)
+    .ok_or_else(|| StdError::generic_err("Cannot calculate the virtual price"))
+}
+
+pub fn try_set_asset_enabled<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: HumanAddr,
+    enabled: bool,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
+        .iter()
+        .position(|t| t.address == token)
+        .ok_or_else(|| ContractError::UnknownAsset(token.clone()))?;
+    let mut supported_token = supported_tokens[index].clone();
+    supported_token.enabled = enabled;
+    store_asset(&mut deps.storage, index as u32, &supported_token)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_asset_enabled"),
+            log("token", token),
+            log("enabled", enabled),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_add_asset<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: Token,
+    viewing_key: String,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut supported_tokens = read_all_assets(&deps.storage)?;
+    if supported_tokens.iter().any(|t| t.address == token.address) {
+        return Err(StdError::generic_err(format!(
+            "Token already supported: {:?}",
+            token.address
+        )));
+    }
+    let id = read_next_asset_id(&deps.storage)?;
+    let (mut asset, mut messages) = register_asset(deps, &env, token, id, &viewing_key)?;
+    // Nothing can trade it before the admin enables it, and with no reserve it stays out of the
+    // invariant until its first deposit, so listing it leaves every other price where it was
+    asset.enabled = false;
+    let logs = vec![
+        log("action", "add_asset"),
+        log("token", asset.address.clone()),
+        log("decimals", asset.decimals),
+    ];
+
+    // Fails while a deposit or batch is waiting on its callback
+    enter_lock(&mut deps.storage)?;
+    // Read before the asset count changes, admin fees default to one per asset
+    let mut reserves = read_reserves(&deps.storage)?;
+    reserves.push(Uint128::zero());
+    store_reserves(&mut deps.storage, &reserves)?;
+    let mut admin_fees = read_admin_fees(&deps.storage)?;
+    admin_fees.push(Uint128::zero());
+    store_admin_fees(&mut deps.storage, &admin_fees)?;
+    supported_tokens.push(asset);
+    store_all_assets(&mut deps.storage, &supported_tokens)?;
+    store_next_asset_id(&mut deps.storage, id + 1)?;
+    exit_lock(&mut deps.storage)?;
+    messages.extend(factory_update_msg(&config, &supported_tokens)?);
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_set_wrapped_native<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: HumanAddr,
+    wrapped_native: bool,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
+        .iter()
+        .position(|t| t.address == token)
+        .ok_or_else(|| ContractError::UnknownAsset(token.clone()))?;
+    let mut supported_token = supported_tokens[index].clone();
+    if supported_token.native {
+        return Err(StdError::generic_err(format!(
+            "Native asset {:?} can't wrap another",
+            token
+        )));
+    }
+    if wrapped_native
+        && supported_tokens
+            .iter()
+            .any(|t| t.wrapped_native && t.address != token)
+    {
+        return Err(StdError::generic_err(format!(
+            "Another asset already wraps {}",
+            WRAPPED_NATIVE_DENOM
+        )));
+    }
+    supported_token.wrapped_native = wrapped_native;
+    store_asset(&mut deps.storage, index as u32, &supported_token)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_wrapped_native"),
+            log("token", token),
+            log("wrapped_native", wrapped_native),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_remove_asset<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: HumanAddr,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
+        .iter()
+        .position(|t| t.address == token)
+        .ok_or_else(|| ContractError::UnknownAsset(token.clone()))?;
+    if supported_tokens.len() <= 2 {
+        return Err(StdError::generic_err(
+            "Cannot remove an asset, the pool needs at least two",
+        ));
+    }
+    let asset = supported_tokens[index].clone();
+    let balance = query_asset_balance(deps, &asset, &env.contract.address)?;
+    let mut reserves = read_reserves(&deps.storage)?;
+    let mut admin_fees = read_admin_fees(&deps.storage)?;
+    let mut messages = vec![];
+    let mut logs = vec![log("action", "remove_asset"), log("token", token.clone())];
+
+    if balance != Uint128::zero() {
+        if !asset.withdraw_only {
+            // Left for swaps and withdrawals to drain before it can go
+            supported_tokens[index].withdraw_only = true;
+            store_asset(&mut deps.storage, index as u32, &supported_tokens[index])?;
+            logs.push(log("withdraw_only", true));
+            return Ok(HandleResponse {
+                messages,
+                log: logs,
+                data: None,
+            });
+        }
+
+        // Swaps can't take it below the min reserve, the dust left then goes to the admin
+        let reserve = normalize(reserves[index], asset.decimals)
+            .ok_or_else(|| StdError::generic_err("Cannot normalize the reserve"))?;
+        if reserve > U256::from(config.min_reserve_after_swap.u128()) {
+            return Err(StdError::generic_err(format!(
+                "The pool still holds {} of {:?}, drain it to {} normalized before removing the asset",
+                reserves[index], token, config.min_reserve_after_swap
+            )));
+        }
+        if admin_fees[index] != Uint128::zero() {
+            return Err(StdError::generic_err(format!(
+                "Withdraw the {} of {:?} in admin fees before removing the asset",
+                admin_fees[index], token
+            )));
+        }
+        messages.push(transfer_asset_msg(
+            &asset,
+            &env.contract.address,
+            config.admin.clone(),
+            balance,
+        )?);
+        logs.push(log("dust", balance));
+    }
+
+    // Fails while a deposit or batch is waiting on its callback
+    enter_lock(&mut deps.storage)?;
+    reserves.remove(index);
+    store_reserves(&mut deps.storage, &reserves)?;
+    admin_fees.remove(index);
+    store_admin_fees(&mut deps.storage, &admin_fees)?;
+    supported_tokens.remove(index);
+    store_all_assets(&mut deps.storage, &supported_tokens)?;
+    exit_lock(&mut deps.storage)?;
+    messages.extend(factory_update_msg(&config, &supported_tokens)?);
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_update_viewing_keys<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    viewing_key: String,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if !is_admin_or_operator(&config, &env.message.sender) {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut messages = vec![];
+    let mut logs = vec![log("action", "update_viewing_keys")];
+    for (index, mut token) in read_all_assets(&deps.storage)?.into_iter().enumerate() {
+        // Native balances are queried through the bank module, without a key
+        if token.native {
+            continue;
+        }
+        messages.push(snip20_utils::set_viewing_key_msg(
+            viewing_key.clone(),
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?);
+        logs.push(log("token", token.address.clone()));
+        token.viewing_key = viewing_key.clone();
+        store_asset(&mut deps.storage, index as u32, &token)?;
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+pub fn try_update_token_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: HumanAddr,
+    viewing_key: String,
+) -> HandleResult {
+    let config = read_config(&deps.storage)?;
+    if !is_admin_or_operator(&config, &env.message.sender) {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
+        .iter()
+        .position(|t| t.address == token)
+        .ok_or_else(|| ContractError::UnknownAsset(token.clone()))?;
+    let mut supported_token = supported_tokens[index].clone();
+    if supported_token.native {
+        return Err(StdError::generic_err(format!(
+            "Native asset {:?} has no viewing key",
+            token
+        )));
+    }
+    supported_token.viewing_key = viewing_key.clone();
+    let message = snip20_utils::set_viewing_key_msg(
+        viewing_key,
+        None,
+        256,
+        supported_token.code_hash.clone(),
+        supported_token.address.clone(),
+    )?;
+    store_asset(&mut deps.storage, index as u32, &supported_token)?;
+
+    Ok(HandleResponse {
+        messages: vec![message],
+        log: vec![
+            log("action", "update_token_viewing_key"),
+            log("token", token),
+        ],
+        data: None,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    swap_fee_nom: Option<Uint128>,
+    swap_fee_denom: Option<Uint128>,
+    round_down_pool_answer_to_nearest: Option<Uint128>,
+    max_swap_out_bps: Option<u32>,
+    dynamic_fee: Option<bool>,
+    dynamic_fee_base_bps: Option<u32>,
+    dynamic_fee_max_bps: Option<u32>,
+    dynamic_fee_min_bps: Option<u32>,
+    depeg_threshold_bps: Option<u32>,
+    max_referral_fee_bps: Option<u32>,
+    admin_fee_bps: Option<u32>,
+    max_volume_per_block: Option<Uint128>,
+    rebalance_bonus_to_lp_bps: Option<u32>,
+    deposit_bonus_bps: Option<u32>,
+    per_asset_deposit_cap: Option<Uint128>,
+    total_tvl_cap: Option<Uint128>,
+    min_reserve_after_swap: Option<Uint128>,
+    status: Option<PoolStatus>,
+) -> HandleResult {
+    let mut config = read_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(ContractError::Unauthorized.into());
+    }
+
+    let mut logs = vec![log("action", "update_config")];
+    if let Some(swap_fee_nom) = swap_fee_nom {
+        logs.push(config_change_log(
+            "swap_fee_nom",
+            config.swap_fee_nom,
+            swap_fee_nom,
+        ));
+        config.swap_fee_nom = swap_fee_nom;
+    }
+    if let Some(swap_fee_denom) = swap_fee_denom {
+        logs.push(config_change_log(
+            "swap_fee_denom",
+            config.swap_fee_denom,
+            swap_fee_denom,
+        ));
+        config.swap_fee_denom = swap_fee_denom;
+    }
+    if let Some(round_down_pool_answer_to_nearest) = round_down_pool_answer_to_nearest {
+        logs.push(config_change_log(
+            "round_down_pool_answer_to_nearest",
+            config.round_down_pool_answer_to_nearest,
+            round_down_pool_answer_to_nearest,
+        ));
+        config.round_down_pool_answer_to_nearest = round_down_pool_answer_to_nearest;
+    }
+    if let Some(max_swap_out_bps) = max_swap_out_bps {
+        logs.push(config_change_log(
+            "max_swap_out_bps",
+            config.max_swap_out_bps,
+            max_swap_out_bps,
+        ));
+        config.max_swap_out_bps = max_swap_out_bps;
+    }
+    if let Some(dynamic_fee) = dynamic_fee {
+        logs.push(config_change_log(
+            "dynamic_fee",
+            config.dynamic_fee,
+            dynamic_fee,
+        ));
+        config.dynamic_fee = dynamic_fee;
+    }
+    if let Some(dynamic_fee_base_bps) = dynamic_fee_base_bps {
+        logs.push(config_change_log(
+            "dynamic_fee_base_bps",
+            config.dynamic_fee_base_bps,
+            dynamic_fee_base_bps,
+        ));
+        config.dynamic_fee_base_bps = dynamic_fee_base_bps;
+    }
+    if let Some(dynamic_fee_max_bps) = dynamic_fee_max_bps {
+        logs.push(config_change_log(
+            "dynamic_fee_max_bps",
+            config.dynamic_fee_max_bps,
+            dynamic_fee_max_bps,
+        ));
+        config.dynamic_fee_max_bps = dynamic_fee_max_bps;
+    }
+    if let Some(dynamic_fee_min_bps) = dynamic_fee_min_bps {
+        logs.push(config_change_log(
+            "dynamic_fee_min_bps",
+            config.dynamic_fee_min_bps,
+            dynamic_fee_min_bps,
+        ));
+        config.dynamic_fee_min_bps = dynamic_fee_min_bps;
+    }
+    if let Some(depeg_threshold_bps) = depeg_threshold_bps {
+        logs.push(config_change_log(
+            "depeg_threshold_bps",
+            config.depeg_threshold_bps,
+            depeg_threshold_bps,
+        ));
+        config.depeg_threshold_bps = depeg_threshold_bps;
+    }
+    if let Some(max_referral_fee_bps) = max_referral_fee_bps {
+        logs.push(config_change_log(
+            "max_referral_fee_bps",
+            config.max_referral_fee_bps,
+            max_referral_fee_bps,
+        ));
+        config.max_referral_fee_bps = max_referral_fee_bps;
+    }
+    if let Some(admin_fee_bps) = admin_fee_bps {
+        logs.push(config_change_log(
+            "admin_fee_bps",
+            config.admin_fee_bps,
+            admin_fee_bps,
+        ));
+        config.admin_fee_bps = admin_fee_bps;
+    }
+    if let Some(max_volume_per_block) = max_volume_per_block {
+        logs.push(config_change_log(
+            "max_volume_per_block",
+            config.max_volume_per_block,
+            max_volume_per_block,
+        ));
+        config.max_volume_per_block = max_volume_per_block;
+    }
+    if let Some(rebalance_bonus_to_lp_bps) = rebalance_bonus_to_lp_bps {
+        logs.push(config_change_log(
+            "rebalance_bonus_to_lp_bps",
+            config.rebalance_bonus_to_lp_bps,
+            rebalance_bonus_to_lp_bps,
+        ));
+        config.rebalance_bonus_to_lp_bps = rebalance_bonus_to_lp_bps;
+    }
+    if let Some(deposit_bonus_bps) = deposit_bonus_bps {
+        logs.push(config_change_log(
+            "deposit_bonus_bps",
+            config.deposit_bonus_bps,
+            deposit_bonus_bps,
+        ));
+        config.deposit_bonus_bps = deposit_bonus_bps;
+    }
+    if let Some(per_asset_deposit_cap) = per_asset_deposit_cap {
+        logs.push(config_change_log(
+            "per_asset_deposit_cap",
+            config.per_asset_deposit_cap,
+            per_asset_deposit_cap,
+        ));
+        config.per_asset_deposit_cap = per_asset_deposit_cap;
+    }
+    if let Some(total_tvl_cap) = total_tvl_cap {
+        logs.push(config_change_log(
+            "total_tvl_cap",
+            config.total_tvl_cap,
+            total_tvl_cap,
+        ));
+        config.total_tvl_cap = total_tvl_cap;
+    }
+    if let Some(min_reserve_after_swap) = min_reserve_after_swap {
+        logs.push(config_change_log(
+            "min_reserve_after_swap",
+            config.min_reserve_after_swap,
+            min_reserve_after_swap,
+        ));
+        config.min_reserve_after_swap = min_reserve_after_swap;
+    }
+    if let Some(status) = status {
+        logs.push(config_change_log(
+            "status",
+            format!("{:?}", config.status),
+            format!("{:?}", status),
+        ));
+        config.status = status;
+    }
+    validate_config(&config)?;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: logs,
+        data: None,
+    })
+}
+
+/// "<old> -> <new>" of a field UpdateConfig set
+fn config_change_log(name: &str, old: impl Display, new: impl Display) -> LogAttribute {
+    log(name, format!("{} -> {}", old, new))
+}
+
+fn validate_config(config: &Config) -> StdResult<()> {
+    if config.swap_fee_denom == Uint128::zero() {
+        return Err(StdError::generic_err("swap_fee_denom must not be zero"));
+    }
+    if config.swap_fee_nom > config.swap_fee_denom {
+        return Err(StdError::generic_err(
+            "swap_fee_nom must not exceed swap_fee_denom",
+        ));
+    }
+    if config.round_down_pool_answer_to_nearest == Uint128::zero()
+        || config.round_down_pool_answer_to_nearest.u128() > MAX_ROUND_DOWN_TO_NEAREST
+    {
+        return Err(StdError::generic_err(format!(
+            "round_down_pool_answer_to_nearest must be between 1 and {}",
+            MAX_ROUND_DOWN_TO_NEAREST
+        )));
+    }
+    validate_bps("max_swap_out_bps", config.max_swap_out_bps)?;
+    validate_bps("dynamic_fee_base_bps", config.dynamic_fee_base_bps)?;
+    validate_bps("dynamic_fee_max_bps", config.dynamic_fee_max_bps)?;
+    validate_bps("dynamic_fee_min_bps", config.dynamic_fee_min_bps)?;
+    validate_bps("depeg_threshold_bps", config.depeg_threshold_bps)?;
+    validate_bps("max_referral_fee_bps", config.max_referral_fee_bps)?;
+    validate_bps("admin_fee_bps", config.admin_fee_bps)?;
+    validate_bps(
+        "rebalance_bonus_to_lp_bps",
+        config.rebalance_bonus_to_lp_bps,
+    )?;
+    validate_bps("deposit_bonus_bps", config.deposit_bonus_bps)?;
+    if config.dynamic_fee_base_bps > config.dynamic_fee_max_bps {
+        return Err(StdError::generic_err(
+            "dynamic_fee_base_bps must not exceed dynamic_fee_max_bps",
+        ));
+    }
+    if config.dynamic_fee_min_bps > config.dynamic_fee_base_bps {
+        return Err(StdError::generic_err(
+            "dynamic_fee_min_bps must not exceed dynamic_fee_base_bps",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_bps(name: &str, bps: u32) -> StdResult<()> {
+    if bps > MAX_BPS {
+        return Err(StdError::generic_err(format!(
+            "{} must not exceed {}",
+            name, MAX_BPS
+        )));
+    }
+    Ok(())
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetTokens {} => to_binary(&query_tokens(deps)?),
+        QueryMsg::MyPosition { address, key } => {
+            check_viewing_key(&deps.storage, &address, &key)?;
+            to_binary(&query_position(deps, address)?)
+        }
+        QueryMsg::TransactionHistory {
+            address,
+            key,
+            page,
+            page_size,
+        } => {
+            check_viewing_key(&deps.storage, &address, &key)?;
+            to_binary(&query_transaction_history(deps, address, page, page_size)?)
+        }
+        QueryMsg::WithPermit { permit, query } => query_with_permit(deps, permit, query),
+        QueryMsg::GetPools {} => to_binary(&query_pools(deps)?),
+        QueryMsg::GetConfig {} => to_binary(&query_config(deps)?),
+        QueryMsg::GetMostNeededToken {} => to_binary(&query_most_needed_token(deps)?),
+        QueryMsg::BatchSwapSimulation { swaps } => {
+            to_binary(&query_batch_swap_simulation(deps, swaps)?)
+        }
+        QueryMsg::LpValue { amount } => to_binary(&query_lp_value(deps, amount)?),
+        QueryMsg::LpNeededForWithdrawal { token, amount } => {
+            to_binary(&query_lp_needed_for_withdrawal(deps, token, amount)?)
+        }
+        QueryMsg::GetVolume {} => to_binary(&query_volume(deps)?),
+        QueryMsg::GetTokenIndex { token } => to_binary(&query_token_index(deps, token)?),
+        QueryMsg::GetTokenByIndex { index } => to_binary(&query_token_by_index(deps, index)?),
+        QueryMsg::GetCurveState {} => to_binary(&query_curve_state(deps)?),
+        QueryMsg::GetRebalancingDeposit {} => to_binary(&query_rebalancing_deposit(deps)?),
+        QueryMsg::GetPoolSnapshot {} => to_binary(&query_pool_snapshot(deps)?),
+        QueryMsg::GetImbalance {} => to_binary(&query_imbalance(deps)?),
+        QueryMsg::GetLiquidityToken {} => to_binary(&query_liquidity_token(deps)?),
+        QueryMsg::GetTvl {} => to_binary(&query_tvl(deps)?),
+        QueryMsg::GetDepositCaps {} => to_binary(&query_deposit_caps(deps)?),
+        QueryMsg::GetLpFlows {} => to_binary(&query_lp_flows(deps)?),
+        QueryMsg::GetAdminFees {} => to_binary(&query_admin_fees(deps)?),
+        QueryMsg::GetVirtualPriceTwap { window_seconds } => {
+            to_binary(&query_virtual_price_twap(deps, window_seconds)?)
+        }
+        QueryMsg::Twap {
+            base,
+            quote,
+            window,
+        } => to_binary(&query_twap(deps, base, quote, window)?),
+        QueryMsg::GetExecutionPrice { offer, to_token } => {
+            to_binary(&query_execution_price(deps, offer, to_token)?)
+        }
+        QueryMsg::GetAmountOut {
+            amount_in,
+            token_in,
+            token_out,
+        } => to_binary(&query_amount_out(deps, amount_in, token_in, token_out)?),
+        QueryMsg::GetAmountIn {
+            amount_out,
+            token_in,
+            token_out,
+        } => to_binary(&query_amount_in(deps, amount_out, token_in, token_out)?),
+        QueryMsg::Simulation {
+            offer_token,
+            ask_token,
+            amount,
+        } => to_binary(&query_simulation(deps, offer_token, ask_token, amount)?),
+        QueryMsg::ReverseSimulation {
+            ask_token,
+            ask_amount,
+            offer_token,
+        } => to_binary(&query_reverse_simulation(
+            deps,
+            ask_token,
+            ask_amount,
+            offer_token,
+        )?),
+        QueryMsg::MaxInputForImpact {
+            from_token,
+            to_token,
+            max_impact_bps,
+        } => to_binary(&query_max_input_for_impact(
+            deps,
+            from_token,
+            to_token,
+            max_impact_bps,
+        )?),
+        QueryMsg::DepthCurve {
+            from_token,
+            to_token,
+            max_input,
+            points,
+        } => to_binary(&query_depth_curve(
+            deps, from_token, to_token, max_input, points,
+        )?),
+        QueryMsg::GetEffectiveFee { offer, to_token } => {
+            to_binary(&query_effective_fee(deps, offer, to_token)?)
+        }
+    }
+}
+
+pub fn query_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<ConfigResponse> {
+    // Destructured so that a new Config field doesn't go unnoticed here
+    let Config {
+        admin,
+        pending_admin,
+        operators,
+        pool_address,
+        amp,
+        swap_fee_nom,
+        swap_fee_denom,
+        status,
+        is_halted: _,
+        round_down_pool_answer_to_nearest,
+        max_swap_out_bps,
+        dynamic_fee,
+        dynamic_fee_base_bps,
+        dynamic_fee_max_bps,
+        dynamic_fee_min_bps,
+        depeg_threshold_bps,
+        max_referral_fee_bps,
+        admin_fee_bps,
+        max_volume_per_block,
+        rebalance_bonus_to_lp_bps,
+        deposit_bonus_bps,
+        per_asset_deposit_cap,
+        total_tvl_cap,
+        min_reserve_after_swap,
+        seed_mode,
+        seed_tvl_cap,
+        lp_token_address,
+        lp_token_code_hash,
+        factory,
+    } = read_config(&deps.storage)?;
+
+    Ok(ConfigResponse {
+        admin,
+        pending_admin,
+        operators,
+        pool_address,
+        amp,
+        swap_fee_nom,
+        swap_fee_denom,
+        status,
+        round_down_pool_answer_to_nearest,
+        max_swap_out_bps,
+        dynamic_fee,
+        dynamic_fee_base_bps,
+        dynamic_fee_max_bps,
+        dynamic_fee_min_bps,
+        depeg_threshold_bps,
+        max_referral_fee_bps,
+        admin_fee_bps,
+        max_volume_per_block,
+        rebalance_bonus_to_lp_bps,
+        deposit_bonus_bps,
+        per_asset_deposit_cap,
+        total_tvl_cap,
+        min_reserve_after_swap,
+        seed_mode,
+        seed_tvl_cap,
+        lp_token_address,
+        lp_token_code_hash,
+        factory,
+    })
+}
+
+pub fn query_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<TokensResponse> {
+    // Viewing keys are not exposed
+    Ok(TokensResponse {
+        tokens: read_all_assets(&deps.storage)?
+            .into_iter()
+            .map(|t| Token {
+                address: t.address,
+                code_hash: t.code_hash,
+                native: t.native,
+            })
+            .collect(),
+    })
+}
+
+pub fn query_pools<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<PoolsResponse> {
+    Ok(PoolsResponse {
+        assets: read_all_assets(&deps.storage)?
+            .into_iter()
+            .zip(read_reserves(&deps.storage)?)
+            .map(|(token, balance)| PoolAsset {
+                address: token.address,
+                decimals: token.decimals,
+                balance,
+            })
+            .collect(),
+    })
+}
+
+/// Compares normalized balances, so assets of any decimals weigh the same
+pub fn query_most_needed_token<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<MostNeededTokenResponse> {
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let balances = normalize_balances(&read_reserves(&deps.storage)?, &supported_tokens)?;
+    let total = balances
+        .iter()
+        .try_fold(U256::zero(), |acc, x| acc.checked_add(*x))
+        .ok_or_else(|| StdError::generic_err("TVL doesn't fit in a U256"))?;
+    let mean = total / U256::from(balances.len().max(1));
+
+    // min_by_key keeps the first of equal balances
+    let needed = supported_tokens
+        .iter()
+        .zip(balances.iter())
+        .filter(|(token, _)| token.enabled && !token.withdraw_only)
+        .min_by_key(|(_, balance)| **balance);
+    Ok(match needed {
+        Some((token, balance)) => MostNeededTokenResponse {
+            token: Some(token.address.clone()),
+            deficit: u256_to_uint128(Some(mean.saturating_sub(*balance)))
+                .ok_or_else(|| StdError::generic_err("Deficit doesn't fit in a Uint128"))?,
+        },
+        None => MostNeededTokenResponse {
+            token: None,
+            deficit: Uint128::zero(),
+        },
+    })
+}
+
+pub fn query_token_index<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    token: HumanAddr,
+) -> StdResult<TokenIndexResponse> {
+    read_all_assets(&deps.storage)?
+        .iter()
+        .find(|t| t.address == token)
+        .map(|t| TokenIndexResponse { index: t.id })
+        .ok_or_else(|| ContractError::UnknownAsset(token.clone()).into())
+}
+
+pub fn query_token_by_index<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    index: u32,
+) -> StdResult<TokenByIndexResponse> {
+    let asset = read_all_assets(&deps.storage)?
+        .into_iter()
+        .find(|t| t.id == index)
+        .ok_or_else(|| {
+            StdError::generic_err(format!("Token index {} is not in the pool", index))
+        })?;
+    Ok(TokenByIndexResponse {
+        token: Token {
+            address: asset.address,
+            code_hash: asset.code_hash,
+            native: asset.native,
+        },
+    })
+}
+
+/// Cumulative volume of every pool asset, including the ones never swapped
+pub fn query_volume<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<VolumeResponse> {
+    let volumes = read_volumes(&deps.storage)?;
+
+    Ok(VolumeResponse {
+        volumes: read_all_assets(&deps.storage)?
+            .into_iter()
+            .map(|t| {
+                volumes
+                    .iter()
+                    .find(|v| v.address == t.address)
+                    .cloned()
+                    .unwrap_or(TokenVolume {
+                        address: t.address,
+                        volume_in: Uint128::zero(),
+                        volume_out: Uint128::zero(),
+                    })
+            })
+            .collect(),
+    })
+}
+
+/// Fee that a swap of `offer` into `to_token` would pay right now
+pub fn query_effective_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer: TokenAmount,
+    to_token: HumanAddr,
+) -> StdResult<EffectiveFeeResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &offer.address)?;
+    let dst_token = find_asset(&supported_tokens, &to_token)?;
+
+    let (fee_nom, fee_denom) = swap_fee_ratio(
+        deps,
+        &config,
+        &supported_tokens,
+        src_token,
+        dst_token,
+        offer.amount,
+    )?;
+
+    Ok(EffectiveFeeResponse {
+        fee_bps: fee_ratio_bps(fee_nom, fee_denom)?,
+        dynamic: config.dynamic_fee,
+    })
+}
+
+/// A (nom, denom) fee ratio in bps, rounded up
+fn fee_ratio_bps(fee_nom: U256, fee_denom: U256) -> StdResult<u32> {
+    div_ceil(
+        mul(Some(fee_nom), Some(U256::from(MAX_BPS))),
+        Some(fee_denom),
+    )
+    .filter(|fee_bps| fee_bps.bits() <= 32)
+    .map(|fee_bps| fee_bps.low_u128() as u32)
+    .ok_or_else(|| StdError::generic_err("Cannot compute the swap fee"))
+}
+
+pub fn query_batch_swap_simulation<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    swaps: Vec<SimulatedSwap>,
+) -> StdResult<BatchSwapSimulationResponse> {
+    if swaps.len() > MAX_BATCH_SIMULATIONS {
+        return Err(StdError::generic_err(format!(
+            "Cannot simulate more than {} swaps at once",
+            MAX_BATCH_SIMULATIONS
+        )));
+    }
+
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    // Every swap is priced against the same current state, not applied sequentially
+    let simulations = swaps
+        .iter()
+        .map(|swap| {
+            let src_token = find_asset(&supported_tokens, &swap.offer.address)?;
+            let dst_token = find_asset(&supported_tokens, &swap.to_token)?;
+            simulate_swap(
+                deps,
+                &config,
+                &supported_tokens,
+                src_token,
+                dst_token,
+                swap.offer.amount,
+            )
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(BatchSwapSimulationResponse { simulations })
+}
+
+pub fn query_amount_out<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    amount_in: Uint128,
+    token_in: HumanAddr,
+    token_out: HumanAddr,
+) -> StdResult<AmountOutResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &token_in)?;
+    let dst_token = find_asset(&supported_tokens, &token_out)?;
+
+    simulate_swap(
+        deps,
+        &config,
+        &supported_tokens,
+        src_token,
+        dst_token,
+        amount_in,
+    )
+    .map(|simulation| AmountOutResponse {
+        amount_out: simulation.return_amount,
+    })
+}
+
+pub fn query_simulation<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_token: HumanAddr,
+    ask_token: HumanAddr,
+    amount: Uint128,
+) -> StdResult<SimulationResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &offer_token)?;
+    let dst_token = find_asset(&supported_tokens, &ask_token)?;
+
+    let balances = read_reserves(&deps.storage)?;
+    let simulation = simulate_swap_at(
+        &config,
+        &supported_tokens,
+        &balances,
+        src_token,
+        dst_token,
+        amount,
+    )?;
+
+    let (price_nom, price_denom) = spot_price_ratio(
+        &config,
+        &supported_tokens,
+        &normalize_balances(&balances, &supported_tokens)?,
+        src_token,
+        dst_token,
+    )?;
+    let spot_output = div(
+        mul(normalize(amount, src_token.decimals), Some(price_nom)),
+        Some(price_denom),
+    )
+    .and_then(|output| denormalize(output, dst_token.decimals))
+    .ok_or_else(|| StdError::generic_err("Cannot compute the spread"))?;
+    let spread_amount = (spot_output - (simulation.return_amount + simulation.fee_amount))
+        .unwrap_or_else(|_| Uint128::zero());
+    let (fee_nom, fee_denom) = swap_fee_ratio_at(
+        &config,
+        &supported_tokens,
+        &balances,
+        src_token,
+        dst_token,
+        amount,
+    )?;
+
+    Ok(SimulationResponse {
+        return_amount: simulation.return_amount,
+        fee_amount: simulation.fee_amount,
+        spread_amount,
+        fee_bps: fee_ratio_bps(fee_nom, fee_denom)?,
+    })
+}
 
-    Ok(HandleResponse {
-        messages,
-        log: vec![
-            log("action", "swap"),
-            log("offer_asset", offer_asset.info.to_string()),
-            log("ask_asset", ask_pool.info.to_string()),
-            log("offer_amount", offer_amount.to_string()),
-            log("return_amount", return_amount.to_string()),
-            log("spread_amount", spread_amount.to_string()),
-            log("commission_amount", commission_amount.to_string()),
-        ],
-        data: None,
+pub fn query_reverse_simulation<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    ask_token: HumanAddr,
+    ask_amount: Uint128,
+    offer_token: HumanAddr,
+) -> StdResult<ReverseSimulationResponse> {
+    let offer_amount = swap_amount_in(deps, ask_amount, offer_token.clone(), ask_token.clone())?;
+    if offer_amount == Uint128::zero() {
+        return Ok(ReverseSimulationResponse {
+            offer_amount,
+            fee_amount: Uint128::zero(),
+            spread_amount: Uint128::zero(),
+        });
+    }
+
+    let simulation = query_simulation(deps, offer_token, ask_token, offer_amount)?;
+    Ok(ReverseSimulationResponse {
+        offer_amount,
+        fee_amount: simulation.fee_amount,
+        spread_amount: simulation.spread_amount,
     })
 }
 
-pub fn query<S: Storage, A: Api, Q: Querier>(
+/// Searched over simulate_swap rather than inverted, so it rounds exactly like a swap does,
+/// dynamic fee included
+pub fn query_amount_in<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
-    msg: QueryMsg,
+    amount_out: Uint128,
+    token_in: HumanAddr,
+    token_out: HumanAddr,
+) -> StdResult<AmountInResponse> {
+    Ok(AmountInResponse {
+        amount_in: swap_amount_in(deps, amount_out, token_in, token_out)?,
+    })
+}
+
+/// Smallest amount of token_in that swaps into at least amount_out of token_out
+fn swap_amount_in<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    amount_out: Uint128,
+    token_in: HumanAddr,
+    token_out: HumanAddr,
+) -> StdResult<Uint128> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &token_in)?;
+    let dst_token = find_asset(&supported_tokens, &token_out)?;
+
+    let return_amount = |input: u128| {
+        simulate_swap(
+            deps,
+            &config,
+            &supported_tokens,
+            src_token,
+            dst_token,
+            Uint128(input),
+        )
+        .map(|simulation| simulation.return_amount)
+    };
+    if amount_out == Uint128::zero() {
+        // Still fails for disabled assets
+        return_amount(0)?;
+        return Ok(Uint128::zero());
+    }
+
+    // `lo` returns less than amount_out and `hi` at least amount_out. Without the fee the
+    // input is amount_out converted to the input's decimals, so start from there.
+    let mut lo = 0u128;
+    let mut hi = convert_decimals(amount_out, dst_token.decimals, src_token.decimals)
+        .map_or(1, |amount| amount.u128().max(1));
+    while return_amount(hi)? < amount_out {
+        lo = hi;
+        hi = hi.checked_mul(2).ok_or_else(|| {
+            StdError::generic_err(format!(
+                "No amount of {:?} swaps into {} {:?}",
+                src_token.address, amount_out, dst_token.address
+            ))
+        })?;
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if return_amount(mid)? < amount_out {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Ok(Uint128(hi))
+}
+
+/// Fewest LP tokens WithdrawLiquidityOneToken pays at least `amount` of `token` for, searched
+/// over withdraw_one_coin the way swap_amount_in searches over the swap simulation
+pub fn query_lp_needed_for_withdrawal<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    token: HumanAddr,
+    amount: Uint128,
+) -> StdResult<LpNeededForWithdrawalResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let index = supported_tokens
+        .iter()
+        .position(|t| t.address == token)
+        .ok_or_else(|| ContractError::UnknownAsset(token.clone()))?;
+    let token_info = &supported_tokens[index];
+    // The withdrawal is priced like a swap into it, which disabled assets don't take
+    if !token_info.enabled {
+        return Err(ContractError::AssetDisabled(token).into());
+    }
+
+    let reserves = read_reserves(&deps.storage)?;
+    if amount > reserves[index] {
+        return Err(StdError::generic_err(format!(
+            "Cannot withdraw {} of {:?}, the pool only holds {}",
+            amount, token, reserves[index]
+        )));
+    }
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    if total_supply == Uint128::zero() {
+        return Err(StdError::generic_err("The pool has no liquidity"));
+    }
+    let balances = normalize_balances(&reserves, &supported_tokens)?;
+    if balances[index].is_zero() {
+        return Err(ContractError::EmptyAsset(token).into());
+    }
+    if amount == Uint128::zero() {
+        return Ok(LpNeededForWithdrawalResponse {
+            lp_amount: Uint128::zero(),
+        });
+    }
+
+    let overflow = || StdError::generic_err("Cannot compute the LP needed for the withdrawal");
+    let (fee_nom, fee_denom) = imbalance_fee_ratio(&config);
+    let payout = |burn: u128| -> StdResult<Uint128> {
+        let (withdrawn, _) = withdraw_one_coin(
+            config.amp,
+            &balances,
+            index,
+            U256::from(burn),
+            U256::from(total_supply.u128()),
+            fee_nom,
+            fee_denom,
+        )?;
+        let withdrawn = denormalize(withdrawn, token_info.decimals).ok_or_else(overflow)?;
+        round_down_answer(&config, token_info, withdrawn)
+    };
+
+    // The last LP token can only be withdrawn in every asset
+    let max_burn = total_supply.u128() - 1;
+    // `lo` pays less than amount and `hi` at least amount. Without the fee and slippage the
+    // burn is amount's share of D, so start from there.
+    let d = get_d(config.amp, &balances)?;
+    let mut lo = 0u128;
+    let mut hi = u256_to_uint128(div(
+        mul(
+            normalize(amount, token_info.decimals),
+            Some(U256::from(total_supply.u128())),
+        ),
+        Some(d),
+    ))
+    .map_or(max_burn, |burn| burn.u128().max(1).min(max_burn));
+    while payout(hi)? < amount {
+        if hi == max_burn {
+            return Err(StdError::generic_err(format!(
+                "No withdrawal of {:?} pays {}",
+                token, amount
+            )));
+        }
+        lo = hi;
+        hi = hi.saturating_mul(2).min(max_burn);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if payout(mid)? < amount {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Ok(LpNeededForWithdrawalResponse {
+        lp_amount: Uint128(hi),
+    })
+}
+
+pub fn query_lp_value<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    amount: Uint128,
+) -> StdResult<LpValueResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    if total_supply == Uint128::zero() {
+        return Ok(LpValueResponse {
+            value: Uint128::zero(),
+            assets: supported_tokens
+                .into_iter()
+                .map(|t| TokenAmount {
+                    address: t.address,
+                    code_hash: t.code_hash,
+                    amount: Uint128::zero(),
+                })
+                .collect(),
+        });
+    }
+
+    let balances = read_reserves(&deps.storage)?;
+    let normalized_balances = normalize_balances(&balances, &supported_tokens)?;
+    let d = get_d(config.amp, &normalized_balances)?;
+
+    let pro_rata = |value: U256| {
+        u256_to_uint128(div(
+            mul(Some(value), Some(U256::from(amount.u128()))),
+            Some(U256::from(total_supply.u128())),
+        ))
+        .ok_or_else(|| StdError::generic_err("Cannot compute LP value"))
+    };
+
+    let mut assets = vec![];
+    for (balance, token) in balances.iter().zip(supported_tokens) {
+        assets.push(TokenAmount {
+            address: token.address,
+            code_hash: token.code_hash,
+            amount: pro_rata(U256::from(balance.u128()))?,
+        });
+    }
+
+    Ok(LpValueResponse {
+        value: pro_rata(d)?,
+        assets,
+    })
+}
+
+fn query_with_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
 ) -> StdResult<Binary> {
-    todo!();
-    match msg {
-        QueryMsg::GetTokens {} => to_binary(&query_pair_info(&deps)?),
-        QueryMsg::GetPools {} => to_binary(&query_pool(&deps)?),
-        QueryMsg::GetConfig {} => to_binary(todo!()),
-        QueryMsg::GetMostNeededToken {} => to_binary(todo!()),
+    // Queries don't see the contract address, the config holds it
+    let pool = read_config(&deps.storage)?.pool_address;
+    match query {
+        QueryWithPermit::MyPosition {} => {
+            let address = validate_permit(&deps.storage, &pool, &permit, Permission::Position)?;
+            to_binary(&query_position(deps, address)?)
+        }
+        QueryWithPermit::TransactionHistory { page, page_size } => {
+            let address = validate_permit(&deps.storage, &pool, &permit, Permission::History)?;
+            to_binary(&query_transaction_history(deps, address, page, page_size)?)
+        }
+    }
+}
+
+/// page_size is capped at MAX_HISTORY_PAGE_SIZE
+pub fn query_transaction_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+    page: Option<u32>,
+    page_size: u32,
+) -> StdResult<TransactionHistoryResponse> {
+    let page_size = page_size.min(MAX_HISTORY_PAGE_SIZE);
+    Ok(TransactionHistoryResponse {
+        txs: read_swap_txs(&deps.storage, &address, page.unwrap_or(0), page_size)?,
+        total: read_swap_tx_count(&deps.storage, &address)?,
+    })
+}
+
+pub fn query_position<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+) -> StdResult<PositionResponse> {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    let position = read_position(&deps.storage, &address)?;
+
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    // Burns of LP received by transfer can exceed what was minted, and transfers out can leave
+    // more counted than the supply
+    let shares = position
+        .lp_minted
+        .u128()
+        .saturating_sub(position.lp_burned.u128())
+        .min(total_supply.u128());
+    let pool_share = if total_supply == Uint128::zero() {
+        Decimal::zero()
+    } else {
+        decimal_from_u256_ratio(U256::from(shares), U256::from(total_supply.u128()))
+            .ok_or_else(|| StdError::generic_err("Cannot compute pool share"))?
+    };
+    let value = query_lp_value(deps, Uint128(shares))?;
+
+    Ok(PositionResponse {
+        shares: Uint128(shares),
+        pool_share,
+        value: value.value,
+        assets: value.assets,
+        lp_minted: position.lp_minted,
+        lp_burned: position.lp_burned,
+        flows: position.assets,
+    })
+}
+
+pub fn query_tvl<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<TvlResponse> {
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let balances = read_reserves(&deps.storage)?;
+    let normalized_balances = normalize_balances(&balances, &supported_tokens)?;
+    let tvl = normalized_balances
+        .iter()
+        .try_fold(U256::zero(), |acc, x| acc.checked_add(*x));
+
+    let to_uint128 = |value: Option<U256>| {
+        u256_to_uint128(value).ok_or_else(|| StdError::generic_err("TVL doesn't fit in a Uint128"))
+    };
+
+    Ok(TvlResponse {
+        tvl: to_uint128(tvl)?,
+        balances: normalized_balances
+            .into_iter()
+            .map(|balance| to_uint128(Some(balance)))
+            .collect::<StdResult<Vec<Uint128>>>()?,
+    })
+}
+
+pub fn query_deposit_caps<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<DepositCapsResponse> {
+    let config = read_config(&deps.storage)?;
+    let tvl = query_tvl(deps)?;
+
+    let headroom =
+        |cap: Uint128, balance: Uint128| (cap - balance).unwrap_or_else(|_| Uint128::zero());
+    let per_asset_headroom = if config.per_asset_deposit_cap > Uint128::zero() {
+        Some(
+            tvl.balances
+                .iter()
+                .map(|balance| headroom(config.per_asset_deposit_cap, *balance))
+                .collect(),
+        )
+    } else {
+        None
+    };
+    let tvl_headroom = if config.total_tvl_cap > Uint128::zero() {
+        Some(headroom(config.total_tvl_cap, tvl.tvl))
+    } else {
+        None
+    };
+
+    Ok(DepositCapsResponse {
+        per_asset_deposit_cap: config.per_asset_deposit_cap,
+        total_tvl_cap: config.total_tvl_cap,
+        per_asset_headroom,
+        tvl_headroom,
+    })
+}
+
+pub fn query_admin_fees<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<AdminFeesResponse> {
+    Ok(AdminFeesResponse {
+        fees: read_all_assets(&deps.storage)?
+            .into_iter()
+            .zip(read_admin_fees(&deps.storage)?)
+            .map(|(token, amount)| TokenAmount {
+                address: token.address,
+                code_hash: token.code_hash,
+                amount,
+            })
+            .collect(),
+    })
+}
+
+pub fn query_lp_flows<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<LpFlowsResponse> {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+    let flows = read_lp_flows(&deps.storage)?;
+
+    Ok(LpFlowsResponse {
+        total_lp_minted: flows.minted,
+        total_lp_burned: flows.burned,
+        lp_total_supply: query_token_total_supply(
+            deps,
+            &config.lp_token_address,
+            &config.lp_token_code_hash,
+        )?,
+    })
+}
+
+pub fn query_virtual_price_twap<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    window_seconds: u64,
+) -> StdResult<VirtualPriceTwapResponse> {
+    let observations = read_virtual_prices(&deps.storage)?;
+    let (first, last) = match (observations.first(), observations.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            return Err(StdError::generic_err(
+                "No virtual price has been recorded yet",
+            ))
+        }
+    };
+
+    // Each observation's price held since the one before it, so the history starts at the first
+    let end_time = last.time;
+    let start_time = end_time.saturating_sub(window_seconds).max(first.time);
+    if start_time == end_time {
+        return Ok(VirtualPriceTwapResponse {
+            twap: last.virtual_price,
+            start_time,
+            end_time,
+        });
+    }
+
+    let mut weighted_sum = Some(U256::zero());
+    for pair in observations.windows(2) {
+        if pair[1].time <= start_time {
+            continue;
+        }
+        let duration = pair[1].time - pair[0].time.max(start_time);
+        weighted_sum = add(
+            weighted_sum,
+            mul(
+                Some(U256::from(pair[1].virtual_price.u128())),
+                Some(U256::from(duration)),
+            ),
+        );
+    }
+    let twap = u256_to_uint128(div(weighted_sum, Some(U256::from(end_time - start_time))))
+        .ok_or_else(|| StdError::generic_err("Cannot calculate the virtual price TWAP"))?;
+
+    Ok(VirtualPriceTwapResponse {
+        twap,
+        start_time,
+        end_time,
+    })
+}
+
+pub fn query_twap<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    base: HumanAddr,
+    quote: HumanAddr,
+    window: u64,
+) -> StdResult<TwapResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let base_token = find_asset(&supported_tokens, &base)?;
+    let quote_token = find_asset(&supported_tokens, &quote)?;
+    if base_token.id == quote_token.id {
+        return Err(StdError::generic_err("base and quote must differ"));
+    }
+
+    let observations: Vec<(u64, U256)> = read_price_observations(&deps.storage)?
+        .iter()
+        .filter_map(|o| {
+            let cumulative = o.cumulative_price(base_token.id, quote_token.id)?;
+            Some((o.time, U256::from(cumulative.u128())))
+        })
+        .collect();
+    let (first, last) = match (observations.first(), observations.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => {
+            return Err(StdError::generic_err(
+                "No price has been recorded for this pair yet",
+            ))
+        }
+    };
+
+    let end_time = last.0;
+    let start_time = end_time.saturating_sub(window).max(first.0);
+    let overflow = || StdError::generic_err("Cannot calculate the TWAP");
+    if start_time == end_time {
+        // No history to average over, the price since the last observation is the spot price
+        let balances = normalize_balances(&read_reserves(&deps.storage)?, &supported_tokens)?;
+        let (price_nom, price_denom) = spot_price_ratio(
+            &config,
+            &supported_tokens,
+            &balances,
+            base_token,
+            quote_token,
+        )?;
+        return Ok(TwapResponse {
+            price: decimal_from_u256_ratio(price_nom, price_denom).ok_or_else(overflow)?,
+            start_time,
+            end_time,
+        });
+    }
+
+    // The accumulator grows linearly between observations, so it can be read at any time
+    // between them
+    let start_cumulative = observations
+        .windows(2)
+        .find(|pair| pair[1].0 > start_time)
+        .map(|pair| {
+            let growth = wrapping_sub(pair[1].1, pair[0].1);
+            add(
+                Some(pair[0].1),
+                div(
+                    mul(Some(growth), Some(U256::from(start_time - pair[0].0))),
+                    Some(U256::from(pair[1].0 - pair[0].0)),
+                ),
+            )
+        })
+        .unwrap_or(Some(last.1))
+        .ok_or_else(overflow)?;
+    let growth = wrapping_sub(last.1, start_cumulative);
+    let duration = mul(
+        Some(U256::from(end_time - start_time)),
+        Some(U256::exp10(NORMALIZED_DECIMALS as usize)),
+    )
+    .ok_or_else(overflow)?;
+    let price = decimal_from_u256_ratio(growth, duration).ok_or_else(overflow)?;
+
+    Ok(TwapResponse {
+        price,
+        start_time,
+        end_time,
+    })
+}
+
+/// `a - b` for cumulative prices, which wrap at u128::MAX
+fn wrapping_sub(a: U256, b: U256) -> U256 {
+    U256::from(a.low_u128().wrapping_sub(b.low_u128()))
+}
+
+/// A is constant for now, so the stored value is the current one
+pub fn query_curve_state<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<CurveStateResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let balances = read_reserves(&deps.storage)?;
+    let normalized_balances = normalize_balances(&balances, &supported_tokens)?;
+    let d = get_d(config.amp, &normalized_balances)?;
+    let lp_total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+
+    let to_uint128 = |value: U256| {
+        u256_to_uint128(Some(value))
+            .ok_or_else(|| StdError::generic_err(format!("{} doesn't fit in a Uint128", value)))
+    };
+
+    Ok(CurveStateResponse {
+        amp: config.amp,
+        d: to_uint128(d)?,
+        balances: normalized_balances
+            .into_iter()
+            .map(to_uint128)
+            .collect::<StdResult<Vec<Uint128>>>()?,
+        lp_total_supply,
+    })
+}
+
+pub fn query_execution_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer: TokenAmount,
+    to_token: HumanAddr,
+) -> StdResult<ExecutionPriceResponse> {
+    if offer.amount == Uint128::zero() {
+        return Err(StdError::generic_err("Cannot price a zero amount"));
     }
+
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &offer.address)?;
+    let dst_token = find_asset(&supported_tokens, &to_token)?;
+
+    let simulation = simulate_swap(
+        deps,
+        &config,
+        &supported_tokens,
+        src_token,
+        dst_token,
+        offer.amount,
+    )?;
+    let overflow = || StdError::generic_err("Cannot compute the execution price");
+    // Tiny inputs can round down to no output at all, which is an average price of zero
+    let average_price = decimal_from_u256_ratio(
+        normalize(simulation.return_amount, dst_token.decimals).ok_or_else(overflow)?,
+        normalize(offer.amount, src_token.decimals).ok_or_else(overflow)?,
+    )
+    .ok_or_else(overflow)?;
+
+    // The invariant's marginal price less the fee. Taking it from the curve rather than
+    // simulating a tiny swap keeps rounding out of the spot price.
+    let (price_nom, price_denom) = spot_price_ratio(
+        &config,
+        &supported_tokens,
+        &normalize_balances(&read_reserves(&deps.storage)?, &supported_tokens)?,
+        src_token,
+        dst_token,
+    )?;
+    let (fee_nom, fee_denom) = swap_fee_ratio(
+        deps,
+        &config,
+        &supported_tokens,
+        src_token,
+        dst_token,
+        Uint128::zero(),
+    )?;
+    let spot_price = decimal_from_u256_ratio(
+        mul(Some(price_nom), Some(fee_denom - fee_nom)).ok_or_else(overflow)?,
+        mul(Some(price_denom), Some(fee_denom)).ok_or_else(overflow)?,
+    )
+    .ok_or_else(overflow)?;
+
+    Ok(ExecutionPriceResponse {
+        average_price,
+        spot_price,
+    })
 }
 
-pub fn query_pair_info<S: Storage, A: Api, Q: Querier>(
+pub fn query_depth_curve<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
-) -> StdResult<PairInfo> {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    pair_info.to_normal(&deps)
+    from_token: HumanAddr,
+    to_token: HumanAddr,
+    max_input: Uint128,
+    points: u32,
+) -> StdResult<DepthCurveResponse> {
+    if points == 0 || points > MAX_DEPTH_POINTS {
+        return Err(StdError::generic_err(format!(
+            "Number of points must be between 1 and {}",
+            MAX_DEPTH_POINTS
+        )));
+    }
+
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &from_token)?;
+    let dst_token = find_asset(&supported_tokens, &to_token)?;
+
+    let mut curve = vec![];
+    for i in (0..points).rev() {
+        let input = u256_to_uint128(div(
+            Some(U256::from(max_input.u128())),
+            pow(Some(U256::from(10)), Some(U256::from(i))),
+        ))
+        .unwrap_or_else(Uint128::zero);
+        // Too small to be worth anything in the source token's decimals
+        if input == Uint128::zero() {
+            continue;
+        }
+
+        let simulation = simulate_swap(
+            deps,
+            &config,
+            &supported_tokens,
+            src_token,
+            dst_token,
+            input,
+        )?;
+        curve.push(DepthPoint {
+            input,
+            output: simulation.return_amount,
+            price_impact_bps: price_impact_bps(
+                input,
+                simulation.return_amount,
+                src_token,
+                dst_token,
+            ),
+        });
+    }
+
+    Ok(DepthCurveResponse { points: curve })
+}
+
+/// Shortfall of `output` against the value of `input` at 1:1, in bps
+fn price_impact_bps(
+    input: Uint128,
+    output: Uint128,
+    src_token: &TokenInfo,
+    dst_token: &TokenInfo,
+) -> u32 {
+    let ideal = convert_decimals(input, src_token.decimals, dst_token.decimals)
+        .unwrap_or_else(Uint128::zero);
+    if ideal == Uint128::zero() {
+        return MAX_BPS;
+    }
+    let shortfall = (ideal - output).unwrap_or_else(|_| Uint128::zero());
+    shortfall.multiply_ratio(MAX_BPS, ideal.u128()).u128() as u32
 }
 
-pub fn query_pool<S: Storage, A: Api, Q: Querier>(
+/// Binary searches the input between one unit and the one worth the whole destination
+/// reserve at 1:1 (or the max_swap_out_bps share of it), assuming the price impact grows
+/// with the input. Stops once the bounds are within 1 bps of each other, or after
+/// MAX_IMPACT_SEARCH_ITERATIONS with the best input found so far.
+pub fn query_max_input_for_impact<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
-) -> StdResult<PoolResponse> {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    let contract_addr = deps.api.human_address(&pair_info.contract_addr)?;
-    let assets: [Token; 2] = pair_info.query_pools(&deps, &contract_addr)?;
-    let total_share: Uint128 = query_token_total_supply(
-        &deps,
-        &deps.api.human_address(&pair_info.liquidity_token)?,
-        &pair_info.token_code_hash,
+    from_token: HumanAddr,
+    to_token: HumanAddr,
+    max_impact_bps: u32,
+) -> StdResult<DepthPoint> {
+    if max_impact_bps > MAX_BPS {
+        return Err(StdError::generic_err(format!(
+            "max_impact_bps must not exceed {}",
+            MAX_BPS
+        )));
+    }
+
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+    let src_token = find_asset(&supported_tokens, &from_token)?;
+    let dst_token = find_asset(&supported_tokens, &to_token)?;
+
+    // Surfaces disabled assets, later failures only mean the input is too big
+    simulate_swap(
+        deps,
+        &config,
+        &supported_tokens,
+        src_token,
+        dst_token,
+        Uint128(1),
     )?;
+    let evaluate = |input: u128| {
+        simulate_swap(
+            deps,
+            &config,
+            &supported_tokens,
+            src_token,
+            dst_token,
+            Uint128(input),
+        )
+        .ok()
+        .map(|simulation| DepthPoint {
+            input: Uint128(input),
+            output: simulation.return_amount,
+            price_impact_bps: price_impact_bps(
+                Uint128(input),
+                simulation.return_amount,
+                src_token,
+                dst_token,
+            ),
+        })
+        .filter(|point| point.price_impact_bps <= max_impact_bps)
+    };
 
-    let resp = PoolResponse {
+    let mut max_out = read_reserves(&deps.storage)?[asset_index(&supported_tokens, dst_token)];
+    if config.max_swap_out_bps > 0 {
+        max_out = max_out.multiply_ratio(config.max_swap_out_bps, MAX_BPS);
+    }
+    let upper = convert_decimals(max_out, dst_token.decimals, src_token.decimals)
+        .map_or(u128::MAX, |amount| amount.u128());
+
+    let mut best = DepthPoint {
+        input: Uint128::zero(),
+        output: Uint128::zero(),
+        price_impact_bps: 0,
+    };
+    if upper == 0 {
+        return Ok(best);
+    }
+    if let Some(point) = evaluate(upper) {
+        return Ok(point);
+    }
+    // `lo` stays within the threshold and `hi` beyond it
+    let (mut lo, mut hi) = (0u128, upper);
+    for _ in 0..MAX_IMPACT_SEARCH_ITERATIONS {
+        if hi - lo <= 1 || hi - lo <= lo / MAX_BPS as u128 {
+            break;
+        }
+        let mid = lo + (hi - lo) / 2;
+        match evaluate(mid) {
+            Some(point) => {
+                lo = mid;
+                best = point;
+            }
+            None => hi = mid,
+        }
+    }
+
+    Ok(best)
+}
+
+pub fn query_pool_snapshot<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<PoolSnapshotResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let balances = read_reserves(&deps.storage)?;
+    let normalized_balances = normalize_balances(&balances, &supported_tokens)?;
+    let lp_total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+
+    let assets = supported_tokens
+        .into_iter()
+        .zip(balances)
+        .zip(normalized_balances)
+        .map(|((token, balance), normalized_balance)| {
+            Ok(AssetSnapshot {
+                address: token.address,
+                code_hash: token.code_hash,
+                decimals: token.decimals,
+                balance,
+                normalized_balance: u256_to_uint128(Some(normalized_balance)).ok_or_else(|| {
+                    StdError::generic_err("Normalized balance doesn't fit in a Uint128")
+                })?,
+            })
+        })
+        .collect::<StdResult<Vec<AssetSnapshot>>>()?;
+
+    Ok(PoolSnapshotResponse {
         assets,
-        total_share,
+        lp_total_supply,
+    })
+}
+
+pub fn query_liquidity_token<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<LiquidityTokenResponse> {
+    let config = read_config(&deps.storage)?;
+    check_initialized(&config)?;
+
+    Ok(LiquidityTokenResponse {
+        address: config.lp_token_address,
+        code_hash: config.lp_token_code_hash,
+    })
+}
+
+pub fn query_imbalance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<ImbalanceResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let balances = read_reserves(&deps.storage)?;
+    let normalized_balances = normalize_balances(&balances, &supported_tokens)?;
+    let (imbalance_bps, index) = max_deviation_bps(config.amp, &normalized_balances)?;
+
+    Ok(ImbalanceResponse {
+        imbalance_bps,
+        token: index.map(|i| supported_tokens[i].address.clone()),
+    })
+}
+
+/// Deposits that lift every asset to the normalized balance of the largest one. Assets that
+/// can't be deposited, being disabled or withdraw-only, are left out and stay where they are.
+pub fn query_rebalancing_deposit<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<RebalancingDepositResponse> {
+    let config = read_config(&deps.storage)?;
+    let supported_tokens = read_all_assets(&deps.storage)?;
+
+    let balances = read_reserves(&deps.storage)?;
+    let normalized_balances = normalize_balances(&balances, &supported_tokens)?;
+    let target = normalized_balances
+        .iter()
+        .max()
+        .cloned()
+        .unwrap_or_else(U256::zero);
+
+    let overflow = || StdError::generic_err("Cannot compute rebalancing deposit");
+    let mut deposits = vec![];
+    let mut new_balances = vec![];
+    for (balance, token) in normalized_balances.iter().zip(supported_tokens.iter()) {
+        // ProvideLiquidity would reject the whole order over them
+        if !token.enabled || token.withdraw_only {
+            new_balances.push(*balance);
+            continue;
+        }
+        // Round up so the deposit reaches the target even after normalization
+        let factor = decimal_factor(token.decimals);
+        let amount = div_ceil(sub(Some(target), Some(*balance)), factor);
+        new_balances.push(add(Some(*balance), mul(amount, factor)).ok_or_else(overflow)?);
+        deposits.push(TokenAmount {
+            address: token.address.clone(),
+            code_hash: token.code_hash.clone(),
+            amount: u256_to_uint128(amount).ok_or_else(overflow)?,
+        });
+    }
+
+    let total_supply =
+        query_token_total_supply(deps, &config.lp_token_address, &config.lp_token_code_hash)?;
+    let lp_out = share_after_bonus(
+        &config,
+        &normalized_balances,
+        &new_balances,
+        deposit_share(&config, &normalized_balances, &new_balances, total_supply)?,
+        total_supply,
+    )?;
+
+    // Bonus = what the minted LP is worth after the deposit, minus what was deposited
+    let deposited_value = sub(
+        new_balances
+            .iter()
+            .try_fold(U256::zero(), |acc, x| acc.checked_add(*x)),
+        normalized_balances
+            .iter()
+            .try_fold(U256::zero(), |acc, x| acc.checked_add(*x)),
+    )
+    .ok_or_else(overflow)?;
+    let new_supply = U256::from(total_supply.u128()) + U256::from(lp_out.u128());
+    let bonus = if new_supply.is_zero() {
+        U256::zero()
+    } else {
+        let d = get_d(config.amp, &new_balances)?;
+        let lp_value = div(
+            mul(Some(U256::from(lp_out.u128())), Some(d)),
+            Some(new_supply),
+        )
+        .ok_or_else(overflow)?;
+        lp_value.saturating_sub(deposited_value)
     };
 
-    Ok(resp)
+    Ok(RebalancingDepositResponse {
+        deposits,
+        lp_out,
+        bonus: u256_to_uint128(Some(bonus)).ok_or_else(overflow)?,
+    })
+}
+
+/// The pool's balance of `tokens[index]` less the admin fees it holds of it, which aren't the
+/// LPs'
+fn query_lp_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    tokens: &[TokenInfo],
+    index: usize,
+    pool_address: &HumanAddr,
+) -> StdResult<Uint128> {
+    let balance = query_asset_balance(deps, &tokens[index], pool_address)?;
+    let admin_fee = read_admin_fees(&deps.storage)?[index];
+    Ok((balance - admin_fee).unwrap_or_else(|_| Uint128::zero()))
+}
+
+/// query_lp_balance of every asset
+fn query_pool_balances<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    tokens: &[TokenInfo],
+) -> StdResult<Vec<Uint128>> {
+    (0..tokens.len())
+        .map(|index| query_lp_balance(deps, tokens, index, &config.pool_address))
+        .collect()
+}
+
+fn normalize_balances(balances: &[Uint128], tokens: &[TokenInfo]) -> StdResult<Vec<U256>> {
+    balances
+        .iter()
+        .zip(tokens)
+        .map(|(balance, token)| normalize(*balance, token.decimals))
+        .collect::<Option<Vec<U256>>>()
+        .ok_or_else(|| StdError::generic_err("Cannot normalize pool balances"))
+}
+
+fn u256_to_uint128(value: Option<U256>) -> Option<Uint128> {
+    value
+        .filter(|value| value.bits() <= 128)
+        .map(|value| Uint128(value.low_u128()))
 }
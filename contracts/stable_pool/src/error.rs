@@ -0,0 +1,104 @@
+use cosmwasm_std::{HumanAddr, StdError, Uint128};
+use thiserror::Error;
+
+use crate::msg::PoolStatus;
+
+/// Failures callers may want to handle. Each message starts with a code in brackets that stays
+/// the same when the wording changes, so clients can match on it in the returned error string.
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("[unauthorized] Unauthorized")]
+    Unauthorized,
+
+    #[error("[not_initialized] Pool is not fully initialized, the LP token is not set yet")]
+    NotInitialized,
+
+    #[error("[not_active] Swaps and deposits are disabled, the pool is {0:?}")]
+    NotActive(PoolStatus),
+
+    #[error("[swaps_disabled] Swaps are disabled, deposits and withdrawals are still open")]
+    SwapsDisabled,
+
+    #[error("[deposits_disabled] Deposits are disabled, swaps and withdrawals are still open")]
+    DepositsDisabled,
+
+    #[error("[halted] Withdrawals are disabled while the pool is halted")]
+    Halted,
+
+    #[error("[reentrant] Reentrant call rejected")]
+    Reentrant,
+
+    #[error("[unknown_asset] Unknown asset {0:?}")]
+    UnknownAsset(HumanAddr),
+
+    #[error("[unknown_asset] Unknown source asset {0:?}")]
+    UnknownSourceAsset(HumanAddr),
+
+    #[error("[unknown_asset] Unknown destination asset {0:?}")]
+    UnknownDestinationAsset(HumanAddr),
+
+    #[error("[unknown_lp_token] {0:?} is not the pool's LP token")]
+    UnknownLpToken(HumanAddr),
+
+    #[error("[asset_disabled] Asset {0:?} is disabled")]
+    AssetDisabled(HumanAddr),
+
+    #[error("[asset_withdraw_only] Asset {0:?} is being removed, it can only be withdrawn")]
+    AssetWithdrawOnly(HumanAddr),
+
+    #[error("[empty_asset] The pool holds no {0:?}, it can't be priced until it's deposited")]
+    EmptyAsset(HumanAddr),
+
+    #[error(
+        "[slippage_exceeded] Deposit would mint {share} LP tokens, less than the minimum of {min_lp_out}"
+    )]
+    LpOutBelowMinimum { share: Uint128, min_lp_out: Uint128 },
+
+    #[error(
+        "[slippage_exceeded] Refund of {amount} {token} is less than the minimum of {min_amount}"
+    )]
+    RefundBelowMinimum {
+        amount: Uint128,
+        token: HumanAddr,
+        min_amount: Uint128,
+    },
+
+    #[error(
+        "[slippage_exceeded] Withdrawal would burn {burn} LP tokens, more than the max of {max_burn}"
+    )]
+    BurnAboveMaximum { burn: Uint128, max_burn: Uint128 },
+
+    #[error("[slippage_exceeded] Swap needs {amount_in} {token}, more than the {sent} sent")]
+    InputAboveSent {
+        amount_in: Uint128,
+        token: HumanAddr,
+        sent: Uint128,
+    },
+
+    #[error("[slippage_exceeded] Swap return {amount} is less than the minimum of {min_return}")]
+    ReturnBelowMinimum {
+        amount: Uint128,
+        min_return: Uint128,
+    },
+
+    #[error(
+        "[no_deposit_bonus] Deposit earns no bonus, it doesn't take an under-weighted asset towards the mean or there are no fees to pay one from"
+    )]
+    NoDepositBonus,
+
+    #[error("[deadline_expired] Deadline {deadline} has passed, the block time is {now}")]
+    DeadlineExpired { deadline: u64, now: u64 },
+
+    #[error("[invalid_permit] Query permit {0}")]
+    InvalidPermit(String),
+}
+
+impl From<ContractError> for StdError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            // Kept as the std variant so callers matching on StdError::Unauthorized still work
+            ContractError::Unauthorized => StdError::unauthorized(),
+            err => StdError::generic_err(err.to_string()),
+        }
+    }
+}
@@ -1,9 +1,16 @@
+pub mod auth;
 pub mod contract;
+pub mod error;
 pub mod math;
 pub mod msg;
 pub mod querier;
 pub mod state;
 pub mod u256_math;
 
+#[cfg(test)]
+mod mock_querier;
+#[cfg(test)]
+mod testing;
+
 #[cfg(all(target_arch = "wasm32", not(feature = "library")))]
 cosmwasm_std::create_entry_points!(contract);
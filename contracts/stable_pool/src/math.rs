@@ -1,4 +1,7 @@
-use cosmwasm_std::{Decimal, StdResult, Uint128};
+use cosmwasm_std::{Decimal, StdError, StdResult, Uint128};
+use primitive_types::U256;
+
+use crate::u256_math::{add, div, div_ceil, mul, pow, sub};
 
 /////////////////////////////////////////////////////////////
 const DECIMAL_FRACTIONAL: Uint128 = Uint128(1_000_000_000u128);
@@ -21,3 +24,1021 @@ pub fn decimal_subtraction(a: Decimal, b: Decimal) -> StdResult<Decimal> {
 pub fn decimal_multiplication(a: Decimal, b: Decimal) -> Decimal {
     Decimal::from_ratio(a * DECIMAL_FRACTIONAL * b, DECIMAL_FRACTIONAL)
 }
+
+/// nom / denom for values too large for Decimal::from_ratio, rounded down to 18 decimals
+pub fn decimal_from_u256_ratio(nom: U256, denom: U256) -> Option<Decimal> {
+    let one = U256::from(1_000_000_000_000_000_000u128);
+    let scaled = div(mul(Some(nom), Some(one)), Some(denom))?;
+    // Decimal::from_ratio scales its numerator up by 10^18 again
+    if scaled.bits() > 128 || scaled.low_u128().checked_mul(one.low_u128()).is_none() {
+        return None;
+    }
+    Some(Decimal::from_ratio(scaled.low_u128(), one.low_u128()))
+}
+
+/////////////////////////////////////////////////////////////
+// Decimal normalization
+
+/// Decimals all asset amounts are scaled to before they're compared or summed
+pub const NORMALIZED_DECIMALS: u8 = 18;
+
+/// 10^(NORMALIZED_DECIMALS - decimals), or None for tokens with too many decimals
+pub fn decimal_factor(decimals: u8) -> Option<U256> {
+    if decimals > NORMALIZED_DECIMALS {
+        return None;
+    }
+    pow(
+        Some(U256::from(10)),
+        Some(U256::from(NORMALIZED_DECIMALS - decimals)),
+    )
+}
+
+/// Scales `amount` of a token with `decimals` decimals up to NORMALIZED_DECIMALS
+pub fn normalize(amount: Uint128, decimals: u8) -> Option<U256> {
+    mul(Some(U256::from(amount.u128())), decimal_factor(decimals))
+}
+
+/// Scales a normalized `amount` back down to `decimals` decimals, rounding down
+pub fn denormalize(amount: U256, decimals: u8) -> Option<Uint128> {
+    div(Some(amount), decimal_factor(decimals))
+        .filter(|amount| amount.bits() <= 128)
+        .map(|amount| Uint128(amount.low_u128()))
+}
+
+/// Converts `amount` from `from_decimals` to `to_decimals` through its normalized value,
+/// rounding down when precision is lost
+pub fn convert_decimals(amount: Uint128, from_decimals: u8, to_decimals: u8) -> Option<Uint128> {
+    normalize(amount, from_decimals).and_then(|amount| denormalize(amount, to_decimals))
+}
+
+/// Rounds `amount`, in `decimals` decimals, down to a multiple of `nearest` normalized.
+/// A `nearest` below one unit of the asset leaves it as is.
+pub fn round_down_normalized(amount: Uint128, decimals: u8, nearest: Uint128) -> Option<Uint128> {
+    let normalized = normalize(amount, decimals)?;
+    let nearest = U256::from(nearest.u128());
+    if nearest.is_zero() {
+        return Some(amount);
+    }
+    denormalize(normalized - normalized % nearest, decimals)
+}
+
+/////////////////////////////////////////////////////////////
+// StableSwap invariant
+//
+// A * n^n * sum(x_i) + D = A * D * n^n + D^(n+1) / (n^n * prod(x_i))
+//
+// All balances are expected to be normalized to the same number of decimals. Empty balances
+// are left out, as if the pool didn't list the asset: prod(x_i) would be zero, and an asset the
+// pool holds none of has no price to give the others.
+
+/// Iteration cap for the Newton solvers, same as Curve's
+pub const MAX_ITERATIONS: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathError {
+    /// Overflow, underflow or division by zero in an intermediate step
+    Overflow,
+    /// Newton's method did not settle within MAX_ITERATIONS
+    NoConvergence,
+    /// Token indices are out of range or equal
+    InvalidIndex,
+    /// The balance swapped or withdrawn is empty, so it has no price
+    EmptyBalance,
+}
+
+impl From<MathError> for StdError {
+    fn from(err: MathError) -> Self {
+        StdError::generic_err(format!("StableSwap math error: {:?}", err))
+    }
+}
+
+/// A * n^n
+fn ann(amp: u64, n_coins: usize) -> Option<U256> {
+    let n = Some(U256::from(n_coins));
+    mul(Some(U256::from(amp)), pow(n, n))
+}
+
+/// Newton's method is considered converged once two iterations are within 1
+fn converged(current: U256, previous: U256) -> bool {
+    if current > previous {
+        current - previous <= U256::one()
+    } else {
+        previous - current <= U256::one()
+    }
+}
+
+/// Number of non-empty balances, the n the invariant is over
+fn n_priced(balances: &[U256]) -> usize {
+    balances.iter().filter(|x| !x.is_zero()).count()
+}
+
+/// Computes the invariant D for the given balances.
+pub fn get_d(amp: u64, balances: &[U256]) -> Result<U256, MathError> {
+    let balances: Vec<U256> = balances.iter().copied().filter(|x| !x.is_zero()).collect();
+    let n_coins = balances.len();
+    let n = Some(U256::from(n_coins));
+    let ann = ann(amp, n_coins);
+
+    let s = balances
+        .iter()
+        .try_fold(U256::zero(), |acc, x| acc.checked_add(*x))
+        .ok_or(MathError::Overflow)?;
+    if s.is_zero() {
+        return Ok(U256::zero());
+    }
+
+    // D = (Ann * S + D_P * n) * D / ((Ann - 1) * D + (n + 1) * D_P)
+    // where D_P = D^(n+1) / (n^n * prod(x_i))
+    let mut d = s;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = Some(d);
+        for x in &balances {
+            d_p = div(mul(d_p, Some(d)), mul(Some(*x), n));
+        }
+        let d_prev = d;
+        d = div(
+            mul(add(mul(ann, Some(s)), mul(d_p, n)), Some(d)),
+            add(
+                mul(sub(ann, Some(U256::one())), Some(d)),
+                mul(add(n, Some(U256::one())), d_p),
+            ),
+        )
+        .ok_or(MathError::Overflow)?;
+
+        if converged(d, d_prev) {
+            return Ok(d);
+        }
+    }
+
+    Err(MathError::NoConvergence)
+}
+
+/// Interpolates a fee between `base_bps` and `max_bps` by how imbalanced `balances` are,
+/// measured as sum(|x_i - D/n|) / D and capped at 1.
+pub fn imbalance_fee_bps(
+    amp: u64,
+    balances: &[U256],
+    base_bps: u32,
+    max_bps: u32,
+) -> Result<u32, MathError> {
+    let (deviation, d) = imbalance(amp, balances)?;
+    if d.is_zero() || max_bps <= base_bps {
+        return Ok(base_bps);
+    }
+
+    let range = max_bps - base_bps;
+    let extra = div(mul(Some(U256::from(range)), Some(deviation)), Some(d))
+        .ok_or(MathError::Overflow)?
+        .min(U256::from(range));
+
+    Ok(base_bps + extra.low_u64() as u32)
+}
+
+/// Lowers a fee from `base_bps` towards `min_bps` by the share of the imbalance of `before`
+/// that `after` removes, imbalance measured as in imbalance_fee_bps. None if `after` isn't
+/// less imbalanced than `before`.
+pub fn rebalancing_fee_bps(
+    amp: u64,
+    before: &[U256],
+    after: &[U256],
+    base_bps: u32,
+    min_bps: u32,
+) -> Result<Option<u32>, MathError> {
+    let (deviation_before, d_before) = imbalance(amp, before)?;
+    let (deviation_after, d_after) = imbalance(amp, after)?;
+    if d_before.is_zero() || d_after.is_zero() {
+        return Ok(None);
+    }
+
+    // deviation_after / d_after < deviation_before / d_before, cross-multiplied
+    let old = mul(Some(deviation_before), Some(d_after)).ok_or(MathError::Overflow)?;
+    let new = mul(Some(deviation_after), Some(d_before)).ok_or(MathError::Overflow)?;
+    if new >= old {
+        return Ok(None);
+    }
+    if base_bps <= min_bps {
+        return Ok(Some(base_bps));
+    }
+
+    let range = base_bps - min_bps;
+    let discount = div(mul(Some(U256::from(range)), Some(old - new)), Some(old))
+        .ok_or(MathError::Overflow)?
+        .min(U256::from(range));
+
+    Ok(Some(base_bps - discount.low_u64() as u32))
+}
+
+/// sum(|x_i - D/n|) of `balances`, along with D
+fn imbalance(amp: u64, balances: &[U256]) -> Result<(U256, U256), MathError> {
+    let d = get_d(amp, balances)?;
+    if d.is_zero() {
+        return Ok((U256::zero(), d));
+    }
+
+    let target = div(Some(d), Some(U256::from(n_priced(balances)))).ok_or(MathError::Overflow)?;
+    let mut deviation = Some(U256::zero());
+    for x in balances.iter().filter(|x| !x.is_zero()) {
+        let diff = if *x > target {
+            *x - target
+        } else {
+            target - *x
+        };
+        deviation = add(deviation, Some(diff));
+    }
+
+    Ok((deviation.ok_or(MathError::Overflow)?, d))
+}
+
+/// Returns the largest deviation of any balance from D/n, in bps of D, along with the
+/// index of that balance. Balanced or empty pools report zero and no index.
+pub fn max_deviation_bps(amp: u64, balances: &[U256]) -> Result<(u32, Option<usize>), MathError> {
+    let d = get_d(amp, balances)?;
+    if d.is_zero() {
+        return Ok((0, None));
+    }
+
+    let target = div(Some(d), Some(U256::from(n_priced(balances)))).ok_or(MathError::Overflow)?;
+    let mut max_diff = U256::zero();
+    let mut max_index = None;
+    for (i, x) in balances.iter().enumerate().filter(|(_, x)| !x.is_zero()) {
+        let diff = if *x > target {
+            *x - target
+        } else {
+            target - *x
+        };
+        if diff > max_diff {
+            max_diff = diff;
+            max_index = Some(i);
+        }
+    }
+
+    let bps = div(mul(Some(U256::from(10_000)), Some(max_diff)), Some(d))
+        .ok_or(MathError::Overflow)?
+        .min(U256::from(10_000));
+    if bps.is_zero() {
+        return Ok((0, None));
+    }
+
+    Ok((bps.low_u64() as u32, max_index))
+}
+
+/// Solves the invariant for the balance of `out_index`, given that the balance of
+/// `in_index` becomes `new_in_balance` and every other balance stays the same.
+pub fn get_y(
+    amp: u64,
+    balances: &[U256],
+    in_index: usize,
+    out_index: usize,
+    new_in_balance: U256,
+    d: U256,
+) -> Result<U256, MathError> {
+    if in_index == out_index || in_index >= balances.len() || out_index >= balances.len() {
+        return Err(MathError::InvalidIndex);
+    }
+    if balances[in_index].is_zero() || balances[out_index].is_zero() {
+        return Err(MathError::EmptyBalance);
+    }
+
+    let n_coins = n_priced(balances);
+    let n = Some(U256::from(n_coins));
+    let ann = ann(amp, n_coins);
+
+    // c = D^(n+1) / (n^n * prod(x_k) * A * n^n), for k != out_index
+    // s = sum(x_k), for k != out_index
+    let mut c = Some(d);
+    let mut s = Some(U256::zero());
+    for (k, balance) in balances.iter().enumerate() {
+        let x = if k == in_index {
+            new_in_balance
+        } else if k != out_index && !balance.is_zero() {
+            *balance
+        } else {
+            continue;
+        };
+        s = add(s, Some(x));
+        c = div(mul(c, Some(d)), mul(Some(x), n));
+    }
+    c = div(mul(c, Some(d)), mul(ann, n));
+    let b = add(s, div(Some(d), ann)).ok_or(MathError::Overflow)?;
+    let c = c.ok_or(MathError::Overflow)?;
+
+    // y = (y^2 + c) / (2y + b - D)
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        y = div(
+            add(mul(Some(y), Some(y)), Some(c)),
+            sub(add(mul(Some(y), Some(U256::from(2))), Some(b)), Some(d)),
+        )
+        .ok_or(MathError::Overflow)?;
+
+        if converged(y, y_prev) {
+            return Ok(y);
+        }
+    }
+
+    Err(MathError::NoConvergence)
+}
+
+/// Solves the invariant for the balance of `index` at `d`, every other balance staying the
+/// same
+pub fn get_y_d(amp: u64, balances: &[U256], index: usize, d: U256) -> Result<U256, MathError> {
+    // Any other non-empty asset can stand in as get_y's unchanged input
+    let other = balances
+        .iter()
+        .enumerate()
+        .position(|(k, x)| k != index && !x.is_zero())
+        .ok_or(MathError::InvalidIndex)?;
+    let other_balance = balances[other];
+    get_y(amp, balances, other, index, other_balance, d)
+}
+
+/// Output of swapping `dx` of `in_index` for `out_index`, before fees. One less than the
+/// invariant gives, as in Curve, so get_y's rounding never works for the trader.
+pub fn get_dy(
+    amp: u64,
+    balances: &[U256],
+    in_index: usize,
+    out_index: usize,
+    dx: U256,
+) -> Result<U256, MathError> {
+    let x = *balances.get(in_index).ok_or(MathError::InvalidIndex)?;
+    let d = get_d(amp, balances)?;
+    let new_in_balance = x.checked_add(dx).ok_or(MathError::Overflow)?;
+    let y = get_y(amp, balances, in_index, out_index, new_in_balance, d)?;
+    Ok(balances[out_index]
+        .saturating_sub(y)
+        .saturating_sub(U256::one()))
+}
+
+/// Input of `in_index` for which get_dy returns at least `dy` of `out_index`, before fees.
+/// One more than the invariant gives, so rounding again never works for the trader.
+pub fn get_dx(
+    amp: u64,
+    balances: &[U256],
+    in_index: usize,
+    out_index: usize,
+    dy: U256,
+) -> Result<U256, MathError> {
+    let x = *balances.get(in_index).ok_or(MathError::InvalidIndex)?;
+    let y = *balances.get(out_index).ok_or(MathError::InvalidIndex)?;
+    let d = get_d(amp, balances)?;
+    // get_dy holds back one unit, so the pool has to give up dy + 1
+    let new_out_balance =
+        sub(Some(y), add(Some(dy), Some(U256::one()))).ok_or(MathError::Overflow)?;
+    let new_in_balance = get_y(amp, balances, out_index, in_index, new_out_balance, d)?;
+    new_in_balance
+        .saturating_sub(x)
+        .checked_add(U256::one())
+        .ok_or(MathError::Overflow)
+}
+
+/// Output per unit of input for an infinitesimal swap of `in_index` for `out_index`, before
+/// fees, as (nom, denom). Neither balance may be empty.
+pub fn marginal_price(
+    amp: u64,
+    balances: &[U256],
+    in_index: usize,
+    out_index: usize,
+) -> Result<(U256, U256), MathError> {
+    if in_index == out_index || in_index >= balances.len() || out_index >= balances.len() {
+        return Err(MathError::InvalidIndex);
+    }
+    if balances[in_index].is_zero() || balances[out_index].is_zero() {
+        return Err(MathError::EmptyBalance);
+    }
+
+    let n_coins = n_priced(balances);
+    let n = Some(U256::from(n_coins));
+    let ann = ann(amp, n_coins);
+    let d = get_d(amp, balances)?;
+    let mut d_p = Some(d);
+    for x in balances.iter().filter(|x| !x.is_zero()) {
+        d_p = div(mul(d_p, Some(d)), mul(Some(*x), n));
+    }
+
+    // The ratio of the invariant's partial derivatives, (Ann + D_P / x_i) / (Ann + D_P / x_j),
+    // multiplied through by x_i * x_j
+    let (x_i, x_j) = (Some(balances[in_index]), Some(balances[out_index]));
+    let nom = mul(add(mul(ann, x_i), d_p), x_j).ok_or(MathError::Overflow)?;
+    let denom = mul(add(mul(ann, x_j), d_p), x_i).ok_or(MathError::Overflow)?;
+    Ok((nom, denom))
+}
+
+/// Balances after the imbalance fee on a deposit taking the pool from `old_balances` to
+/// `new_balances`, like Curve's add_liquidity: every asset pays a rate of
+/// fee_nom * n / (fee_denom * 4 * (n - 1)) on how far it ends up from where a balanced deposit
+/// reaching the same D would have put it. Nothing is charged on a deposit into an empty pool.
+pub fn balances_after_imbalance_fee(
+    amp: u64,
+    old_balances: &[U256],
+    new_balances: &[U256],
+    fee_nom: U256,
+    fee_denom: U256,
+) -> Result<Vec<U256>, MathError> {
+    let n_coins = n_priced(new_balances);
+    let d0 = get_d(amp, old_balances)?;
+    if n_coins < 2 || d0.is_zero() {
+        return Ok(new_balances.to_vec());
+    }
+    let d1 = get_d(amp, new_balances)?;
+
+    let fee_nom = mul(Some(fee_nom), Some(U256::from(n_coins)));
+    let fee_denom = mul(Some(fee_denom), Some(U256::from(4 * (n_coins - 1))));
+    old_balances
+        .iter()
+        .zip(new_balances)
+        .map(|(old, new)| {
+            let ideal = div(mul(Some(d1), Some(*old)), Some(d0)).ok_or(MathError::Overflow)?;
+            let diff = if ideal > *new {
+                ideal - *new
+            } else {
+                *new - ideal
+            };
+            // Rounded up, so rounding never favors the depositor
+            let fee = div_ceil(mul(fee_nom, Some(diff)), fee_denom).ok_or(MathError::Overflow)?;
+            Ok(new.saturating_sub(fee))
+        })
+        .collect()
+}
+
+/// Amount of `index` paid out for burning `burn` of `supply` LP, and the fee kept from it,
+/// like Curve's remove_liquidity_one_coin. The fee is charged at the imbalance fee rate on how
+/// far the withdrawal is from a proportional one.
+pub fn withdraw_one_coin(
+    amp: u64,
+    balances: &[U256],
+    index: usize,
+    burn: U256,
+    supply: U256,
+    fee_nom: U256,
+    fee_denom: U256,
+) -> Result<(U256, U256), MathError> {
+    let n_coins = n_priced(balances);
+    if index >= balances.len() || n_coins < 2 {
+        return Err(MathError::InvalidIndex);
+    }
+    if balances[index].is_zero() {
+        return Err(MathError::EmptyBalance);
+    }
+    let d0 = get_d(amp, balances)?;
+    let d1 =
+        sub(Some(d0), div(mul(Some(burn), Some(d0)), Some(supply))).ok_or(MathError::Overflow)?;
+    let new_y = get_y_d(amp, balances, index, d1)?;
+
+    let fee_nom = mul(Some(fee_nom), Some(U256::from(n_coins)));
+    let fee_denom = mul(Some(fee_denom), Some(U256::from(4 * (n_coins - 1))));
+    let reduced = balances
+        .iter()
+        .enumerate()
+        .map(|(j, x)| {
+            let proportional = div(mul(Some(*x), Some(d1)), Some(d0)).ok_or(MathError::Overflow)?;
+            let expected = if j == index {
+                proportional.saturating_sub(new_y)
+            } else {
+                x.saturating_sub(proportional)
+            };
+            let fee =
+                div_ceil(mul(fee_nom, Some(expected)), fee_denom).ok_or(MathError::Overflow)?;
+            Ok(x.saturating_sub(fee))
+        })
+        .collect::<Result<Vec<U256>, MathError>>()?;
+
+    let dy = reduced[index]
+        .saturating_sub(get_y_d(amp, &reduced, index, d1)?)
+        .saturating_sub(U256::one());
+    let fee = balances[index].saturating_sub(new_y).saturating_sub(dy);
+    Ok((dy, fee))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn tokens(amount: u128) -> U256 {
+        U256::from(amount) * U256::from(1_000_000_000_000_000_000u128)
+    }
+
+    fn u256(value: &str) -> U256 {
+        value
+            .chars()
+            .fold(U256::zero(), |acc, c| acc * 10 + c.to_digit(10).unwrap())
+    }
+
+    // Reference values below come from Curve's get_D/get_y (Vyper) ported to
+    // Python with exact integer arithmetic and Ann = A * n^n.
+
+    #[test]
+    fn get_d_equal_balances() {
+        let d = get_d(100, &[tokens(1_000), tokens(1_000)]).unwrap();
+        assert_eq!(d, tokens(2_000));
+
+        let d = get_d(1, &[tokens(5), tokens(5), tokens(5)]).unwrap();
+        assert_eq!(d, tokens(15));
+
+        assert_eq!(get_d(100, &[U256::zero(), U256::zero()]), Ok(U256::zero()));
+    }
+
+    #[test]
+    fn get_d_imbalanced_balances() {
+        let d = get_d(85, &[tokens(2_000), tokens(500)]).unwrap();
+        assert_eq!(d, u256("2495908255683374329048"));
+
+        let d = get_d(200, &[tokens(1_000), tokens(1_200), tokens(800)]).unwrap();
+        assert_eq!(d, u256("2999976865418221102546"));
+
+        let d = get_d(100, &[tokens(1_000_000), tokens(1)]).unwrap();
+        assert_eq!(d, u256("112414596047076015371970"));
+    }
+
+    #[test]
+    fn get_d_monotonic_in_balance() {
+        let mut previous = U256::zero();
+        for amount in &[1u128, 10, 100, 1_000, 10_000, 100_000, 1_000_000] {
+            let d = get_d(100, &[tokens(1_000), tokens(*amount)]).unwrap();
+            assert!(d > previous);
+            previous = d;
+        }
+
+        let d = get_d(100, &[tokens(1_000), tokens(1_000)]).unwrap();
+        let d_plus_one = get_d(100, &[tokens(1_000) + 1, tokens(1_000)]).unwrap();
+        assert!(d_plus_one > d);
+    }
+
+    #[test]
+    fn get_d_leaves_out_empty_reserves() {
+        assert_eq!(
+            get_d(100, &[tokens(1_000), U256::zero()]),
+            Ok(tokens(1_000))
+        );
+        assert_eq!(
+            get_d(100, &[tokens(1_000), U256::zero(), tokens(1_000)]),
+            get_d(100, &[tokens(1_000), tokens(1_000)])
+        );
+    }
+
+    #[test]
+    fn imbalance_fee_bps_scales_with_imbalance() {
+        let balanced = [tokens(1_000), tokens(1_000)];
+        assert_eq!(imbalance_fee_bps(100, &balanced, 4, 50), Ok(4));
+
+        // pool is 1500/500, swapping 100 either way
+        let worse = [tokens(1_600), tokens(400)];
+        let better = [tokens(1_400), tokens(600)];
+        let worse_fee = imbalance_fee_bps(100, &worse, 4, 50).unwrap();
+        let better_fee = imbalance_fee_bps(100, &better, 4, 50).unwrap();
+        assert!(better_fee < worse_fee);
+        assert!(worse_fee <= 50);
+
+        // fully drained side is capped at the max
+        let drained = [tokens(2_000), tokens(1)];
+        assert_eq!(imbalance_fee_bps(1, &drained, 4, 50), Ok(50));
+
+        // misconfigured range falls back to the base fee
+        assert_eq!(imbalance_fee_bps(100, &worse, 50, 4), Ok(50));
+    }
+
+    #[test]
+    fn rebalancing_fee_bps_discounts_improving_swaps() {
+        // pool is 1500/500
+        let before = [tokens(1_500), tokens(500)];
+        let worse = [tokens(1_600), tokens(400)];
+        assert_eq!(rebalancing_fee_bps(100, &before, &worse, 4, 1), Ok(None));
+        assert_eq!(rebalancing_fee_bps(100, &before, &before, 4, 1), Ok(None));
+
+        // the more of the imbalance a swap removes, the closer it gets to the floor
+        let better = [tokens(1_400), tokens(600)];
+        let balanced = [tokens(1_000), tokens(1_000)];
+        let better_fee = rebalancing_fee_bps(100, &before, &better, 40, 10)
+            .unwrap()
+            .unwrap();
+        assert!(better_fee < 40 && better_fee > 10);
+        assert_eq!(
+            rebalancing_fee_bps(100, &before, &balanced, 40, 10),
+            Ok(Some(10))
+        );
+
+        // no range to discount within
+        assert_eq!(
+            rebalancing_fee_bps(100, &before, &better, 10, 40),
+            Ok(Some(10))
+        );
+        let empty = [U256::zero(), U256::zero()];
+        assert_eq!(rebalancing_fee_bps(100, &empty, &better, 40, 10), Ok(None));
+    }
+
+    #[test]
+    fn max_deviation_bps_finds_most_off_token() {
+        let balanced = [tokens(1_000), tokens(1_000)];
+        assert_eq!(max_deviation_bps(100, &balanced), Ok((0, None)));
+
+        let empty = [U256::zero(), U256::zero()];
+        assert_eq!(max_deviation_bps(100, &empty), Ok((0, None)));
+
+        let skewed = [tokens(1_500), tokens(500)];
+        assert_eq!(max_deviation_bps(100, &skewed), Ok((2_506, Some(0))));
+
+        let three = [tokens(1_000), tokens(1_000), tokens(400)];
+        assert_eq!(max_deviation_bps(100, &three), Ok((1_666, Some(2))));
+    }
+
+    #[test]
+    fn get_y_two_tokens() {
+        let balances = [tokens(1_000), tokens(1_000)];
+        let d = tokens(2_000);
+
+        let y = get_y(100, &balances, 0, 1, tokens(1_010), d).unwrap();
+        assert_eq!(y, u256("990000497536942118076"));
+
+        let y = get_y(100, &balances, 0, 1, tokens(1_500), d).unwrap();
+        assert_eq!(y, u256("501644807803215474885"));
+
+        let balances = [tokens(2_000), tokens(500)];
+        let d = u256("2495908255683374329048");
+        let y = get_y(85, &balances, 1, 0, tokens(600), d).unwrap();
+        assert_eq!(y, u256("1898603367407949395994"));
+    }
+
+    #[test]
+    fn get_y_three_tokens() {
+        let balances = [tokens(1_000), tokens(1_200), tokens(800)];
+        let d = u256("2999976865418221102546");
+
+        let y = get_y(200, &balances, 2, 0, tokens(850), d).unwrap();
+        assert_eq!(y, u256("949994628450463912772"));
+    }
+
+    #[test]
+    fn get_y_unchanged_balance_returns_current() {
+        let balances = [tokens(1_000), tokens(1_000)];
+        let y = get_y(100, &balances, 0, 1, tokens(1_000), tokens(2_000)).unwrap();
+        assert!(converged(y, tokens(1_000)));
+    }
+
+    #[test]
+    fn get_y_invalid_input() {
+        let balances = [tokens(1_000), tokens(1_000)];
+        let d = tokens(2_000);
+
+        assert_eq!(
+            get_y(100, &balances, 0, 0, tokens(1_010), d),
+            Err(MathError::InvalidIndex)
+        );
+        assert_eq!(
+            get_y(100, &balances, 0, 2, tokens(1_010), d),
+            Err(MathError::InvalidIndex)
+        );
+        // an empty reserve can't be part of the product term
+        assert_eq!(
+            get_y(100, &balances, 0, 1, U256::zero(), d),
+            Err(MathError::Overflow)
+        );
+        // nor can a swap into or out of an asset the pool holds none of
+        assert_eq!(
+            get_y(100, &[tokens(1_000), U256::zero()], 0, 1, tokens(1_010), d),
+            Err(MathError::EmptyBalance)
+        );
+    }
+
+    #[test]
+    fn get_y_leaves_out_empty_balances() {
+        let balances = [tokens(1_000), U256::zero(), tokens(1_000)];
+        let y = get_y(100, &balances, 0, 2, tokens(1_010), tokens(2_000)).unwrap();
+        assert_eq!(y, u256("990000497536942118076"));
+    }
+
+    #[test]
+    fn decimal_from_u256_ratio_handles_normalized_amounts() {
+        // both sides are far beyond what Decimal::from_ratio can multiply by 10^18
+        assert_eq!(
+            decimal_from_u256_ratio(tokens(997_000), tokens(1_000_000)),
+            Some(Decimal::permille(997))
+        );
+        assert_eq!(
+            decimal_from_u256_ratio(U256::from(2), U256::from(3)),
+            Some(Decimal::from_ratio(
+                666_666_666_666_666_666u128,
+                tokens(1).low_u128()
+            ))
+        );
+        assert_eq!(
+            decimal_from_u256_ratio(U256::zero(), tokens(1)),
+            Some(Decimal::zero())
+        );
+        assert_eq!(decimal_from_u256_ratio(tokens(1), U256::zero()), None);
+        assert_eq!(
+            decimal_from_u256_ratio(U256::max_value(), U256::one()),
+            None
+        );
+    }
+
+    #[test]
+    fn get_dy_slips_with_trade_size() {
+        let balances = [tokens(1_000), tokens(1_000)];
+        assert_eq!(
+            get_dy(100, &balances, 0, 1, tokens(1)),
+            Ok(u256("999995024895447954"))
+        );
+        assert_eq!(
+            get_dy(100, &balances, 0, 1, tokens(500)),
+            Ok(u256("498355192196784525114"))
+        );
+        // a lower amplification curves more
+        assert_eq!(
+            get_dy(10, &balances, 0, 1, tokens(500)),
+            Ok(u256("485244355821438167911"))
+        );
+
+        // selling the scarce asset pays more than selling the plentiful one
+        let balances = [tokens(500), tokens(1_500)];
+        assert_eq!(
+            get_dy(100, &balances, 0, 1, tokens(100)),
+            Ok(u256("100709504098662648228"))
+        );
+        assert_eq!(
+            get_dy(100, &balances, 1, 0, tokens(100)),
+            Ok(u256("98878790860277961564"))
+        );
+
+        assert_eq!(
+            get_dy(100, &balances, 2, 0, tokens(1)),
+            Err(MathError::InvalidIndex)
+        );
+        assert_eq!(
+            get_dy(100, &balances, 0, 0, tokens(1)),
+            Err(MathError::InvalidIndex)
+        );
+    }
+
+    #[test]
+    fn get_dx_invalid_input() {
+        let balances = [tokens(1_000), tokens(1_000)];
+
+        assert_eq!(
+            get_dx(100, &balances, 2, 0, tokens(1)),
+            Err(MathError::InvalidIndex)
+        );
+        assert_eq!(
+            get_dx(100, &balances, 0, 0, tokens(1)),
+            Err(MathError::InvalidIndex)
+        );
+        // the pool can't pay out its whole reserve
+        assert_eq!(
+            get_dx(100, &balances, 0, 1, tokens(1_000)),
+            Err(MathError::Overflow)
+        );
+        assert_eq!(
+            get_dx(100, &balances, 0, 1, tokens(1_000) - 1),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn marginal_price_follows_scarcity() {
+        let (nom, denom) = marginal_price(100, &[tokens(1_000), tokens(1_000)], 0, 1).unwrap();
+        assert_eq!(nom, denom);
+
+        let balances = [tokens(500), tokens(1_500)];
+        let per_million = |(nom, denom): (U256, U256)| nom * U256::from(1_000_000) / denom;
+        assert_eq!(
+            per_million(marginal_price(100, &balances, 0, 1).unwrap()),
+            U256::from(1_008_827)
+        );
+        assert_eq!(
+            per_million(marginal_price(100, &balances, 1, 0).unwrap()),
+            U256::from(991_249)
+        );
+
+        assert_eq!(
+            marginal_price(100, &balances, 1, 1),
+            Err(MathError::InvalidIndex)
+        );
+        assert_eq!(
+            marginal_price(100, &[U256::zero(), tokens(1)], 0, 1),
+            Err(MathError::EmptyBalance)
+        );
+    }
+
+    #[test]
+    fn imbalance_fee_charges_the_skew() {
+        let balances = [tokens(1_000), tokens(1_000)];
+        let fee = |new: &[U256]| {
+            balances_after_imbalance_fee(100, &balances, new, U256::from(3), U256::from(1_000))
+        };
+
+        // a balanced deposit pays nothing, and neither does the first one
+        let balanced = [tokens(1_100), tokens(1_100)];
+        assert_eq!(fee(&balanced), Ok(balanced.to_vec()));
+        assert_eq!(
+            balances_after_imbalance_fee(
+                100,
+                &[U256::zero(), U256::zero()],
+                &balances,
+                U256::from(3),
+                U256::from(1_000)
+            ),
+            Ok(balances.to_vec())
+        );
+
+        // 100 in token0 alone is ~50 off a balanced deposit in each asset, charged 3/2000
+        assert_eq!(
+            fee(&[tokens(1_100), tokens(1_000)]),
+            Ok(vec![
+                u256("1099924991095808717632"),
+                u256("999925008904191282367")
+            ])
+        );
+    }
+
+    #[test]
+    fn withdraw_one_coin_pays_slippage_and_fee() {
+        let fee = (U256::from(3), U256::from(1_000));
+        let withdraw = |balances: &[U256], index| {
+            withdraw_one_coin(
+                100,
+                balances,
+                index,
+                tokens(100),
+                tokens(2_000),
+                fee.0,
+                fee.1,
+            )
+        };
+
+        // 5% of a 1000/1000 pool, all in token0
+        assert_eq!(
+            withdraw(&[tokens(1_000), tokens(1_000)], 0),
+            Ok((u256("99836934153243874014"), u256("149940864457325593")))
+        );
+
+        // the plentiful asset pays out more than the scarce one
+        let balances = [tokens(500), tokens(1_500)];
+        let (scarce, _) = withdraw(&balances, 0).unwrap();
+        let (plentiful, _) = withdraw(&balances, 1).unwrap();
+        assert_eq!(scarce, u256("98982326013651590604"));
+        assert_eq!(plentiful, u256("100132195564102781895"));
+
+        assert_eq!(withdraw(&balances, 2), Err(MathError::InvalidIndex));
+    }
+
+    #[test]
+    fn normalize_scales_up_to_normalized_decimals() {
+        assert_eq!(
+            normalize(Uint128(1_500_000), 6),
+            Some(U256::from(1_500_000_000_000_000_000u128))
+        );
+        assert_eq!(
+            normalize(Uint128(42), NORMALIZED_DECIMALS),
+            Some(U256::from(42))
+        );
+        assert_eq!(normalize(Uint128(1), 19), None);
+        assert_eq!(
+            normalize(Uint128(u128::MAX), 0),
+            mul(Some(U256::from(u128::MAX)), decimal_factor(0))
+        );
+    }
+
+    #[test]
+    fn denormalize_rounds_down() {
+        assert_eq!(denormalize(tokens(3), 6), Some(Uint128(3_000_000)));
+        assert_eq!(
+            denormalize(U256::from(1_999_999_999_999u128), 6),
+            Some(Uint128(1))
+        );
+        assert_eq!(
+            denormalize(U256::from(42), NORMALIZED_DECIMALS),
+            Some(Uint128(42))
+        );
+        assert_eq!(denormalize(U256::one(), 19), None);
+        // doesn't fit back into 128 bits
+        assert_eq!(denormalize(U256::max_value(), 6), None);
+    }
+
+    #[test]
+    fn convert_decimals_between_equal_greater_and_lesser() {
+        assert_eq!(
+            convert_decimals(Uint128(1_234_567), 6, 6),
+            Some(Uint128(1_234_567))
+        );
+        assert_eq!(
+            convert_decimals(Uint128(1_234_567), 6, 18),
+            Some(Uint128(1_234_567 * 10u128.pow(12)))
+        );
+        assert_eq!(
+            convert_decimals(Uint128(1_234_567_999_999_999_999), 18, 6),
+            Some(Uint128(1_234_567))
+        );
+        assert_eq!(
+            convert_decimals(Uint128(999_999_999_999), 18, 6),
+            Some(Uint128(0))
+        );
+        assert_eq!(convert_decimals(Uint128(1), 6, 19), None);
+        // fits normalized but not in 128 bits at 0 decimals -> 18 decimals
+        assert_eq!(convert_decimals(Uint128(u128::MAX), 0, 18), None);
+    }
+
+    #[test]
+    fn normalize_round_trip() {
+        for decimals in [0u8, 6, 8, 18].iter() {
+            let amount = Uint128(123_456_789);
+            let normalized = normalize(amount, *decimals).unwrap();
+            assert_eq!(denormalize(normalized, *decimals), Some(amount));
+        }
+    }
+
+    #[test]
+    fn round_down_normalized_to_nearest() {
+        // 0.01 normalized
+        let cent = Uint128(10u128.pow(16));
+        assert_eq!(
+            round_down_normalized(Uint128(1_234_567), 6, cent),
+            Some(Uint128(1_230_000))
+        );
+        assert_eq!(
+            round_down_normalized(Uint128(1_239_999_999_999_999_999), 18, cent),
+            Some(Uint128(1_230_000_000_000_000_000))
+        );
+        assert_eq!(
+            round_down_normalized(Uint128(9_999), 6, cent),
+            Some(Uint128(0))
+        );
+        // Finer than the asset's own unit
+        assert_eq!(
+            round_down_normalized(Uint128(1_234_567), 6, Uint128(1)),
+            Some(Uint128(1_234_567))
+        );
+        assert_eq!(
+            round_down_normalized(Uint128(1_234_567), 6, Uint128::zero()),
+            Some(Uint128(1_234_567))
+        );
+    }
+
+    /// A pool of two or three assets between 1k and 1bn tokens each, and a swap of between
+    /// 1bp and all of the input's balance
+    fn swap_case() -> impl Strategy<Value = (u64, Vec<U256>, usize, usize, U256)> {
+        (
+            1u64..=5_000,
+            prop::collection::vec(1_000u128..=1_000_000_000, 2..=3),
+        )
+            .prop_flat_map(|(amp, amounts)| {
+                let n_coins = amounts.len();
+                (
+                    Just(amp),
+                    Just(amounts),
+                    0..n_coins,
+                    1..n_coins,
+                    1u64..=10_000,
+                )
+            })
+            .prop_map(|(amp, amounts, in_index, offset, bps)| {
+                let balances: Vec<U256> = amounts.into_iter().map(tokens).collect();
+                let out_index = (in_index + offset) % balances.len();
+                let dx = balances[in_index] * bps / 10_000;
+                (amp, balances, in_index, out_index, dx)
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn fee_charging_swap_never_decreases_d(
+            (amp, balances, in_index, out_index, dx) in swap_case(),
+            fee_bps in 1u64..=100,
+        ) {
+            let dy = get_dy(amp, &balances, in_index, out_index, dx).unwrap();
+            let fee = dy * fee_bps / 10_000;
+            let mut after = balances.clone();
+            after[in_index] += dx;
+            after[out_index] -= dy - fee;
+
+            prop_assert!(get_d(amp, &after).unwrap() >= get_d(amp, &balances).unwrap());
+        }
+
+        #[test]
+        fn round_trip_swap_never_profits(
+            (amp, balances, in_index, out_index, dx) in swap_case(),
+        ) {
+            let dy = get_dy(amp, &balances, in_index, out_index, dx).unwrap();
+            let mut after = balances.clone();
+            after[in_index] += dx;
+            after[out_index] -= dy;
+
+            let dx_back = get_dy(amp, &after, out_index, in_index, dy).unwrap();
+            prop_assert!(dx_back <= dx);
+        }
+
+        #[test]
+        fn get_dx_inverts_get_dy(
+            (amp, balances, in_index, out_index, dx) in swap_case(),
+        ) {
+            let dy = get_dy(amp, &balances, in_index, out_index, dx).unwrap();
+            let dx_needed = get_dx(amp, &balances, in_index, out_index, dy).unwrap();
+            let dy_received = get_dy(amp, &balances, in_index, out_index, dx_needed).unwrap();
+
+            // never short, and off by no more than rounding either way
+            prop_assert!(dy_received >= dy);
+            prop_assert!(dy_received - dy <= dy / 1_000_000_000 + 1);
+            prop_assert!(dx_needed <= dx + dx / 1_000_000_000 + 1);
+        }
+    }
+}
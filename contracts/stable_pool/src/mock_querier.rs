@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, Coin, Empty, Extern, HumanAddr, Querier, QuerierResult,
+    QueryRequest, SystemError, Uint128, WasmQuery,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// mock_dependencies is a drop-in replacement for cosmwasm_std::testing::mock_dependencies
+/// this uses our CustomQuerier.
+pub fn mock_dependencies(
+    canonical_length: usize,
+    contract_balance: &[Coin],
+) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let contract_addr = HumanAddr::from(MOCK_CONTRACT_ADDR);
+    let custom_querier: WasmMockQuerier =
+        WasmMockQuerier::new(MockQuerier::new(&[(&contract_addr, contract_balance)]));
+
+    Extern {
+        storage: MockStorage::default(),
+        api: MockApi::new(canonical_length),
+        querier: custom_querier,
+    }
+}
+
+pub struct WasmMockQuerier {
+    base: MockQuerier,
+    token_querier: TokenQuerier,
+}
+
+#[derive(Clone, Default)]
+pub struct MockToken {
+    pub code_hash: String,
+    pub decimals: u8,
+    pub total_supply: Option<Uint128>,
+    pub balances: HashMap<HumanAddr, Uint128>,
+    // (owner, spender) -> allowance
+    pub allowances: HashMap<(HumanAddr, HumanAddr), Uint128>,
+}
+
+#[derive(Clone, Default)]
+pub struct TokenQuerier {
+    // token address -> mocked SNIP-20 state
+    tokens: HashMap<HumanAddr, MockToken>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum MockSnip20QueryMsg {
+    TokenInfo {},
+    Balance {
+        address: HumanAddr,
+        key: String,
+    },
+    Allowance {
+        owner: HumanAddr,
+        spender: HumanAddr,
+        key: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum MockSnip20QueryAnswer {
+    TokenInfo {
+        name: String,
+        symbol: String,
+        decimals: u8,
+        total_supply: Option<Uint128>,
+    },
+    Balance {
+        amount: Uint128,
+    },
+    Allowance {
+        spender: HumanAddr,
+        owner: HumanAddr,
+        allowance: Uint128,
+        expiration: Option<u64>,
+    },
+}
+
+impl Querier for WasmMockQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = match from_slice(bin_request) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SystemError::InvalidRequest {
+                    error: format!("Parsing query request: {}", e),
+                    request: bin_request.into(),
+                })
+            }
+        };
+        self.handle_query(&request)
+    }
+}
+
+impl WasmMockQuerier {
+    pub fn new(base: MockQuerier) -> Self {
+        WasmMockQuerier {
+            base,
+            token_querier: TokenQuerier::default(),
+        }
+    }
+
+    pub fn handle_query(&self, request: &QueryRequest<Empty>) -> QuerierResult {
+        match request {
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr,
+                callback_code_hash,
+                msg,
+            }) => {
+                let token = match self.token_querier.tokens.get(contract_addr) {
+                    Some(token) => token,
+                    None => {
+                        return Err(SystemError::NoSuchContract {
+                            addr: contract_addr.clone(),
+                        })
+                    }
+                };
+                if &token.code_hash != callback_code_hash {
+                    return Err(SystemError::InvalidRequest {
+                        error: format!("Wrong code hash for {}", contract_addr),
+                        request: msg.clone(),
+                    });
+                }
+
+                match from_binary(msg) {
+                    Ok(MockSnip20QueryMsg::TokenInfo {}) => {
+                        Ok(to_binary(&MockSnip20QueryAnswer::TokenInfo {
+                            name: "Mock Token".to_string(),
+                            symbol: "MOCK".to_string(),
+                            decimals: token.decimals,
+                            total_supply: token.total_supply,
+                        }))
+                    }
+                    Ok(MockSnip20QueryMsg::Balance { address, .. }) => {
+                        Ok(to_binary(&MockSnip20QueryAnswer::Balance {
+                            amount: *token.balances.get(&address).unwrap_or(&Uint128::zero()),
+                        }))
+                    }
+                    Ok(MockSnip20QueryMsg::Allowance { owner, spender, .. }) => {
+                        let allowance = *token
+                            .allowances
+                            .get(&(owner.clone(), spender.clone()))
+                            .unwrap_or(&Uint128::zero());
+                        Ok(to_binary(&MockSnip20QueryAnswer::Allowance {
+                            spender,
+                            owner,
+                            allowance,
+                            expiration: None,
+                        }))
+                    }
+                    Err(e) => Ok(Err(e)),
+                }
+            }
+            _ => self.base.handle_query(request),
+        }
+    }
+
+    /// Registers a SNIP-20 token contract at `address`, with code hash `<address>_hash`
+    pub fn with_token(&mut self, address: &str, decimals: u8, total_supply: Option<Uint128>) {
+        self.token_querier.tokens.insert(
+            HumanAddr::from(address),
+            MockToken {
+                code_hash: format!("{}_hash", address),
+                decimals,
+                total_supply,
+                balances: HashMap::new(),
+                allowances: HashMap::new(),
+            },
+        );
+    }
+
+    pub fn set_balance(&mut self, token: &str, holder: &str, amount: u128) {
+        self.token_querier
+            .tokens
+            .get_mut(&HumanAddr::from(token))
+            .expect("token must be registered with with_token first")
+            .balances
+            .insert(HumanAddr::from(holder), Uint128(amount));
+    }
+
+    pub fn set_allowance(&mut self, token: &str, owner: &str, spender: &str, amount: u128) {
+        self.token_querier
+            .tokens
+            .get_mut(&HumanAddr::from(token))
+            .expect("token must be registered with with_token first")
+            .allowances
+            .insert(
+                (HumanAddr::from(owner), HumanAddr::from(spender)),
+                Uint128(amount),
+            );
+    }
+
+    /// Adds to a balance, like a transfer into `holder` would
+    pub fn add_balance(&mut self, token: &str, holder: &str, amount: u128) {
+        let balance = self
+            .token_querier
+            .tokens
+            .get_mut(&HumanAddr::from(token))
+            .expect("token must be registered with with_token first")
+            .balances
+            .entry(HumanAddr::from(holder))
+            .or_insert_with(Uint128::zero);
+        *balance = Uint128(balance.u128().saturating_add(amount));
+    }
+
+    pub fn set_total_supply(&mut self, token: &str, total_supply: u128) {
+        self.token_querier
+            .tokens
+            .get_mut(&HumanAddr::from(token))
+            .expect("token must be registered with with_token first")
+            .total_supply = Some(Uint128(total_supply));
+    }
+
+    pub fn set_native_balance(&mut self, holder: &str, balance: Vec<Coin>) {
+        self.base.update_balance(holder, balance);
+    }
+}
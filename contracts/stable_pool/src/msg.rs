@@ -1,18 +1,22 @@
-use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use cosmwasm_std::{Binary, Decimal, HumanAddr, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct Token {
+    /// SNIP-20 contract address, or the denom of a native coin
     pub address: HumanAddr,
     pub code_hash: String,
+    #[serde(default)]
+    pub native: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct TokenAmount {
     pub address: HumanAddr,
+    /// Informational in requests, the pool calls assets with the code hash it stored
     pub code_hash: String,
     pub amount: Uint128,
 }
@@ -20,21 +24,183 @@ pub struct TokenAmount {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct TokenInfo {
+    /// Assigned in listing order and never reused, so it doesn't change when other assets
+    /// are removed
+    pub id: u32,
     pub address: HumanAddr,
     pub code_hash: String,
     pub viewing_key: String,
     pub decimals: u8,
+    pub enabled: bool,
+    #[serde(default)]
+    pub native: bool,
+    /// Set by RemoveAsset on an asset the pool still holds. It takes no deposits or swaps into
+    /// the pool, but can still be swapped and withdrawn out until it's drained and removed.
+    #[serde(default)]
+    pub withdraw_only: bool,
+    /// A SNIP-20 wrapping uscrt 1:1, like sSCRT. Payouts of it can be unwrapped through its
+    /// Redeem, and uscrt sent with SwapNative is wrapped through its Deposit.
+    #[serde(default)]
+    pub wrapped_native: bool,
+}
+
+/// What a query permit grants, as listed in its permissions
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// The signer's LP position
+    Position,
+    /// The signer's transaction history
+    History,
+    /// Every query scoped to the signer
+    Owner,
+}
+
+/// A SNIP-24 query permit. Signed offline by the account it authenticates, so wallets can run
+/// the queries scoped to it without first setting a viewing key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Permit {
+    pub params: PermitParams,
+    pub signature: PermitSignature,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitParams {
+    /// Contracts the permit is valid for, the pool must be one of them
+    pub allowed_tokens: Vec<HumanAddr>,
+    /// Revoking a name with RevokePermit revokes every permit signed with it
+    pub permit_name: String,
+    pub chain_id: String,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitSignature {
+    pub pub_key: PubKey,
+    /// 64 byte secp256k1 signature of the params' amino sign doc
+    pub signature: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PubKey {
+    /// Must be "tendermint/PubKeySecp256k1"
+    pub r#type: String,
+    /// Compressed secp256k1 public key
+    pub value: Binary,
+}
+
+/// Factory a pool reports its assets and admin to, so it can keep a registry of its pools
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct FactoryInfo {
+    pub address: HumanAddr,
+    pub code_hash: String,
+}
+
+/// Callbacks a pool sends to its factory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FactoryHandleMsg {
+    /// Sent from init, the factory records the sender as the pool it's instantiating
+    RegisterPool { assets: Vec<HumanAddr> },
+    /// Sent when the pool's asset list or admin changes
+    UpdatePool {
+        assets: Vec<HumanAddr>,
+        admin: HumanAddr,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStatus {
+    #[default]
+    Active,
+    /// Swaps and deposits are frozen but LPs can still withdraw, for winding a pool down
+    WithdrawOnly,
+    /// Everything is frozen, only the admin can move funds
+    Halted,
+    /// Deposits and withdrawals stay open, for when prices can't be trusted
+    SwapsDisabled,
+    /// Swaps and withdrawals stay open, for when the pool shouldn't grow
+    DepositsDisabled,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct Config {
     pub admin: HumanAddr,
+    /// Proposed with ProposeNewAdmin, becomes the admin once it calls AcceptAdmin
+    #[serde(default)]
+    pub pending_admin: Option<HumanAddr>,
+    /// Can halt the pool and rotate viewing keys, but not change fees or withdraw admin fees.
+    /// Set by the admin with SetOperators.
+    #[serde(default)]
+    pub operators: Vec<HumanAddr>,
+    pub pool_address: HumanAddr,
+    pub amp: u64,
     pub swap_fee_nom: Uint128,
     pub swap_fee_denom: Uint128,
-    pub is_halted: bool,
+    #[serde(default)]
+    pub status: PoolStatus,
+    /// Pre-PoolStatus configs stored a halted flag instead, read_config folds it into status
+    #[serde(default, skip_serializing)]
+    #[schemars(skip)]
+    pub(crate) is_halted: bool,
+    pub round_down_pool_answer_to_nearest: Uint128,
+    /// Max share of the destination reserve a single swap may take out, 0 disables the cap
+    pub max_swap_out_bps: u32,
+    /// Scale the swap fee from the base to the max fee by post-swap imbalance,
+    /// instead of charging swap_fee_nom/swap_fee_denom
+    pub dynamic_fee: bool,
+    pub dynamic_fee_base_bps: u32,
+    pub dynamic_fee_max_bps: u32,
+    /// Floor the dynamic fee is lowered to, from the base fee, by the share of the imbalance a
+    /// swap removes. 0 leaves rebalancing swaps on the post-swap imbalance like any other.
+    #[serde(default)]
+    pub dynamic_fee_min_bps: u32,
+    /// Max share of the pool a token may reach through swaps into it, 0 disables the check
+    pub depeg_threshold_bps: u32,
+    /// Max share of a swap's fee that can be paid out to a referrer
+    pub max_referral_fee_bps: u32,
+    /// Share of a swap's fee, after any referral cut, set aside for the protocol instead of
+    /// left to the LPs. The admin claims it with WithdrawAdminFees.
+    #[serde(default)]
+    pub admin_fee_bps: u32,
+    /// Max total swap input per block, normalized to 18 decimals, 0 disables the limit
+    pub max_volume_per_block: Uint128,
+    /// Share of a deposit's bonus kept by the existing LPs instead of minted to the depositor,
+    /// the bonus being the value of the minted LP after the deposit above the deposited value
+    #[serde(default)]
+    pub rebalance_bonus_to_lp_bps: u32,
+    /// Bonus paid on ProvideLiquidity deposits of an under-weighted asset, on the part that
+    /// takes it up to the mean balance, as LP minted for that asset's admin fees moved into
+    /// the pool. Capped by those fees, 0 disables it.
+    #[serde(default)]
+    pub deposit_bonus_bps: u32,
+    /// Max normalized balance deposits may take an asset to, 0 means unlimited
+    #[serde(default)]
+    pub per_asset_deposit_cap: Uint128,
+    /// Max normalized TVL deposits may take the pool to, 0 means unlimited
+    #[serde(default)]
+    pub total_tvl_cap: Uint128,
+    /// Normalized balance a swap must leave of the asset it pays out, 0 disables the floor
+    #[serde(default = "default_min_reserve_after_swap")]
+    pub min_reserve_after_swap: Uint128,
+    /// Admin swaps are fee-free while the pool is being seeded. Only set at init, and ends for
+    /// good once the normalized reserves reach seed_tvl_cap or the admin calls EndSeedMode.
+    #[serde(default)]
+    pub seed_mode: bool,
+    #[serde(default)]
+    pub seed_tvl_cap: Uint128,
     pub lp_token_address: HumanAddr,
     pub lp_token_code_hash: String,
+    /// Called back with FactoryHandleMsg on init and whenever the assets or admin change
+    #[serde(default)]
+    pub factory: Option<FactoryInfo>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -48,35 +214,316 @@ pub struct InitMsg {
     pub lp_token_label: String,
 
     pub admin: HumanAddr,
+    pub amp: u64,
     pub swap_fee_nom: Uint128,
     pub swap_fee_denom: Uint128,
-    pub is_halted: bool,
+    /// Defaults to active
+    pub status: Option<PoolStatus>,
+    pub round_down_pool_answer_to_nearest: Uint128,
+    pub max_swap_out_bps: Option<u32>,
+    pub dynamic_fee: Option<bool>,
+    pub dynamic_fee_base_bps: Option<u32>,
+    pub dynamic_fee_max_bps: Option<u32>,
+    pub dynamic_fee_min_bps: Option<u32>,
+    pub depeg_threshold_bps: Option<u32>,
+    pub max_referral_fee_bps: Option<u32>,
+    pub admin_fee_bps: Option<u32>,
+    pub max_volume_per_block: Option<Uint128>,
+    pub rebalance_bonus_to_lp_bps: Option<u32>,
+    pub deposit_bonus_bps: Option<u32>,
+    pub per_asset_deposit_cap: Option<Uint128>,
+    pub total_tvl_cap: Option<Uint128>,
+    /// Defaults to DEFAULT_MIN_RESERVE_AFTER_SWAP
+    pub min_reserve_after_swap: Option<Uint128>,
+    /// Starts the pool in seed mode until it holds this normalized TVL, unset or 0 skips it
+    pub seed_tvl_cap: Option<Uint128>,
+    /// Factory instantiating the pool, registered with it from init
+    pub factory: Option<FactoryInfo>,
+}
+
+/// Swaps leave at least a micro-unit of a 6-decimal token, normalized, unless configured
+pub const DEFAULT_MIN_RESERVE_AFTER_SWAP: u128 = 1_000_000_000_000;
+
+fn default_min_reserve_after_swap() -> Uint128 {
+    Uint128(DEFAULT_MIN_RESERVE_AFTER_SWAP)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum HandleMsg {
     Receive {
         from: HumanAddr,
         msg: Binary,
         amount: Uint128,
     },
+    /// LP is minted by FinalizeDeposit for the amounts the pool actually receives, which is
+    /// less than `assets` for tokens that charge a fee on transfer
     ProvideLiquidity {
         assets: Vec<TokenAmount>,
+        /// Fail if the deposit earns no deposit_bonus_bps bonus
         cancel_if_no_bonus: Option<bool>,
+        /// Fail if fewer LP tokens than this would be minted
+        min_lp_out: Option<Uint128>,
+        /// Fail once the block time is past this, in seconds
+        deadline: Option<u64>,
+    },
+    /// Callback the pool sends itself after the ProvideLiquidity transfers
+    FinalizeDeposit {
+        depositor: HumanAddr,
+        deposits: Vec<TokenAmount>,
+        cancel_if_no_bonus: Option<bool>,
+        min_lp_out: Option<Uint128>,
+    },
+    /// Swap the native coin sent with the message. uscrt, when it isn't a pool asset itself,
+    /// is wrapped into the pool's wrapped_native asset and swapped from that.
+    SwapNative {
+        to_token: HumanAddr,
+        recipient: Option<HumanAddr>,
+        referrer: Option<HumanAddr>,
+        referral_fee_bps: Option<u32>,
+    },
+    /// Callback the pool sends itself after SwapNative wraps the sent uscrt into `token`
+    FinalizeWrappedSwap {
+        token: HumanAddr,
+        amount: Uint128,
+        to_token: HumanAddr,
+        swapper: HumanAddr,
+        recipient: HumanAddr,
+        referrer: Option<HumanAddr>,
+        referral_fee_bps: Option<u32>,
     },
     PostInitialize {},
-    UpdateViewingKeys {},
+    /// Sets the pool's viewing key with every SNIP-20 asset. Admin or operator only
+    UpdateViewingKeys {
+        viewing_key: String,
+    },
+    /// Sets the pool's viewing key with a single SNIP-20 asset. Admin or operator only
+    UpdateTokenViewingKey {
+        token: HumanAddr,
+        viewing_key: String,
+    },
+    SetAssetEnabled {
+        token: HumanAddr,
+        enabled: bool,
+    },
+    /// Lists a new asset, disabled and with a zero reserve, setting `viewing_key` and the pool's
+    /// receive hook on it if it's a SNIP-20 token. It takes no part in pricing until the pool
+    /// holds some: once SetAssetEnabled turns it on, the first deposit of it seeds it. Admin only.
+    AddAsset {
+        token: Token,
+        viewing_key: String,
+    },
+    /// Flags a SNIP-20 asset as wrapping uscrt 1:1, like sSCRT. Admin only.
+    SetWrappedNative {
+        token: HumanAddr,
+        wrapped_native: bool,
+    },
+    /// Removes an asset the pool holds none of. An asset it still holds is made withdraw-only
+    /// instead, and removed by calling this again once swaps and withdrawals have drained it to
+    /// min_reserve_after_swap, the dust left being sent to the admin. Its admin fees must be
+    /// withdrawn first. Admin only, and at least two assets must remain.
+    RemoveAsset {
+        token: HumanAddr,
+    },
+    /// Admin only. Logs the old and new value of every field set
+    UpdateConfig {
+        swap_fee_nom: Option<Uint128>,
+        swap_fee_denom: Option<Uint128>,
+        round_down_pool_answer_to_nearest: Option<Uint128>,
+        max_swap_out_bps: Option<u32>,
+        dynamic_fee: Option<bool>,
+        dynamic_fee_base_bps: Option<u32>,
+        dynamic_fee_max_bps: Option<u32>,
+        dynamic_fee_min_bps: Option<u32>,
+        depeg_threshold_bps: Option<u32>,
+        max_referral_fee_bps: Option<u32>,
+        admin_fee_bps: Option<u32>,
+        max_volume_per_block: Option<Uint128>,
+        rebalance_bonus_to_lp_bps: Option<u32>,
+        deposit_bonus_bps: Option<u32>,
+        per_asset_deposit_cap: Option<Uint128>,
+        total_tvl_cap: Option<Uint128>,
+        min_reserve_after_swap: Option<Uint128>,
+        status: Option<PoolStatus>,
+    },
+    /// Sends the full balance of every asset to `recipient`, for migrating to a new pool.
+    /// Admin only, and only while the pool is Halted.
+    EmergencyWithdraw {
+        recipient: HumanAddr,
+    },
+    /// Sends the admin fees collected so far to `recipient`. Admin only.
+    WithdrawAdminFees {
+        recipient: HumanAddr,
+    },
+    SetRounding {
+        round_down_to_nearest: Uint128,
+    },
+    /// Records the virtual price and advances the price accumulators now, so the TWAPs reach
+    /// the current block even when no swap or liquidity handler ran in it. Admin only.
+    SnapshotVirtualPrice {},
+    /// Runs the operations in order, each priced on the pool as the previous ones left it,
+    /// and reverts all of them if any fails. Only each asset's net amount is transferred, so
+    /// swap outputs can fund later operations. SNIP-20 assets only. The results are set as
+    /// data, a list of BatchOperationResult.
+    Batch {
+        operations: Vec<BatchOperation>,
+    },
+    /// Callback the pool sends itself after the Batch transfers
+    FinalizeBatch {},
+    /// Sets the tracked reserves to the pool's balances, handing tokens sent to the pool
+    /// directly to the LPs. Admin only, and not while a deposit or batch is in flight.
+    SyncReserves {},
+    /// Ends seed mode early, after which admin swaps pay fees like any other. Admin only, and
+    /// seed mode can't be turned back on.
+    EndSeedMode {},
+    /// Stops swaps and deposits, leaving withdrawals open, by setting the status to
+    /// WithdrawOnly. Halted, which also stops withdrawals, is set through SetStatus.
+    /// Admin or operator only.
+    Halt {},
+    /// Sets the status back to Active. Admin only.
+    Resume {},
+    /// Sets any status, as UpdateConfig does. Admin only, except that operators may set
+    /// WithdrawOnly or Halted.
+    SetStatus {
+        status: PoolStatus,
+    },
+    /// Proposes `address` as the new admin, replacing any earlier proposal. The current admin
+    /// stays in charge until `address` calls AcceptAdmin. Admin only.
+    ProposeNewAdmin {
+        address: HumanAddr,
+    },
+    /// Makes the sender the admin. Only the address proposed with ProposeNewAdmin may call it.
+    AcceptAdmin {},
+    /// Replaces the operator set. Admin only.
+    SetOperators {
+        operators: Vec<HumanAddr>,
+    },
+    /// Sets the sender's viewing key, which authenticates the queries scoped to them
+    SetViewingKey {
+        key: String,
+    },
+    /// Revokes the sender's query permits named `permit_name`, for good
+    RevokePermit {
+        permit_name: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOperation {
+    ProvideLiquidity {
+        assets: Vec<TokenAmount>,
+        min_lp_out: Option<Uint128>,
+    },
+    Swap {
+        offer: TokenAmount,
+        to_token: HumanAddr,
+        /// Fail if the swap returns less than this
+        min_return: Option<Uint128>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOperationResult {
+    ProvideLiquidity(ProvideLiquidityResponse),
+    Swap(SwapResponse),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Snip20ReceiveMsg {
+    /// Priced on the amount the pool actually received rather than the amount sent
     Swap {
         to_token: HumanAddr,
         recipient: Option<HumanAddr>,
+        /// Receives `referral_fee_bps` of the swap fee, defaults to the max allowed by Config
+        referrer: Option<HumanAddr>,
+        referral_fee_bps: Option<u32>,
+        /// Reverts, returning the sent tokens, if the output is more than max_slippage_bps
+        /// below expected_return. No slippage is allowed when max_slippage_bps is not set.
+        expected_return: Option<Uint128>,
+        max_slippage_bps: Option<u32>,
+        /// Fail once the block time is past this, in seconds, so a swap that sat in the
+        /// mempool can't execute at a stale price
+        deadline: Option<u64>,
+        /// Pay out a wrapped_native asset as uscrt, redeemed from it
+        unwrap: Option<bool>,
+    },
+    /// Swaps the input GetAmountIn reports for `ask_amount`, on the amount the pool actually
+    /// received, and returns the rest of it to the sender. Reverts if that's not enough. The
+    /// output can be slightly more than ask_amount, since the input is rounded up.
+    SwapExactOut {
+        to_token: HumanAddr,
+        ask_amount: Uint128,
+        recipient: Option<HumanAddr>,
+    },
+    /// Refunds are the amounts the pool sends, tokens with a transfer fee deliver less.
+    /// `unwrap` pays out wrapped_native assets as uscrt.
+    WithdrawLiquidity {
+        deadline: Option<u64>,
+        unwrap: Option<bool>,
+    },
+    /// Reverts unless every refund is at least its min amount, given in asset order
+    WithdrawLiquidityWithMin {
+        min_amounts: Vec<Uint128>,
+        deadline: Option<u64>,
+        unwrap: Option<bool>,
+    },
+    /// Pays the whole withdrawal in `token`, priced on the invariant less the imbalance fee.
+    /// Reverts if that's less than min_amount.
+    WithdrawLiquidityOneToken {
+        token: HumanAddr,
+        min_amount: Uint128,
+        deadline: Option<u64>,
+        unwrap: Option<bool>,
+    },
+    /// Withdraws exactly `amounts`, given in asset order, burning the LP their share of the
+    /// invariant is worth plus the imbalance fee. Reverts if that's more than max_burn or the
+    /// LP sent, the rest of which is returned.
+    WithdrawImbalanced {
+        amounts: Vec<Uint128>,
+        max_burn: Uint128,
+        deadline: Option<u64>,
+    },
+    /// Single-token deposit. Part of it is swapped into every other enabled asset in
+    /// proportion to the pool's balances, paying the swap fee, and the rest is deposited with
+    /// the swap outputs on the pool as the swaps left it. That deposit is close to balanced,
+    /// so it pays little imbalance fee. The ProvideLiquidity response is set as data.
+    ZapIn { min_lp_out: Option<Uint128> },
+    /// Single-token deposit of the sent tokens as they are, without an allowance. Pays the
+    /// imbalance fee like any imbalanced ProvideLiquidity. The ProvideLiquidity response is
+    /// set as data.
+    Deposit {
+        min_lp_out: Option<Uint128>,
+        deadline: Option<u64>,
     },
-    WithdrawLiquidity {},
+}
+
+/// Set as the HandleResponse data of ProvideLiquidity, with the share priced on the requested
+/// `deposits`. That's an upper bound, FinalizeDeposit mints less when fee-on-transfer tokens
+/// deliver less, and sets the amounts received and the LP actually minted as its own data.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ProvideLiquidityResponse {
+    pub lp_amount: Uint128,
+    pub deposits: Vec<TokenAmount>,
+}
+
+/// Set as the HandleResponse data of swaps
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SwapResponse {
+    pub return_amount: Uint128,
+    pub fee_amount: Uint128,
+}
+
+/// Set as the HandleResponse data of liquidity withdrawals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WithdrawLiquidityResponse {
+    pub refunds: Vec<TokenAmount>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -84,6 +531,504 @@ pub enum Snip20ReceiveMsg {
 pub enum QueryMsg {
     GetConfig {},
     GetTokens {},
+    /// Assets with their tracked reserves
     GetPools {},
+    /// The asset a deposit or swap into the pool helps balance the most
     GetMostNeededToken {},
+    BatchSwapSimulation {
+        swaps: Vec<SimulatedSwap>,
+    },
+    LpValue {
+        amount: Uint128,
+    },
+    /// Fewest LP tokens that WithdrawLiquidityOneToken pays at least `amount` of `token` for,
+    /// imbalance fee included
+    LpNeededForWithdrawal {
+        token: HumanAddr,
+        amount: Uint128,
+    },
+    GetVolume {},
+    /// Stable index of `token`. Indices follow the order tokens were listed in, as GetTokens
+    /// does, and removing a token doesn't shift the others, so there can be gaps.
+    GetTokenIndex {
+        token: HumanAddr,
+    },
+    GetTokenByIndex {
+        index: u32,
+    },
+    GetCurveState {},
+    GetRebalancingDeposit {},
+    /// Fee that a swap would pay right now, including the dynamic fee adjustment
+    GetEffectiveFee {
+        offer: TokenAmount,
+        to_token: HumanAddr,
+    },
+    /// Assets with their balances and the LP supply, all read in the same query
+    GetPoolSnapshot {},
+    /// Simulates swaps of max_input / 10^(points - 1), ..., max_input / 10, max_input
+    DepthCurve {
+        from_token: HumanAddr,
+        to_token: HumanAddr,
+        max_input: Uint128,
+        points: u32,
+    },
+    GetExecutionPrice {
+        offer: TokenAmount,
+        to_token: HumanAddr,
+    },
+    /// Output of swapping amount_in of token_in, after fees
+    GetAmountOut {
+        amount_in: Uint128,
+        token_in: HumanAddr,
+        token_out: HumanAddr,
+    },
+    /// Smallest amount of token_in that swaps into at least amount_out of token_out
+    GetAmountIn {
+        amount_out: Uint128,
+        token_in: HumanAddr,
+        token_out: HumanAddr,
+    },
+    /// Quote for swapping `amount` of offer_token into ask_token
+    Simulation {
+        offer_token: HumanAddr,
+        ask_token: HumanAddr,
+        amount: Uint128,
+    },
+    /// Quote for receiving at least ask_amount of ask_token, with the smallest offer that does
+    ReverseSimulation {
+        ask_token: HumanAddr,
+        ask_amount: Uint128,
+        offer_token: HumanAddr,
+    },
+    /// How far the most imbalanced asset is from its share of the invariant
+    GetImbalance {},
+    /// Fails until PostInitialize has bound the LP token
+    GetLiquidityToken {},
+    /// Total value locked: the sum of all normalized balances
+    GetTvl {},
+    /// Deposit caps and how much more can be deposited under them
+    GetDepositCaps {},
+    /// LP minted and burned since inception, with the current supply
+    GetLpFlows {},
+    /// Admin fees collected and not yet withdrawn, per asset
+    GetAdminFees {},
+    /// Time-weighted average virtual price over the last window_seconds of recorded history.
+    /// Queries can't see the block time, so the window ends at the latest observation.
+    GetVirtualPriceTwap {
+        window_seconds: u64,
+    },
+    /// Time-weighted average spot price of `base` in `quote`, before fees, over the last
+    /// `window` seconds of recorded history. Read from the price accumulators every
+    /// swap and liquidity handler advances, so a price pushed and reverted within a block
+    /// carries no weight. Like GetVirtualPriceTwap the window ends at the latest observation.
+    Twap {
+        base: HumanAddr,
+        quote: HumanAddr,
+        window: u64,
+    },
+    /// Largest input whose price impact stays within max_impact_bps, as a depth point.
+    /// Zero input if even the smallest unit exceeds it.
+    MaxInputForImpact {
+        from_token: HumanAddr,
+        to_token: HumanAddr,
+        max_impact_bps: u32,
+    },
+    /// LP position of `address`, authenticated with the viewing key it set
+    MyPosition {
+        address: HumanAddr,
+        key: String,
+    },
+    /// Swaps `address` made or received, newest first, authenticated with the viewing key it
+    /// set. Page 0, the default, holds the latest page_size swaps.
+    TransactionHistory {
+        address: HumanAddr,
+        key: String,
+        page: Option<u32>,
+        page_size: u32,
+    },
+    /// Queries scoped to the account that signed `permit`
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    /// As MyPosition, the permit must grant Position
+    MyPosition {},
+    /// As TransactionHistory, the permit must grant History
+    TransactionHistory { page: Option<u32>, page_size: u32 },
+}
+
+/// The pool's Config, as GetConfig returns it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ConfigResponse {
+    pub admin: HumanAddr,
+    pub pending_admin: Option<HumanAddr>,
+    pub operators: Vec<HumanAddr>,
+    pub pool_address: HumanAddr,
+    pub amp: u64,
+    pub swap_fee_nom: Uint128,
+    pub swap_fee_denom: Uint128,
+    pub status: PoolStatus,
+    pub round_down_pool_answer_to_nearest: Uint128,
+    pub max_swap_out_bps: u32,
+    pub dynamic_fee: bool,
+    pub dynamic_fee_base_bps: u32,
+    pub dynamic_fee_max_bps: u32,
+    pub dynamic_fee_min_bps: u32,
+    pub depeg_threshold_bps: u32,
+    pub max_referral_fee_bps: u32,
+    pub admin_fee_bps: u32,
+    pub max_volume_per_block: Uint128,
+    pub rebalance_bonus_to_lp_bps: u32,
+    pub deposit_bonus_bps: u32,
+    pub per_asset_deposit_cap: Uint128,
+    pub total_tvl_cap: Uint128,
+    pub min_reserve_after_swap: Uint128,
+    pub seed_mode: bool,
+    pub seed_tvl_cap: Uint128,
+    pub lp_token_address: HumanAddr,
+    pub lp_token_code_hash: String,
+    pub factory: Option<FactoryInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokensResponse {
+    /// In listing order
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PoolsResponse {
+    /// In GetTokens order
+    pub assets: Vec<PoolAsset>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MostNeededTokenResponse {
+    /// The asset taking deposits with the lowest normalized balance, the first in GetTokens
+    /// order on a tie. None if no asset takes deposits.
+    pub token: Option<HumanAddr>,
+    /// Normalized amount its balance is below the mean of all balances, zero if it isn't
+    pub deficit: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BatchSwapSimulationResponse {
+    /// In the order the swaps were given
+    pub simulations: Vec<SwapSimulationResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LpNeededForWithdrawalResponse {
+    pub lp_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct VolumeResponse {
+    /// In GetTokens order, including the assets never swapped
+    pub volumes: Vec<TokenVolume>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenIndexResponse {
+    pub index: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenByIndexResponse {
+    pub token: Token,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DepthCurveResponse {
+    /// Smallest input first
+    pub points: Vec<DepthPoint>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AmountOutResponse {
+    pub amount_out: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AmountInResponse {
+    pub amount_in: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminFeesResponse {
+    /// In GetTokens order
+    pub fees: Vec<TokenAmount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SimulatedSwap {
+    pub offer: TokenAmount,
+    pub to_token: HumanAddr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SwapSimulationResponse {
+    pub return_amount: Uint128,
+    pub fee_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SimulationResponse {
+    pub return_amount: Uint128,
+    pub fee_amount: Uint128,
+    /// What the output before fees falls short of the offer's value at the spot price, in
+    /// ask_token. Zero if it doesn't.
+    pub spread_amount: Uint128,
+    /// Fee rate charged, rounded up to a whole bps, as GetEffectiveFee reports it
+    pub fee_bps: u32,
+}
+
+/// Fee and spread are those of swapping offer_amount, as Simulation reports them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReverseSimulationResponse {
+    pub offer_amount: Uint128,
+    pub fee_amount: Uint128,
+    pub spread_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LpValueResponse {
+    /// Value of the LP amount normalized to 18 decimals (amount * D / total_supply)
+    pub value: Uint128,
+    /// Pro-rata share of each pool asset, in the asset's own decimals
+    pub assets: Vec<TokenAmount>,
+}
+
+/// A pool asset and its tracked reserve, in the asset's own decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PoolAsset {
+    pub address: HumanAddr,
+    pub decimals: u8,
+    pub balance: Uint128,
+}
+
+/// Cumulative swap volume of a token, in the token's own decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenVolume {
+    pub address: HumanAddr,
+    pub volume_in: Uint128,
+    pub volume_out: Uint128,
+}
+
+/// Amounts of a token an address deposited into and withdrew from the pool, in the token's own
+/// decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AssetFlow {
+    pub address: HumanAddr,
+    pub deposited: Uint128,
+    pub withdrawn: Uint128,
+}
+
+/// The pool only sees LP it mints and burns, so LP an address transferred or received isn't
+/// counted in its shares
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PositionResponse {
+    /// LP minted to the address less the LP it burned, capped at the LP supply
+    pub shares: Uint128,
+    /// Fraction of the LP supply the shares are
+    pub pool_share: Decimal,
+    /// Value of the shares normalized to 18 decimals, as LpValue reports it
+    pub value: Uint128,
+    /// Pro-rata share of each pool asset, in the asset's own decimals
+    pub assets: Vec<TokenAmount>,
+    pub lp_minted: Uint128,
+    pub lp_burned: Uint128,
+    /// Per token ever deposited or withdrawn, including since removed assets
+    pub flows: Vec<AssetFlow>,
+}
+
+/// A swap in the history of the address that made it and of its recipient, amounts in each
+/// token's own decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SwapTx {
+    /// Numbered across every address, in the order the swaps ran
+    pub id: u64,
+    pub from: HumanAddr,
+    pub recipient: HumanAddr,
+    pub src_token: HumanAddr,
+    /// What the pool received, less than sent for tokens with a transfer fee
+    pub src_amount: Uint128,
+    pub dst_token: HumanAddr,
+    pub dst_amount: Uint128,
+    /// In dst_token, including any referral and admin cut of it
+    pub fee_amount: Uint128,
+    pub block_height: u64,
+    pub block_time: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TransactionHistoryResponse {
+    pub txs: Vec<SwapTx>,
+    /// Swaps in the address's whole history
+    pub total: u64,
+}
+
+/// Snapshot of everything needed to reproduce the curve offline
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CurveStateResponse {
+    pub amp: u64,
+    /// Invariant of the normalized balances
+    pub d: Uint128,
+    /// Pool balances normalized to 18 decimals, in GetTokens order
+    pub balances: Vec<Uint128>,
+    pub lp_total_supply: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TvlResponse {
+    /// Sum of the normalized balances
+    pub tvl: Uint128,
+    /// Pool balances normalized to 18 decimals, in GetTokens order
+    pub balances: Vec<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DepositCapsResponse {
+    pub per_asset_deposit_cap: Uint128,
+    pub total_tvl_cap: Uint128,
+    /// Normalized amount each asset can still take, in GetTokens order. None if uncapped.
+    pub per_asset_headroom: Option<Vec<Uint128>>,
+    /// Normalized amount the pool can still take. None if uncapped.
+    pub tvl_headroom: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LpFlowsResponse {
+    pub total_lp_minted: Uint128,
+    pub total_lp_burned: Uint128,
+    pub lp_total_supply: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct VirtualPriceTwapResponse {
+    /// Normalized reserves per LP token, scaled by 10^18
+    pub twap: Uint128,
+    /// Shorter than the requested window when the history doesn't reach that far back
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TwapResponse {
+    /// Units of quote per unit of base, after normalizing decimals. The spot price when the
+    /// window is empty.
+    pub price: Decimal,
+    /// Shorter than the requested window when the pair's history doesn't reach that far back
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RebalancingDepositResponse {
+    /// Amount of each depositable asset to deposit, zero for the ones already at the max
+    /// balance. Disabled and withdraw-only assets aren't listed.
+    pub deposits: Vec<TokenAmount>,
+    /// LP tokens the deposit would mint
+    pub lp_out: Uint128,
+    /// Normalized value of the minted LP tokens above the deposited value
+    pub bonus: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AssetSnapshot {
+    pub address: HumanAddr,
+    pub code_hash: String,
+    pub decimals: u8,
+    pub balance: Uint128,
+    /// Balance normalized to 18 decimals
+    pub normalized_balance: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PoolSnapshotResponse {
+    /// In GetTokens order
+    pub assets: Vec<AssetSnapshot>,
+    pub lp_total_supply: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DepthPoint {
+    pub input: Uint128,
+    pub output: Uint128,
+    /// Shortfall of the output against the input's value at 1:1, fees included
+    pub price_impact_bps: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LiquidityTokenResponse {
+    pub address: HumanAddr,
+    pub code_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ImbalanceResponse {
+    /// Largest deviation of a normalized balance from D / n, in bps of D
+    pub imbalance_bps: u32,
+    /// The asset with that deviation, none if the pool is balanced or empty
+    pub token: Option<HumanAddr>,
+}
+
+/// Prices in units of `to_token` per unit of the offered token, after normalizing decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ExecutionPriceResponse {
+    /// Output / input of the whole swap, fees included
+    pub average_price: Decimal,
+    /// Price of an infinitesimally small swap at the current balances
+    pub spot_price: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct EffectiveFeeResponse {
+    /// Rounded up to a whole bps. Referral cuts come out of this fee rather than adding to it
+    pub fee_bps: u32,
+    /// Whether the fee depends on the pool imbalance rather than the static swap fee
+    pub dynamic: bool,
 }
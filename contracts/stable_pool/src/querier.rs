@@ -2,6 +2,8 @@ use cosmwasm_std::{Api, Extern, HumanAddr, Querier, StdError, StdResult, Storage
 
 use secret_toolkit::snip20 as snip20_utils;
 
+use crate::msg::TokenInfo;
+
 pub fn query_token_balance<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     token_address: &HumanAddr,
@@ -57,3 +59,52 @@ pub fn query_token_decimals<S: Storage, A: Api, Q: Querier>(
 
     Ok(token_info.decimals)
 }
+
+/// Amount `spender` may still transfer out of `owner`'s account, zero once the allowance
+/// has expired
+pub fn query_token_allowance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    token_address: &HumanAddr,
+    token_code_hash: &str,
+    owner: &HumanAddr,
+    spender: &HumanAddr,
+    viewing_key: &str,
+    block_time: u64,
+) -> StdResult<Uint128> {
+    let allowance = snip20_utils::allowance_query(
+        &deps.querier,
+        owner.clone(),
+        spender.clone(),
+        viewing_key.to_string(),
+        256,
+        token_code_hash.to_string(),
+        token_address.clone(),
+    )?;
+
+    match allowance.expiration {
+        Some(expiration) if expiration <= block_time => Ok(Uint128::zero()),
+        _ => Ok(allowance.allowance),
+    }
+}
+
+/// Balance of a pool asset held by `account`, read from the bank module for native coins
+pub fn query_asset_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    token: &TokenInfo,
+    account: &HumanAddr,
+) -> StdResult<Uint128> {
+    if token.native {
+        Ok(deps
+            .querier
+            .query_balance(account.clone(), token.address.as_str())?
+            .amount)
+    } else {
+        query_token_balance(
+            deps,
+            &token.address,
+            &token.code_hash,
+            account,
+            &token.viewing_key,
+        )
+    }
+}
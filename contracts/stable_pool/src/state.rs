@@ -1,16 +1,55 @@
-use cosmwasm_std::{StdResult, Storage};
-use cosmwasm_storage::{ReadonlySingleton, Singleton};
+use cosmwasm_std::{HumanAddr, StdResult, Storage, Uint128};
+use cosmwasm_storage::{bucket, bucket_read, to_length_prefixed, ReadonlySingleton, Singleton};
+use serde::{Deserialize, Serialize};
 
-use crate::msg::{TokenInfo, Config};
+use crate::msg::{AssetFlow, Config, PoolStatus, SwapTx, TokenInfo, TokenVolume};
 
-const ALL_ASSETS_KEY: &[u8] = b"all_assets";
+// Assets are stored one per key, by index, so updating one doesn't rewrite the others
+const ASSETS_PREFIX: &[u8] = b"assets";
+const ASSET_COUNT_KEY: &[u8] = b"asset_count";
 
-pub fn store_all_assets<S: Storage>(storage: &mut S, assets: &Vec<TokenInfo>) -> StdResult<()> {
-    Singleton::new(storage, ALL_ASSETS_KEY).save(assets)
+pub fn store_all_assets<S: Storage>(storage: &mut S, assets: &[TokenInfo]) -> StdResult<()> {
+    for (index, asset) in assets.iter().enumerate() {
+        store_asset(storage, index as u32, asset)?;
+    }
+    // Clear the entries left over when the list shrinks
+    let previous_count: u32 = ReadonlySingleton::new(storage, ASSET_COUNT_KEY)
+        .may_load()?
+        .unwrap_or(0);
+    for index in assets.len() as u32..previous_count {
+        bucket::<S, TokenInfo>(ASSETS_PREFIX, storage).remove(&index.to_be_bytes());
+    }
+    Singleton::new(storage, ASSET_COUNT_KEY).save(&(assets.len() as u32))
+}
+
+/// Overwrites the asset at `index`, which must be below read_asset_count
+pub fn store_asset<S: Storage>(storage: &mut S, index: u32, asset: &TokenInfo) -> StdResult<()> {
+    bucket(ASSETS_PREFIX, storage).save(&index.to_be_bytes(), asset)
+}
+
+pub fn read_asset_count<S: Storage>(storage: &S) -> StdResult<u32> {
+    ReadonlySingleton::new(storage, ASSET_COUNT_KEY).load()
+}
+
+pub fn read_asset<S: Storage>(storage: &S, index: u32) -> StdResult<TokenInfo> {
+    bucket_read(ASSETS_PREFIX, storage).load(&index.to_be_bytes())
 }
 
 pub fn read_all_assets<S: Storage>(storage: &S) -> StdResult<Vec<TokenInfo>> {
-    ReadonlySingleton::new(storage, ALL_ASSETS_KEY).load()
+    (0..read_asset_count(storage)?)
+        .map(|index| read_asset(storage, index))
+        .collect()
+}
+
+const NEXT_ASSET_ID_KEY: &[u8] = b"next_asset_id";
+
+pub fn store_next_asset_id<S: Storage>(storage: &mut S, id: u32) -> StdResult<()> {
+    Singleton::new(storage, NEXT_ASSET_ID_KEY).save(&id)
+}
+
+/// Id the next listed asset gets
+pub fn read_next_asset_id<S: Storage>(storage: &S) -> StdResult<u32> {
+    ReadonlySingleton::new(storage, NEXT_ASSET_ID_KEY).load()
 }
 
 const CONFIG_KEY: &[u8] = b"config";
@@ -20,5 +59,345 @@ pub fn store_config<S: Storage>(storage: &mut S, config: &Config) -> StdResult<(
 }
 
 pub fn read_config<S: Storage>(storage: &S) -> StdResult<Config> {
-    ReadonlySingleton::new(storage, CONFIG_KEY).load()
+    let mut config: Config = ReadonlySingleton::new(storage, CONFIG_KEY).load()?;
+    // Migrate the old halted flag, it's dropped the next time the config is stored
+    if config.is_halted {
+        config.status = PoolStatus::Halted;
+        config.is_halted = false;
+    }
+    Ok(config)
+}
+
+const VOLUMES_KEY: &[u8] = b"volumes";
+
+pub fn store_volumes<S: Storage>(storage: &mut S, volumes: &[TokenVolume]) -> StdResult<()> {
+    Singleton::new(storage, VOLUMES_KEY).save(&volumes.to_vec())
+}
+
+pub fn read_volumes<S: Storage>(storage: &S) -> StdResult<Vec<TokenVolume>> {
+    Ok(ReadonlySingleton::new(storage, VOLUMES_KEY)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+const LOCK_KEY: &[u8] = b"lock";
+
+pub fn store_lock<S: Storage>(storage: &mut S, locked: bool) -> StdResult<()> {
+    Singleton::new(storage, LOCK_KEY).save(&locked)
+}
+
+pub fn read_lock<S: Storage>(storage: &S) -> StdResult<bool> {
+    Ok(ReadonlySingleton::new(storage, LOCK_KEY)
+        .may_load()?
+        .unwrap_or(false))
+}
+
+/// Swap volume of the current block, normalized to 18 decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockVolume {
+    pub height: u64,
+    pub volume: Uint128,
+}
+
+const BLOCK_VOLUME_KEY: &[u8] = b"block_volume";
+
+pub fn store_block_volume<S: Storage>(storage: &mut S, volume: &BlockVolume) -> StdResult<()> {
+    Singleton::new(storage, BLOCK_VOLUME_KEY).save(volume)
+}
+
+pub fn read_block_volume<S: Storage>(storage: &S) -> StdResult<Option<BlockVolume>> {
+    ReadonlySingleton::new(storage, BLOCK_VOLUME_KEY).may_load()
+}
+
+const RESERVES_KEY: &[u8] = b"reserves";
+
+/// Pool balance of each asset, in asset order, once the last handler's transfers go through.
+/// Every swap, deposit, withdrawal and query is priced on these rather than on queried
+/// balances. Whatever the pool holds above this was received since then.
+pub fn store_reserves<S: Storage>(storage: &mut S, reserves: &[Uint128]) -> StdResult<()> {
+    Singleton::new(storage, RESERVES_KEY).save(&reserves.to_vec())
+}
+
+pub fn read_reserves<S: Storage>(storage: &S) -> StdResult<Vec<Uint128>> {
+    ReadonlySingleton::new(storage, RESERVES_KEY).load()
+}
+
+const ADMIN_FEES_KEY: &[u8] = b"admin_fees";
+
+/// Admin fees collected and not yet withdrawn, in asset order. The pool holds them on top of
+/// the reserves.
+pub fn store_admin_fees<S: Storage>(storage: &mut S, admin_fees: &[Uint128]) -> StdResult<()> {
+    Singleton::new(storage, ADMIN_FEES_KEY).save(&admin_fees.to_vec())
+}
+
+pub fn read_admin_fees<S: Storage>(storage: &S) -> StdResult<Vec<Uint128>> {
+    match ReadonlySingleton::new(storage, ADMIN_FEES_KEY).may_load()? {
+        Some(admin_fees) => Ok(admin_fees),
+        None => Ok(vec![Uint128::zero(); read_asset_count(storage)? as usize]),
+    }
+}
+
+/// Virtual price, the normalized reserves per LP token scaled by 10^18, that held until `time`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VirtualPriceObservation {
+    pub time: u64,
+    pub virtual_price: Uint128,
+}
+
+// A ring buffer, the oldest observation is overwritten once it's full
+const VIRTUAL_PRICES_PREFIX: &[u8] = b"virtual_prices";
+const VIRTUAL_PRICE_COUNT_KEY: &[u8] = b"virtual_price_count";
+const MAX_VIRTUAL_PRICE_OBSERVATIONS: u64 = 64;
+
+pub fn push_virtual_price<S: Storage>(
+    storage: &mut S,
+    observation: &VirtualPriceObservation,
+) -> StdResult<()> {
+    let count = read_virtual_price_count(storage)?;
+    bucket(VIRTUAL_PRICES_PREFIX, storage).save(
+        &(count % MAX_VIRTUAL_PRICE_OBSERVATIONS).to_be_bytes(),
+        observation,
+    )?;
+    Singleton::new(storage, VIRTUAL_PRICE_COUNT_KEY).save(&(count + 1))
+}
+
+/// Oldest first
+pub fn read_virtual_prices<S: Storage>(storage: &S) -> StdResult<Vec<VirtualPriceObservation>> {
+    let count = read_virtual_price_count(storage)?;
+    (count.saturating_sub(MAX_VIRTUAL_PRICE_OBSERVATIONS)..count)
+        .map(|i| read_virtual_price(storage, i))
+        .collect()
+}
+
+pub fn read_last_virtual_price<S: Storage>(
+    storage: &S,
+) -> StdResult<Option<VirtualPriceObservation>> {
+    match read_virtual_price_count(storage)? {
+        0 => Ok(None),
+        count => read_virtual_price(storage, count - 1).map(Some),
+    }
+}
+
+fn read_virtual_price<S: Storage>(storage: &S, i: u64) -> StdResult<VirtualPriceObservation> {
+    bucket_read(VIRTUAL_PRICES_PREFIX, storage)
+        .load(&(i % MAX_VIRTUAL_PRICE_OBSERVATIONS).to_be_bytes())
+}
+
+fn read_virtual_price_count<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(ReadonlySingleton::new(storage, VIRTUAL_PRICE_COUNT_KEY)
+        .may_load()?
+        .unwrap_or(0))
+}
+
+/// Spot price of `base` in `quote`, normalized, before fees and scaled by 10^18, summed over
+/// every second since the pair was first observed. Assets are keyed by id, so pairs don't move
+/// when assets are removed. Wraps on overflow, differences between observations stay exact.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CumulativePrice {
+    pub base: u32,
+    pub quote: u32,
+    pub cumulative: Uint128,
+}
+
+/// Cumulative prices of every ordered asset pair up to `time`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PriceObservation {
+    pub time: u64,
+    pub cumulative_prices: Vec<CumulativePrice>,
+}
+
+impl PriceObservation {
+    pub fn cumulative_price(&self, base: u32, quote: u32) -> Option<Uint128> {
+        self.cumulative_prices
+            .iter()
+            .find(|p| p.base == base && p.quote == quote)
+            .map(|p| p.cumulative)
+    }
+}
+
+// A ring buffer like the virtual prices
+const PRICE_OBSERVATIONS_PREFIX: &[u8] = b"price_observations";
+const PRICE_OBSERVATION_COUNT_KEY: &[u8] = b"price_observation_count";
+const MAX_PRICE_OBSERVATIONS: u64 = 64;
+
+pub fn push_price_observation<S: Storage>(
+    storage: &mut S,
+    observation: &PriceObservation,
+) -> StdResult<()> {
+    let count = read_price_observation_count(storage)?;
+    bucket(PRICE_OBSERVATIONS_PREFIX, storage)
+        .save(&(count % MAX_PRICE_OBSERVATIONS).to_be_bytes(), observation)?;
+    Singleton::new(storage, PRICE_OBSERVATION_COUNT_KEY).save(&(count + 1))
+}
+
+/// Oldest first
+pub fn read_price_observations<S: Storage>(storage: &S) -> StdResult<Vec<PriceObservation>> {
+    let count = read_price_observation_count(storage)?;
+    (count.saturating_sub(MAX_PRICE_OBSERVATIONS)..count)
+        .map(|i| read_price_observation(storage, i))
+        .collect()
+}
+
+pub fn read_last_price_observation<S: Storage>(storage: &S) -> StdResult<Option<PriceObservation>> {
+    match read_price_observation_count(storage)? {
+        0 => Ok(None),
+        count => read_price_observation(storage, count - 1).map(Some),
+    }
+}
+
+fn read_price_observation<S: Storage>(storage: &S, i: u64) -> StdResult<PriceObservation> {
+    bucket_read(PRICE_OBSERVATIONS_PREFIX, storage)
+        .load(&(i % MAX_PRICE_OBSERVATIONS).to_be_bytes())
+}
+
+fn read_price_observation_count<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(ReadonlySingleton::new(storage, PRICE_OBSERVATION_COUNT_KEY)
+        .may_load()?
+        .unwrap_or(0))
+}
+
+/// LP minted and burned since the pool was created, saturating at u128::MAX
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LpFlows {
+    pub minted: Uint128,
+    pub burned: Uint128,
+}
+
+const LP_FLOWS_KEY: &[u8] = b"lp_flows";
+
+pub fn store_lp_flows<S: Storage>(storage: &mut S, flows: &LpFlows) -> StdResult<()> {
+    Singleton::new(storage, LP_FLOWS_KEY).save(flows)
+}
+
+pub fn read_lp_flows<S: Storage>(storage: &S) -> StdResult<LpFlows> {
+    Ok(ReadonlySingleton::new(storage, LP_FLOWS_KEY)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// LP minted to and burned by an address through the pool, saturating at u128::MAX, and the
+/// amounts it deposited and withdrew
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Position {
+    pub lp_minted: Uint128,
+    pub lp_burned: Uint128,
+    pub assets: Vec<AssetFlow>,
+}
+
+const POSITIONS_PREFIX: &[u8] = b"positions";
+
+pub fn store_position<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    position: &Position,
+) -> StdResult<()> {
+    bucket(POSITIONS_PREFIX, storage).save(address.as_str().as_bytes(), position)
+}
+
+pub fn read_position<S: Storage>(storage: &S, address: &HumanAddr) -> StdResult<Position> {
+    Ok(bucket_read(POSITIONS_PREFIX, storage)
+        .may_load(address.as_str().as_bytes())?
+        .unwrap_or_default())
+}
+
+// Each address's swaps are stored one per key, by their index in its history
+const SWAP_HISTORY_PREFIX: &[u8] = b"swap_history";
+const SWAP_HISTORY_COUNTS_PREFIX: &[u8] = b"swap_history_counts";
+const NEXT_SWAP_TX_ID_KEY: &[u8] = b"next_swap_tx_id";
+
+fn swap_history_key(address: &HumanAddr, index: u64) -> Vec<u8> {
+    [
+        to_length_prefixed(address.as_str().as_bytes()),
+        index.to_be_bytes().to_vec(),
+    ]
+    .concat()
+}
+
+/// Returns the id of the next swap and advances it
+pub fn next_swap_tx_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    let id: u64 = ReadonlySingleton::new(storage, NEXT_SWAP_TX_ID_KEY)
+        .may_load()?
+        .unwrap_or(0);
+    Singleton::new(storage, NEXT_SWAP_TX_ID_KEY).save(&(id + 1))?;
+    Ok(id)
+}
+
+pub fn append_swap_tx<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    tx: &SwapTx,
+) -> StdResult<()> {
+    let count = read_swap_tx_count(storage, address)?;
+    bucket(SWAP_HISTORY_PREFIX, storage).save(&swap_history_key(address, count), tx)?;
+    bucket(SWAP_HISTORY_COUNTS_PREFIX, storage).save(address.as_str().as_bytes(), &(count + 1))
+}
+
+pub fn read_swap_tx_count<S: Storage>(storage: &S, address: &HumanAddr) -> StdResult<u64> {
+    Ok(bucket_read(SWAP_HISTORY_COUNTS_PREFIX, storage)
+        .may_load(address.as_str().as_bytes())?
+        .unwrap_or(0))
+}
+
+/// Newest first, page 0 holding the latest `page_size` swaps
+pub fn read_swap_txs<S: Storage>(
+    storage: &S,
+    address: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Vec<SwapTx>> {
+    let count = read_swap_tx_count(storage, address)?;
+    let end = count.saturating_sub(page as u64 * page_size as u64);
+    let start = end.saturating_sub(page_size as u64);
+    (start..end)
+        .rev()
+        .map(|index| {
+            bucket_read(SWAP_HISTORY_PREFIX, storage).load(&swap_history_key(address, index))
+        })
+        .collect()
+}
+
+const VIEWING_KEYS_PREFIX: &[u8] = b"viewing_keys";
+
+/// sha256 of the viewing key `address` set, the key itself isn't stored
+pub fn store_viewing_key<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    hashed_key: &[u8; 32],
+) -> StdResult<()> {
+    bucket(VIEWING_KEYS_PREFIX, storage).save(address.as_str().as_bytes(), hashed_key)
+}
+
+pub fn read_viewing_key<S: Storage>(
+    storage: &S,
+    address: &HumanAddr,
+) -> StdResult<Option<[u8; 32]>> {
+    bucket_read(VIEWING_KEYS_PREFIX, storage).may_load(address.as_str().as_bytes())
+}
+
+const REVOKED_PERMITS_PREFIX: &[u8] = b"revoked_permits";
+
+fn revoked_permit_key(address: &HumanAddr, permit_name: &str) -> Vec<u8> {
+    [
+        to_length_prefixed(address.as_str().as_bytes()),
+        permit_name.as_bytes().to_vec(),
+    ]
+    .concat()
+}
+
+pub fn revoke_permit<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    permit_name: &str,
+) -> StdResult<()> {
+    bucket(REVOKED_PERMITS_PREFIX, storage).save(&revoked_permit_key(address, permit_name), &true)
+}
+
+pub fn is_permit_revoked<S: Storage>(
+    storage: &S,
+    address: &HumanAddr,
+    permit_name: &str,
+) -> StdResult<bool> {
+    Ok(bucket_read(REVOKED_PERMITS_PREFIX, storage)
+        .may_load(&revoked_permit_key(address, permit_name))?
+        .unwrap_or(false))
 }
@@ -0,0 +1,5241 @@
+use cosmwasm_std::testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{
+    coins, from_binary, log, to_binary, BankMsg, Binary, CosmosMsg, Decimal, Env, Extern,
+    HandleResponse, HandleResult, HumanAddr, ReadonlyStorage, StdError, Storage, Uint128, WasmMsg,
+};
+use cosmwasm_storage::to_length_prefixed;
+use lp_token as snip20;
+use primitive_types::U256;
+use secret_toolkit::snip20 as snip20_utils;
+
+use crate::auth::{check_viewing_key, validate_permit};
+use crate::contract::{
+    handle, init, query, MAX_BATCH_OPERATIONS, MAX_BATCH_SIMULATIONS, MAX_BPS, MAX_DEPTH_POINTS,
+    MAX_ROUND_DOWN_TO_NEAREST,
+};
+use crate::error::ContractError;
+use crate::math::marginal_price;
+use crate::mock_querier::{mock_dependencies, WasmMockQuerier};
+use crate::msg::{
+    AdminFeesResponse, AmountInResponse, AmountOutResponse, AssetFlow, AssetSnapshot,
+    BatchOperation, BatchOperationResult, BatchSwapSimulationResponse, ConfigResponse,
+    CurveStateResponse, DepositCapsResponse, DepthCurveResponse, DepthPoint, EffectiveFeeResponse,
+    ExecutionPriceResponse, FactoryHandleMsg, FactoryInfo, HandleMsg, ImbalanceResponse, InitMsg,
+    LiquidityTokenResponse, LpFlowsResponse, LpNeededForWithdrawalResponse, LpValueResponse,
+    MostNeededTokenResponse, Permission, Permit, PermitParams, PermitSignature, PoolAsset,
+    PoolSnapshotResponse, PoolStatus, PoolsResponse, PositionResponse, ProvideLiquidityResponse,
+    PubKey, QueryMsg, QueryWithPermit, RebalancingDepositResponse, ReverseSimulationResponse,
+    SimulatedSwap, SimulationResponse, Snip20ReceiveMsg, SwapResponse, SwapTx, Token, TokenAmount,
+    TokenByIndexResponse, TokenIndexResponse, TokensResponse, TransactionHistoryResponse,
+    TvlResponse, TwapResponse, VirtualPriceTwapResponse, VolumeResponse, WithdrawLiquidityResponse,
+    DEFAULT_MIN_RESERVE_AFTER_SWAP,
+};
+use crate::state::{
+    read_admin_fees, read_all_assets, read_config, read_lock, read_reserves, store_admin_fees,
+    store_config, store_reserves,
+};
+
+const ADMIN: &str = "admin";
+const USER: &str = "user";
+const LP_TOKEN: &str = "lp_token";
+const VIEWING_KEY: &str = "viewing_key";
+
+fn code_hash(address: &str) -> String {
+    format!("{}_hash", address)
+}
+
+/// Instantiates a pool over `tokens` and binds the LP token, like the after-init hook would
+fn init_pool(tokens: &[(&str, u8)]) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    init_pool_with_native(tokens, &[])
+}
+
+/// Like init_pool, with native `denoms` listed after the SNIP-20 tokens
+fn init_pool_with_native(
+    tokens: &[(&str, u8)],
+    denoms: &[&str],
+) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let mut deps = init_pool_without_lp_token(tokens, denoms);
+    handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        HandleMsg::PostInitialize {},
+    )
+    .unwrap();
+
+    deps
+}
+
+/// Instantiates a pool whose LP token hasn't called back yet
+fn init_pool_without_lp_token(
+    tokens: &[(&str, u8)],
+    denoms: &[&str],
+) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let mut deps = mock_token_deps(tokens);
+    init(&mut deps, mock_env(ADMIN, &[]), init_msg(tokens, denoms)).unwrap();
+
+    deps
+}
+
+/// Mocks `tokens` and the LP token, with USER's allowances granted to the pool
+fn mock_token_deps(tokens: &[(&str, u8)]) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let mut deps = mock_dependencies(20, &[]);
+    for (address, decimals) in tokens {
+        deps.querier
+            .with_token(address, *decimals, Some(Uint128::zero()));
+        deps.querier
+            .set_allowance(address, USER, MOCK_CONTRACT_ADDR, u128::MAX);
+    }
+    deps.querier.with_token(LP_TOKEN, 18, Some(Uint128::zero()));
+
+    deps
+}
+
+fn init_msg(tokens: &[(&str, u8)], denoms: &[&str]) -> InitMsg {
+    InitMsg {
+        assets: tokens
+            .iter()
+            .map(|(address, _)| Token {
+                address: HumanAddr::from(*address),
+                code_hash: code_hash(address),
+                native: false,
+            })
+            .chain(denoms.iter().map(|denom| Token {
+                address: HumanAddr::from(*denom),
+                code_hash: String::new(),
+                native: true,
+            }))
+            .collect(),
+        initial_tokens_viewing_key: VIEWING_KEY.to_string(),
+        lp_token_code_id: 1,
+        lp_token_code_hash: code_hash(LP_TOKEN),
+        lp_token_prng_seed: Binary(b"seed".to_vec()),
+        lp_token_label: "stable-lp".to_string(),
+        admin: HumanAddr::from(ADMIN),
+        amp: 100,
+        swap_fee_nom: Uint128(3),
+        swap_fee_denom: Uint128(1000),
+        status: None,
+        round_down_pool_answer_to_nearest: Uint128(1),
+        max_swap_out_bps: None,
+        dynamic_fee: None,
+        dynamic_fee_base_bps: None,
+        dynamic_fee_max_bps: None,
+        dynamic_fee_min_bps: None,
+        depeg_threshold_bps: None,
+        max_referral_fee_bps: None,
+        admin_fee_bps: None,
+        max_volume_per_block: None,
+        rebalance_bonus_to_lp_bps: None,
+        deposit_bonus_bps: None,
+        per_asset_deposit_cap: None,
+        total_tvl_cap: None,
+        min_reserve_after_swap: None,
+        seed_tvl_cap: None,
+        factory: None,
+    }
+}
+
+fn swap_msg(to_token: &str, amount: u128) -> HandleMsg {
+    HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(amount),
+        msg: to_binary(&Snip20ReceiveMsg::Swap {
+            to_token: HumanAddr::from(to_token),
+            recipient: None,
+            referrer: None,
+            referral_fee_bps: None,
+            expected_return: None,
+            max_slippage_bps: None,
+            deadline: None,
+            unwrap: None,
+        })
+        .unwrap(),
+    }
+}
+
+/// Credits `amount` of the sender token to the pool and swaps it, like a SNIP-20 Send would
+fn swap(
+    deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+    env: Env,
+    to_token: &str,
+    amount: u128,
+) -> HandleResult {
+    deps.querier
+        .add_balance(env.message.sender.as_str(), MOCK_CONTRACT_ADDR, amount);
+    handle(deps, env, swap_msg(to_token, amount))
+}
+
+/// Runs the FinalizeDeposit callback that ends a ProvideLiquidity response
+fn finalize_deposit(
+    deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+    res: &HandleResponse,
+) -> HandleResult {
+    match res.messages.last() {
+        Some(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        })) if contract_addr == &HumanAddr::from(MOCK_CONTRACT_ADDR) => handle(
+            deps,
+            mock_env(MOCK_CONTRACT_ADDR, &[]),
+            from_binary(msg).unwrap(),
+        ),
+        _ => panic!("Must end with the FinalizeDeposit callback"),
+    }
+}
+
+fn provide_msg(deposits: &[(&str, u128)]) -> HandleMsg {
+    HandleMsg::ProvideLiquidity {
+        assets: deposits
+            .iter()
+            .map(|(address, amount)| token_amount(address, *amount))
+            .collect(),
+        cancel_if_no_bonus: None,
+        min_lp_out: None,
+        deadline: None,
+    }
+}
+
+fn token_amount(address: &str, amount: u128) -> TokenAmount {
+    TokenAmount {
+        address: HumanAddr::from(address),
+        code_hash: code_hash(address),
+        amount: Uint128(amount),
+    }
+}
+
+fn withdraw_msg(amount: u128) -> HandleMsg {
+    HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(amount),
+        msg: to_binary(&Snip20ReceiveMsg::WithdrawLiquidity {
+            deadline: None,
+            unwrap: None,
+        })
+        .unwrap(),
+    }
+}
+
+/// Fields of UpdateConfig, all left unchanged by default
+#[derive(Default)]
+struct ConfigUpdate {
+    swap_fee_nom: Option<Uint128>,
+    swap_fee_denom: Option<Uint128>,
+    round_down_pool_answer_to_nearest: Option<Uint128>,
+    max_swap_out_bps: Option<u32>,
+    dynamic_fee: Option<bool>,
+    dynamic_fee_base_bps: Option<u32>,
+    dynamic_fee_max_bps: Option<u32>,
+    dynamic_fee_min_bps: Option<u32>,
+    depeg_threshold_bps: Option<u32>,
+    max_referral_fee_bps: Option<u32>,
+    admin_fee_bps: Option<u32>,
+    max_volume_per_block: Option<Uint128>,
+    rebalance_bonus_to_lp_bps: Option<u32>,
+    deposit_bonus_bps: Option<u32>,
+    per_asset_deposit_cap: Option<Uint128>,
+    total_tvl_cap: Option<Uint128>,
+    min_reserve_after_swap: Option<Uint128>,
+    status: Option<PoolStatus>,
+}
+
+fn update_config_msg(update: ConfigUpdate) -> HandleMsg {
+    HandleMsg::UpdateConfig {
+        swap_fee_nom: update.swap_fee_nom,
+        swap_fee_denom: update.swap_fee_denom,
+        round_down_pool_answer_to_nearest: update.round_down_pool_answer_to_nearest,
+        max_swap_out_bps: update.max_swap_out_bps,
+        dynamic_fee: update.dynamic_fee,
+        dynamic_fee_base_bps: update.dynamic_fee_base_bps,
+        dynamic_fee_max_bps: update.dynamic_fee_max_bps,
+        dynamic_fee_min_bps: update.dynamic_fee_min_bps,
+        depeg_threshold_bps: update.depeg_threshold_bps,
+        max_referral_fee_bps: update.max_referral_fee_bps,
+        admin_fee_bps: update.admin_fee_bps,
+        max_volume_per_block: update.max_volume_per_block,
+        rebalance_bonus_to_lp_bps: update.rebalance_bonus_to_lp_bps,
+        deposit_bonus_bps: update.deposit_bonus_bps,
+        per_asset_deposit_cap: update.per_asset_deposit_cap,
+        total_tvl_cap: update.total_tvl_cap,
+        min_reserve_after_swap: update.min_reserve_after_swap,
+        status: update.status,
+    }
+}
+
+fn set_asset_enabled(
+    deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+    token: &str,
+    enabled: bool,
+) {
+    handle(
+        deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::SetAssetEnabled {
+            token: HumanAddr::from(token),
+            enabled,
+        },
+    )
+    .unwrap();
+}
+
+/// Sets the mock balances to the tracked reserves, as if the pool's payouts had gone through
+fn settle_payouts(deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>) {
+    let assets = read_all_assets(&deps.storage).unwrap();
+    let reserves = read_reserves(&deps.storage).unwrap();
+    for (token, reserve) in assets.iter().zip(reserves) {
+        if !token.native {
+            deps.querier
+                .set_balance(token.address.as_str(), MOCK_CONTRACT_ADDR, reserve.u128());
+        }
+    }
+}
+
+/// Sets the pool's balance of `token` along with its tracked reserve, as if it had been
+/// deposited
+fn fund_pool(deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, token: &str, amount: u128) {
+    let assets = read_all_assets(&deps.storage).unwrap();
+    let index = assets
+        .iter()
+        .position(|t| t.address == HumanAddr::from(token))
+        .unwrap();
+    if assets[index].native {
+        deps.querier
+            .set_native_balance(MOCK_CONTRACT_ADDR, coins(amount, token));
+    } else {
+        deps.querier.set_balance(token, MOCK_CONTRACT_ADDR, amount);
+    }
+    let mut reserves = read_reserves(&deps.storage).unwrap();
+    reserves[index] = Uint128(amount);
+    store_reserves(&mut deps.storage, &reserves).unwrap();
+}
+
+// Permits signed offline with Keplr's amino sign doc, for chain secret-4
+const PERMIT_SIGNER: &str = "secret19gftn6lkw6rz7t28fvknzzf9nklernm8wc2n8n";
+const PERMIT_PUB_KEY: &str = "A+nJDpBijqlVvkofGWb2RLxOxTG2zF4xTZWJ4bOfaDiR";
+/// Owner permit for the pool
+const OWNER_PERMIT_SIGNATURE: &str =
+    "jpDRAtw+89OGzOReArHjdXgSGUUhFLsXzvGyvB34qqYtae3cZONudmPFR9AjJ5GOTND6R1cMI26b4oSfw6gW9Q==";
+/// Owner permit for secret1other
+const OTHER_CONTRACT_PERMIT_SIGNATURE: &str =
+    "JiQlsIW3KereE5tiozpD+fF/EINmcMl0iFDZG9f/qTw+4G4k9xH342z/pVv0J4yRqtgfToTC1hSNfS8FG2IJIw==";
+/// History permit for the pool
+const HISTORY_PERMIT_SIGNATURE: &str =
+    "/YWxJ/1b/3IMcxDou7+7Y9IqYQas0pgCRVL/iNthCA9xV/MI+Ef0IZG9ac1uK9X1LZU1XsKDwQefDWHLgLIVxA==";
+
+/// A permit named pool_queries from PERMIT_SIGNER, with `signature` as is
+fn permit(allowed_token: &str, permission: Permission, signature: &str) -> Permit {
+    Permit {
+        params: PermitParams {
+            allowed_tokens: vec![HumanAddr::from(allowed_token)],
+            permit_name: "pool_queries".to_string(),
+            chain_id: "secret-4".to_string(),
+            permissions: vec![permission],
+        },
+        signature: PermitSignature {
+            pub_key: PubKey {
+                r#type: "tendermint/PubKeySecp256k1".to_string(),
+                value: Binary::from_base64(PERMIT_PUB_KEY).unwrap(),
+            },
+            signature: Binary::from_base64(signature).unwrap(),
+        },
+    }
+}
+
+fn assert_generic_err<T: std::fmt::Debug>(res: Result<T, StdError>, expected: &str) {
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert!(
+            msg.contains(expected),
+            "expected error containing {:?}, got {:?}",
+            expected,
+            msg
+        ),
+        other => panic!("Must return generic error, got {:?}", other),
+    }
+}
+
+#[test]
+fn add_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    deps.querier.with_token("token2", 8, Some(Uint128::zero()));
+    let add = |token: &str| HandleMsg::AddAsset {
+        token: Token {
+            address: HumanAddr::from(token),
+            code_hash: code_hash(token),
+            native: false,
+        },
+        viewing_key: VIEWING_KEY.to_string(),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), add("token2"));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), add("token0"));
+    assert_generic_err(res, "Token already supported");
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), add("token2")).unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert!(res.log.contains(&log("decimals", "8")));
+
+    let assets = read_all_assets(&deps.storage).unwrap();
+    assert_eq!(assets.len(), 3);
+    assert_eq!(assets[2].id, 2);
+    assert_eq!(assets[2].decimals, 8);
+    assert_eq!(assets[2].viewing_key, VIEWING_KEY);
+    assert!(!assets[2].enabled);
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(1_000_000), Uint128(1_000_000), Uint128::zero()]
+    );
+    assert_eq!(read_admin_fees(&deps.storage).unwrap().len(), 3);
+
+    // A removed asset's id isn't handed out again
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::RemoveAsset {
+            token: HumanAddr::from("token2"),
+        },
+    )
+    .unwrap();
+    handle(&mut deps, mock_env(ADMIN, &[]), add("token2")).unwrap();
+    assert_eq!(read_all_assets(&deps.storage).unwrap()[2].id, 3);
+}
+
+#[test]
+fn add_asset_leaves_prices_unchanged() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 2_000_000_000);
+    let quote = query(
+        &deps,
+        QueryMsg::Simulation {
+            offer_token: HumanAddr::from("token0"),
+            ask_token: HumanAddr::from("token1"),
+            amount: Uint128(1_000_000),
+        },
+    )
+    .unwrap();
+    let quote: SimulationResponse = from_binary(&quote).unwrap();
+
+    deps.querier.with_token("token2", 6, Some(Uint128::zero()));
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::AddAsset {
+            token: Token {
+                address: HumanAddr::from("token2"),
+                code_hash: code_hash("token2"),
+                native: false,
+            },
+            viewing_key: VIEWING_KEY.to_string(),
+        },
+    )
+    .unwrap();
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000_000).unwrap();
+    let swapped: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(swapped.return_amount, quote.return_amount);
+
+    // and the new asset can't be traded before it's enabled and seeded
+    let res = swap(&mut deps, mock_env("token0", &[]), "token2", 1_000);
+    assert_generic_err(res, "[asset_disabled]");
+    set_asset_enabled(&mut deps, "token2", true);
+    let res = swap(&mut deps, mock_env("token0", &[]), "token2", 1_000);
+    assert_generic_err(res, "[empty_asset]");
+}
+
+#[test]
+fn remove_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6), ("token2", 6)]);
+    fund_pool(&mut deps, "token1", 1_000);
+    let remove = |token: &str| HandleMsg::RemoveAsset {
+        token: HumanAddr::from(token),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), remove("token1"));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), remove("unknown"));
+    assert_generic_err(res, "[unknown_asset] ");
+
+    // Still held, so it's made withdraw-only first
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), remove("token1")).unwrap();
+    assert!(res.log.contains(&log("withdraw_only", "true")));
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), remove("token1"));
+    assert_generic_err(res, "still holds 1000 of");
+
+    handle(&mut deps, mock_env(ADMIN, &[]), remove("token0")).unwrap();
+    let assets = read_all_assets(&deps.storage).unwrap();
+    assert_eq!(
+        assets
+            .iter()
+            .map(|t| t.address.clone())
+            .collect::<Vec<HumanAddr>>(),
+        vec![HumanAddr::from("token1"), HumanAddr::from("token2")]
+    );
+    assert_eq!(read_reserves(&deps.storage).unwrap().len(), 2);
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), remove("token2"));
+    assert_generic_err(res, "needs at least two");
+}
+
+#[test]
+fn withdraw_only_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6), ("token2", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    fund_pool(&mut deps, "token2", 1_000_000);
+    let remove = HandleMsg::RemoveAsset {
+        token: HumanAddr::from("token2"),
+    };
+
+    handle(&mut deps, mock_env(ADMIN, &[]), remove.clone()).unwrap();
+    assert!(read_all_assets(&deps.storage).unwrap()[2].withdraw_only);
+
+    // Nothing goes in
+    let res = swap(&mut deps, mock_env("token2", &[]), "token0", 1_000);
+    assert_generic_err(res, "[asset_withdraw_only]");
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token2", 1_000)]),
+    );
+    assert_generic_err(res, "[asset_withdraw_only]");
+
+    // but it can be swapped out
+    swap(&mut deps, mock_env("token0", &[]), "token2", 1_000).unwrap();
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), remove.clone());
+    assert_generic_err(res, "drain it to 1000000000000 normalized");
+
+    // Once drained to the min reserve, the dust goes to the admin with the asset
+    let mut reserves = read_reserves(&deps.storage).unwrap();
+    reserves[2] = Uint128(1);
+    store_reserves(&mut deps.storage, &reserves).unwrap();
+    deps.querier.set_balance("token2", MOCK_CONTRACT_ADDR, 1);
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), remove).unwrap();
+    assert!(res.log.contains(&log("dust", "1")));
+    assert_eq!(
+        res.messages,
+        vec![snip20_utils::transfer_msg(
+            HumanAddr::from(ADMIN),
+            Uint128(1),
+            None,
+            256,
+            code_hash("token2"),
+            HumanAddr::from("token2"),
+        )
+        .unwrap()]
+    );
+    assert_eq!(read_all_assets(&deps.storage).unwrap().len(), 2);
+    assert_eq!(read_reserves(&deps.storage).unwrap().len(), 2);
+}
+
+#[test]
+fn set_asset_enabled_admin_only() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::SetAssetEnabled {
+            token: HumanAddr::from("token0"),
+            enabled: false,
+        },
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::SetAssetEnabled {
+            token: HumanAddr::from("unknown"),
+            enabled: false,
+        },
+    );
+    assert_generic_err(res, "[unknown_asset] ");
+}
+
+#[test]
+fn swap_rejects_disabled_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    set_asset_enabled(&mut deps, "token1", false);
+
+    // disabled destination
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 100);
+    assert_generic_err(res, "is disabled");
+
+    // disabled source
+    let res = swap(&mut deps, mock_env("token1", &[]), "token0", 100);
+    assert_generic_err(res, "is disabled");
+
+    set_asset_enabled(&mut deps, "token1", true);
+    swap(&mut deps, mock_env("token0", &[]), "token1", 100).unwrap();
+}
+
+#[test]
+fn provide_liquidity_rejects_disabled_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    set_asset_enabled(&mut deps, "token0", false);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 100), ("token1", 100)]),
+    );
+    assert_generic_err(res, "is disabled");
+
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token1", 100)]),
+    )
+    .unwrap();
+}
+
+#[test]
+fn withdraw_liquidity_returns_disabled_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000);
+    fund_pool(&mut deps, "token1", 1_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000);
+    set_asset_enabled(&mut deps, "token1", false);
+
+    let res = handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000)).unwrap();
+    assert_eq!(res.messages.len(), 3);
+    assert_eq!(
+        res.messages[1],
+        snip20_utils::transfer_msg(
+            HumanAddr::from(USER),
+            Uint128(500),
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn withdraw_liquidity_from_every_asset() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18), ("token2", 8)]);
+    fund_pool(&mut deps, "token0", 3_000_000);
+    fund_pool(&mut deps, "token1", 3 * 10u128.pow(18));
+    fund_pool(&mut deps, "token2", 3 * 10u128.pow(8));
+    deps.querier.set_total_supply(LP_TOKEN, 9 * 10u128.pow(18));
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(3 * 10u128.pow(18)),
+    )
+    .unwrap();
+    let data: WithdrawLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        data.refunds,
+        vec![
+            token_amount("token0", 1_000_000),
+            token_amount("token1", 10u128.pow(18)),
+            token_amount("token2", 10u128.pow(8)),
+        ]
+    );
+    // a transfer per asset and the burn
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![
+            Uint128(2_000_000),
+            Uint128(2 * 10u128.pow(18)),
+            Uint128(2 * 10u128.pow(8)),
+        ]
+    );
+}
+
+#[test]
+fn batch_swap_simulation() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18), ("token2", 8)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    fund_pool(&mut deps, "token2", 100_000_000_000);
+
+    let swap = SimulatedSwap {
+        offer: token_amount("token0", 1_000_000),
+        to_token: HumanAddr::from("token1"),
+    };
+    let res = query(
+        &deps,
+        QueryMsg::BatchSwapSimulation {
+            swaps: vec![
+                swap.clone(),
+                swap.clone(),
+                SimulatedSwap {
+                    offer: token_amount("token2", 1_000),
+                    to_token: HumanAddr::from("token0"),
+                },
+            ],
+        },
+    )
+    .unwrap();
+    let simulations = from_binary::<BatchSwapSimulationResponse>(&res)
+        .unwrap()
+        .simulations;
+    assert_eq!(simulations.len(), 3);
+    // swaps are priced independently, so repeating one doesn't move its price
+    assert_eq!(simulations[0], simulations[1]);
+
+    let res = query(
+        &deps,
+        QueryMsg::BatchSwapSimulation {
+            swaps: vec![SimulatedSwap {
+                offer: token_amount("unknown", 1_000),
+                to_token: HumanAddr::from("token0"),
+            }],
+        },
+    );
+    assert_generic_err(res, "Unknown asset");
+
+    let res = query(
+        &deps,
+        QueryMsg::BatchSwapSimulation {
+            swaps: vec![swap; MAX_BATCH_SIMULATIONS + 1],
+        },
+    );
+    assert_generic_err(res, "Cannot simulate more than");
+}
+
+#[test]
+fn swap_normalization_overflow() {
+    let return_amount = |res: HandleResult| {
+        from_binary::<SwapResponse>(&res.unwrap().data.unwrap())
+            .unwrap()
+            .return_amount
+    };
+
+    // amounts far past any reserve, either way, are paid no more than the pool holds
+    for (from, to, reserve) in [
+        ("token6", "token18", 1_000 * 10u128.pow(18)),
+        ("token18", "token6", 1_000_000_000),
+    ] {
+        let mut deps = init_pool(&[("token6", 6), ("token18", 18)]);
+        fund_pool(&mut deps, "token6", 1_000_000_000);
+        fund_pool(&mut deps, "token18", 1_000 * 10u128.pow(18));
+        let res = swap(&mut deps, mock_env(from, &[]), to, u128::MAX / 2);
+        assert!(return_amount(res).u128() < reserve);
+    }
+}
+
+#[test]
+fn lp_value() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+
+    let res = query(&deps, QueryMsg::LpValue { amount: Uint128(1) }).unwrap();
+    let value: LpValueResponse = from_binary(&res).unwrap();
+    assert_eq!(value.value, Uint128::zero());
+    assert!(value.assets.iter().all(|a| a.amount == Uint128::zero()));
+
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+
+    let res = query(
+        &deps,
+        QueryMsg::LpValue {
+            amount: Uint128(1_000 * 10u128.pow(18)),
+        },
+    )
+    .unwrap();
+    let value: LpValueResponse = from_binary(&res).unwrap();
+    assert_eq!(value.value, Uint128(1_000 * 10u128.pow(18)));
+    assert_eq!(
+        value.assets,
+        vec![
+            token_amount("token0", 500_000_000),
+            token_amount("token1", 500 * 10u128.pow(18)),
+        ]
+    );
+}
+
+#[test]
+fn swap_rejects_zero_amount() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 0);
+    assert_generic_err(res, "Cannot swap a zero amount");
+}
+
+#[test]
+fn provide_liquidity_rejects_zero_deposits() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 0), ("token1", 0)]),
+    );
+    assert_generic_err(res, "without a non-zero deposit");
+
+    let res = handle(&mut deps, mock_env(USER, &[]), provide_msg(&[]));
+    assert_generic_err(res, "without a non-zero deposit");
+}
+
+#[test]
+fn update_config_admin_only() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let msg = update_config_msg(ConfigUpdate {
+        max_swap_out_bps: Some(3_000),
+        ..Default::default()
+    });
+
+    let res = handle(&mut deps, mock_env(USER, &[]), msg.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            max_swap_out_bps: Some(MAX_BPS + 1),
+            ..Default::default()
+        }),
+    );
+    assert_generic_err(res, "max_swap_out_bps must not exceed");
+
+    handle(&mut deps, mock_env(ADMIN, &[]), msg).unwrap();
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.max_swap_out_bps, 3_000);
+}
+
+#[test]
+fn update_config_swap_fee() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let fee_msg = |swap_fee_nom: u128, swap_fee_denom: u128| {
+        update_config_msg(ConfigUpdate {
+            swap_fee_nom: Some(Uint128(swap_fee_nom)),
+            swap_fee_denom: Some(Uint128(swap_fee_denom)),
+            ..Default::default()
+        })
+    };
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), fee_msg(0, 0));
+    assert_generic_err(res, "swap_fee_denom must not be zero");
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), fee_msg(11, 10));
+    assert_generic_err(res, "swap_fee_nom must not exceed swap_fee_denom");
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), fee_msg(1, 10_000)).unwrap();
+    assert!(res.log.contains(&log("swap_fee_nom", "3 -> 1")));
+    assert!(res.log.contains(&log("swap_fee_denom", "1000 -> 10000")));
+    assert!(!res.log.iter().any(|l| l.key == "max_swap_out_bps"));
+
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.swap_fee_nom, Uint128(1));
+    assert_eq!(config.swap_fee_denom, Uint128(10_000));
+}
+
+#[test]
+fn swap_output_capped_by_reserve() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            max_swap_out_bps: Some(3_000),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 500_000_000);
+    assert_generic_err(res, "exceeds the max of 300000");
+
+    swap(&mut deps, mock_env("token0", &[]), "token1", 100_000).unwrap();
+}
+
+#[test]
+fn dynamic_fee_charges_imbalancing_swaps_more() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    fund_pool(&mut deps, "token1", 500_000_000);
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            dynamic_fee: Some(true),
+            dynamic_fee_base_bps: Some(50),
+            dynamic_fee_max_bps: Some(4),
+            ..Default::default()
+        }),
+    );
+    assert_generic_err(res, "must not exceed dynamic_fee_max_bps");
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            dynamic_fee: Some(true),
+            dynamic_fee_base_bps: Some(4),
+            dynamic_fee_max_bps: Some(50),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    let res = query(
+        &deps,
+        QueryMsg::BatchSwapSimulation {
+            swaps: vec![
+                SimulatedSwap {
+                    offer: token_amount("token0", 100_000_000),
+                    to_token: HumanAddr::from("token1"),
+                },
+                SimulatedSwap {
+                    offer: token_amount("token1", 100_000_000),
+                    to_token: HumanAddr::from("token0"),
+                },
+            ],
+        },
+    )
+    .unwrap();
+    let simulations = from_binary::<BatchSwapSimulationResponse>(&res)
+        .unwrap()
+        .simulations;
+    let imbalancing = &simulations[0];
+    let rebalancing = &simulations[1];
+
+    assert!(rebalancing.fee_amount < imbalancing.fee_amount);
+    assert!(rebalancing.fee_amount >= Uint128(100_000_000).multiply_ratio(4u128, 10_000u128));
+    assert!(imbalancing.fee_amount <= Uint128(100_000_000).multiply_ratio(50u128, 10_000u128));
+}
+
+#[test]
+fn dynamic_fee_floor_discounts_rebalancing_swaps() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    fund_pool(&mut deps, "token1", 500_000_000);
+    let update_fees = |min_bps: u32| {
+        update_config_msg(ConfigUpdate {
+            dynamic_fee: Some(true),
+            dynamic_fee_base_bps: Some(30),
+            dynamic_fee_max_bps: Some(50),
+            dynamic_fee_min_bps: Some(min_bps),
+            ..Default::default()
+        })
+    };
+    let fee_bps = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, offer: &str, ask: &str| {
+        from_binary::<SimulationResponse>(
+            &query(
+                deps,
+                QueryMsg::Simulation {
+                    offer_token: HumanAddr::from(offer),
+                    ask_token: HumanAddr::from(ask),
+                    amount: Uint128(100_000_000),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+        .fee_bps
+    };
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), update_fees(31));
+    assert_generic_err(res, "must not exceed dynamic_fee_base_bps");
+
+    // without a floor, every swap pays at least the base fee
+    handle(&mut deps, mock_env(ADMIN, &[]), update_fees(0)).unwrap();
+    let rebalancing_fee = fee_bps(&deps, "token1", "token0");
+    assert!(rebalancing_fee >= 30);
+
+    handle(&mut deps, mock_env(ADMIN, &[]), update_fees(5)).unwrap();
+    let imbalancing_fee = fee_bps(&deps, "token0", "token1");
+    let discounted_fee = fee_bps(&deps, "token1", "token0");
+    assert!((31..=50).contains(&imbalancing_fee));
+    assert!((5..30).contains(&discounted_fee));
+
+    // the effective fee matches the simulated one
+    let res = query(
+        &deps,
+        QueryMsg::GetEffectiveFee {
+            offer: token_amount("token1", 100_000_000),
+            to_token: HumanAddr::from("token0"),
+        },
+    )
+    .unwrap();
+    let effective: EffectiveFeeResponse = from_binary(&res).unwrap();
+    assert_eq!(effective.fee_bps, discounted_fee);
+}
+
+#[test]
+fn swap_volume() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6), ("token2", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+
+    swap(&mut deps, mock_env("token0", &[]), "token1", 1_000_000).unwrap();
+    let res = swap(&mut deps, mock_env("token1", &[]), "token0", 2_000_000).unwrap();
+    let token0_out = res
+        .log
+        .iter()
+        .find(|l| l.key == "dst_amount")
+        .unwrap()
+        .value
+        .parse::<u128>()
+        .unwrap();
+
+    let res = query(&deps, QueryMsg::GetVolume {}).unwrap();
+    let volumes = from_binary::<VolumeResponse>(&res).unwrap().volumes;
+    assert_eq!(volumes.len(), 3);
+    assert_eq!(volumes[0].address, HumanAddr::from("token0"));
+    assert_eq!(volumes[0].volume_in, Uint128(1_000_000));
+    assert_eq!(volumes[0].volume_out, Uint128(token0_out));
+    assert_eq!(volumes[1].volume_in, Uint128(2_000_000));
+    assert_eq!(volumes[2].volume_in, Uint128::zero());
+    assert_eq!(volumes[2].volume_out, Uint128::zero());
+}
+
+#[test]
+fn depeg_threshold_blocks_swaps_into_dominant_token() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 700_000_000);
+    fund_pool(&mut deps, "token1", 300_000_000);
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            depeg_threshold_bps: Some(7_500),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    // 700 + 100 out of 1000 is 80%
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 100_000_000);
+    assert_generic_err(res, "above the depeg threshold of 7500 bps");
+
+    // 700 + 10 out of 1000 is 71%
+    swap(&mut deps, mock_env("token0", &[]), "token1", 10_000_000).unwrap();
+
+    // swapping out of the dominant token is always allowed
+    swap(&mut deps, mock_env("token1", &[]), "token0", 100_000_000).unwrap();
+}
+
+#[test]
+fn provide_liquidity_uses_stored_code_hash() {
+    let mut deps = init_pool(&[("token0", 6)]);
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::ProvideLiquidity {
+            assets: vec![TokenAmount {
+                address: HumanAddr::from("token0"),
+                code_hash: "spoofed_hash".to_string(),
+                amount: Uint128(1_000_000),
+            }],
+            cancel_if_no_bonus: None,
+            min_lp_out: None,
+            deadline: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0],
+        snip20_utils::transfer_from_msg(
+            HumanAddr::from(USER),
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            Uint128(1_000_000),
+            None,
+            256,
+            code_hash("token0"),
+            HumanAddr::from("token0"),
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn provide_liquidity_native() {
+    let mut deps = init_pool_with_native(&[("token0", 6)], &["uscrt"]);
+    let msg = provide_msg(&[("token0", 1_000_000), ("uscrt", 1_000_000)]);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &coins(999_999, "uscrt")),
+        msg.clone(),
+    );
+    assert_generic_err(res, "does not match the sent funds");
+
+    let mut sent = coins(1_000_000, "uscrt");
+    sent.extend(coins(1, "uatom"));
+    let res = handle(&mut deps, mock_env(USER, &sent), msg.clone());
+    assert_generic_err(res, "uatom are not part of the deposit");
+
+    let res = handle(&mut deps, mock_env(USER, &coins(1_000_000, "uscrt")), msg).unwrap();
+    // transfer_from for token0 only, then the FinalizeDeposit callback
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[0],
+        snip20_utils::transfer_from_msg(
+            HumanAddr::from(USER),
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            Uint128(1_000_000),
+            None,
+            256,
+            code_hash("token0"),
+            HumanAddr::from("token0"),
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn swap_native() {
+    let mut deps = init_pool_with_native(&[("token0", 6)], &["uscrt"]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "uscrt", 1_000_000_000);
+    let msg = HandleMsg::SwapNative {
+        to_token: HumanAddr::from("token0"),
+        recipient: None,
+        referrer: None,
+        referral_fee_bps: None,
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), msg.clone());
+    assert_generic_err(res, "Exactly one native coin");
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &coins(1_000, "uatom")),
+        msg.clone(),
+    );
+    assert_generic_err(res, "Unknown source asset");
+
+    // sent coins are already in the pool's balance when it runs
+    deps.querier
+        .set_native_balance(MOCK_CONTRACT_ADDR, coins(1_001_000_000, "uscrt"));
+    let res = handle(&mut deps, mock_env(USER, &coins(1_000_000, "uscrt")), msg).unwrap();
+    match &res.messages[0] {
+        CosmosMsg::Wasm(_) => {}
+        _ => panic!("Must pay out token0 through its contract"),
+    }
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "uscrt", 1_000_000).unwrap();
+    match &res.messages[0] {
+        CosmosMsg::Bank(BankMsg::Send {
+            from_address,
+            to_address,
+            amount,
+        }) => {
+            assert_eq!(from_address, &HumanAddr::from(MOCK_CONTRACT_ADDR));
+            assert_eq!(to_address, &HumanAddr::from(USER));
+            assert_eq!(amount[0].denom, "uscrt");
+        }
+        _ => panic!("Must pay out uscrt through the bank module"),
+    }
+}
+
+#[test]
+fn withdraw_liquidity_native() {
+    let mut deps = init_pool_with_native(&[("token0", 6)], &["uscrt"]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "uscrt", 1_000_000_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000_000_000);
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(1_000_000_000),
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[1],
+        CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            to_address: HumanAddr::from(USER),
+            amount: coins(500_000_000, "uscrt"),
+        })
+    );
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(500_000_000), Uint128(500_000_000)]
+    );
+}
+
+#[test]
+fn wrapped_native_swaps_and_withdrawals() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    let set_wrapped = HandleMsg::SetWrappedNative {
+        token: HumanAddr::from("token1"),
+        wrapped_native: true,
+    };
+    let res = handle(&mut deps, mock_env(USER, &[]), set_wrapped.clone());
+    assert_eq!(res.unwrap_err(), StdError::unauthorized());
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::SetWrappedNative {
+            token: HumanAddr::from("token9"),
+            wrapped_native: true,
+        },
+    );
+    assert_generic_err(res, "[unknown_asset] ");
+    handle(&mut deps, mock_env(ADMIN, &[]), set_wrapped).unwrap();
+
+    // uscrt is wrapped into token1 first, then swapped by the pool's own callback
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &coins(1_000_000, "uscrt")),
+        HandleMsg::SwapNative {
+            to_token: HumanAddr::from("token0"),
+            recipient: None,
+            referrer: None,
+            referral_fee_bps: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0],
+        snip20_utils::deposit_msg(
+            Uint128(1_000_000),
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()
+    );
+    let callback = match &res.messages[1] {
+        CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary::<HandleMsg>(msg).unwrap(),
+        _ => panic!("Must finish the swap through a callback"),
+    };
+    let res = handle(&mut deps, mock_env(USER, &[]), callback.clone());
+    assert_eq!(res.unwrap_err(), StdError::unauthorized());
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 1_000_000);
+    let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), callback).unwrap();
+    match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("token0"))
+        }
+        _ => panic!("Must pay out token0 through its contract"),
+    }
+
+    // Payouts in the wrapped token can be redeemed to uscrt on the way out
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    let unwrap_swap = |to_token: &str| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(1_000_000),
+        msg: to_binary(&Snip20ReceiveMsg::Swap {
+            to_token: HumanAddr::from(to_token),
+            recipient: None,
+            referrer: None,
+            referral_fee_bps: None,
+            expected_return: None,
+            max_slippage_bps: None,
+            deadline: None,
+            unwrap: Some(true),
+        })
+        .unwrap(),
+    };
+    let res = handle(&mut deps, mock_env("token0", &[]), unwrap_swap("token1")).unwrap();
+    let returned = match &res.messages[1] {
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address, amount, ..
+        }) => {
+            assert_eq!(to_address, &HumanAddr::from(USER));
+            assert_eq!(amount[0].denom, "uscrt");
+            amount[0].amount
+        }
+        _ => panic!("Must pay out the redeemed uscrt through the bank module"),
+    };
+    assert_eq!(
+        res.messages[0],
+        snip20_utils::redeem_msg(
+            returned,
+            None,
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()
+    );
+
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 1_000_000);
+    let res = handle(&mut deps, mock_env("token1", &[]), unwrap_swap("token0"));
+    assert_generic_err(res, "is not a wrapped native token");
+}
+
+#[test]
+fn amount_in_and_out() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    let amount_out = |amount_in: u128, token_in: &str, token_out: &str| {
+        from_binary::<AmountOutResponse>(
+            &query(
+                &deps,
+                QueryMsg::GetAmountOut {
+                    amount_in: Uint128(amount_in),
+                    token_in: HumanAddr::from(token_in),
+                    token_out: HumanAddr::from(token_out),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+        .amount_out
+        .u128()
+    };
+    let amount_in = |amount_out: u128, token_in: &str, token_out: &str| {
+        from_binary::<AmountInResponse>(
+            &query(
+                &deps,
+                QueryMsg::GetAmountIn {
+                    amount_out: Uint128(amount_out),
+                    token_in: HumanAddr::from(token_in),
+                    token_out: HumanAddr::from(token_out),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+        .amount_in
+        .u128()
+    };
+
+    // 3/1000 fee, rounded up, and a little slippage
+    assert_eq!(
+        amount_out(1_000_000, "token0", "token1"),
+        996_995_039_820_761_610
+    );
+    assert_eq!(amount_out(10u128.pow(18), "token1", "token0"), 996_995);
+    assert_eq!(
+        amount_in(996_995_039_820_761_610, "token0", "token1"),
+        1_000_000
+    );
+    assert_eq!(amount_in(0, "token0", "token1"), 0);
+
+    // The smallest input that reaches the output, including across decimals
+    for (out, token_in, token_out) in [
+        (1u128, "token1", "token0"),
+        (997_000, "token1", "token0"),
+        (123_456_789, "token1", "token0"),
+        (1, "token0", "token1"),
+        (5 * 10u128.pow(17) + 3, "token0", "token1"),
+    ]
+    .iter()
+    {
+        let input = amount_in(*out, token_in, token_out);
+        assert!(amount_out(input, token_in, token_out) >= *out);
+        assert!(amount_out(input - 1, token_in, token_out) < *out);
+    }
+}
+
+#[test]
+fn simulation() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    let simulation = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, offer: &str| {
+        query(
+            deps,
+            QueryMsg::Simulation {
+                offer_token: HumanAddr::from(offer),
+                ask_token: HumanAddr::from("token1"),
+                amount: Uint128(100_000_000),
+            },
+        )
+        .and_then(|res| from_binary::<SimulationResponse>(&res))
+    };
+
+    // an empty pool has no price to quote
+    assert_generic_err(simulation(&deps, "token0"), "[empty_asset]");
+
+    // a balanced pool's spot price is 1:1, the spread is the slippage
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    let quote = simulation(&deps, "token0").unwrap();
+    assert_eq!(
+        quote,
+        SimulationResponse {
+            return_amount: Uint128(99_649_927_439_765_219_628),
+            fee_amount: Uint128(299_849_330_310_226_338),
+            spread_amount: Uint128(50_223_229_924_554_034),
+            fee_bps: 30,
+        }
+    );
+    assert_eq!(
+        quote.return_amount.u128() + quote.fee_amount.u128() + quote.spread_amount.u128(),
+        100 * 10u128.pow(18)
+    );
+
+    assert_generic_err(simulation(&deps, "token2"), "Unknown asset");
+}
+
+#[test]
+fn reverse_simulation() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    let reverse_simulation = |ask_amount: u128| {
+        from_binary::<ReverseSimulationResponse>(
+            &query(
+                &deps,
+                QueryMsg::ReverseSimulation {
+                    ask_token: HumanAddr::from("token1"),
+                    ask_amount: Uint128(ask_amount),
+                    offer_token: HumanAddr::from("token0"),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    };
+
+    // the output of a 100 token0 swap, see simulation
+    assert_eq!(
+        reverse_simulation(99_649_927_439_765_219_628),
+        ReverseSimulationResponse {
+            offer_amount: Uint128(100_000_000),
+            fee_amount: Uint128(299_849_330_310_226_338),
+            spread_amount: Uint128(50_223_229_924_554_034),
+        }
+    );
+    // one more unit takes a whole unit of token0 more
+    assert_eq!(
+        reverse_simulation(99_649_927_439_765_219_629).offer_amount,
+        Uint128(100_000_001)
+    );
+    assert_eq!(
+        reverse_simulation(0),
+        ReverseSimulationResponse {
+            offer_amount: Uint128::zero(),
+            fee_amount: Uint128::zero(),
+            spread_amount: Uint128::zero(),
+        }
+    );
+}
+
+fn lp_needed(
+    deps: &Extern<MockStorage, MockApi, WasmMockQuerier>,
+    token: &str,
+    amount: u128,
+) -> Result<Uint128, StdError> {
+    query(
+        deps,
+        QueryMsg::LpNeededForWithdrawal {
+            token: HumanAddr::from(token),
+            amount: Uint128(amount),
+        },
+    )
+    .and_then(|res| from_binary::<LpNeededForWithdrawalResponse>(&res))
+    .map(|res| res.lp_amount)
+}
+
+#[test]
+fn lp_needed_for_withdrawal() {
+    // skewed, so a withdrawal of the scarce token1 alone pays a sizeable imbalance fee
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    fund_pool(&mut deps, "token1", 500 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    let withdraw = |burn: u128| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(burn),
+        msg: to_binary(&Snip20ReceiveMsg::WithdrawLiquidityOneToken {
+            token: HumanAddr::from("token1"),
+            min_amount: Uint128(100 * 10u128.pow(18)),
+            deadline: None,
+            unwrap: None,
+        })
+        .unwrap(),
+    };
+
+    assert_eq!(lp_needed(&deps, "token0", 0), Ok(Uint128::zero()));
+    assert_generic_err(
+        lp_needed(&deps, "token0", 1_500_000_001),
+        "the pool only holds 1500000000",
+    );
+    assert_generic_err(lp_needed(&deps, "token2", 1), "Unknown asset");
+
+    // the quote is the fewest LP that WithdrawLiquidityOneToken pays the amount for
+    let lp_amount = lp_needed(&deps, "token1", 100 * 10u128.pow(18))
+        .unwrap()
+        .u128();
+    let res = handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw(lp_amount - 1));
+    assert_generic_err(res, "is less than the minimum of");
+    let res = handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw(lp_amount)).unwrap();
+    let data: WithdrawLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert!(data.refunds[0].amount >= Uint128(100 * 10u128.pow(18)));
+
+    set_asset_enabled(&mut deps, "token1", false);
+    assert_generic_err(lp_needed(&deps, "token1", 1), "[asset_disabled]");
+}
+
+#[test]
+fn token_index() {
+    let deps = init_pool(&[("token0", 6), ("token1", 18), ("token2", 8)]);
+
+    let res = query(
+        &deps,
+        QueryMsg::GetTokenIndex {
+            token: HumanAddr::from("token2"),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        from_binary::<TokenIndexResponse>(&res).unwrap(),
+        TokenIndexResponse { index: 2 }
+    );
+
+    let res = query(&deps, QueryMsg::GetTokenByIndex { index: 1 }).unwrap();
+    let token = from_binary::<TokenByIndexResponse>(&res).unwrap().token;
+    assert_eq!(token.address, HumanAddr::from("token1"));
+    assert_eq!(token.code_hash, code_hash("token1"));
+
+    let res = query(
+        &deps,
+        QueryMsg::GetTokenIndex {
+            token: HumanAddr::from("token3"),
+        },
+    );
+    assert_generic_err(res, "Unknown asset");
+
+    let res = query(&deps, QueryMsg::GetTokenByIndex { index: 3 });
+    assert_generic_err(res, "Token index 3 is not in the pool");
+}
+
+#[test]
+fn token_indices_survive_removals() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18), ("token2", 8)]);
+    let token_index = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, token: &str| {
+        query(
+            deps,
+            QueryMsg::GetTokenIndex {
+                token: HumanAddr::from(token),
+            },
+        )
+        .and_then(|res| from_binary::<TokenIndexResponse>(&res))
+        .map(|res| res.index)
+    };
+    let token_by_index = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, index: u32| {
+        query(deps, QueryMsg::GetTokenByIndex { index })
+            .and_then(|res| from_binary::<TokenByIndexResponse>(&res))
+            .map(|res| res.token.address)
+    };
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::RemoveAsset {
+            token: HumanAddr::from("token0"),
+        },
+    )
+    .unwrap();
+
+    assert_generic_err(token_index(&deps, "token0"), "Unknown asset");
+    assert_eq!(token_index(&deps, "token1"), Ok(1));
+    assert_eq!(token_index(&deps, "token2"), Ok(2));
+    assert_generic_err(token_by_index(&deps, 0), "Token index 0 is not in the pool");
+    assert_eq!(token_by_index(&deps, 1), Ok(HumanAddr::from("token1")));
+    assert_eq!(token_by_index(&deps, 2), Ok(HumanAddr::from("token2")));
+    // GetTokens keeps the same order
+    let tokens = from_binary::<TokensResponse>(&query(&deps, QueryMsg::GetTokens {}).unwrap())
+        .unwrap()
+        .tokens;
+    assert_eq!(
+        tokens.iter().map(|t| t.address.clone()).collect::<Vec<_>>(),
+        vec![HumanAddr::from("token1"), HumanAddr::from("token2")]
+    );
+}
+
+#[test]
+fn reentrant_calls_rejected() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000);
+    fund_pool(&mut deps, "token1", 1_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000);
+
+    // the lock is released once a call completes
+    swap(&mut deps, mock_env("token0", &[]), "token1", 100).unwrap();
+    assert!(!read_lock(&deps.storage).unwrap());
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(100)).unwrap();
+    assert!(!read_lock(&deps.storage).unwrap());
+
+    // a call arriving while a deposit is waiting on its FinalizeDeposit callback
+    deps.querier.add_balance("token0", MOCK_CONTRACT_ADDR, 100);
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 100)]),
+    )
+    .unwrap();
+    let swap_res = swap(&mut deps, mock_env("token0", &[]), "token1", 100);
+    assert_generic_err(swap_res, "Reentrant call rejected");
+    let withdraw_res = handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(100));
+    assert_generic_err(withdraw_res, "Reentrant call rejected");
+
+    // and goes through once the deposit is finalized
+    finalize_deposit(&mut deps, &res).unwrap();
+    assert!(!read_lock(&deps.storage).unwrap());
+    swap(&mut deps, mock_env("token0", &[]), "token1", 100).unwrap();
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(100)).unwrap();
+}
+
+#[test]
+fn sync_reserves() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+
+    let res = handle(&mut deps, mock_env(USER, &[]), HandleMsg::SyncReserves {});
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // tokens sent to the pool directly aren't priced until the reserves are synced
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 500_000);
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(1_000_000); 2]
+    );
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::SyncReserves {}).unwrap();
+    assert!(res.log.contains(&log("reserve", "1500000 token0")));
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(1_500_000), Uint128(1_000_000)]
+    );
+
+    // not while a deposit is waiting on its callback
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000)]),
+    )
+    .unwrap();
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::SyncReserves {});
+    assert_generic_err(res, "Reentrant call rejected");
+}
+
+#[test]
+fn swap_referral_fee() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            max_referral_fee_bps: Some(2_000),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    let referral_swap = |referral_fee_bps: Option<u32>| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(1_000_000),
+        msg: to_binary(&Snip20ReceiveMsg::Swap {
+            to_token: HumanAddr::from("token1"),
+            recipient: None,
+            referrer: Some(HumanAddr::from("partner")),
+            referral_fee_bps,
+            expected_return: None,
+            max_slippage_bps: None,
+            deadline: None,
+            unwrap: None,
+        })
+        .unwrap(),
+    };
+
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        referral_swap(Some(2_001)),
+    );
+    assert_generic_err(res, "exceeds the max of 2000 bps");
+
+    let plain = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000_000).unwrap();
+    let res = query(
+        &deps,
+        QueryMsg::BatchSwapSimulation {
+            swaps: vec![SimulatedSwap {
+                offer: token_amount("token0", 1_000_000),
+                to_token: HumanAddr::from("token1"),
+            }],
+        },
+    )
+    .unwrap();
+    let simulation = &from_binary::<BatchSwapSimulationResponse>(&res)
+        .unwrap()
+        .simulations[0];
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    let referred = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        referral_swap(Some(1_000)),
+    )
+    .unwrap();
+
+    // the swapper's payout doesn't change, the referrer is paid from the fee
+    assert_eq!(plain.messages.len(), 1);
+    assert_eq!(
+        referred.messages[0],
+        snip20_utils::transfer_msg(
+            HumanAddr::from(USER),
+            simulation.return_amount,
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()
+    );
+    let fee_amount = referred
+        .log
+        .iter()
+        .find(|l| l.key == "fee_amount")
+        .unwrap()
+        .value
+        .parse::<u128>()
+        .unwrap();
+    let referral_amount = Uint128(fee_amount).multiply_ratio(1_000u128, 10_000u128);
+    assert_eq!(
+        referred.messages[1],
+        snip20_utils::transfer_msg(
+            HumanAddr::from("partner"),
+            referral_amount,
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()
+    );
+    assert!(referred
+        .log
+        .iter()
+        .any(|l| l.key == "referral" && l.value == format!("{} partner", referral_amount)));
+}
+
+#[test]
+fn swap_exact_out() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    let amount_in = from_binary::<AmountInResponse>(
+        &query(
+            &deps,
+            QueryMsg::GetAmountIn {
+                amount_out: Uint128(1_000_000),
+                token_in: HumanAddr::from("token0"),
+                token_out: HumanAddr::from("token1"),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap()
+    .amount_in;
+    let swap_exact_out = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, sent: u128| {
+        deps.querier.add_balance("token0", MOCK_CONTRACT_ADDR, sent);
+        handle(
+            deps,
+            mock_env("token0", &[]),
+            HandleMsg::Receive {
+                from: HumanAddr::from(USER),
+                amount: Uint128(sent),
+                msg: to_binary(&Snip20ReceiveMsg::SwapExactOut {
+                    to_token: HumanAddr::from("token1"),
+                    ask_amount: Uint128(1_000_000),
+                    recipient: None,
+                })
+                .unwrap(),
+            },
+        )
+    };
+
+    let res = swap_exact_out(&mut deps, amount_in.u128() - 1);
+    assert_generic_err(res, "[slippage_exceeded] Swap needs");
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000_000);
+
+    // only amount_in is swapped, the other 500 are sent back
+    let res = swap_exact_out(&mut deps, amount_in.u128() + 500).unwrap();
+    let swapped: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert!(swapped.return_amount >= Uint128(1_000_000));
+    assert!(res.log.contains(&log("src_amount", amount_in.to_string())));
+    assert!(res.log.contains(&log("refund", "500")));
+    assert_eq!(
+        res.messages.last().unwrap(),
+        &snip20_utils::transfer_msg(
+            HumanAddr::from(USER),
+            Uint128(500),
+            None,
+            256,
+            code_hash("token0"),
+            HumanAddr::from("token0"),
+        )
+        .unwrap()
+    );
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap()[0],
+        Uint128(1_000_000_000 + amount_in.u128())
+    );
+}
+
+#[test]
+fn swap_expected_return() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    let quote = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let res = query(
+            deps,
+            QueryMsg::Simulation {
+                offer_token: HumanAddr::from("token0"),
+                ask_token: HumanAddr::from("token1"),
+                amount: Uint128(1_000_000),
+            },
+        )
+        .unwrap();
+        from_binary::<SimulationResponse>(&res)
+            .unwrap()
+            .return_amount
+    };
+    let guarded_swap = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+                        expected_return: Option<Uint128>,
+                        max_slippage_bps: Option<u32>| {
+        deps.querier
+            .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+        handle(
+            deps,
+            mock_env("token0", &[]),
+            HandleMsg::Receive {
+                from: HumanAddr::from(USER),
+                amount: Uint128(1_000_000),
+                msg: to_binary(&Snip20ReceiveMsg::Swap {
+                    to_token: HumanAddr::from("token1"),
+                    recipient: None,
+                    referrer: None,
+                    referral_fee_bps: None,
+                    expected_return,
+                    max_slippage_bps,
+                    deadline: None,
+                    unwrap: None,
+                })
+                .unwrap(),
+            },
+        )
+    };
+
+    let res = guarded_swap(&mut deps, None, Some(10));
+    assert_generic_err(res, "requires an expected_return");
+    let res = guarded_swap(&mut deps, Some(Uint128(1)), Some(10_001));
+    assert_generic_err(res, "must not exceed 10000");
+
+    // one unit short of the expected return, with no slippage allowed
+    let quoted = quote(&deps);
+    let expected = quoted + Uint128(1);
+    let res = guarded_swap(&mut deps, Some(expected), None);
+    assert_generic_err(
+        res,
+        &format!(
+            "[slippage_exceeded] Swap return {} is less than the minimum of {}",
+            quoted, expected
+        ),
+    );
+
+    // within 1 bps of it
+    let expected = quote(&deps) + Uint128(1);
+    let res = guarded_swap(&mut deps, Some(expected), Some(1)).unwrap();
+    assert_eq!(
+        from_binary::<SwapResponse>(&res.data.unwrap())
+            .unwrap()
+            .return_amount,
+        (expected - Uint128(1)).unwrap()
+    );
+}
+
+#[test]
+fn deadline_rejects_late_operations() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    let now = mock_env(USER, &[]).block.time;
+    let swap_until = |deadline: u64| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(1_000_000),
+        msg: to_binary(&Snip20ReceiveMsg::Swap {
+            to_token: HumanAddr::from("token1"),
+            recipient: None,
+            referrer: None,
+            referral_fee_bps: None,
+            expected_return: None,
+            max_slippage_bps: None,
+            deadline: Some(deadline),
+            unwrap: None,
+        })
+        .unwrap(),
+    };
+
+    let res = handle(&mut deps, mock_env("token0", &[]), swap_until(now - 1));
+    assert_generic_err(
+        res,
+        &format!(
+            "[deadline_expired] Deadline {} has passed, the block time is {}",
+            now - 1,
+            now
+        ),
+    );
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::ProvideLiquidity {
+            assets: vec![token_amount("token0", 1_000_000)],
+            cancel_if_no_bonus: None,
+            min_lp_out: None,
+            deadline: Some(now - 1),
+        },
+    );
+    assert_generic_err(res, "[deadline_expired] ");
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(10u128.pow(18)),
+            msg: to_binary(&Snip20ReceiveMsg::WithdrawLiquidity {
+                deadline: Some(now - 1),
+                unwrap: None,
+            })
+            .unwrap(),
+        },
+    );
+    assert_generic_err(res, "[deadline_expired] ");
+
+    // the deadline itself is still in time
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    handle(&mut deps, mock_env("token0", &[]), swap_until(now)).unwrap();
+}
+
+#[test]
+fn swap_leaves_min_reserve() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(
+        config.min_reserve_after_swap,
+        Uint128(DEFAULT_MIN_RESERVE_AFTER_SWAP)
+    );
+    // an empty pool has no price to swap at
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000_000);
+    assert_generic_err(res, "[empty_asset]");
+
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            min_reserve_after_swap: Some(Uint128(10 * 10u128.pow(18))),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    // 991 out of 1,000 would leave 9
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_343_507_523);
+    assert_generic_err(
+        res,
+        "token1 in the pool, below the minimum of 10000000000000000000",
+    );
+    // 989 leaves 11
+    swap(&mut deps, mock_env("token0", &[]), "token1", 1_262_402_206).unwrap();
+}
+
+#[test]
+fn seed_mode_waives_only_admin_swap_fees() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let seed = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let mut config = read_config(&deps.storage).unwrap();
+        config.seed_mode = true;
+        config.seed_tvl_cap = Uint128(1_000 * 10u128.pow(18));
+        store_config(&mut deps.storage, &config).unwrap();
+    };
+    let swap_from = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, from: &str| {
+        deps.querier
+            .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+        let mut msg = swap_msg("token1", 1_000_000);
+        if let HandleMsg::Receive { from: sender, .. } = &mut msg {
+            *sender = HumanAddr::from(from);
+        }
+        let res = handle(deps, mock_env("token0", &[]), msg).unwrap();
+        from_binary::<SwapResponse>(&res.data.unwrap())
+            .unwrap()
+            .fee_amount
+    };
+    let seed_mode = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        from_binary::<ConfigResponse>(&query(deps, QueryMsg::GetConfig {}).unwrap())
+            .unwrap()
+            .seed_mode
+    };
+    assert!(!seed_mode(&deps));
+    fund_pool(&mut deps, "token0", 100_000_000);
+    fund_pool(&mut deps, "token1", 100_000_000);
+
+    seed(&mut deps);
+    assert_eq!(swap_from(&mut deps, ADMIN), Uint128::zero());
+    assert_eq!(swap_from(&mut deps, USER), Uint128(3_000));
+
+    // Reaching the cap ends it, and it stays off if the TVL drops back
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000_000)]),
+    )
+    .unwrap();
+    finalize_deposit(&mut deps, &res).unwrap();
+    assert!(!seed_mode(&deps));
+    deps.querier.set_total_supply(LP_TOKEN, 10u128.pow(18));
+    handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(9 * 10u128.pow(17)),
+    )
+    .unwrap();
+    settle_payouts(&mut deps);
+    // the pool is mostly token0 now, so the swap pays less and the fee on it is lower
+    assert_eq!(swap_from(&mut deps, ADMIN), Uint128(2_728));
+
+    // or the admin ends it early, for good
+    seed(&mut deps);
+    let res = handle(&mut deps, mock_env(USER, &[]), HandleMsg::EndSeedMode {});
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::EndSeedMode {}).unwrap();
+    assert!(!seed_mode(&deps));
+    assert_eq!(swap_from(&mut deps, ADMIN), Uint128(2_674));
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::EndSeedMode {});
+    assert_generic_err(res, "not in seed mode");
+}
+
+#[test]
+fn swap_volume_limited_per_block() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            max_volume_per_block: Some(Uint128(1_000 * 10u128.pow(18))),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    // 600 + 400 fills the block exactly, volume is normalized across decimals
+    swap(&mut deps, mock_env("token0", &[]), "token1", 600_000_000).unwrap();
+    swap(
+        &mut deps,
+        mock_env("token1", &[]),
+        "token0",
+        400 * 10u128.pow(18),
+    )
+    .unwrap();
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1);
+    assert_generic_err(res, "above the limit of");
+
+    // the limit resets with the next block
+    let mut env = mock_env("token0", &[]);
+    env.block.height += 1;
+    swap(&mut deps, env, "token1", 1_000_000).unwrap();
+}
+
+#[test]
+fn deposit_caps() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 100_000_000);
+    fund_pool(&mut deps, "token1", 100 * 10u128.pow(18));
+    let caps = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        from_binary::<DepositCapsResponse>(&query(deps, QueryMsg::GetDepositCaps {}).unwrap())
+            .unwrap()
+    };
+    assert_eq!(caps(&deps).tvl_headroom, None);
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            per_asset_deposit_cap: Some(Uint128(150 * 10u128.pow(18))),
+            total_tvl_cap: Some(Uint128(280 * 10u128.pow(18))),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        caps(&deps),
+        DepositCapsResponse {
+            per_asset_deposit_cap: Uint128(150 * 10u128.pow(18)),
+            total_tvl_cap: Uint128(280 * 10u128.pow(18)),
+            per_asset_headroom: Some(vec![Uint128(50 * 10u128.pow(18)); 2]),
+            tvl_headroom: Some(Uint128(80 * 10u128.pow(18))),
+        }
+    );
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 50_000_001)]),
+    );
+    assert_generic_err(res, "over the per-asset cap");
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 40_000_000), ("token1", 40 * 10u128.pow(18) + 1)]),
+    );
+    assert_generic_err(res, "over the TVL cap");
+
+    // right up to the cap is fine
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 50_000_000)]),
+    )
+    .unwrap();
+}
+
+#[test]
+fn virtual_price_twap_ignores_single_block_spikes() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000_000);
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 1_000_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000_000), ("token1", 1_000_000_000)]),
+    )
+    .unwrap();
+    finalize_deposit(&mut deps, &res).unwrap();
+    // 2,000 normalized in reserves
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+
+    let snapshot = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, time: u64| {
+        let mut env = mock_env(ADMIN, &[]);
+        env.block.time = time;
+        handle(deps, env, HandleMsg::SnapshotVirtualPrice {}).unwrap();
+    };
+    let twap = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, window_seconds: u64| {
+        from_binary::<VirtualPriceTwapResponse>(
+            &query(deps, QueryMsg::GetVirtualPriceTwap { window_seconds }).unwrap(),
+        )
+        .unwrap()
+    };
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::SnapshotVirtualPrice {},
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    snapshot(&mut deps, 1_000);
+    snapshot(&mut deps, 2_000);
+    snapshot(&mut deps, 3_000);
+    // Doubles the virtual price within the block, the later snapshot in it isn't recorded
+    deps.querier
+        .set_total_supply(LP_TOKEN, 1_000 * 10u128.pow(18));
+    snapshot(&mut deps, 3_000);
+    assert_eq!(
+        twap(&deps, 10_000),
+        VirtualPriceTwapResponse {
+            twap: Uint128(10u128.pow(18)),
+            start_time: 1_000,
+            end_time: 3_000,
+        }
+    );
+
+    // Held for one second, then reverted
+    snapshot(&mut deps, 3_001);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    snapshot(&mut deps, 4_001);
+    assert_eq!(
+        twap(&deps, 3_001).twap,
+        Uint128(3_002 * 10u128.pow(18) / 3_001)
+    );
+    assert_eq!(twap(&deps, 1_000).twap, Uint128(10u128.pow(18)));
+    assert_eq!(twap(&deps, 0).twap, Uint128(10u128.pow(18)));
+}
+
+#[test]
+fn twap_from_price_accumulators() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    let one = 10u128.pow(18);
+
+    let snapshot = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, time: u64| {
+        let mut env = mock_env(ADMIN, &[]);
+        env.block.time = time;
+        handle(deps, env, HandleMsg::SnapshotVirtualPrice {}).unwrap();
+    };
+    let twap = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>,
+                base: &str,
+                quote: &str,
+                window: u64| {
+        query(
+            deps,
+            QueryMsg::Twap {
+                base: HumanAddr::from(base),
+                quote: HumanAddr::from(quote),
+                window,
+            },
+        )
+        .map(|res| from_binary::<TwapResponse>(&res).unwrap())
+    };
+    // Marginal price of base in quote at 1,500 token0 to 500 token1, scaled by 10^18
+    let imbalanced_price = |base: usize, quote: usize| {
+        let balances = [U256::from(1_500 * one), U256::from(500 * one)];
+        let (nom, denom) = marginal_price(100, &balances, base, quote).unwrap();
+        (nom * U256::from(one) / denom).as_u128()
+    };
+
+    let res = twap(&deps, "token0", "token1", 1_000);
+    assert_generic_err(res, "No price has been recorded");
+
+    // Handlers record before they move the reserves, like these snapshots
+    snapshot(&mut deps, 1_000);
+    snapshot(&mut deps, 2_000);
+    snapshot(&mut deps, 3_000);
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    fund_pool(&mut deps, "token1", 500_000_000);
+    // Later in the same block, doesn't move the accumulators
+    snapshot(&mut deps, 3_000);
+    snapshot(&mut deps, 4_000);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+
+    // Balanced from 1,000 to 3,000, then imbalanced until 4,000
+    let price = imbalanced_price(0, 1);
+    assert!(price < one);
+    assert_eq!(
+        twap(&deps, "token0", "token1", 10_000).unwrap(),
+        TwapResponse {
+            price: Decimal::from_ratio((2_000 * one + 1_000 * price) / 3_000, one),
+            start_time: 1_000,
+            end_time: 4_000,
+        }
+    );
+    // Starts between observations
+    assert_eq!(
+        twap(&deps, "token0", "token1", 1_500).unwrap().price,
+        Decimal::from_ratio((500 * one + 1_000 * price) / 1_500, one)
+    );
+    assert_eq!(
+        twap(&deps, "token1", "token0", 1_000).unwrap().price,
+        Decimal::from_ratio(imbalanced_price(1, 0), one)
+    );
+    // An empty window reads the spot price
+    assert_eq!(
+        twap(&deps, "token0", "token1", 0).unwrap().price,
+        Decimal::one()
+    );
+
+    let res = twap(&deps, "token0", "token0", 1_000);
+    assert_generic_err(res, "base and quote must differ");
+    let res = twap(&deps, "token0", "token2", 1_000);
+    assert_generic_err(res, "[unknown_asset]");
+}
+
+#[test]
+fn batch_threads_pool_state_between_operations() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            max_swap_out_bps: Some(1_000),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    let provide = BatchOperation::ProvideLiquidity {
+        assets: vec![token_amount("token0", 100_000_000)],
+        min_lp_out: None,
+    };
+    let swap = BatchOperation::Swap {
+        offer: token_amount("token1", 105_000_000),
+        to_token: HumanAddr::from("token0"),
+        min_return: None,
+    };
+    let batch = |operations: Vec<BatchOperation>| HandleMsg::Batch { operations };
+
+    // Takes more than 10% of the token0 reserve on its own
+    let res = handle(&mut deps, mock_env(USER, &[]), batch(vec![swap.clone()]));
+    assert_generic_err(res, "exceeds the max of 100000000");
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        batch(vec![provide.clone(); MAX_BATCH_OPERATIONS + 1]),
+    );
+    assert_generic_err(res, "between 1 and");
+
+    // but not once the deposit has grown it
+    let res = handle(&mut deps, mock_env(USER, &[]), batch(vec![provide, swap])).unwrap();
+    assert_eq!(
+        from_binary::<Vec<BatchOperationResult>>(&res.data.clone().unwrap()).unwrap(),
+        vec![
+            BatchOperationResult::ProvideLiquidity(ProvideLiquidityResponse {
+                lp_amount: Uint128(99_838_126_897_289_852_107),
+                deposits: vec![token_amount("token0", 100_000_000)],
+            }),
+            BatchOperationResult::Swap(SwapResponse {
+                return_amount: Uint128(104_682_507),
+                fee_amount: Uint128(314_993),
+            }),
+        ]
+    );
+    // Only the net amounts move, the deposit is paid for by the swap output
+    assert_eq!(
+        res.messages[..3],
+        [
+            snip20_utils::transfer_from_msg(
+                HumanAddr::from(USER),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(105_000_000),
+                None,
+                256,
+                code_hash("token1"),
+                HumanAddr::from("token1"),
+            )
+            .unwrap(),
+            snip20_utils::transfer_msg(
+                HumanAddr::from(USER),
+                Uint128(4_682_507),
+                None,
+                256,
+                code_hash("token0"),
+                HumanAddr::from("token0"),
+            )
+            .unwrap(),
+            snip20_utils::mint_msg(
+                HumanAddr::from(USER),
+                Uint128(99_838_126_897_289_852_107),
+                None,
+                256,
+                code_hash(LP_TOKEN),
+                HumanAddr::from(LP_TOKEN),
+            )
+            .unwrap(),
+        ]
+    );
+
+    // The callback checks the transfers delivered what the batch was priced on
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 1_104_000_000);
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 995_317_493);
+    let finalize = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        handle(
+            deps,
+            mock_env(MOCK_CONTRACT_ADDR, &[]),
+            HandleMsg::FinalizeBatch {},
+        )
+    };
+    assert_eq!(res.messages.len(), 4);
+    let res = finalize(&mut deps);
+    assert_generic_err(res, "less than the 1105000000 the batch was priced on");
+    assert!(read_lock(&deps.storage).unwrap());
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 1_105_000_000);
+    finalize(&mut deps).unwrap();
+    assert!(!read_lock(&deps.storage).unwrap());
+}
+
+#[test]
+fn lp_flows() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let flows = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        from_binary::<LpFlowsResponse>(&query(deps, QueryMsg::GetLpFlows {}).unwrap()).unwrap()
+    };
+
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000)]),
+    )
+    .unwrap();
+    finalize_deposit(&mut deps, &res).unwrap();
+    deps.querier.set_total_supply(LP_TOKEN, 10u128.pow(18));
+    handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(10u128.pow(17)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        flows(&deps),
+        LpFlowsResponse {
+            total_lp_minted: Uint128(10u128.pow(18)),
+            total_lp_burned: Uint128(10u128.pow(17)),
+            lp_total_supply: Uint128(10u128.pow(18)),
+        }
+    );
+}
+
+#[test]
+fn lp_positions() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let my_position = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, key: &str| {
+        query(
+            deps,
+            QueryMsg::MyPosition {
+                address: HumanAddr::from(USER),
+                key: key.to_string(),
+            },
+        )
+        .and_then(|res| from_binary::<PositionResponse>(&res))
+    };
+
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 1_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000), ("token1", 1_000_000)]),
+    )
+    .unwrap();
+    finalize_deposit(&mut deps, &res).unwrap();
+    deps.querier.set_total_supply(LP_TOKEN, 2 * 10u128.pow(18));
+    handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(5 * 10u128.pow(17)),
+    )
+    .unwrap();
+    settle_payouts(&mut deps);
+    // Another LP has since minted as much as USER still holds
+    deps.querier.set_total_supply(LP_TOKEN, 3 * 10u128.pow(18));
+
+    match my_position(&deps, "key") {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        my_position(&deps, "key").unwrap(),
+        PositionResponse {
+            shares: Uint128(15 * 10u128.pow(17)),
+            pool_share: Decimal::percent(50),
+            value: Uint128(75 * 10u128.pow(16)),
+            assets: vec![
+                token_amount("token0", 375_000),
+                token_amount("token1", 375_000),
+            ],
+            lp_minted: Uint128(2 * 10u128.pow(18)),
+            lp_burned: Uint128(5 * 10u128.pow(17)),
+            flows: vec![
+                AssetFlow {
+                    address: HumanAddr::from("token0"),
+                    deposited: Uint128(1_000_000),
+                    withdrawn: Uint128(250_000),
+                },
+                AssetFlow {
+                    address: HumanAddr::from("token1"),
+                    deposited: Uint128(1_000_000),
+                    withdrawn: Uint128(250_000),
+                },
+            ],
+        }
+    );
+
+    // The permit signer has never provided liquidity
+    let res = query(
+        &deps,
+        QueryMsg::WithPermit {
+            permit: permit(
+                MOCK_CONTRACT_ADDR,
+                Permission::Owner,
+                OWNER_PERMIT_SIGNATURE,
+            ),
+            query: QueryWithPermit::MyPosition {},
+        },
+    )
+    .unwrap();
+    let position: PositionResponse = from_binary(&res).unwrap();
+    assert_eq!(position.shares, Uint128::zero());
+    assert_eq!(position.pool_share, Decimal::zero());
+    assert!(position.flows.is_empty());
+    let res = query(
+        &deps,
+        QueryMsg::WithPermit {
+            permit: permit(
+                MOCK_CONTRACT_ADDR,
+                Permission::History,
+                HISTORY_PERMIT_SIGNATURE,
+            ),
+            query: QueryWithPermit::MyPosition {},
+        },
+    );
+    assert_generic_err(res, "does not grant Position");
+}
+
+#[test]
+fn swap_history() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    for address in [USER, ADMIN].iter() {
+        handle(
+            &mut deps,
+            mock_env(*address, &[]),
+            HandleMsg::SetViewingKey {
+                key: "key".to_string(),
+            },
+        )
+        .unwrap();
+    }
+    let history = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>,
+                   address: &str,
+                   page: Option<u32>,
+                   page_size: u32| {
+        query(
+            deps,
+            QueryMsg::TransactionHistory {
+                address: HumanAddr::from(address),
+                key: "key".to_string(),
+                page,
+                page_size,
+            },
+        )
+        .and_then(|res| from_binary::<TransactionHistoryResponse>(&res))
+    };
+
+    let mut expected = vec![];
+    for (i, (from_token, to_token)) in [
+        ("token0", "token1"),
+        ("token1", "token0"),
+        ("token0", "token1"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let env = mock_env(*from_token, &[]);
+        let block_height = env.block.height;
+        let block_time = env.block.time;
+        let res = swap(&mut deps, env, to_token, 1_000).unwrap();
+        settle_payouts(&mut deps);
+        let data: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+        expected.push(SwapTx {
+            id: i as u64,
+            from: HumanAddr::from(USER),
+            recipient: HumanAddr::from(USER),
+            src_token: HumanAddr::from(*from_token),
+            src_amount: Uint128(1_000),
+            dst_token: HumanAddr::from(*to_token),
+            dst_amount: data.return_amount,
+            fee_amount: data.fee_amount,
+            block_height,
+            block_time,
+        });
+    }
+    // Also in the recipient's history
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 1_000);
+    let res = handle(
+        &mut deps,
+        mock_env("token1", &[]),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(1_000),
+            msg: to_binary(&Snip20ReceiveMsg::Swap {
+                to_token: HumanAddr::from("token0"),
+                recipient: Some(HumanAddr::from(ADMIN)),
+                referrer: None,
+                referral_fee_bps: None,
+                expected_return: None,
+                max_slippage_bps: None,
+                deadline: None,
+                unwrap: None,
+            })
+            .unwrap(),
+        },
+    )
+    .unwrap();
+    let data: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    let to_admin = SwapTx {
+        id: 3,
+        from: HumanAddr::from(USER),
+        recipient: HumanAddr::from(ADMIN),
+        src_token: HumanAddr::from("token1"),
+        src_amount: Uint128(1_000),
+        dst_token: HumanAddr::from("token0"),
+        dst_amount: data.return_amount,
+        fee_amount: data.fee_amount,
+        block_height: expected[0].block_height,
+        block_time: expected[0].block_time,
+    };
+    expected.push(to_admin.clone());
+    expected.reverse();
+
+    // Newest first
+    assert_eq!(
+        history(&deps, USER, None, 3).unwrap(),
+        TransactionHistoryResponse {
+            txs: expected[..3].to_vec(),
+            total: 4,
+        }
+    );
+    assert_eq!(history(&deps, USER, Some(1), 3).unwrap().txs, expected[3..]);
+    assert!(history(&deps, USER, Some(2), 3).unwrap().txs.is_empty());
+    assert_eq!(
+        history(&deps, ADMIN, None, 10).unwrap(),
+        TransactionHistoryResponse {
+            txs: vec![to_admin],
+            total: 1,
+        }
+    );
+    match query(
+        &deps,
+        QueryMsg::TransactionHistory {
+            address: HumanAddr::from(USER),
+            key: "other".to_string(),
+            page: None,
+            page_size: 10,
+        },
+    ) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // The permit signer has never swapped
+    let res = query(
+        &deps,
+        QueryMsg::WithPermit {
+            permit: permit(
+                MOCK_CONTRACT_ADDR,
+                Permission::History,
+                HISTORY_PERMIT_SIGNATURE,
+            ),
+            query: QueryWithPermit::TransactionHistory {
+                page: None,
+                page_size: 10,
+            },
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        from_binary::<TransactionHistoryResponse>(&res).unwrap(),
+        TransactionHistoryResponse {
+            txs: vec![],
+            total: 0,
+        }
+    );
+}
+
+#[test]
+fn provide_liquidity_names_tokens_with_short_allowance() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6), ("token2", 6)]);
+    deps.querier
+        .set_allowance("token0", USER, MOCK_CONTRACT_ADDR, 999);
+    deps.querier
+        .set_allowance("token2", USER, MOCK_CONTRACT_ADDR, 0);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000), ("token1", 1_000), ("token2", 1_000)]),
+    );
+    assert_generic_err(
+        res,
+        "Insufficient allowance for token0 (allowance 999, deposit 1000), \
+         token2 (allowance 0, deposit 1000)",
+    );
+
+    // the lock was never taken, so an approved retry goes through
+    deps.querier
+        .set_allowance("token0", USER, MOCK_CONTRACT_ADDR, 1_000);
+    deps.querier
+        .set_allowance("token2", USER, MOCK_CONTRACT_ADDR, 1_000);
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000), ("token1", 1_000), ("token2", 1_000)]),
+    )
+    .unwrap();
+}
+
+#[test]
+fn rejects_native_coins_sent_to_snip20_handlers() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 6)], &["uscrt"]);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    fund_pool(&mut deps, "uscrt", 1_000_000);
+
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &coins(1_000, "uscrt")),
+        swap_msg("token1", 1_000),
+    );
+    assert_generic_err(res, "This message does not accept native coins");
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &coins(1_000, "uscrt")),
+        HandleMsg::SetRounding {
+            round_down_to_nearest: Uint128(1),
+        },
+    );
+    assert_generic_err(res, "This message does not accept native coins");
+
+    // the handlers that take native coins still do
+    deps.querier
+        .set_native_balance(MOCK_CONTRACT_ADDR, coins(1_001_000, "uscrt"));
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &coins(1_000, "uscrt")),
+        HandleMsg::SwapNative {
+            to_token: HumanAddr::from("token1"),
+            recipient: None,
+            referrer: None,
+            referral_fee_bps: None,
+        },
+    );
+    assert!(res.is_ok());
+}
+
+#[test]
+fn provide_liquidity_rejects_duplicate_assets() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000), ("token1", 1_000), ("token0", 1_000)]),
+    );
+    assert_generic_err(res, "Duplicate deposit of asset");
+}
+
+#[test]
+fn provide_liquidity_min_lp_out() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    let msg = |min_lp_out: u128| HandleMsg::ProvideLiquidity {
+        assets: vec![
+            token_amount("token0", 1_000_000),
+            token_amount("token1", 10u128.pow(18)),
+        ],
+        cancel_if_no_bonus: None,
+        min_lp_out: Some(Uint128(min_lp_out)),
+        deadline: None,
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), msg(2 * 10u128.pow(18) + 1));
+    assert_generic_err(
+        res,
+        "Deposit would mint 2000000000000000000 LP tokens, less than the minimum of 2000000000000000001",
+    );
+
+    handle(&mut deps, mock_env(USER, &[]), msg(2 * 10u128.pow(18))).unwrap();
+}
+
+#[test]
+fn imbalanced_deposits_mint_less() {
+    let minted = |deposits: &[(&str, u128)]| {
+        let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+        fund_pool(&mut deps, "token0", 1_000_000_000);
+        fund_pool(&mut deps, "token1", 1_000_000_000);
+        deps.querier
+            .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+        let res = handle(&mut deps, mock_env(USER, &[]), provide_msg(deposits)).unwrap();
+        for (token, amount) in deposits {
+            deps.querier.add_balance(token, MOCK_CONTRACT_ADDR, *amount);
+        }
+        let res = finalize_deposit(&mut deps, &res).unwrap();
+        from_binary::<ProvideLiquidityResponse>(&res.data.unwrap())
+            .unwrap()
+            .lp_amount
+    };
+
+    // the same 100 deposited, balanced and all in token0, which also pays the imbalance fee
+    assert_eq!(
+        minted(&[("token0", 50_000_000), ("token1", 50_000_000)]),
+        Uint128(100 * 10u128.pow(18))
+    );
+    assert_eq!(
+        minted(&[("token0", 100_000_000)]),
+        Uint128(99_838_126_897_289_852_107)
+    );
+}
+
+#[test]
+fn deposit_bonus_paid_from_admin_fees() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    fund_pool(&mut deps, "token1", 500_000_000);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    // 10 token1 of admin fees held on top of the reserves
+    store_admin_fees(&mut deps.storage, &[Uint128::zero(), Uint128(10_000_000)]).unwrap();
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 10_000_000);
+    let provide = |token: &str| HandleMsg::ProvideLiquidity {
+        assets: vec![token_amount(token, 100_000_000)],
+        cancel_if_no_bonus: Some(true),
+        min_lp_out: None,
+        deadline: None,
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), provide("token1"));
+    assert_generic_err(res, "[no_deposit_bonus] ");
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            deposit_bonus_bps: Some(100),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    // token0 is over-weighted, so depositing it earns nothing
+    let res = handle(&mut deps, mock_env(USER, &[]), provide("token0"));
+    assert_generic_err(res, "[no_deposit_bonus] ");
+
+    // 1% of the token1 deposit moves from the admin fees into the pool
+    let res = handle(&mut deps, mock_env(USER, &[]), provide("token1")).unwrap();
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 100_000_000);
+    let res = finalize_deposit(&mut deps, &res).unwrap();
+    assert!(res.log.contains(&log("bonus", "1000000 token1")));
+    assert_eq!(
+        read_admin_fees(&deps.storage).unwrap(),
+        vec![Uint128::zero(), Uint128(9_000_000)]
+    );
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(1_500_000_000), Uint128(601_000_000)]
+    );
+}
+
+#[test]
+fn curve_state() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+
+    let res = query(&deps, QueryMsg::GetCurveState {}).unwrap();
+    let state: CurveStateResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        state,
+        CurveStateResponse {
+            amp: 100,
+            d: Uint128(2_000 * 10u128.pow(18)),
+            balances: vec![
+                Uint128(1_000 * 10u128.pow(18)),
+                Uint128(1_000 * 10u128.pow(18))
+            ],
+            lp_total_supply: Uint128(2_000 * 10u128.pow(18)),
+        }
+    );
+}
+
+#[test]
+fn update_viewing_keys() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 6)], &["uscrt"]);
+    let msg = HandleMsg::UpdateViewingKeys {
+        viewing_key: "new_key".to_string(),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), msg.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // the native asset has no key to set
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), msg).unwrap();
+    assert_eq!(
+        res.messages,
+        ["token0", "token1"]
+            .iter()
+            .map(|token| snip20_utils::set_viewing_key_msg(
+                "new_key".to_string(),
+                None,
+                256,
+                code_hash(token),
+                HumanAddr::from(*token),
+            )
+            .unwrap())
+            .collect::<Vec<CosmosMsg>>()
+    );
+
+    let assets = read_all_assets(&deps.storage).unwrap();
+    assert_eq!(assets[0].viewing_key, "new_key");
+    assert_eq!(assets[1].viewing_key, "new_key");
+    assert_eq!(assets[2].viewing_key, "");
+}
+
+#[test]
+fn update_token_viewing_key() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 6)], &["uscrt"]);
+    let msg = |token: &str| HandleMsg::UpdateTokenViewingKey {
+        token: HumanAddr::from(token),
+        viewing_key: "new_key".to_string(),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), msg("token1"));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), msg("token2"));
+    assert_generic_err(res, "[unknown_asset] ");
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), msg("uscrt"));
+    assert_generic_err(res, "has no viewing key");
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), msg("token1")).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![snip20_utils::set_viewing_key_msg(
+            "new_key".to_string(),
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()]
+    );
+
+    let assets = read_all_assets(&deps.storage).unwrap();
+    assert_eq!(assets[0].viewing_key, VIEWING_KEY);
+    assert_eq!(assets[1].viewing_key, "new_key");
+}
+
+#[test]
+fn swap_converts_between_decimals() {
+    let mut deps = init_pool(&[("token6", 6), ("other6", 6), ("token18", 18)]);
+    fund_pool(&mut deps, "token6", 1_000_000_000);
+    fund_pool(&mut deps, "other6", 1_000_000_000);
+    fund_pool(&mut deps, "token18", 1_000 * 10u128.pow(18));
+    let return_amount = |res: HandleResult| {
+        from_binary::<SwapResponse>(&res.unwrap().data.unwrap())
+            .unwrap()
+            .return_amount
+    };
+
+    // equal decimals, a little under 0.3% off for the fee and the slippage
+    let res = swap(&mut deps, mock_env("token6", &[]), "other6", 10_000_000);
+    assert_eq!(return_amount(res), Uint128(9_969_889));
+
+    // to more decimals
+    let res = swap(&mut deps, mock_env("token6", &[]), "token18", 10_000_000);
+    assert_eq!(return_amount(res), Uint128(9_969_780_816_643_785_185));
+
+    // to fewer decimals, the dust below 6 decimals is kept by the pool. The pool now holds
+    // more token6 than token18, so it pays a bit more.
+    let res = swap(
+        &mut deps,
+        mock_env("token18", &[]),
+        "token6",
+        10 * 10u128.pow(18) + 999_999_999_999,
+    );
+    assert_eq!(return_amount(res), Uint128(9_970_219));
+}
+
+#[test]
+fn swap_rounding_favors_pool() {
+    let mut deps = init_pool(&[("token6", 6), ("token18", 18), ("token8", 8)]);
+    fund_pool(&mut deps, "token6", 1_000_000_000_000);
+    fund_pool(&mut deps, "token18", 1_000_000 * 10u128.pow(18));
+    fund_pool(&mut deps, "token8", 100_000_000_000_000);
+
+    let pairs = [
+        ("token6", "token18"),
+        ("token18", "token6"),
+        ("token8", "token6"),
+        ("token6", "token8"),
+    ];
+    for (src, dst) in pairs.iter() {
+        for amount in &[1u128, 7, 999, 1_001, 123_456_789, 10u128.pow(15) + 1] {
+            let res = query(
+                &deps,
+                QueryMsg::BatchSwapSimulation {
+                    swaps: vec![SimulatedSwap {
+                        offer: token_amount(src, *amount),
+                        to_token: HumanAddr::from(*dst),
+                    }],
+                },
+            )
+            .unwrap();
+            let simulation = &from_binary::<BatchSwapSimulationResponse>(&res)
+                .unwrap()
+                .simulations[0];
+
+            // the trader never gets more than the input is worth in destination units
+            let (src_decimals, dst_decimals) = (decimals_of(src), decimals_of(dst));
+            let input_value = if src_decimals > dst_decimals {
+                *amount / 10u128.pow(src_decimals - dst_decimals)
+            } else {
+                *amount * 10u128.pow(dst_decimals - src_decimals)
+            };
+            let output = simulation.return_amount.u128() + simulation.fee_amount.u128();
+            assert!(output <= input_value);
+
+            // a 3/1000 fee rounded up
+            let fee = output * 3;
+            let expected_fee = fee / 1000 + (fee % 1000 != 0) as u128;
+            assert_eq!(simulation.fee_amount.u128(), expected_fee);
+        }
+    }
+}
+
+fn decimals_of(token: &str) -> u32 {
+    token.trim_start_matches("token").parse().unwrap()
+}
+
+#[test]
+fn rebalancing_deposit() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18), ("token2", 8)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 100 * 10u128.pow(18) + 1);
+    fund_pool(&mut deps, "token2", 900 * 10u128.pow(8));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+
+    let res = query(&deps, QueryMsg::GetRebalancingDeposit {}).unwrap();
+    let rebalancing: RebalancingDepositResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        rebalancing.deposits,
+        vec![
+            token_amount("token0", 0),
+            token_amount("token1", 900 * 10u128.pow(18) - 1),
+            token_amount("token2", 100 * 10u128.pow(8)),
+        ]
+    );
+    assert_eq!(rebalancing.lp_out, Uint128(1_000_620_911_738_523_475_662));
+    // balancing the pool grows D by more than the 1000 deposited, even after the imbalance fee
+    let bonus = rebalancing.bonus.u128();
+    assert_eq!(bonus, 413_855_503_102_340_848);
+
+    // existing LPs keep half of the bonus
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            rebalance_bonus_to_lp_bps: Some(5_000),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    let res = query(&deps, QueryMsg::GetRebalancingDeposit {}).unwrap();
+    let shared: RebalancingDepositResponse = from_binary(&res).unwrap();
+    assert_eq!(shared.deposits, rebalancing.deposits);
+    assert_eq!(shared.lp_out, Uint128(1_000_310_423_744_970_543_058));
+    // the depositor's and the LPs' halves add up to the whole bonus, up to rounding
+    let shared_bonus = shared.bonus.u128();
+    assert_eq!(shared_bonus, 206_927_751_551_170_423);
+    assert!(bonus - 2 * shared_bonus <= 2);
+
+    // and the deposit mints what the query reports
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::ProvideLiquidity {
+            assets: shared.deposits.clone(),
+            cancel_if_no_bonus: None,
+            min_lp_out: None,
+            deadline: None,
+        },
+    )
+    .unwrap();
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 900 * 10u128.pow(18) - 1);
+    deps.querier
+        .add_balance("token2", MOCK_CONTRACT_ADDR, 100 * 10u128.pow(8));
+    let res = finalize_deposit(&mut deps, &res).unwrap();
+    let data: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(data.lp_amount, shared.lp_out);
+}
+
+#[test]
+fn rebalancing_deposit_skips_undepositable_assets() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6), ("token2", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 100_000_000);
+    fund_pool(&mut deps, "token2", 100_000_000);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 1_200 * 10u128.pow(18));
+    set_asset_enabled(&mut deps, "token2", false);
+
+    let res = query(&deps, QueryMsg::GetRebalancingDeposit {}).unwrap();
+    let rebalancing: RebalancingDepositResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        rebalancing.deposits,
+        vec![
+            token_amount("token0", 0),
+            token_amount("token1", 900_000_000),
+        ]
+    );
+
+    // so the order goes through as returned
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::ProvideLiquidity {
+            assets: rebalancing.deposits.clone(),
+            cancel_if_no_bonus: None,
+            min_lp_out: None,
+            deadline: None,
+        },
+    )
+    .unwrap();
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 900_000_000);
+    let res = finalize_deposit(&mut deps, &res).unwrap();
+    let data: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(data.lp_amount, rebalancing.lp_out);
+
+    // withdraw-only assets are left out as well
+    set_asset_enabled(&mut deps, "token2", true);
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::RemoveAsset {
+            token: HumanAddr::from("token2"),
+        },
+    )
+    .unwrap();
+    let res = query(&deps, QueryMsg::GetRebalancingDeposit {}).unwrap();
+    let rebalancing: RebalancingDepositResponse = from_binary(&res).unwrap();
+    assert!(rebalancing
+        .deposits
+        .iter()
+        .all(|deposit| deposit.address != HumanAddr::from("token2")));
+}
+
+#[test]
+fn init_registers_assets_and_instantiates_lp_token() {
+    let mut deps = mock_token_deps(&[("usdt", 6), ("dai", 18)]);
+    let env = mock_env(ADMIN, &[]);
+    let res = init(
+        &mut deps,
+        env.clone(),
+        init_msg(&[("usdt", 6), ("dai", 18)], &["uscrt"]),
+    )
+    .unwrap();
+
+    // Native denoms need no viewing key or receive registration
+    assert_eq!(res.messages.len(), 5);
+    for (i, token) in ["usdt", "dai"].iter().enumerate() {
+        assert_eq!(
+            res.messages[2 * i],
+            snip20_utils::set_viewing_key_msg(
+                VIEWING_KEY.to_string(),
+                None,
+                256,
+                code_hash(token),
+                HumanAddr::from(*token),
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            res.messages[2 * i + 1],
+            snip20_utils::register_receive_msg(
+                env.contract_code_hash.clone(),
+                None,
+                256,
+                code_hash(token),
+                HumanAddr::from(*token),
+            )
+            .unwrap()
+        );
+    }
+
+    match &res.messages[4] {
+        CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id,
+            msg,
+            label,
+            callback_code_hash,
+            ..
+        }) => {
+            assert_eq!(*code_id, 1);
+            assert_eq!(label, "stable-lp");
+            assert_eq!(callback_code_hash, &code_hash(LP_TOKEN));
+
+            let lp_init: snip20::msg::InitMsg = from_binary(msg).unwrap();
+            assert_eq!(lp_init.admin, Some(HumanAddr::from(MOCK_CONTRACT_ADDR)));
+            assert_eq!(lp_init.decimals, 18);
+            assert_eq!(lp_init.prng_seed, Binary(b"seed".to_vec()));
+            let hook = lp_init.after_init_hook.unwrap();
+            assert_eq!(hook.contract_addr, HumanAddr::from(MOCK_CONTRACT_ADDR));
+            assert_eq!(hook.code_hash, env.contract_code_hash);
+            assert_eq!(
+                from_binary::<HandleMsg>(&hook.msg).unwrap(),
+                HandleMsg::PostInitialize {}
+            );
+        }
+        other => panic!("Must instantiate the LP token, got {:?}", other),
+    }
+
+    // Decimals come from each token's token_info
+    let assets = read_all_assets(&deps.storage).unwrap();
+    assert_eq!(
+        assets
+            .iter()
+            .map(|asset| (asset.address.0.as_str(), asset.decimals, asset.native))
+            .collect::<Vec<_>>(),
+        vec![("usdt", 6, false), ("dai", 18, false), ("uscrt", 6, true)]
+    );
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128::zero(); 3]
+    );
+}
+
+#[test]
+fn init_rejects_invalid_params() {
+    let tokens = [("usdt", 6), ("dai", 18)];
+
+    let mut deps = mock_token_deps(&tokens);
+    let mut msg = init_msg(&tokens, &[]);
+    msg.swap_fee_denom = Uint128::zero();
+    let res = init(&mut deps, mock_env(ADMIN, &[]), msg);
+    assert_generic_err(res, "swap_fee_denom cannot be zero");
+
+    let mut deps = mock_token_deps(&tokens);
+    let mut msg = init_msg(&tokens, &[]);
+    msg.amp = 0;
+    let res = init(&mut deps, mock_env(ADMIN, &[]), msg);
+    assert_generic_err(res, "amp cannot be zero");
+
+    // Tokens beyond the normalized precision can't be pooled
+    let mut deps = mock_token_deps(&[("usdt", 6), ("wide", 24)]);
+    let res = init(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        init_msg(&[("usdt", 6), ("wide", 24)], &[]),
+    );
+    assert_generic_err(res, "Decimals must not exceed 18");
+}
+
+#[test]
+fn post_initialize_rejects_wrong_code_hash() {
+    let mut deps = init_pool_without_lp_token(&[("usdt", 6), ("dai", 18)], &[]);
+    deps.querier.with_token("rogue", 18, Some(Uint128::zero()));
+
+    let res = handle(
+        &mut deps,
+        mock_env("rogue", &[]),
+        HandleMsg::PostInitialize {},
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        HandleMsg::PostInitialize {},
+    )
+    .unwrap();
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.lp_token_address, HumanAddr::from(LP_TOKEN));
+}
+
+#[test]
+fn post_initialize_rejects_lp_token_decimals() {
+    let mut deps = init_pool_without_lp_token(&[("token0", 6), ("token1", 6)], &[]);
+    deps.querier.with_token(LP_TOKEN, 6, Some(Uint128::zero()));
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        HandleMsg::PostInitialize {},
+    );
+    assert_generic_err(res, "LP token must have 18 decimals, got 6");
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.lp_token_address, HumanAddr::default());
+}
+
+#[test]
+fn liquidity_token() {
+    let mut deps = init_pool_without_lp_token(&[("token0", 6), ("token1", 6)], &[]);
+    let res = query(&deps, QueryMsg::GetLiquidityToken {});
+    assert_generic_err(res, "Pool is not fully initialized");
+
+    handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        HandleMsg::PostInitialize {},
+    )
+    .unwrap();
+    let res = query(&deps, QueryMsg::GetLiquidityToken {}).unwrap();
+    assert_eq!(
+        from_binary::<LiquidityTokenResponse>(&res).unwrap(),
+        LiquidityTokenResponse {
+            address: HumanAddr::from(LP_TOKEN),
+            code_hash: code_hash(LP_TOKEN),
+        }
+    );
+}
+
+#[test]
+fn withdraw_liquidity_with_min() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000);
+    fund_pool(&mut deps, "token1", 2_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000);
+
+    let withdraw_with_min = |min_amounts: &[u128]| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(1_000),
+        msg: to_binary(&Snip20ReceiveMsg::WithdrawLiquidityWithMin {
+            min_amounts: min_amounts.iter().map(|amount| Uint128(*amount)).collect(),
+            deadline: None,
+            unwrap: None,
+        })
+        .unwrap(),
+    };
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_with_min(&[500]),
+    );
+    assert_generic_err(res, "Expected 2 min amounts, got 1");
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_with_min(&[500, 1_001]),
+    );
+    assert_generic_err(
+        res,
+        "Refund of 1000 token1 is less than the minimum of 1001",
+    );
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_with_min(&[500, 1_000]),
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 3);
+}
+
+#[test]
+fn withdraw_liquidity_one_token() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    let withdraw = |token: &str, amount: u128, min_amount: u128| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(amount),
+        msg: to_binary(&Snip20ReceiveMsg::WithdrawLiquidityOneToken {
+            token: HumanAddr::from(token),
+            min_amount: Uint128(min_amount),
+            deadline: None,
+            unwrap: None,
+        })
+        .unwrap(),
+    };
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw("token2", 100 * 10u128.pow(18), 0),
+    );
+    assert_generic_err(res, "Unknown destination asset");
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw("token0", 2_000 * 10u128.pow(18), 0),
+    );
+    assert_generic_err(res, "can only be withdrawn in every asset");
+
+    // 5% of the pool, all in token0, less slippage and the imbalance fee
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw("token0", 100 * 10u128.pow(18), 99_836_935),
+    );
+    assert_generic_err(
+        res,
+        "Refund of 99836934 token0 is less than the minimum of 99836935",
+    );
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw("token0", 100 * 10u128.pow(18), 99_836_934),
+    )
+    .unwrap();
+    let data: WithdrawLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(data.refunds, vec![token_amount("token0", 99_836_934)]);
+    assert!(res.log.contains(&log("fee_amount", "149940")));
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20_utils::transfer_msg(
+                HumanAddr::from(USER),
+                Uint128(99_836_934),
+                None,
+                256,
+                code_hash("token0"),
+                HumanAddr::from("token0"),
+            )
+            .unwrap(),
+            snip20_utils::burn_msg(
+                Uint128(100 * 10u128.pow(18)),
+                None,
+                256,
+                code_hash(LP_TOKEN),
+                HumanAddr::from(LP_TOKEN),
+            )
+            .unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn withdraw_imbalanced() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_000 * 10u128.pow(18));
+    let withdraw = |amounts: &[u128], max_burn: u128| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(200 * 10u128.pow(18)),
+        msg: to_binary(&Snip20ReceiveMsg::WithdrawImbalanced {
+            amounts: amounts.iter().map(|amount| Uint128(*amount)).collect(),
+            max_burn: Uint128(max_burn),
+            deadline: None,
+        })
+        .unwrap(),
+    };
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw(&[100_000_000], u128::MAX),
+    );
+    assert_generic_err(res, "Expected 2 amounts, got 1");
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw(&[1_000_000_000, 0], u128::MAX),
+    );
+    assert_generic_err(res, "Withdrawal would leave no token0 in the pool");
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw(&[0, 1_000 * 10u128.pow(17)], 10u128.pow(18)),
+    );
+    assert_generic_err(res, "more than the max of 1000000000000000000");
+
+    // a balanced withdrawal burns its share of the pool
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw(&[50_000_000, 50 * 10u128.pow(18)], u128::MAX),
+    )
+    .unwrap();
+    assert!(res
+        .log
+        .contains(&log("withdrawn_share", "100000000000000000000")));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 1_900 * 10u128.pow(18));
+
+    // 100 in token0 alone also pays the imbalance fee, and the unburned LP is sent back
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw(&[100_000_000, 0], u128::MAX),
+    )
+    .unwrap();
+    let burn = 100_163_863_412_790_253_339;
+    let data: WithdrawLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(data.refunds, vec![token_amount("token0", 100_000_000)]);
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20_utils::transfer_msg(
+                HumanAddr::from(USER),
+                Uint128(100_000_000),
+                None,
+                256,
+                code_hash("token0"),
+                HumanAddr::from("token0"),
+            )
+            .unwrap(),
+            snip20_utils::transfer_msg(
+                HumanAddr::from(USER),
+                Uint128(200 * 10u128.pow(18) - burn),
+                None,
+                256,
+                code_hash(LP_TOKEN),
+                HumanAddr::from(LP_TOKEN),
+            )
+            .unwrap(),
+            snip20_utils::burn_msg(
+                Uint128(burn),
+                None,
+                256,
+                code_hash(LP_TOKEN),
+                HumanAddr::from(LP_TOKEN),
+            )
+            .unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn handle_responses_carry_data() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000), ("token1", 10u128.pow(18))]),
+    )
+    .unwrap();
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 10u128.pow(18));
+    let res = finalize_deposit(&mut deps, &res).unwrap();
+    let provided: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(provided.lp_amount, Uint128(2 * 10u128.pow(18)));
+    assert_eq!(
+        provided.deposits,
+        vec![
+            token_amount("token0", 1_000_000),
+            token_amount("token1", 10u128.pow(18))
+        ]
+    );
+    deps.querier.set_total_supply(LP_TOKEN, 2 * 10u128.pow(18));
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000).unwrap();
+    let swapped: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        swapped,
+        SwapResponse {
+            return_amount: Uint128(996_995_039_820_760),
+            fee_amount: Uint128(2_999_985_074_687),
+        }
+    );
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(10u128.pow(18)),
+    )
+    .unwrap();
+    let withdrawn: WithdrawLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        withdrawn.refunds,
+        vec![
+            token_amount("token0", 500_500),
+            token_amount("token1", 499_501_502_480_089_620)
+        ]
+    );
+}
+
+#[test]
+fn effective_fee() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    fund_pool(&mut deps, "token1", 500_000_000);
+
+    let effective_fee = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, src, dst| {
+        let res = query(
+            deps,
+            QueryMsg::GetEffectiveFee {
+                offer: token_amount(src, 100_000_000),
+                to_token: HumanAddr::from(dst),
+            },
+        )
+        .unwrap();
+        from_binary::<EffectiveFeeResponse>(&res).unwrap()
+    };
+
+    // the static 3/1000 fee
+    assert_eq!(
+        effective_fee(&deps, "token0", "token1"),
+        EffectiveFeeResponse {
+            fee_bps: 30,
+            dynamic: false
+        }
+    );
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            dynamic_fee: Some(true),
+            dynamic_fee_base_bps: Some(4),
+            dynamic_fee_max_bps: Some(50),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    let imbalancing = effective_fee(&deps, "token0", "token1");
+    let rebalancing = effective_fee(&deps, "token1", "token0");
+    assert!(imbalancing.dynamic);
+    assert!(rebalancing.fee_bps < imbalancing.fee_bps);
+    assert!(rebalancing.fee_bps >= 4);
+    assert!(imbalancing.fee_bps <= 50);
+}
+
+#[test]
+fn fee_on_transfer_tokens_credit_received_amounts() {
+    let mut deps = init_pool(&[("token0", 6), ("fee_token", 6)]);
+
+    // fee_token keeps 1% of every transfer
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000), ("fee_token", 1_000_000)]),
+    )
+    .unwrap();
+    // ProvideLiquidity reports the share of the requested amounts, as FinalizeDeposit's data
+    // doesn't reach the caller
+    let quoted: ProvideLiquidityResponse = from_binary(res.data.as_ref().unwrap()).unwrap();
+    assert_eq!(
+        quoted.deposits,
+        vec![
+            token_amount("token0", 1_000_000),
+            token_amount("fee_token", 1_000_000)
+        ]
+    );
+    assert_eq!(quoted.lp_amount, Uint128(2 * 10u128.pow(18)));
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    deps.querier
+        .set_balance("fee_token", MOCK_CONTRACT_ADDR, 990_000);
+    let res = finalize_deposit(&mut deps, &res).unwrap();
+    let provided: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        provided.deposits,
+        vec![
+            token_amount("token0", 1_000_000),
+            token_amount("fee_token", 990_000)
+        ]
+    );
+    // the first deposit mints D, a little under the sum since it's imbalanced
+    assert_eq!(provided.lp_amount, Uint128(1_989_999_874_993_741_776));
+    assert!(provided.lp_amount < quoted.lp_amount);
+    assert!(!read_lock(&deps.storage).unwrap());
+
+    // only the pool itself can finalize a deposit
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::FinalizeDeposit {
+            depositor: HumanAddr::from(USER),
+            deposits: vec![token_amount("token0", 1_000_000)],
+            cancel_if_no_bonus: None,
+            min_lp_out: None,
+        },
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // sending 10_000 delivers 9_900, which is swapped minus the 3/1000 fee rounded up
+    deps.querier
+        .add_balance("fee_token", MOCK_CONTRACT_ADDR, 9_900);
+    let res = handle(
+        &mut deps,
+        mock_env("fee_token", &[]),
+        swap_msg("token0", 10_000),
+    )
+    .unwrap();
+    let swapped: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        swapped,
+        SwapResponse {
+            return_amount: Uint128(9_870),
+            fee_amount: Uint128(30),
+        }
+    );
+
+    // nothing new arrived
+    let res = handle(
+        &mut deps,
+        mock_env("fee_token", &[]),
+        swap_msg("token0", 10_000),
+    );
+    assert_generic_err(res, "No tokens were received for the swap");
+}
+
+fn set_status(deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, status: PoolStatus) {
+    handle(
+        deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            status: Some(status),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+}
+
+#[test]
+fn pool_status_gates_operations() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000_000);
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.status, PoolStatus::Active);
+
+    let deposit = provide_msg(&[("token0", 1_000), ("token1", 1_000)]);
+    let emergency = HandleMsg::EmergencyWithdraw {
+        recipient: HumanAddr::from("new_pool"),
+    };
+
+    // Active: everything but the emergency withdrawal
+    let res = handle(&mut deps, mock_env(USER, &[]), deposit.clone()).unwrap();
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000);
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 1_000);
+    finalize_deposit(&mut deps, &res).unwrap();
+    swap(&mut deps, mock_env("token0", &[]), "token1", 1_000).unwrap();
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000)).unwrap();
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), emergency.clone());
+    assert_generic_err(res, "requires the pool to be halted");
+
+    // WithdrawOnly: LPs can still get out
+    set_status(&mut deps, PoolStatus::WithdrawOnly);
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.status, PoolStatus::WithdrawOnly);
+    let res = handle(&mut deps, mock_env(USER, &[]), deposit.clone());
+    assert_generic_err(
+        res,
+        "Swaps and deposits are disabled, the pool is WithdrawOnly",
+    );
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000);
+    assert_generic_err(
+        res,
+        "Swaps and deposits are disabled, the pool is WithdrawOnly",
+    );
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000)).unwrap();
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), emergency.clone());
+    assert_generic_err(res, "requires the pool to be halted");
+
+    // Halted: only the admin's emergency withdrawal
+    set_status(&mut deps, PoolStatus::Halted);
+    let res = handle(&mut deps, mock_env(USER, &[]), deposit.clone());
+    assert_generic_err(res, "Swaps and deposits are disabled, the pool is Halted");
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000);
+    assert_generic_err(res, "Swaps and deposits are disabled, the pool is Halted");
+    let res = handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000));
+    assert_generic_err(res, "Withdrawals are disabled while the pool is halted");
+    handle(&mut deps, mock_env(ADMIN, &[]), emergency).unwrap();
+
+    // and back
+    set_status(&mut deps, PoolStatus::Active);
+    handle(&mut deps, mock_env(USER, &[]), deposit).unwrap();
+}
+
+#[test]
+fn halt_and_resume() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000_000);
+
+    let res = handle(&mut deps, mock_env(USER, &[]), HandleMsg::Halt {});
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::Halt {}).unwrap();
+    assert!(res.log.contains(&log("status", "WithdrawOnly")));
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000);
+    assert_generic_err(res, "the pool is WithdrawOnly");
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000)]),
+    );
+    assert_generic_err(res, "the pool is WithdrawOnly");
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000)).unwrap();
+
+    let res = handle(&mut deps, mock_env(USER, &[]), HandleMsg::Resume {});
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::Resume {}).unwrap();
+    assert!(res.log.contains(&log("previous_status", "WithdrawOnly")));
+    swap(&mut deps, mock_env("token0", &[]), "token1", 1_000).unwrap();
+}
+
+#[test]
+fn swaps_and_deposits_disabled_separately() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000_000);
+    let set_pool_status = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, status| {
+        handle(deps, mock_env(ADMIN, &[]), HandleMsg::SetStatus { status })
+    };
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::SetStatus {
+            status: PoolStatus::Active,
+        },
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // SwapsDisabled: zaps are priced on a swap, so they're out too
+    set_pool_status(&mut deps, PoolStatus::SwapsDisabled).unwrap();
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000);
+    assert_generic_err(res, "[swaps_disabled] ");
+    let res = handle(&mut deps, mock_env("token0", &[]), zap_in_msg(1_000));
+    assert_generic_err(res, "[swaps_disabled] ");
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000)).unwrap();
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000)]),
+    )
+    .unwrap();
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000);
+    finalize_deposit(&mut deps, &res).unwrap();
+
+    // DepositsDisabled, including deposits in a batch
+    set_pool_status(&mut deps, PoolStatus::DepositsDisabled).unwrap();
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000)]),
+    );
+    assert_generic_err(res, "[deposits_disabled] ");
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::Batch {
+            operations: vec![BatchOperation::ProvideLiquidity {
+                assets: vec![token_amount("token0", 1_000)],
+                min_lp_out: None,
+            }],
+        },
+    );
+    assert_generic_err(res, "[deposits_disabled] ");
+    swap(&mut deps, mock_env("token0", &[]), "token1", 1_000).unwrap();
+    handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000)).unwrap();
+}
+
+#[test]
+fn factory_callbacks() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let factory = FactoryInfo {
+        address: HumanAddr::from("factory"),
+        code_hash: code_hash("factory"),
+    };
+    let mut config = read_config(&deps.storage).unwrap();
+    config.factory = Some(factory.clone());
+    store_config(&mut deps.storage, &config).unwrap();
+    let update_msg = |assets: &[&str], admin: &str| {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: factory.address.clone(),
+            callback_code_hash: factory.code_hash.clone(),
+            msg: to_binary(&FactoryHandleMsg::UpdatePool {
+                assets: assets.iter().map(|a| HumanAddr::from(*a)).collect(),
+                admin: HumanAddr::from(admin),
+            })
+            .unwrap(),
+            send: vec![],
+        })
+    };
+
+    deps.querier.with_token("token2", 6, Some(Uint128::zero()));
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::AddAsset {
+            token: Token {
+                address: HumanAddr::from("token2"),
+                code_hash: code_hash("token2"),
+                native: false,
+            },
+            viewing_key: VIEWING_KEY.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages.last(),
+        Some(&update_msg(&["token0", "token1", "token2"], ADMIN))
+    );
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::RemoveAsset {
+            token: HumanAddr::from("token2"),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages.last(),
+        Some(&update_msg(&["token0", "token1"], ADMIN))
+    );
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::ProposeNewAdmin {
+            address: HumanAddr::from("new_admin"),
+        },
+    )
+    .unwrap();
+    let res = handle(
+        &mut deps,
+        mock_env("new_admin", &[]),
+        HandleMsg::AcceptAdmin {},
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![update_msg(&["token0", "token1"], "new_admin")]
+    );
+}
+
+#[test]
+fn viewing_keys_and_query_permits() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let pool = HumanAddr::from(MOCK_CONTRACT_ADDR);
+
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+        },
+    )
+    .unwrap();
+    check_viewing_key(&deps.storage, &HumanAddr::from(USER), "key").unwrap();
+    for (address, key) in [(USER, "other"), (ADMIN, "key")].iter() {
+        match check_viewing_key(&deps.storage, &HumanAddr::from(*address), key) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Must return unauthorized error"),
+        }
+    }
+
+    let owner_permit = permit(
+        MOCK_CONTRACT_ADDR,
+        Permission::Owner,
+        OWNER_PERMIT_SIGNATURE,
+    );
+    for permission in [Permission::Position, Permission::History].iter() {
+        assert_eq!(
+            validate_permit(&deps.storage, &pool, &owner_permit, *permission).unwrap(),
+            HumanAddr::from(PERMIT_SIGNER)
+        );
+    }
+    let history_permit = permit(
+        MOCK_CONTRACT_ADDR,
+        Permission::History,
+        HISTORY_PERMIT_SIGNATURE,
+    );
+    validate_permit(&deps.storage, &pool, &history_permit, Permission::History).unwrap();
+    let res = validate_permit(&deps.storage, &pool, &history_permit, Permission::Position);
+    assert_generic_err(res, "does not grant Position");
+
+    let res = validate_permit(
+        &deps.storage,
+        &pool,
+        &permit(
+            "secret1other",
+            Permission::Owner,
+            OTHER_CONTRACT_PERMIT_SIGNATURE,
+        ),
+        Permission::Position,
+    );
+    assert_generic_err(res, "is not valid for this pool");
+    // Signed for another contract, with the pool swapped in
+    let res = validate_permit(
+        &deps.storage,
+        &pool,
+        &permit(
+            MOCK_CONTRACT_ADDR,
+            Permission::Owner,
+            OTHER_CONTRACT_PERMIT_SIGNATURE,
+        ),
+        Permission::Position,
+    );
+    assert_generic_err(res, "signature does not match");
+    let mut wrong_key_type = owner_permit.clone();
+    wrong_key_type.signature.pub_key.r#type = "tendermint/PubKeyEd25519".to_string();
+    let res = validate_permit(&deps.storage, &pool, &wrong_key_type, Permission::Position);
+    assert_generic_err(res, "[invalid_permit]");
+
+    // Only the signer can revoke its permits
+    handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::RevokePermit {
+            permit_name: "pool_queries".to_string(),
+        },
+    )
+    .unwrap();
+    validate_permit(&deps.storage, &pool, &owner_permit, Permission::Position).unwrap();
+    handle(
+        &mut deps,
+        mock_env(PERMIT_SIGNER, &[]),
+        HandleMsg::RevokePermit {
+            permit_name: "pool_queries".to_string(),
+        },
+    )
+    .unwrap();
+    let res = validate_permit(&deps.storage, &pool, &owner_permit, Permission::Position);
+    assert_generic_err(res, "was revoked");
+}
+
+#[test]
+fn two_step_admin_transfer() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let new_admin = "new_admin";
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::ProposeNewAdmin {
+            address: HumanAddr::from(USER),
+        },
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // Nothing to accept before a proposal
+    let res = handle(
+        &mut deps,
+        mock_env(new_admin, &[]),
+        HandleMsg::AcceptAdmin {},
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::ProposeNewAdmin {
+            address: HumanAddr::from(new_admin),
+        },
+    )
+    .unwrap();
+    assert!(res.log.contains(&log("pending_admin", new_admin)));
+
+    // The current admin stays in charge until the proposal is accepted
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.admin, HumanAddr::from(ADMIN));
+    assert_eq!(config.pending_admin, Some(HumanAddr::from(new_admin)));
+    handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::Halt {}).unwrap();
+
+    let res = handle(&mut deps, mock_env(USER, &[]), HandleMsg::AcceptAdmin {});
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env(new_admin, &[]),
+        HandleMsg::AcceptAdmin {},
+    )
+    .unwrap();
+    assert!(res.log.contains(&log("previous_admin", ADMIN)));
+    assert!(res.log.contains(&log("admin", new_admin)));
+
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.admin, HumanAddr::from(new_admin));
+    assert_eq!(config.pending_admin, None);
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::Resume {});
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    handle(&mut deps, mock_env(new_admin, &[]), HandleMsg::Resume {}).unwrap();
+
+    // The proposal is used up
+    let res = handle(
+        &mut deps,
+        mock_env(new_admin, &[]),
+        HandleMsg::AcceptAdmin {},
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+}
+
+#[test]
+fn operators_can_halt_but_not_manage() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let operator = "operator";
+    let assert_unauthorized = |res: HandleResult| match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    };
+
+    let set_operators = HandleMsg::SetOperators {
+        operators: vec![HumanAddr::from(operator)],
+    };
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        set_operators.clone(),
+    ));
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), set_operators).unwrap();
+    assert!(res.log.contains(&log("operator", operator)));
+
+    handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        HandleMsg::UpdateViewingKeys {
+            viewing_key: "new_key".to_string(),
+        },
+    )
+    .unwrap();
+
+    handle(&mut deps, mock_env(operator, &[]), HandleMsg::Halt {}).unwrap();
+    handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        HandleMsg::SetStatus {
+            status: PoolStatus::Halted,
+        },
+    )
+    .unwrap();
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        HandleMsg::Resume {},
+    ));
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        HandleMsg::SetStatus {
+            status: PoolStatus::SwapsDisabled,
+        },
+    ));
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        update_config_msg(ConfigUpdate {
+            swap_fee_nom: Some(Uint128(1)),
+            ..Default::default()
+        }),
+    ));
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        HandleMsg::WithdrawAdminFees {
+            recipient: HumanAddr::from(operator),
+        },
+    ));
+
+    // Removed operators lose their rights
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::SetOperators { operators: vec![] },
+    )
+    .unwrap();
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env(operator, &[]),
+        HandleMsg::Halt {},
+    ));
+}
+
+#[test]
+fn legacy_halted_flag_migrates_to_status() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000);
+    let key = to_length_prefixed(b"config");
+    let stored = String::from_utf8(deps.storage.get(&key).unwrap()).unwrap();
+    assert!(stored.contains(r#""status":"active""#));
+    assert!(!stored.contains("is_halted"));
+
+    let legacy = stored.replace(r#""status":"active""#, r#""is_halted":true"#);
+    deps.storage.set(&key, legacy.as_bytes());
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.status, PoolStatus::Halted);
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000);
+    assert_generic_err(res, "the pool is Halted");
+
+    // the flag is dropped once the config is stored again
+    set_status(&mut deps, PoolStatus::Active);
+    let stored = String::from_utf8(deps.storage.get(&key).unwrap()).unwrap();
+    assert!(!stored.contains("is_halted"));
+    swap(&mut deps, mock_env("token0", &[]), "token1", 1_000).unwrap();
+}
+
+#[test]
+fn emergency_withdraw() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 6)], &["uscrt"]);
+    fund_pool(&mut deps, "token0", 1_000);
+    deps.querier
+        .set_native_balance(MOCK_CONTRACT_ADDR, coins(2_000, "uscrt"));
+    let msg = HandleMsg::EmergencyWithdraw {
+        recipient: HumanAddr::from("new_pool"),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), msg.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), msg.clone());
+    assert_generic_err(res, "requires the pool to be halted");
+
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            status: Some(PoolStatus::Halted),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+
+    // token1 has nothing to withdraw
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), msg).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20_utils::transfer_msg(
+                HumanAddr::from("new_pool"),
+                Uint128(1_000),
+                None,
+                256,
+                code_hash("token0"),
+                HumanAddr::from("token0"),
+            )
+            .unwrap(),
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                to_address: HumanAddr::from("new_pool"),
+                amount: coins(2_000, "uscrt"),
+            }),
+        ]
+    );
+    assert!(res
+        .log
+        .iter()
+        .any(|l| l.key == "withdrawn" && l.value == "2000 uscrt"));
+}
+
+#[test]
+fn admin_fees() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    let set_admin_fee = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, bps: u32| {
+        handle(
+            deps,
+            mock_env(ADMIN, &[]),
+            update_config_msg(ConfigUpdate {
+                admin_fee_bps: Some(bps),
+                ..Default::default()
+            }),
+        )
+    };
+    let admin_fees = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        from_binary::<AdminFeesResponse>(&query(deps, QueryMsg::GetAdminFees {}).unwrap())
+            .unwrap()
+            .fees
+            .into_iter()
+            .map(|fee| fee.amount.u128())
+            .collect::<Vec<u128>>()
+    };
+    assert_generic_err(
+        set_admin_fee(&mut deps, MAX_BPS + 1),
+        "admin_fee_bps must not exceed",
+    );
+    set_admin_fee(&mut deps, 5_000).unwrap();
+
+    // half of the 3,000 fee is set aside, the other half stays in the reserve for the LPs
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_000_000).unwrap();
+    assert!(res.log.contains(&log("fee_amount", "3000")));
+    assert!(res.log.contains(&log("admin_fee", "1500")));
+    assert_eq!(admin_fees(&deps), vec![0, 1_500]);
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(1_001_000_000), Uint128(999_001_505)]
+    );
+
+    // syncing doesn't hand them to the LPs
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 999_003_005);
+    handle(&mut deps, mock_env(ADMIN, &[]), HandleMsg::SyncReserves {}).unwrap();
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap()[1],
+        Uint128(999_001_505)
+    );
+
+    let withdraw = HandleMsg::WithdrawAdminFees {
+        recipient: HumanAddr::from("treasury"),
+    };
+    let res = handle(&mut deps, mock_env(USER, &[]), withdraw.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), withdraw.clone()).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![snip20_utils::transfer_msg(
+            HumanAddr::from("treasury"),
+            Uint128(1_500),
+            None,
+            256,
+            code_hash("token1"),
+            HumanAddr::from("token1"),
+        )
+        .unwrap()]
+    );
+    assert_eq!(admin_fees(&deps), vec![0, 0]);
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), withdraw);
+    assert_generic_err(res, "There are no admin fees to withdraw");
+}
+
+#[test]
+fn pool_snapshot() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 18)], &["uscrt"]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    fund_pool(&mut deps, "uscrt", 500_000_000);
+    deps.querier
+        .set_total_supply(LP_TOKEN, 2_500 * 10u128.pow(18));
+
+    let res = query(&deps, QueryMsg::GetPoolSnapshot {}).unwrap();
+    let snapshot: PoolSnapshotResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        snapshot,
+        PoolSnapshotResponse {
+            assets: vec![
+                AssetSnapshot {
+                    address: HumanAddr::from("token0"),
+                    code_hash: code_hash("token0"),
+                    decimals: 6,
+                    balance: Uint128(1_000_000_000),
+                    normalized_balance: Uint128(1_000 * 10u128.pow(18)),
+                },
+                AssetSnapshot {
+                    address: HumanAddr::from("token1"),
+                    code_hash: code_hash("token1"),
+                    decimals: 18,
+                    balance: Uint128(1_000 * 10u128.pow(18)),
+                    normalized_balance: Uint128(1_000 * 10u128.pow(18)),
+                },
+                AssetSnapshot {
+                    address: HumanAddr::from("uscrt"),
+                    code_hash: String::new(),
+                    decimals: 6,
+                    balance: Uint128(500_000_000),
+                    normalized_balance: Uint128(500 * 10u128.pow(18)),
+                },
+            ],
+            lp_total_supply: Uint128(2_500 * 10u128.pow(18)),
+        }
+    );
+}
+
+#[test]
+fn most_needed_token() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18), ("token2", 8)]);
+    let most_needed = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        from_binary::<MostNeededTokenResponse>(
+            &query(deps, QueryMsg::GetMostNeededToken {}).unwrap(),
+        )
+        .unwrap()
+    };
+    // an empty pool needs every asset alike
+    assert_eq!(
+        most_needed(&deps),
+        MostNeededTokenResponse {
+            token: Some(HumanAddr::from("token0")),
+            deficit: Uint128::zero(),
+        }
+    );
+
+    fund_pool(&mut deps, "token0", 3_000_000);
+    fund_pool(&mut deps, "token1", 10u128.pow(18));
+    fund_pool(&mut deps, "token2", 2 * 10u128.pow(8));
+    assert_eq!(
+        most_needed(&deps),
+        MostNeededTokenResponse {
+            token: Some(HumanAddr::from("token1")),
+            deficit: Uint128(10u128.pow(18)),
+        }
+    );
+
+    // only assets that take deposits are considered
+    set_asset_enabled(&mut deps, "token1", false);
+    assert_eq!(
+        most_needed(&deps),
+        MostNeededTokenResponse {
+            token: Some(HumanAddr::from("token2")),
+            deficit: Uint128::zero(),
+        }
+    );
+    set_asset_enabled(&mut deps, "token0", false);
+    set_asset_enabled(&mut deps, "token2", false);
+    assert_eq!(
+        most_needed(&deps),
+        MostNeededTokenResponse {
+            token: None,
+            deficit: Uint128::zero(),
+        }
+    );
+}
+
+#[test]
+fn pools() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 18)], &["uscrt"]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "uscrt", 500_000_000);
+    // sent to the pool directly, so not tracked
+    deps.querier
+        .add_balance("token1", MOCK_CONTRACT_ADDR, 10u128.pow(18));
+
+    let res = query(&deps, QueryMsg::GetPools {}).unwrap();
+    let pools = from_binary::<PoolsResponse>(&res).unwrap().assets;
+    assert_eq!(
+        pools,
+        vec![
+            PoolAsset {
+                address: HumanAddr::from("token0"),
+                decimals: 6,
+                balance: Uint128(1_000_000_000),
+            },
+            PoolAsset {
+                address: HumanAddr::from("token1"),
+                decimals: 18,
+                balance: Uint128::zero(),
+            },
+            PoolAsset {
+                address: HumanAddr::from("uscrt"),
+                decimals: 6,
+                balance: Uint128(500_000_000),
+            },
+        ]
+    );
+}
+
+#[test]
+fn imbalance() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    let imbalance = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let res = query(deps, QueryMsg::GetImbalance {}).unwrap();
+        from_binary::<ImbalanceResponse>(&res).unwrap()
+    };
+
+    // empty pool
+    assert_eq!(
+        imbalance(&deps),
+        ImbalanceResponse {
+            imbalance_bps: 0,
+            token: None,
+        }
+    );
+
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    assert_eq!(imbalance(&deps).imbalance_bps, 0);
+
+    fund_pool(&mut deps, "token1", 500 * 10u128.pow(18));
+    fund_pool(&mut deps, "token0", 1_500_000_000);
+    assert_eq!(
+        imbalance(&deps),
+        ImbalanceResponse {
+            imbalance_bps: 2_506,
+            token: Some(HumanAddr::from("token0")),
+        }
+    );
+}
+
+#[test]
+fn tvl() {
+    let mut deps = init_pool_with_native(&[("token0", 6), ("token1", 18)], &["uscrt"]);
+    let tvl = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        from_binary::<TvlResponse>(&query(deps, QueryMsg::GetTvl {}).unwrap()).unwrap()
+    };
+    assert_eq!(
+        tvl(&deps),
+        TvlResponse {
+            tvl: Uint128::zero(),
+            balances: vec![Uint128::zero(); 3],
+        }
+    );
+
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 250 * 10u128.pow(18));
+    fund_pool(&mut deps, "uscrt", 500_000_000);
+    assert_eq!(
+        tvl(&deps),
+        TvlResponse {
+            tvl: Uint128(1_750 * 10u128.pow(18)),
+            balances: vec![
+                Uint128(1_000 * 10u128.pow(18)),
+                Uint128(250 * 10u128.pow(18)),
+                Uint128(500 * 10u128.pow(18)),
+            ],
+        }
+    );
+}
+
+#[test]
+fn calls_rejected_before_post_initialize() {
+    let mut deps = init_pool_without_lp_token(&[("token0", 6), ("token1", 6)], &[]);
+    fund_pool(&mut deps, "token0", 1_000);
+    fund_pool(&mut deps, "token1", 1_000);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 100), ("token1", 100)]),
+    );
+    assert_generic_err(res, "Pool is not fully initialized");
+
+    let res = handle(&mut deps, mock_env("token0", &[]), swap_msg("token1", 100));
+    assert_generic_err(res, "Pool is not fully initialized");
+}
+
+#[test]
+fn depth_curve() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    let depth_curve = |max_input: u128, points: u32| {
+        query(
+            &deps,
+            QueryMsg::DepthCurve {
+                from_token: HumanAddr::from("token0"),
+                to_token: HumanAddr::from("token1"),
+                max_input: Uint128(max_input),
+                points,
+            },
+        )
+    };
+
+    assert_generic_err(depth_curve(1_000_000_000, 0), "must be between 1 and");
+    assert_generic_err(
+        depth_curve(1_000_000_000, MAX_DEPTH_POINTS + 1),
+        "must be between 1 and",
+    );
+
+    let curve = from_binary::<DepthCurveResponse>(&depth_curve(1_000_000_000, 4).unwrap())
+        .unwrap()
+        .points;
+    // the 3/1000 fee, then slippage as the input grows to the size of the pool
+    let expected: Vec<DepthPoint> = [
+        (1_000_000u128, 996_995_039_820_761_610u128, 30),
+        (10_000_000, 9_969_503_955_668_708_277, 30),
+        (100_000_000, 99_649_927_439_765_219_628, 35),
+        (1_000_000_000, 949_580_226_396_706_791_572, 504),
+    ]
+    .iter()
+    .map(|(input, output, price_impact_bps)| DepthPoint {
+        input: Uint128(*input),
+        output: Uint128(*output),
+        price_impact_bps: *price_impact_bps,
+    })
+    .collect();
+    assert_eq!(curve, expected);
+
+    // inputs that round down to zero are skipped
+    let curve = from_binary::<DepthCurveResponse>(&depth_curve(100, 5).unwrap())
+        .unwrap()
+        .points;
+    assert_eq!(curve.len(), 3);
+    assert_eq!(curve[0].input, Uint128(1));
+}
+
+#[test]
+fn max_input_for_impact() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    let max_input = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, max_impact_bps: u32| {
+        query(
+            deps,
+            QueryMsg::MaxInputForImpact {
+                from_token: HumanAddr::from("token0"),
+                to_token: HumanAddr::from("token1"),
+                max_impact_bps,
+            },
+        )
+        .and_then(|res| from_binary::<DepthPoint>(&res))
+    };
+
+    assert_generic_err(max_input(&deps, MAX_BPS + 1), "must not exceed");
+
+    // the flat 3/1000 fee is already over the threshold
+    assert_eq!(
+        max_input(&deps, 20).unwrap(),
+        DepthPoint {
+            input: Uint128::zero(),
+            output: Uint128::zero(),
+            price_impact_bps: 0,
+        }
+    );
+    // and meets it for small swaps, until slippage adds another bps at about 20 token0
+    let point = max_input(&deps, 30).unwrap();
+    assert!(point.input > Uint128(10_000_000) && point.input < Uint128(50_000_000));
+    assert_eq!(point.price_impact_bps, 30);
+
+    // with a dynamic fee, impact grows as token0 piles up
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        update_config_msg(ConfigUpdate {
+            dynamic_fee: Some(true),
+            dynamic_fee_base_bps: Some(4),
+            dynamic_fee_max_bps: Some(50),
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    let point = max_input(&deps, 20).unwrap();
+    assert!(point.input > Uint128::zero() && point.input < Uint128(1_000_000_000));
+    assert!(point.price_impact_bps <= 20);
+    // a slightly bigger input crosses the threshold
+    let bigger = point.input.multiply_ratio(10_001u128, 10_000u128) + Uint128(1);
+    let res = query(
+        &deps,
+        QueryMsg::DepthCurve {
+            from_token: HumanAddr::from("token0"),
+            to_token: HumanAddr::from("token1"),
+            max_input: bigger,
+            points: 1,
+        },
+    )
+    .unwrap();
+    let curve = from_binary::<DepthCurveResponse>(&res).unwrap().points;
+    assert!(curve[0].price_impact_bps > 20);
+}
+
+fn zap_in_msg(amount: u128) -> HandleMsg {
+    HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(amount),
+        msg: to_binary(&Snip20ReceiveMsg::ZapIn { min_lp_out: None }).unwrap(),
+    }
+}
+
+#[test]
+fn zap_in() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+
+    // empty pool, deposited as is
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 100_000_000);
+    let res = handle(&mut deps, mock_env("token0", &[]), zap_in_msg(100_000_000)).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![snip20_utils::mint_msg(
+            HumanAddr::from(USER),
+            Uint128(100 * 10u128.pow(18)),
+            None,
+            256,
+            code_hash(LP_TOKEN),
+            HumanAddr::from(LP_TOKEN),
+        )
+        .unwrap()]
+    );
+
+    // 100/100 pool, half of the zap is swapped into token1, less slippage and the 3/1000 fee
+    fund_pool(&mut deps, "token1", 100 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 200 * 10u128.pow(18));
+    let quote: SimulationResponse = from_binary(
+        &query(
+            &deps,
+            QueryMsg::Simulation {
+                offer_token: HumanAddr::from("token0"),
+                ask_token: HumanAddr::from("token1"),
+                amount: Uint128(5_000_000),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 10_000_000);
+    let res = handle(&mut deps, mock_env("token0", &[]), zap_in_msg(10_000_000)).unwrap();
+    let data: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        data.deposits,
+        vec![
+            token_amount("token0", 5_000_000),
+            token_amount("token1", quote.return_amount.u128()),
+        ]
+    );
+    assert!(res.log.contains(&log(
+        "swapped",
+        format!("5000000 token0 {} token1", quote.return_amount)
+    )));
+    // and the rest is deposited with the output, minting what that deposit would after a swap
+    let mut swapped = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut swapped, "token0", 100_000_000);
+    fund_pool(&mut swapped, "token1", 100 * 10u128.pow(18));
+    swapped
+        .querier
+        .set_total_supply(LP_TOKEN, 200 * 10u128.pow(18));
+    swap(&mut swapped, mock_env("token0", &[]), "token1", 5_000_000).unwrap();
+    let provided = handle(
+        &mut swapped,
+        mock_env(USER, &[]),
+        provide_msg(&[
+            ("token0", 5_000_000),
+            ("token1", quote.return_amount.u128()),
+        ]),
+    )
+    .unwrap();
+    let provided: ProvideLiquidityResponse = from_binary(&provided.data.unwrap()).unwrap();
+    assert_eq!(data.lp_amount, provided.lp_amount);
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(110_000_000), Uint128(100 * 10u128.pow(18))]
+    );
+    // nothing is sent out, the swapped token1 stays in the pool
+    assert_eq!(res.messages.len(), 1);
+
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(1_000),
+            msg: to_binary(&Snip20ReceiveMsg::ZapIn {
+                min_lp_out: Some(Uint128(10u128.pow(18))),
+            })
+            .unwrap(),
+        },
+    );
+    assert_generic_err(res, "No tokens were received for the deposit");
+
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 1_000);
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(1_000),
+            msg: to_binary(&Snip20ReceiveMsg::ZapIn {
+                min_lp_out: Some(Uint128(10u128.pow(18))),
+            })
+            .unwrap(),
+        },
+    );
+    assert_generic_err(res, "less than the minimum of");
+}
+
+#[test]
+fn zap_in_mints_more_than_deposit() {
+    // token1 is scarce, so the zap's swap into token0 rebalances the pool and gets the discounted
+    // dynamic fee, while a Deposit of token1 alone pays the imbalance fee on all of it
+    let pool = || {
+        let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+        fund_pool(&mut deps, "token0", 150_000_000);
+        fund_pool(&mut deps, "token1", 50_000_000);
+        deps.querier
+            .set_total_supply(LP_TOKEN, 200 * 10u128.pow(18));
+        handle(
+            &mut deps,
+            mock_env(ADMIN, &[]),
+            update_config_msg(ConfigUpdate {
+                dynamic_fee: Some(true),
+                dynamic_fee_base_bps: Some(30),
+                dynamic_fee_max_bps: Some(50),
+                dynamic_fee_min_bps: Some(1),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+        deps.querier
+            .add_balance("token1", MOCK_CONTRACT_ADDR, 40_000_000);
+        deps
+    };
+    let minted = |res: HandleResponse| {
+        from_binary::<ProvideLiquidityResponse>(&res.data.unwrap())
+            .unwrap()
+            .lp_amount
+    };
+
+    let mut zapped = pool();
+    let res = handle(&mut zapped, mock_env("token1", &[]), zap_in_msg(40_000_000)).unwrap();
+    assert!(res.log.iter().any(|l| l.key == "swapped"));
+    let zap = minted(res);
+
+    let mut deposited = pool();
+    let deposit = minted(
+        handle(
+            &mut deposited,
+            mock_env("token1", &[]),
+            HandleMsg::Receive {
+                from: HumanAddr::from(USER),
+                amount: Uint128(40_000_000),
+                msg: to_binary(&Snip20ReceiveMsg::Deposit {
+                    min_lp_out: None,
+                    deadline: None,
+                })
+                .unwrap(),
+            },
+        )
+        .unwrap(),
+    );
+
+    assert!(zap > deposit);
+    // both leave the pool holding the same, the zap's swap fee stays with the LPs
+    assert_eq!(
+        read_reserves(&zapped.storage).unwrap(),
+        read_reserves(&deposited.storage).unwrap()
+    );
+}
+
+#[test]
+fn deposit_by_send() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    let deposit_msg = |amount: u128, deadline: Option<u64>| HandleMsg::Receive {
+        from: HumanAddr::from(USER),
+        amount: Uint128(amount),
+        msg: to_binary(&Snip20ReceiveMsg::Deposit {
+            min_lp_out: None,
+            deadline,
+        })
+        .unwrap(),
+    };
+
+    let res = handle(
+        &mut deps,
+        mock_env("unknown", &[]),
+        deposit_msg(1_000_000, None),
+    );
+    assert_generic_err(res, "[unknown_asset]");
+    let now = mock_env(USER, &[]).block.time;
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        deposit_msg(1_000_000, Some(now - 1)),
+    );
+    assert_generic_err(res, "[deadline_expired] ");
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        deposit_msg(1_000_000, None),
+    );
+    assert_generic_err(res, "No tokens were received");
+
+    // empty pool, minted 1:1 on the normalized value
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 100_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        deposit_msg(100_000_000, None),
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![snip20_utils::mint_msg(
+            HumanAddr::from(USER),
+            Uint128(100 * 10u128.pow(18)),
+            None,
+            256,
+            code_hash(LP_TOKEN),
+            HumanAddr::from(LP_TOKEN),
+        )
+        .unwrap()]
+    );
+
+    // A single-sided deposit, nothing is swapped
+    fund_pool(&mut deps, "token1", 100 * 10u128.pow(18));
+    deps.querier
+        .set_total_supply(LP_TOKEN, 200 * 10u128.pow(18));
+    deps.querier
+        .add_balance("token0", MOCK_CONTRACT_ADDR, 10_000_000);
+    let res = handle(
+        &mut deps,
+        mock_env("token0", &[]),
+        deposit_msg(10_000_000, None),
+    )
+    .unwrap();
+    let data: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        data,
+        ProvideLiquidityResponse {
+            lp_amount: Uint128(9_983_812_689_728_985_209),
+            deposits: vec![token_amount("token0", 10_000_000)],
+        }
+    );
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128(110_000_000), Uint128(100 * 10u128.pow(18))]
+    );
+}
+
+#[test]
+fn set_rounding() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    let set_rounding = |round_down_to_nearest: u128| HandleMsg::SetRounding {
+        round_down_to_nearest: Uint128(round_down_to_nearest),
+    };
+
+    let res = handle(&mut deps, mock_env(USER, &[]), set_rounding(100));
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), set_rounding(0));
+    assert_generic_err(res, "must be between 1 and");
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        set_rounding(MAX_ROUND_DOWN_TO_NEAREST + 1),
+    );
+    assert_generic_err(res, "must be between 1 and");
+
+    let res = handle(&mut deps, mock_env(ADMIN, &[]), set_rounding(100)).unwrap();
+    assert!(res
+        .log
+        .iter()
+        .any(|l| l.key == "round_down_to_nearest" && l.value == "100"));
+    let config: ConfigResponse =
+        from_binary(&query(&deps, QueryMsg::GetConfig {}).unwrap()).unwrap();
+    assert_eq!(config.round_down_pool_answer_to_nearest, Uint128(100));
+}
+
+#[test]
+fn rounds_down_pool_answers() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000_000_000);
+    deps.querier.set_total_supply(LP_TOKEN, 2_000_000_000);
+    // 0.01 normalized, 10_000 units of a 6 decimal token
+    handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::SetRounding {
+            round_down_to_nearest: Uint128(10u128.pow(16)),
+        },
+    )
+    .unwrap();
+    let amounts = |res: &HandleResponse, key: &str| -> Vec<u128> {
+        res.log
+            .iter()
+            .filter(|l| l.key == key)
+            .map(|l| l.value.split(' ').next().unwrap().parse().unwrap())
+            .collect()
+    };
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 1_234_567).unwrap();
+    let dst_amount = amounts(&res, "dst_amount")[0];
+    assert_eq!(dst_amount, 1_230_000);
+
+    let res = handle(
+        &mut deps,
+        mock_env(LP_TOKEN, &[]),
+        withdraw_msg(1_234_567_890),
+    )
+    .unwrap();
+    let refunds = amounts(&res, "refund_asset");
+    assert_eq!(refunds.len(), 2);
+    assert!(refunds.iter().all(|refund| refund % 10_000 == 0));
+}
+
+#[test]
+fn execution_price() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+    fund_pool(&mut deps, "token0", 1_000_000_000);
+    fund_pool(&mut deps, "token1", 1_000 * 10u128.pow(18));
+    let execution_price = |src: &str, dst: &str, amount: u128| {
+        query(
+            &deps,
+            QueryMsg::GetExecutionPrice {
+                offer: token_amount(src, amount),
+                to_token: HumanAddr::from(dst),
+            },
+        )
+    };
+
+    assert_generic_err(
+        execution_price("token0", "token1", 0),
+        "Cannot price a zero amount",
+    );
+
+    // prices are normalized across decimals, and the average pays for the slippage
+    let price: ExecutionPriceResponse =
+        from_binary(&execution_price("token0", "token1", 1_000_000).unwrap()).unwrap();
+    assert_eq!(
+        price,
+        ExecutionPriceResponse {
+            average_price: Decimal::from_ratio(996_995_039_820_761_610u128, 10u128.pow(18)),
+            spot_price: Decimal::permille(997),
+        }
+    );
+
+    // the fee rounds up to a whole unit of token0, so the average is well below spot
+    let price: ExecutionPriceResponse =
+        from_binary(&execution_price("token1", "token0", 10u128.pow(12)).unwrap()).unwrap();
+    assert_eq!(price.average_price, Decimal::zero());
+    assert_eq!(price.spot_price, Decimal::permille(997));
+}
+
+#[test]
+fn provide_liquidity_logs() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 18)]);
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        provide_msg(&[("token0", 1_000_000), ("token1", 2 * 10u128.pow(18))]),
+    )
+    .unwrap();
+    deps.querier
+        .set_balance("token0", MOCK_CONTRACT_ADDR, 1_000_000);
+    deps.querier
+        .set_balance("token1", MOCK_CONTRACT_ADDR, 2 * 10u128.pow(18));
+    let res = finalize_deposit(&mut deps, &res).unwrap();
+
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "finalize_deposit"),
+            log("depositor", USER),
+            log("deposited", "1000000 token0 1000000000000000000"),
+            log(
+                "deposited",
+                "2000000000000000000 token1 2000000000000000000"
+            ),
+            log("share", "2999068031122620559"),
+        ]
+    );
+}
+
+#[test]
+fn receive_rejects_withdrawals_from_other_tokens() {
+    let mut deps = init_pool(&[("usdt", 6), ("dai", 18)]);
+
+    // Only LP tokens may be redeemed, whichever withdrawal flavour is asked for
+    let withdrawals = vec![
+        withdraw_msg(100),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(100),
+            msg: to_binary(&Snip20ReceiveMsg::WithdrawLiquidityOneToken {
+                token: HumanAddr::from("dai"),
+                min_amount: Uint128::zero(),
+                deadline: None,
+                unwrap: None,
+            })
+            .unwrap(),
+        },
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(100),
+            msg: to_binary(&Snip20ReceiveMsg::WithdrawImbalanced {
+                amounts: vec![Uint128(1), Uint128(1)],
+                max_burn: Uint128(100),
+                deadline: None,
+            })
+            .unwrap(),
+        },
+    ];
+    for msg in withdrawals {
+        let res = handle(&mut deps, mock_env("usdt", &[]), msg);
+        assert_generic_err(res, "[unknown_lp_token] ");
+    }
+
+    let res = handle(
+        &mut deps,
+        mock_env("usdt", &[]),
+        HandleMsg::Receive {
+            from: HumanAddr::from(USER),
+            amount: Uint128(100),
+            msg: Binary(b"{\"not_a_hook\":{}}".to_vec()),
+        },
+    );
+    match res {
+        Err(StdError::ParseErr { .. }) => {}
+        other => panic!("Must return parse error, got {:?}", other),
+    }
+    assert_eq!(
+        read_reserves(&deps.storage).unwrap(),
+        vec![Uint128::zero(); 2]
+    );
+}
+
+#[test]
+fn swap_rejects_unknown_assets() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token9", 100);
+    assert_generic_err(res, "Unknown destination asset HumanAddr(\"token9\")");
+
+    deps.querier.with_token("token8", 6, Some(Uint128::zero()));
+    let res = swap(&mut deps, mock_env("token8", &[]), "token1", 100);
+    assert_generic_err(res, "Unknown source asset HumanAddr(\"token8\")");
+}
+
+#[test]
+fn errors_start_with_a_stable_code() {
+    let mut deps = init_pool(&[("token0", 6), ("token1", 6)]);
+
+    let res = swap(&mut deps, mock_env("token0", &[]), "token9", 100);
+    assert_generic_err(res, "[unknown_asset] ");
+    let res = query(
+        &deps,
+        QueryMsg::GetTokenIndex {
+            token: HumanAddr::from("token9"),
+        },
+    );
+    assert_generic_err(res, "[unknown_asset] ");
+    let res = handle(
+        &mut deps,
+        mock_env(ADMIN, &[]),
+        HandleMsg::SetAssetEnabled {
+            token: HumanAddr::from("token9"),
+            enabled: false,
+        },
+    );
+    assert_generic_err(res, "[unknown_asset] ");
+    let res = handle(&mut deps, mock_env("token0", &[]), withdraw_msg(1_000));
+    assert_generic_err(res, "[unknown_lp_token] ");
+
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::ProvideLiquidity {
+            assets: vec![token_amount("token0", 1_000_000)],
+            cancel_if_no_bonus: None,
+            min_lp_out: Some(Uint128(2 * 10u128.pow(18))),
+            deadline: None,
+        },
+    );
+    assert_generic_err(res, "[slippage_exceeded] ");
+
+    set_status(&mut deps, PoolStatus::Halted);
+    let res = swap(&mut deps, mock_env("token0", &[]), "token1", 100);
+    assert_generic_err(res, "[not_active] ");
+    let res = handle(&mut deps, mock_env(LP_TOKEN, &[]), withdraw_msg(1_000));
+    assert_generic_err(res, "[halted] ");
+
+    // Unauthorized keeps its own StdError variant
+    let res = handle(
+        &mut deps,
+        mock_env(USER, &[]),
+        HandleMsg::EmergencyWithdraw {
+            recipient: HumanAddr::from("new_pool"),
+        },
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    assert_eq!(
+        StdError::from(ContractError::DeadlineExpired {
+            deadline: 10,
+            now: 11,
+        }),
+        StdError::generic_err("[deadline_expired] Deadline 10 has passed, the block time is 11")
+    );
+}
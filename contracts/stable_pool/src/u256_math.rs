@@ -22,6 +22,16 @@ pub fn div(nom: Option<U256>, denom: Option<U256>) -> Option<U256> {
     }
 }
 
+/// Division rounding up instead of down
+pub fn div_ceil(nom: Option<U256>, denom: Option<U256>) -> Option<U256> {
+    let quotient = div(nom, denom)?;
+    if mul(Some(quotient), denom)? == nom? {
+        Some(quotient)
+    } else {
+        add(Some(quotient), Some(U256::one()))
+    }
+}
+
 fn checked_add(b: U256) -> impl Fn(U256) -> Option<U256> {
     move |a: U256| a.checked_add(b)
 }
@@ -44,6 +54,17 @@ pub fn mul(a: Option<U256>, b: Option<U256>) -> Option<U256> {
     }
 }
 
+fn checked_pow(exp: U256) -> impl Fn(U256) -> Option<U256> {
+    move |base: U256| base.checked_pow(exp)
+}
+
+pub fn pow(base: Option<U256>, exp: Option<U256>) -> Option<U256> {
+    match exp {
+        Some(exp) => base.and_then(checked_pow(exp)),
+        None => None,
+    }
+}
+
 /// U256 sqrt ported from here: https://ethereum.stackexchange.com/a/87713/12112
 ///
 /// function sqrt(uint y) internal pure returns (uint z) {
@@ -63,10 +84,10 @@ pub fn mul(a: Option<U256>, b: Option<U256>) -> Option<U256> {
 pub fn u256_sqrt(y: U256) -> Option<U256> {
     let mut z = U256::from(0);
     if y.gt(&U256::from(3)) {
-        z = y.clone();
+        z = y;
         let mut x = y.checked_div(U256::from(2))?.checked_add(U256::from(1))?;
         while x.lt(&z) {
-            z = x.clone();
+            z = x;
             x = y
                 .checked_div(x)?
                 .checked_add(x)?
@@ -76,5 +97,121 @@ pub fn u256_sqrt(y: U256) -> Option<U256> {
         z = U256::from(1);
     }
 
-    return Some(z);
+    Some(z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near_max() -> Vec<U256> {
+        vec![
+            U256::max_value(),
+            U256::max_value() - 1,
+            U256::max_value() / 2 + 1,
+            U256::from(u128::MAX) + 1,
+        ]
+    }
+
+    #[test]
+    fn add_overflow_returns_none() {
+        for a in near_max() {
+            assert_eq!(add(Some(a), Some(U256::max_value())), None);
+            assert_eq!(add(Some(U256::max_value()), Some(a)), None);
+        }
+        assert_eq!(
+            add(Some(U256::max_value() - 1), Some(U256::one())),
+            Some(U256::max_value())
+        );
+    }
+
+    #[test]
+    fn sub_underflow_returns_none() {
+        for a in near_max() {
+            assert_eq!(sub(Some(a - 1), Some(a)), None);
+            assert_eq!(sub(Some(a), Some(a)), Some(U256::zero()));
+        }
+        assert_eq!(sub(Some(U256::zero()), Some(U256::one())), None);
+    }
+
+    #[test]
+    fn mul_overflow_returns_none() {
+        for a in near_max() {
+            assert_eq!(mul(Some(a), Some(a)), None);
+        }
+        assert_eq!(
+            mul(Some(U256::max_value() / 2 + 1), Some(U256::from(2))),
+            None
+        );
+        assert_eq!(
+            mul(Some(U256::max_value()), Some(U256::one())),
+            Some(U256::max_value())
+        );
+        assert_eq!(
+            mul(Some(U256::max_value()), Some(U256::zero())),
+            Some(U256::zero())
+        );
+    }
+
+    #[test]
+    fn div_by_zero_returns_none() {
+        for a in near_max() {
+            assert_eq!(div(Some(a), Some(U256::zero())), None);
+            assert_eq!(div(Some(a), Some(a)), Some(U256::one()));
+        }
+    }
+
+    #[test]
+    fn div_ceil_rounds_up() {
+        let ten = Some(U256::from(10));
+        assert_eq!(div_ceil(Some(U256::from(30)), ten), Some(U256::from(3)));
+        assert_eq!(div_ceil(Some(U256::from(31)), ten), Some(U256::from(4)));
+        assert_eq!(div_ceil(Some(U256::from(39)), ten), Some(U256::from(4)));
+        assert_eq!(div_ceil(Some(U256::zero()), ten), Some(U256::zero()));
+        assert_eq!(div_ceil(Some(U256::from(1)), ten), Some(U256::one()));
+        assert_eq!(
+            div_ceil(Some(U256::max_value()), Some(U256::from(2))),
+            Some(U256::max_value() / 2 + 1)
+        );
+        assert_eq!(div_ceil(ten, Some(U256::zero())), None);
+        assert_eq!(div_ceil(None, ten), None);
+    }
+
+    #[test]
+    fn pow_overflow_returns_none() {
+        let ten = Some(U256::from(10));
+        assert_eq!(
+            pow(ten, Some(U256::from(18))),
+            Some(U256::from(1_000_000_000_000_000_000u128))
+        );
+        // 10^77 < 2^256 < 10^78
+        assert!(pow(ten, Some(U256::from(77))).is_some());
+        assert_eq!(pow(ten, Some(U256::from(78))), None);
+        assert_eq!(
+            pow(Some(U256::from(u128::MAX) + 1), Some(U256::from(2))),
+            None
+        );
+        for a in near_max() {
+            assert_eq!(pow(Some(a), Some(U256::one())), Some(a));
+            assert_eq!(pow(Some(a), Some(U256::from(2))), None);
+        }
+    }
+
+    #[test]
+    fn none_propagates() {
+        let one = Some(U256::one());
+        assert_eq!(add(None, one), None);
+        assert_eq!(sub(one, None), None);
+        assert_eq!(mul(None, one), None);
+        assert_eq!(div(one, None), None);
+        assert_eq!(pow(None, one), None);
+    }
+
+    #[test]
+    fn sqrt_near_max() {
+        assert_eq!(u256_sqrt(U256::zero()), Some(U256::zero()));
+        assert_eq!(u256_sqrt(U256::from(3)), Some(U256::one()));
+        assert_eq!(u256_sqrt(U256::from(16)), Some(U256::from(4)));
+        assert_eq!(u256_sqrt(U256::max_value()), Some(U256::from(u128::MAX)));
+    }
 }